@@ -0,0 +1,202 @@
+use crate::stats::data_dir;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Something done once in a game, kept for good once unlocked. Apart from the first win
+/// they are only earned on preset boards played by the classic rules without hints, as
+/// other games make them too easy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Achievement {
+    FirstWin,
+    ExpertUnder100,
+    NoFlags,
+    NoChords,
+    WinningStreak,
+    DailyWeek,
+}
+
+impl Achievement {
+    pub(crate) const ALL: [Achievement; 6] = [
+        Achievement::FirstWin,
+        Achievement::ExpertUnder100,
+        Achievement::NoFlags,
+        Achievement::NoChords,
+        Achievement::WinningStreak,
+        Achievement::DailyWeek,
+    ];
+
+    /// Name in the achievements file.
+    fn key(self) -> &'static str {
+        match self {
+            Achievement::FirstWin => "first-win",
+            Achievement::ExpertUnder100 => "expert-under-100",
+            Achievement::NoFlags => "no-flags",
+            Achievement::NoChords => "no-chords",
+            Achievement::WinningStreak => "winning-streak",
+            Achievement::DailyWeek => "daily-week",
+        }
+    }
+
+    pub(crate) fn title(self) -> &'static str {
+        match self {
+            Achievement::FirstWin => "First win",
+            Achievement::ExpertUnder100 => "Speed demon",
+            Achievement::NoFlags => "Flagless",
+            Achievement::NoChords => "One at a time",
+            Achievement::WinningStreak => "On a roll",
+            Achievement::DailyWeek => "Daily habit",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Achievement::FirstWin => "Win a game",
+            Achievement::ExpertUnder100 => "Win on expert in under 100 seconds",
+            Achievement::NoFlags => "Win on intermediate or expert without placing a flag",
+            Achievement::NoChords => "Win on intermediate or expert without chording",
+            Achievement::WinningStreak => "Win 5 games in a row on one difficulty",
+            Achievement::DailyWeek => "Win the daily challenge 7 days running",
+        }
+    }
+}
+
+/// A game just won, as far as achievements go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Win<'a> {
+    /// The board's preset, if it was played by the classic rules without hints.
+    pub(crate) preset: Option<&'a str>,
+    pub(crate) millis: u64,
+    /// Whether a flag was placed, or a chord played; either is assumed of a game that
+    /// was resumed, as what happened before it was saved isn't known.
+    pub(crate) flagged: bool,
+    pub(crate) chorded: bool,
+    /// Wins in a row on the board's difficulty, this one included.
+    pub(crate) streak: u32,
+    /// Longest run of days with the daily challenge won.
+    pub(crate) daily_run: usize,
+}
+
+impl Win<'_> {
+    /// The achievements the win earns, whether unlocked before or not.
+    pub(crate) fn earned(&self) -> Vec<Achievement> {
+        let hard = matches!(self.preset, Some("intermediate" | "expert"));
+        Achievement::ALL.into_iter()
+            .filter(|achievement| match achievement {
+                Achievement::FirstWin => true,
+                Achievement::ExpertUnder100 => self.preset == Some("expert") && self.millis < 100_000,
+                Achievement::NoFlags => hard && !self.flagged,
+                Achievement::NoChords => hard && !self.chorded,
+                Achievement::WinningStreak => self.preset.is_some() && self.streak >= 5,
+                Achievement::DailyWeek => self.daily_run >= 7,
+            })
+            .collect()
+    }
+}
+
+/// The achievements unlocked so far, stored in `$XDG_DATA_HOME/mineswept/achievements`:
+/// one `<name> <unix time>` line each, of when it was unlocked.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Achievements {
+    pub(crate) unlocked: BTreeMap<Achievement, u64>,
+}
+
+impl Achievements {
+    /// Loads the achievements file; a missing file means none unlocked yet. Lines naming
+    /// achievements this version doesn't know are skipped.
+    pub(crate) fn load() -> io::Result<Achievements> {
+        let Some(path) = achievements_path() else {
+            return Ok(Achievements::default());
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Achievements::default()),
+            Err(e) => return Err(e),
+        };
+
+        let unlocked = text.lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let key = fields.next()?;
+                let achievement = Achievement::ALL.into_iter().find(|achievement| achievement.key() == key)?;
+                Some((achievement, fields.next()?.parse().ok()?))
+            })
+            .collect();
+        Ok(Achievements { unlocked })
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let path = achievements_path().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No data directory (HOME unset)"))?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut contents = String::from("# mineswept achievements\n");
+        for (achievement, unlocked_at) in &self.unlocked {
+            contents += &format!("{} {}\n", achievement.key(), unlocked_at);
+        }
+        let partial = path.with_extension("partial");
+        fs::write(&partial, contents)?;
+        fs::rename(&partial, path)
+    }
+
+    /// Unlocks the `earned` achievements that weren't yet and saves them, returning those.
+    pub(crate) fn unlock(earned: &[Achievement]) -> io::Result<Vec<Achievement>> {
+        let mut achievements = Achievements::load()?;
+        let new: Vec<Achievement> = earned.iter().copied()
+            .filter(|achievement| !achievements.unlocked.contains_key(achievement))
+            .collect();
+        if !new.is_empty() {
+            let unlocked_at = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+            achievements.unlocked.extend(new.iter().map(|&achievement| (achievement, unlocked_at)));
+            achievements.save()?;
+        }
+        Ok(new)
+    }
+
+    /// Lines for the in-game achievements screen, short enough for the smallest board.
+    pub(crate) fn summary(&self) -> Vec<String> {
+        let mut lines = vec![format!("Achievements {}/{}", self.unlocked.len(), Achievement::ALL.len())];
+        for achievement in Achievement::ALL {
+            let mark = if self.unlocked.contains_key(&achievement) { '*' } else { '-' };
+            lines.push(format!("{} {}", mark, achievement.title()));
+        }
+        lines
+    }
+}
+
+impl fmt::Display for Achievements {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Achievements ({} of {})", self.unlocked.len(), Achievement::ALL.len())?;
+        for achievement in Achievement::ALL {
+            let mark = if self.unlocked.contains_key(&achievement) { '*' } else { ' ' };
+            writeln!(f, "{} {:<14} {}", mark, achievement.title(), achievement.description())?;
+        }
+        Ok(())
+    }
+}
+
+fn achievements_path() -> Option<PathBuf> {
+    Some(data_dir()?.join("achievements"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wins_earn_achievements_on_the_boards_they_are_for() {
+        let win = Win { preset: Some("expert"), millis: 95_000, flagged: true, chorded: false, streak: 5, daily_run: 2 };
+        assert_eq!(win.earned(), [Achievement::FirstWin, Achievement::ExpertUnder100, Achievement::NoChords, Achievement::WinningStreak]);
+
+        let custom = Win { preset: None, millis: 1_000, flagged: false, chorded: false, streak: 9, daily_run: 7 };
+        assert_eq!(custom.earned(), [Achievement::FirstWin, Achievement::DailyWeek]);
+
+        let beginner = Win { preset: Some("beginner"), flagged: false, ..custom };
+        assert!(!beginner.earned().contains(&Achievement::NoFlags));
+    }
+}
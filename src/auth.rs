@@ -0,0 +1,217 @@
+use crate::x11comm::{local_hostname, DisplayName};
+use byteorder::{BigEndian, ReadBytesExt};
+use des::cipher::{BlockEncrypt, KeyInit};
+use des::Des;
+use std::env;
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const AUTH_ENTRY_FAMILY_LOCAL: u16 = 256;
+const AUTH_ENTRY_FAMILY_WILD: u16 = 65535;
+pub(crate) const AUTH_NAME_MAGIC_COOKIE: &str = "MIT-MAGIC-COOKIE-1";
+const AUTH_NAME_XDM_AUTHORIZATION: &str = "XDM-AUTHORIZATION-1";
+
+/// XDM-AUTHORIZATION-1 entries hold 8 bytes of "rho" followed by the 56-bit DES key,
+/// stored in 8 bytes whose first byte is unused.
+const XDM_AUTH_DATA_LEN: usize = 16;
+/// Encrypted length: rho, address, port and timestamp, padded to whole DES blocks.
+const XDM_AUTH_MESSAGE_LEN: usize = 24;
+
+/// Authorization protocol name and data sent in the connection setup. The default, with
+/// both empty, asks for an unauthenticated connection.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AuthToken {
+    pub(crate) name: String,
+    pub(crate) data: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct AuthEntry {
+    family: u16,
+    address: Vec<u8>,
+    display_number: String,
+    auth_name: String,
+    auth_data: Vec<u8>,
+}
+
+/// Reads one length-prefixed field of an authority file entry.
+fn read_counted(buffer: &mut Cursor<Vec<u8>>) -> io::Result<Vec<u8>> {
+    let len = buffer.read_u16::<BigEndian>()?;
+    let mut field = vec![0u8; len as usize];
+    buffer.read_exact(&mut field)?;
+    Ok(field)
+}
+
+fn read_x11_auth_entry(buffer: &mut Cursor<Vec<u8>>) -> io::Result<Option<AuthEntry>> {
+    let family = buffer.read_u16::<BigEndian>()?;
+    let address = read_counted(buffer)?;
+    let display_number = String::from_utf8_lossy(&read_counted(buffer)?).to_string();
+    let auth_name = String::from_utf8_lossy(&read_counted(buffer)?).to_string();
+    let auth_data = read_counted(buffer)?;
+
+    Ok(Some(AuthEntry {
+        family,
+        address,
+        display_number,
+        auth_name,
+        auth_data,
+    }))
+}
+
+impl AuthEntry {
+    /// Whether this entry applies to `display`, following the same rules as Xlib: a
+    /// wildcard family matches any address, a local entry must name this machine, and an
+    /// empty display number matches every display.
+    fn matches(&self, display: &DisplayName, hostname: Option<&str>) -> bool {
+        let address_matches = match self.family {
+            AUTH_ENTRY_FAMILY_WILD => true,
+            AUTH_ENTRY_FAMILY_LOCAL => match display.host.as_deref() {
+                None | Some("unix") => hostname.is_none_or(|hostname| self.address == hostname.as_bytes()),
+                Some(host) => self.address == host.as_bytes(),
+            },
+            _ => false,
+        };
+        let display_matches = self.display_number.is_empty()
+            || self.display_number == display.display_number.to_string();
+
+        address_matches && display_matches
+    }
+
+    /// The token to send for this entry, or `None` if its scheme isn't supported or its
+    /// data is malformed.
+    fn to_token(&self) -> Option<AuthToken> {
+        let data = match self.auth_name.as_str() {
+            AUTH_NAME_MAGIC_COOKIE if !self.auth_data.is_empty() => self.auth_data.clone(),
+            AUTH_NAME_XDM_AUTHORIZATION if self.auth_data.len() == XDM_AUTH_DATA_LEN => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs() as u32);
+                xdm_authorization_data(&self.auth_data, now)
+            }
+            _ => return None,
+        };
+        Some(AuthToken { name: self.auth_name.clone(), data })
+    }
+}
+
+/// Finds the Xauthority entry for `display`. Returns `None` when there is no Xauthority
+/// file or no usable entry in it, in which case the connection is attempted without
+/// authorization, as many local servers allow.
+pub(crate) fn load_x11_auth_token(display: &DisplayName) -> io::Result<Option<AuthToken>> {
+    let filename = match env::var_os("XAUTHORITY") {
+        Some(filename) => PathBuf::from(filename),
+        None => match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".Xauthority"),
+            None => return Ok(None),
+        },
+    };
+
+    let data = match fs::read(&filename) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut buffer = Cursor::new(data);
+    let hostname = local_hostname();
+
+    // Like Xlib, use the first usable entry in file order, whatever its scheme
+    while let Ok(Some(auth_entry)) = read_x11_auth_entry(&mut buffer) {
+        if !auth_entry.matches(display, hostname.as_deref()) {
+            continue;
+        }
+        if let Some(token) = auth_entry.to_token() {
+            return Ok(Some(token));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Finds the MIT-MAGIC-COOKIE-1 that the ICE authority file holds for `protocol_name`
+/// ("ICE" for the connection itself, or e.g. "XSMP") at `network_id`, the address as
+/// given in `SESSION_MANAGER`. Returns `None` when there is no file or no such entry.
+pub(crate) fn load_ice_auth_cookie(protocol_name: &str, network_id: &str) -> io::Result<Option<Vec<u8>>> {
+    let filename = match env::var_os("ICEAUTHORITY") {
+        Some(filename) => PathBuf::from(filename),
+        None => {
+            // libICE 1.0.10 and later keep the file in the runtime directory
+            let runtime_file = env::var_os("XDG_RUNTIME_DIR").map(|dir| PathBuf::from(dir).join("ICEauthority"));
+            match (runtime_file, env::var_os("HOME")) {
+                (Some(file), _) if file.exists() => file,
+                (_, Some(home)) => PathBuf::from(home).join(".ICEauthority"),
+                _ => return Ok(None),
+            }
+        }
+    };
+
+    let data = match fs::read(&filename) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut buffer = Cursor::new(data);
+
+    // Entries are protocol name, protocol data, network ID, auth name and auth data
+    while let Ok(fields) = (0..5).map(|_| read_counted(&mut buffer)).collect::<io::Result<Vec<_>>>() {
+        if fields[0] == protocol_name.as_bytes()
+            && fields[2] == network_id.as_bytes()
+            && fields[3] == AUTH_NAME_MAGIC_COOKIE.as_bytes()
+        {
+            return Ok(Some(fields[4].clone()));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Builds the XDM-AUTHORIZATION-1 message for `entry_data` at `time`: rho, a client
+/// address, port and the time, DES-CBC encrypted under the entry's key.
+///
+/// Unix socket connections have no address, so like xcb we send a made-up one that is
+/// unique per connection and use the pid as the port. The server uses the triple to
+/// reject replayed messages.
+fn xdm_authorization_data(entry_data: &[u8], time: u32) -> Vec<u8> {
+    static NONCE: AtomicU32 = AtomicU32::new(0);
+
+    let (rho, key) = entry_data.split_at(8);
+    let address = u32::MAX - NONCE.fetch_add(1, Ordering::Relaxed);
+    let port = process::id() as u16;
+
+    let mut message = Vec::with_capacity(XDM_AUTH_MESSAGE_LEN);
+    message.extend_from_slice(rho);
+    message.extend_from_slice(&address.to_be_bytes());
+    message.extend_from_slice(&port.to_be_bytes());
+    message.extend_from_slice(&time.to_be_bytes());
+    message.resize(XDM_AUTH_MESSAGE_LEN, 0);
+
+    xdm_wrap(&mut message, key.try_into().unwrap());
+    message
+}
+
+/// Encrypts `data` in place with DES in CBC mode and a zero IV, as XdmcpWrap does.
+/// `data` must be a whole number of 8-byte blocks.
+fn xdm_wrap(data: &mut [u8], stored_key: [u8; 8]) {
+    let cipher = Des::new(&xdm_des_key(stored_key).into());
+    let mut previous = [0u8; 8];
+    for block in data.chunks_exact_mut(8) {
+        for (byte, chained) in block.iter_mut().zip(previous) {
+            *byte ^= chained;
+        }
+        cipher.encrypt_block(block.into());
+        previous.copy_from_slice(block);
+    }
+}
+
+/// Spreads the 56 key bits stored after the unused first byte into the top 7 bits of each
+/// of the 8 DES key bytes, with odd parity in the low bit.
+fn xdm_des_key(stored: [u8; 8]) -> [u8; 8] {
+    let bits = u64::from_be_bytes(stored) & ((1 << 56) - 1);
+    let mut key = [0u8; 8];
+    for (i, byte) in key.iter_mut().enumerate() {
+        let seven_bits = (bits >> (49 - 7 * i)) as u8 & 0x7f;
+        *byte = seven_bits << 1 | seven_bits.count_ones().is_multiple_of(2) as u8;
+    }
+    key
+}
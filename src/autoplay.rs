@@ -0,0 +1,100 @@
+use crate::config::Adjacency;
+use crate::game::EntityKind;
+use crate::solver::{self, Deduction};
+use std::time::{Duration, Instant};
+
+/// Time between the solver pointing out a cell and playing it, and between moves.
+pub(crate) const MOVE_DELAY: Duration = Duration::from_millis(350);
+/// Time a finished game stays up before the next one is dealt.
+pub(crate) const GAME_OVER_DELAY: Duration = Duration::from_secs(3);
+
+/// A click the solver has settled on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Move {
+    pub(crate) cell: usize,
+    /// 1 to reveal, 3 to flag, as for `Scene::click_cell`.
+    pub(crate) button: u8,
+    /// Nothing proved the cell safe, so it may hold a mine.
+    pub(crate) guess: bool,
+}
+
+/// The solver playing the board by itself (`--autoplay`): the move it is about to make,
+/// shown by the focus ring, and when it makes it.
+#[derive(Debug)]
+pub(crate) struct Autoplay {
+    pub(crate) next_at: Instant,
+    pub(crate) pending: Option<Move>,
+    /// Guesses made this game.
+    pub(crate) guesses: u32,
+}
+
+impl Autoplay {
+    pub(crate) fn new() -> Autoplay {
+        Autoplay { next_at: Instant::now() + MOVE_DELAY, pending: None, guesses: 0 }
+    }
+}
+
+/// Picks the next move on a board of `columns` columns showing `cells`, among the cells
+/// `playable` allows: a cell the numbers prove safe, or else flags one proven to hold a
+/// mine while `can_flag`, or else reveals the covered cell `pick` chooses the index of
+/// among those left.
+pub(crate) fn next_move(
+    cells: &[EntityKind],
+    columns: usize,
+    adjacency: Adjacency,
+    anti_mines: bool,
+    can_flag: bool,
+    playable: impl Fn(usize) -> bool,
+    pick: impl FnOnce(&[usize]) -> usize,
+) -> Option<Move> {
+    let deductions: Vec<Deduction> = solver::deduce(cells, columns, adjacency, anti_mines).into_iter()
+        .filter(|deduction| playable(deduction.cell()))
+        .collect();
+    let safe = deductions.iter().find_map(|&deduction| match deduction {
+        Deduction::Safe(cell) if cells[cell] == EntityKind::Covered => Some(cell),
+        _ => None,
+    });
+    if let Some(cell) = safe {
+        return Some(Move { cell, button: 1, guess: false });
+    }
+    let mine = deductions.iter().find_map(|&deduction| match deduction {
+        Deduction::Mine(cell) if can_flag && cells[cell] == EntityKind::Covered => Some(cell),
+        _ => None,
+    });
+    if let Some(cell) = mine {
+        return Some(Move { cell, button: 3, guess: false });
+    }
+
+    // Cells proven to hold a mine are only left covered when they can't be flagged
+    let proven: Vec<usize> = deductions.iter().map(|deduction| deduction.cell()).collect();
+    let candidates: Vec<usize> = (0..cells.len())
+        .filter(|&cell| cells[cell] == EntityKind::Covered && playable(cell) && !proven.contains(&cell))
+        .collect();
+    if candidates.is_empty() {
+        return None;
+    }
+    Some(Move { cell: candidates[pick(&candidates)], button: 1, guess: true })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plays_what_the_numbers_prove_before_guessing() {
+        use EntityKind::{Covered, Uncovered0, Uncovered1};
+        // Ones around a single covered cell
+        let cells = [Uncovered1, Covered, Uncovered1, Uncovered1, Uncovered1, Uncovered1];
+        let next = |can_flag| next_move(&cells, 3, Adjacency::King, false, can_flag, |_| true, |_| 0);
+        assert_eq!(next(true), Some(Move { cell: 1, button: 3, guess: false }));
+        assert_eq!(next(false), None);
+
+        let cells = [Covered, Covered, Covered, Covered, Uncovered0, Covered];
+        let safe = next_move(&cells, 3, Adjacency::King, false, true, |cell| cell != 0, |_| 0);
+        assert_eq!(safe, Some(Move { cell: 1, button: 1, guess: false }));
+
+        let cells = [Covered, Covered, Covered, Covered];
+        let guess = next_move(&cells, 2, Adjacency::King, false, true, |cell| cell != 0, |_| 1);
+        assert_eq!(guess, Some(Move { cell: 2, button: 1, guess: true }));
+    }
+}
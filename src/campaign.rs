@@ -0,0 +1,146 @@
+use crate::savegame::SavedBoard;
+use crate::stats::data_dir;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Stars a win can earn on a level.
+pub(crate) const MAX_STARS: u8 = 3;
+
+/// A level of the campaign (`--campaign`): a board to clear, starting from an opening
+/// already uncovered, without having to guess.
+#[derive(Debug)]
+pub(crate) struct Level {
+    /// The board as a bare grid (see `SavedBoard`), bundled from `resources/campaign`.
+    board: &'static str,
+    /// Winning within these many seconds earns a second star, and a third.
+    star_seconds: [u64; 2],
+}
+
+impl Level {
+    pub(crate) fn board(&self) -> SavedBoard {
+        self.board.parse().expect("campaign boards are valid")
+    }
+
+    /// Stars earned by clearing the level in `millis`.
+    pub(crate) fn stars(&self, millis: u64) -> u8 {
+        1 + self.star_seconds.iter().filter(|&&seconds| millis <= seconds * 1000).count() as u8
+    }
+}
+
+pub(crate) const LEVELS: [Level; 8] = [
+    Level { board: include_str!("../resources/campaign/01.board"), star_seconds: [30, 15] },
+    Level { board: include_str!("../resources/campaign/02.board"), star_seconds: [45, 25] },
+    Level { board: include_str!("../resources/campaign/03.board"), star_seconds: [60, 35] },
+    Level { board: include_str!("../resources/campaign/04.board"), star_seconds: [90, 50] },
+    Level { board: include_str!("../resources/campaign/05.board"), star_seconds: [120, 70] },
+    Level { board: include_str!("../resources/campaign/06.board"), star_seconds: [150, 90] },
+    Level { board: include_str!("../resources/campaign/07.board"), star_seconds: [240, 140] },
+    Level { board: include_str!("../resources/campaign/08.board"), star_seconds: [300, 180] },
+];
+
+/// The most stars earned on each completed level, stored in
+/// `$XDG_DATA_HOME/mineswept/campaign`: one `<level> <stars>` line each, levels
+/// counted from 1. Each level is unlocked by completing the one before.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct CampaignProgress {
+    pub(crate) stars: BTreeMap<usize, u8>,
+}
+
+impl CampaignProgress {
+    /// Loads the campaign file; a missing file means no level completed yet.
+    pub(crate) fn load() -> io::Result<CampaignProgress> {
+        let Some(path) = campaign_path() else {
+            return Ok(CampaignProgress::default());
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(CampaignProgress::default()),
+            Err(e) => return Err(e),
+        };
+
+        let stars = text.lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let level: usize = fields.next()?.parse().ok().filter(|level| (1..=LEVELS.len()).contains(level))?;
+                let stars: u8 = fields.next()?.parse().ok()?;
+                Some((level - 1, stars.clamp(1, MAX_STARS)))
+            })
+            .collect();
+        Ok(CampaignProgress { stars })
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let path = campaign_path().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No data directory (HOME unset)"))?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut contents = String::from("# mineswept campaign\n");
+        for (level, stars) in &self.stars {
+            contents += &format!("{} {}\n", level + 1, stars);
+        }
+        let partial = path.with_extension("partial");
+        fs::write(&partial, contents)?;
+        fs::rename(&partial, path)
+    }
+
+    /// Records `stars` earned on `level`, unless it was completed with more before.
+    pub(crate) fn record(level: usize, stars: u8) -> io::Result<()> {
+        let mut progress = CampaignProgress::load()?;
+        let best = progress.stars.entry(level).or_default();
+        if stars > *best {
+            *best = stars;
+            progress.save()?;
+        }
+        Ok(())
+    }
+
+    /// Whether `level` can be played: the first always can, the others once the one
+    /// before has been completed.
+    pub(crate) fn is_unlocked(&self, level: usize) -> bool {
+        level == 0 || self.stars.contains_key(&(level - 1))
+    }
+
+    /// The first level not completed yet, or the last once all are.
+    pub(crate) fn next_level(&self) -> usize {
+        (0..LEVELS.len()).find(|level| !self.stars.contains_key(level)).unwrap_or(LEVELS.len() - 1)
+    }
+}
+
+impl fmt::Display for CampaignProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stars: u32 = self.stars.values().map(|&stars| stars as u32).sum();
+        writeln!(f, "Campaign: {} of {} levels, {} of {} stars", self.stars.len(), LEVELS.len(), stars, LEVELS.len() * MAX_STARS as usize)?;
+        for level in 0..LEVELS.len() {
+            let earned = match self.stars.get(&level) {
+                Some(&stars) => "*".repeat(stars as usize),
+                None if self.is_unlocked(level) => "not completed".to_string(),
+                None => "locked".to_string(),
+            };
+            writeln!(f, "Level {:<2} {}", level + 1, earned)?;
+        }
+        Ok(())
+    }
+}
+
+fn campaign_path() -> Option<PathBuf> {
+    Some(data_dir()?.join("campaign"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::clears_without_guessing;
+
+    #[test]
+    fn levels_can_be_cleared_by_reasoning_and_earn_stars_by_time() {
+        for level in &LEVELS {
+            assert!(clears_without_guessing(&level.board()));
+        }
+        assert_eq!([40_000, 30_000, 15_000].map(|millis| LEVELS[0].stars(millis)), [1, 2, 3]);
+    }
+}
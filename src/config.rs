@@ -1,4 +1,788 @@
-pub(crate) const ENTITIES_ROW_COUNT: u16 = 16;
-pub(crate) const ENTITIES_COLUMN_COUNT: u16 = 16;
-pub(crate) const ENTITIES_WIDTH: u16 = 16;
-pub(crate) const ENTITIES_HEIGHT: u16 = 16;
\ No newline at end of file
+use crate::campaign::LEVELS;
+use crate::pattern::Pattern;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU16, Ordering};
+
+/// Size of a cell in the sprite sheet as bundled.
+pub(crate) const SPRITE_WIDTH: u16 = 16;
+pub(crate) const SPRITE_HEIGHT: u16 = 16;
+/// Height of the text strip below the board, when cells aren't scaled up.
+const STATUS_BAR_HEIGHT: u16 = 20;
+
+/// Factor the sprite sheet is scaled up by with `--scale`, set once before it is loaded.
+static SCALE: AtomicU16 = AtomicU16::new(1);
+
+pub(crate) fn set_scale(scale: Scale) {
+    SCALE.store(scale.0, Ordering::Relaxed);
+}
+
+pub(crate) fn scale() -> u16 {
+    SCALE.load(Ordering::Relaxed)
+}
+
+/// Width of a cell on screen, in pixels.
+pub(crate) fn entities_width() -> u16 {
+    SPRITE_WIDTH * scale()
+}
+
+/// Height of a cell on screen, in pixels.
+pub(crate) fn entities_height() -> u16 {
+    SPRITE_HEIGHT * scale()
+}
+
+/// Height of the text strip below the board, grown once cells are scaled up to fit the
+/// flag mode button at its right: a cell with 2 pixels above and below.
+pub(crate) fn status_bar_height() -> u16 {
+    STATUS_BAR_HEIGHT.max(entities_height() + 4)
+}
+
+pub(crate) const USAGE: &str = "Usage: mineswept-x11 [OPTIONS]
+
+Options:
+  --screen <N>              Run on screen N (overrides the .N suffix of DISPLAY)
+  --socket <PATH>           Connect to the X server socket at PATH instead of the one from DISPLAY
+  --connect-timeout <SECS>  Give up connecting after SECS seconds (default 5)
+  --connect-retries <N>     Retry connecting up to N times (default 3)
+  --placement <WHERE>       Center the window on the primary monitor (primary, default) or on
+                            the monitor under the pointer (pointer)
+  --position <X,Y>          Place the window at X,Y instead of centering it
+  --always-on-top           Ask the window manager to keep the window above others
+  --translucent             Use a 32-bit visual with alpha so a compositor can blend the window
+  --scale <N>               Draw the cells N times bigger (1 to 4, default 1), for screens
+                            with small pixels
+  --difficulty <LEVEL>      Start on a beginner (9x9, 10 mines), intermediate (16x16, 40 mines,
+                            default) or expert (30x16, 99 mines) board; D cycles them in game
+  --rows <N>                Play on N rows (2 to 255) instead of the preset's; mines keep
+                            the preset's density
+  --cols <N>                Play on N columns (2 to 255) instead of the preset's
+  --mines <N>               Hide N mines, at least one cell fewer than the board has
+  --density <FRACTION>      Hide a mine in FRACTION of the cells, between 0 and 1 (e.g. 0.2)
+  --anti-mines <N>          Make N of the mines anti-mines: numbers show the mines around a
+                            cell minus the anti-mines, and both must be avoided
+  --adjacency <RULE>        Count mines in the eight surrounding cells (king, default), in
+                            the cells a knight's move away (knight), or in the 24 cells
+                            within two steps (far-sight)
+  --wrap                    Join the opposite edges of the board, so numbers on an edge count
+                            the mines along the other (marked) edge too
+  --daily                   Play today's challenge: the same board for everyone on a given UTC
+                            day and difficulty, once, recorded apart from other games
+  --seed <N>                Generate the first board from seed N, as printed for every board,
+                            to retry or share it (given the same first click)
+  --pattern <SHAPE>         Deal the mines in a shape: heart, creeper or any word of up to 10
+                            letters, with the mines it has no room for scattered around it
+  --opening                 Make the first click of a game always open an area, by keeping
+                            mines off its neighbours too (the first click is always safe)
+  --auto-flag               Flag the mines an uncovered number leaves no doubt about, such as
+                            a 1 with a single covered neighbour
+  --guess-protection        When nothing left on the board can be worked out and a guess hits
+                            a mine, move the mine to a cell next to the very same numbers,
+                            where there is one; such games don't count for best times
+  --win-by-flagging         Also win once every mine is flagged and no flag is wrong, without
+                            uncovering the rest; such games don't count for best times
+  --blitz <SECS>            Clear the board within SECS seconds (1 to 9999) of the first reveal;
+                            the countdown is shown, and the game is lost when it runs out
+  --flag-limit <N|mines>    Allow at most N flags at a time, or as many as there are mines;
+                            the flags left are shown and further ones refused
+  --endless                 Play a board without edges, scrolled with the mouse wheel or by
+                            moving the focus past the window's edge; the score is how many
+                            cells are cleared before a mine goes off
+  --fog <RADIUS>            Hide the board but for the cells within RADIUS (1 to 5) of an
+                            uncovered one, which are the only ones that can be played
+  --chaos <N|Ns>            Move the mines under covered, unflagged cells to other such cells
+                            after every N cells clicked open, or every N seconds with Ns
+                            (1 to 999); the numbers change to match
+  --tutorial                Learn to play on a few small boards, each showing what to do on
+                            it; Space moves on once it is done
+  --autoplay                Watch the solver play, one paced move at a time, guessing only
+                            when it has to, and start over after every game, until a key
+                            or button is pressed
+  --campaign                Play a series of handcrafted levels that need no guessing, each
+                            unlocked by clearing the one before; faster wins earn up to three
+                            stars
+  --level <N>               With --campaign, play level N (1 to 8, once unlocked) instead of
+                            the first one not cleared yet
+  --hot-seat                Take turns with another player at the same board: each reveal
+                            passes the turn and scores the cells it uncovers, and whoever
+                            sets off a mine is out while the other plays on
+  --splits <PERCENTS>       Take split times at these percentages of the board's 3BV cleared
+                            (1 to 99, e.g. 25,50,75), shown against those of the best time,
+                            on boards with best times
+  --lives <N>               Play on after hitting a mine until N mines (1 to 9, default 1)
+                            have gone off; such games don't count for best times
+  --bell-volume <PERCENT>   Ring the bell at PERCENT of the base volume when a game ends,
+                            from -100 (silent) to 100 (default 0)
+  --import-board <PATH>     Play the board in PATH (also --board): a saved or exported board,
+                            just rows of `.` (no mine) and `*` (mine), or an MBF board file
+                            (`.mbf`); its mines stay where they are
+  --board-from-image <PATH> Play a board drawn from the PNG in PATH, stretched over the board
+                            size, with a mine in every cell that is dark in the picture
+  --export-board <PATH>     Write the board to PATH on E and when quitting, to share it or
+                            attach it to a bug report (E alone writes to the current directory)
+  --export-replays <DIR>    Write a replay of every game finished to DIR, with the mines and
+                            every click and its time, in the RawVF format of the community's
+                            video tools; only games by the classic rules are written
+  --resume                  Continue the game saved with Ctrl+S or by quitting mid-game
+  --host <PORT>             Wait for another player to join on PORT, then play the board
+                            together: both see and play every reveal and flag
+  --join <HOST:PORT>        Play the board of the game hosted at HOST:PORT
+  --race                    With --host or --join, race on copies of the same board instead:
+                            the first to clear it wins, and hitting a mine loses
+  --sm-client-id <ID>       Rejoin the desktop session as client ID and restore the board
+                            saved at logout (passed by the session manager)
+  --debug-mines             Mark the cells hiding a mine, and print the board whenever its
+                            mines are placed or move, to debug the game; such games don't
+                            count for best times
+  --stats                   Print the statistics, best times and achievements of past games
+                            and exit
+  --reset-scores            Forget the best times of every difficulty and exit
+  -h, --help                Print this help";
+
+/// Which monitor the window is centered on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Placement {
+    #[default]
+    Primary,
+    Pointer,
+}
+
+impl FromStr for Placement {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "primary" => Ok(Placement::Primary),
+            "pointer" => Ok(Placement::Pointer),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Explicit window position, given as `X,Y`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct WindowPosition {
+    pub(crate) x: u16,
+    pub(crate) y: u16,
+}
+
+impl FromStr for WindowPosition {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (x, y) = value.split_once(',').ok_or(())?;
+        Ok(WindowPosition {
+            x: x.trim().parse().map_err(|_| ())?,
+            y: y.trim().parse().map_err(|_| ())?,
+        })
+    }
+}
+
+/// Size of a board in cells, and how many mines are hidden in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BoardSize {
+    pub(crate) rows: u16,
+    pub(crate) columns: u16,
+    pub(crate) mines: u16,
+    /// How many of the mines are anti-mines, which count -1 in the numbers around them.
+    pub(crate) anti_mines: u16,
+}
+
+impl BoardSize {
+    pub(crate) fn cell_count(&self) -> usize {
+        self.rows as usize * self.columns as usize
+    }
+
+    /// Width of the board in pixels.
+    pub(crate) fn width(&self) -> u16 {
+        self.columns * entities_width()
+    }
+
+    /// Height of the board in pixels, without the status bar.
+    pub(crate) fn height(&self) -> u16 {
+        self.rows * entities_height()
+    }
+}
+
+/// Which cells a number counts the mines of. Revealing, flood filling, chording and the
+/// solver all go through this, so a new rule only has to say where its neighbours are.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Adjacency {
+    /// The eight surrounding cells.
+    #[default]
+    King,
+    /// The up to eight cells a knight's move away.
+    Knight,
+    /// The 24 cells within two steps in any direction, numbers going up to 24.
+    FarSight,
+    /// As `King`, `Knight` and `FarSight`, on a board whose opposite edges meet (`--wrap`).
+    WrappedKing,
+    WrappedKnight,
+    WrappedFarSight,
+}
+
+impl Adjacency {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Adjacency::King => "king",
+            Adjacency::Knight => "knight",
+            Adjacency::FarSight => "far-sight",
+            Adjacency::WrappedKing => "wrapped-king",
+            Adjacency::WrappedKnight => "wrapped-knight",
+            Adjacency::WrappedFarSight => "wrapped-far-sight",
+        }
+    }
+
+    /// The same rule on a board that wraps around at the edges.
+    pub(crate) fn wrapped(self) -> Adjacency {
+        match self {
+            Adjacency::King | Adjacency::WrappedKing => Adjacency::WrappedKing,
+            Adjacency::Knight | Adjacency::WrappedKnight => Adjacency::WrappedKnight,
+            Adjacency::FarSight | Adjacency::WrappedFarSight => Adjacency::WrappedFarSight,
+        }
+    }
+
+    pub(crate) fn wraps(self) -> bool {
+        matches!(self, Adjacency::WrappedKing | Adjacency::WrappedKnight | Adjacency::WrappedFarSight)
+    }
+
+    /// Most neighbours a cell can have, and so the highest number shown. Anti-mines are
+    /// only played with up to 8, as negative numbers only go down to -8.
+    pub(crate) fn reach(self) -> usize {
+        self.offsets().len()
+    }
+
+    fn offsets(self) -> &'static [(i32, i32)] {
+        match self {
+            Adjacency::King | Adjacency::WrappedKing => &[(-1, -1), (-1, 0), (-1, 1), (0, -1), (0, 1), (1, -1), (1, 0), (1, 1)],
+            Adjacency::Knight | Adjacency::WrappedKnight => &[(-2, -1), (-2, 1), (-1, -2), (-1, 2), (1, -2), (1, 2), (2, -1), (2, 1)],
+            Adjacency::FarSight | Adjacency::WrappedFarSight => &[
+                (-2, -2), (-2, -1), (-2, 0), (-2, 1), (-2, 2),
+                (-1, -2), (-1, -1), (-1, 0), (-1, 1), (-1, 2),
+                (0, -2), (0, -1), (0, 1), (0, 2),
+                (1, -2), (1, -1), (1, 0), (1, 1), (1, 2),
+                (2, -2), (2, -1), (2, 0), (2, 1), (2, 2),
+            ],
+        }
+    }
+
+    /// Indices of the neighbours of cell `idx` on a board of `rows` by `columns`.
+    pub(crate) fn neighbours(self, rows: usize, columns: usize, idx: usize) -> impl Iterator<Item = usize> {
+        let (row, column) = ((idx / columns) as i32, (idx % columns) as i32);
+        let wraps = self.wraps();
+        let mut cells: Vec<usize> = self.offsets().iter()
+            .map(|&(dr, dc)| (row + dr, column + dc))
+            .filter_map(|(r, c)| match wraps {
+                true => Some((r.rem_euclid(rows as i32), c.rem_euclid(columns as i32))),
+                false => (r >= 0 && c >= 0 && (r as usize) < rows && (c as usize) < columns).then_some((r, c)),
+            })
+            .map(|(r, c)| r as usize * columns + c as usize)
+            .collect();
+        if wraps {
+            // A board narrower than the rule reaches wraps onto the same cells twice, or
+            // onto the cell itself
+            cells.sort_unstable();
+            cells.dedup();
+            cells.retain(|&n| n != idx);
+        }
+        cells.into_iter()
+    }
+}
+
+impl FromStr for Adjacency {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "king" => Ok(Adjacency::King),
+            "knight" => Ok(Adjacency::Knight),
+            "far-sight" => Ok(Adjacency::FarSight),
+            "wrapped-king" => Ok(Adjacency::WrappedKing),
+            "wrapped-knight" => Ok(Adjacency::WrappedKnight),
+            "wrapped-far-sight" => Ok(Adjacency::WrappedFarSight),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The classic board presets.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Difficulty {
+    Beginner,
+    #[default]
+    Intermediate,
+    Expert,
+}
+
+impl Difficulty {
+    pub(crate) const ALL: [Difficulty; 3] = [Difficulty::Beginner, Difficulty::Intermediate, Difficulty::Expert];
+
+    /// Name as given to `--difficulty` and used to key statistics.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Difficulty::Beginner => "beginner",
+            Difficulty::Intermediate => "intermediate",
+            Difficulty::Expert => "expert",
+        }
+    }
+
+    pub(crate) fn board_size(self) -> BoardSize {
+        match self {
+            Difficulty::Beginner => BoardSize { rows: 9, columns: 9, mines: 10, anti_mines: 0 },
+            Difficulty::Intermediate => BoardSize { rows: 16, columns: 16, mines: 40, anti_mines: 0 },
+            Difficulty::Expert => BoardSize { rows: 16, columns: 30, mines: 99, anti_mines: 0 },
+        }
+    }
+
+    /// The next harder preset, wrapping around to beginner after expert.
+    pub(crate) fn next(self) -> Difficulty {
+        match self {
+            Difficulty::Beginner => Difficulty::Intermediate,
+            Difficulty::Intermediate => Difficulty::Expert,
+            Difficulty::Expert => Difficulty::Beginner,
+        }
+    }
+}
+
+impl FromStr for Difficulty {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "beginner" => Ok(Difficulty::Beginner),
+            "intermediate" => Ok(Difficulty::Intermediate),
+            "expert" => Ok(Difficulty::Expert),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Row or column count given on the command line. Capped so every cell index fits in a
+/// u16 and the window stays within what X can describe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BoardDimension(pub(crate) u16);
+
+impl FromStr for BoardDimension {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.parse() {
+            Ok(count @ 2..=255) => Ok(BoardDimension(count)),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Fraction of the cells holding a mine, given with `--density`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Density(pub(crate) f64);
+
+impl FromStr for Density {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.parse() {
+            Ok(density) if density > 0.0 && density < 1.0 => Ok(Density(density)),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Campaign level to play, given with `--level`: counted from 1, stored from 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Level(pub(crate) usize);
+
+impl FromStr for Level {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.parse() {
+            Ok(level) if (1..=LEVELS.len()).contains(&level) => Ok(Level(level - 1)),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Percentages of the 3BV cleared to take split times at, given with `--splits` as a
+/// comma-separated list; kept in increasing order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Splits(pub(crate) Vec<u8>);
+
+impl FromStr for Splits {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut percents: Vec<u8> = value.split(',')
+            .map(|percent| percent.trim().parse().ok().filter(|percent| (1..=99).contains(percent)))
+            .collect::<Option<_>>()
+            .ok_or(())?;
+        percents.sort_unstable();
+        percents.dedup();
+        Ok(Splits(percents))
+    }
+}
+
+/// Mines that can be hit before the game is lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Lives(pub(crate) u8);
+
+impl Default for Lives {
+    fn default() -> Self {
+        Lives(1)
+    }
+}
+
+impl FromStr for Lives {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.parse() {
+            Ok(count @ 1..=9) => Ok(Lives(count)),
+            _ => Err(()),
+        }
+    }
+}
+
+/// How many flags may be placed at a time, given with `--flag-limit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FlagLimit {
+    /// As many as the board has mines.
+    Mines,
+    /// This many, or the mine count if that is lower.
+    Count(u16),
+}
+
+impl FlagLimit {
+    /// Flags allowed on a board of `size`.
+    pub(crate) fn flags(self, size: BoardSize) -> usize {
+        match self {
+            FlagLimit::Mines => size.mines as usize,
+            FlagLimit::Count(count) => count.min(size.mines) as usize,
+        }
+    }
+}
+
+impl FromStr for FlagLimit {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "mines" => Ok(FlagLimit::Mines),
+            _ => value.parse().map(FlagLimit::Count).map_err(|_| ()),
+        }
+    }
+}
+
+/// How often the hidden mines move with `--chaos`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Chaos {
+    /// After this many cells are clicked open.
+    Reveals(u16),
+    /// After this many seconds of play.
+    Seconds(u16),
+}
+
+impl FromStr for Chaos {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (count, chaos): (&str, fn(u16) -> Chaos) = match value.strip_suffix('s') {
+            Some(seconds) => (seconds, Chaos::Seconds),
+            None => (value, Chaos::Reveals),
+        };
+        match count.parse() {
+            Ok(count @ 1..=999) => Ok(chaos(count)),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Seconds a game may last, given with `--blitz`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TimeLimit(pub(crate) u32);
+
+impl FromStr for TimeLimit {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.parse() {
+            Ok(seconds @ 1..=9999) => Ok(TimeLimit(seconds)),
+            _ => Err(()),
+        }
+    }
+}
+
+/// How far around the uncovered cells the board can be seen with `--fog`, in cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FogRadius(pub(crate) u8);
+
+impl FromStr for FogRadius {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.parse() {
+            Ok(cells @ 1..=5) => Ok(FogRadius(cells)),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Bell volume relative to the base volume, as accepted by the Bell request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct BellVolume(pub(crate) i8);
+
+impl FromStr for BellVolume {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.parse() {
+            Ok(percent @ -100..=100) => Ok(BellVolume(percent)),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Factor the cells are drawn bigger by, given with `--scale`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Scale(pub(crate) u16);
+
+impl Default for Scale {
+    fn default() -> Self {
+        Scale(1)
+    }
+}
+
+impl FromStr for Scale {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.parse() {
+            Ok(factor @ 1..=4) => Ok(Scale(factor)),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Command line options.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Options {
+    pub(crate) screen: Option<usize>,
+    pub(crate) socket: Option<PathBuf>,
+    pub(crate) connect_timeout: Option<u64>,
+    pub(crate) connect_retries: Option<u32>,
+    pub(crate) placement: Placement,
+    pub(crate) position: Option<WindowPosition>,
+    pub(crate) always_on_top: bool,
+    pub(crate) translucent: bool,
+    pub(crate) scale: Scale,
+    pub(crate) difficulty: Difficulty,
+    pub(crate) rows: Option<BoardDimension>,
+    pub(crate) columns: Option<BoardDimension>,
+    pub(crate) mines: Option<u16>,
+    pub(crate) density: Option<Density>,
+    pub(crate) anti_mines: Option<u16>,
+    pub(crate) adjacency: Adjacency,
+    pub(crate) wrap: bool,
+    pub(crate) seed: Option<u64>,
+    pub(crate) pattern: Option<Pattern>,
+    pub(crate) daily: bool,
+    pub(crate) guaranteed_opening: bool,
+    pub(crate) auto_flag: bool,
+    pub(crate) guess_protection: bool,
+    pub(crate) win_by_flagging: bool,
+    pub(crate) blitz: Option<TimeLimit>,
+    pub(crate) flag_limit: Option<FlagLimit>,
+    pub(crate) endless: bool,
+    pub(crate) fog: Option<FogRadius>,
+    pub(crate) chaos: Option<Chaos>,
+    pub(crate) tutorial: bool,
+    pub(crate) autoplay: bool,
+    pub(crate) campaign: bool,
+    pub(crate) level: Option<Level>,
+    pub(crate) hot_seat: bool,
+    pub(crate) splits: Option<Splits>,
+    pub(crate) lives: Lives,
+    pub(crate) bell_volume: BellVolume,
+    pub(crate) import_board: Option<PathBuf>,
+    pub(crate) board_from_image: Option<PathBuf>,
+    pub(crate) export_board: Option<PathBuf>,
+    pub(crate) export_replays: Option<PathBuf>,
+    pub(crate) resume: bool,
+    pub(crate) host: Option<u16>,
+    pub(crate) join: Option<String>,
+    pub(crate) race: bool,
+    pub(crate) sm_client_id: Option<String>,
+    pub(crate) debug_mines: bool,
+    pub(crate) stats: bool,
+    pub(crate) reset_scores: bool,
+    pub(crate) help: bool,
+}
+
+impl Options {
+    /// Board to start with: the difficulty preset, resized by `--rows`/`--cols` if given,
+    /// with `--mines` or `--density` mines, or else the mine count scaled to keep the
+    /// preset's density, `--anti-mines` of them anti-mines.
+    pub(crate) fn board_size(&self) -> BoardSize {
+        let preset = self.difficulty.board_size();
+        let rows = self.rows.map_or(preset.rows, |rows| rows.0);
+        let columns = self.columns.map_or(preset.columns, |columns| columns.0);
+        let cell_count = rows as usize * columns as usize;
+        let mines = match (self.mines, self.density) {
+            (Some(mines), _) => mines as usize,
+            (None, Some(density)) => (density.0 * cell_count as f64).round().max(1.0) as usize,
+            (None, None) => (preset.mines as usize * cell_count).div_ceil(preset.cell_count()),
+        };
+        let mines = mines.min(cell_count - 1) as u16;
+        BoardSize { rows, columns, mines, anti_mines: self.anti_mines.unwrap_or(0).min(mines) }
+    }
+
+    pub(crate) fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options::default();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--screen" => options.screen = Some(parse_value(&arg, args.next())?),
+                "--socket" => options.socket = Some(parse_value(&arg, args.next())?),
+                "--connect-timeout" => options.connect_timeout = Some(parse_value(&arg, args.next())?),
+                "--connect-retries" => options.connect_retries = Some(parse_value(&arg, args.next())?),
+                "--placement" => options.placement = parse_value(&arg, args.next())?,
+                "--position" => options.position = Some(parse_value(&arg, args.next())?),
+                "--always-on-top" => options.always_on_top = true,
+                "--translucent" => options.translucent = true,
+                "--scale" => options.scale = parse_value(&arg, args.next())?,
+                "--difficulty" => options.difficulty = parse_value(&arg, args.next())?,
+                "--rows" => options.rows = Some(parse_value(&arg, args.next())?),
+                "--cols" => options.columns = Some(parse_value(&arg, args.next())?),
+                "--mines" => options.mines = Some(parse_value(&arg, args.next())?),
+                "--density" => options.density = Some(parse_value(&arg, args.next())?),
+                "--anti-mines" => options.anti_mines = Some(parse_value(&arg, args.next())?),
+                "--adjacency" => options.adjacency = parse_value(&arg, args.next())?,
+                "--wrap" => options.wrap = true,
+                "--seed" => options.seed = Some(parse_value(&arg, args.next())?),
+                "--pattern" => options.pattern = Some(parse_value(&arg, args.next())?),
+                "--daily" => options.daily = true,
+                "--opening" => options.guaranteed_opening = true,
+                "--auto-flag" => options.auto_flag = true,
+                "--guess-protection" => options.guess_protection = true,
+                "--win-by-flagging" => options.win_by_flagging = true,
+                "--blitz" => options.blitz = Some(parse_value(&arg, args.next())?),
+                "--flag-limit" => options.flag_limit = Some(parse_value(&arg, args.next())?),
+                "--endless" => options.endless = true,
+                "--fog" => options.fog = Some(parse_value(&arg, args.next())?),
+                "--chaos" => options.chaos = Some(parse_value(&arg, args.next())?),
+                "--tutorial" => options.tutorial = true,
+                "--autoplay" => options.autoplay = true,
+                "--campaign" => options.campaign = true,
+                "--level" => options.level = Some(parse_value(&arg, args.next())?),
+                "--hot-seat" => options.hot_seat = true,
+                "--splits" => options.splits = Some(parse_value(&arg, args.next())?),
+                "--lives" => options.lives = parse_value(&arg, args.next())?,
+                "--bell-volume" => options.bell_volume = parse_value(&arg, args.next())?,
+                "--import-board" | "--board" => options.import_board = Some(parse_value(&arg, args.next())?),
+                "--board-from-image" => options.board_from_image = Some(parse_value(&arg, args.next())?),
+                "--export-board" => options.export_board = Some(parse_value(&arg, args.next())?),
+                "--export-replays" => options.export_replays = Some(parse_value(&arg, args.next())?),
+                "--resume" => options.resume = true,
+                "--host" => options.host = Some(parse_value(&arg, args.next())?),
+                "--join" => options.join = Some(parse_value(&arg, args.next())?),
+                "--race" => options.race = true,
+                "--sm-client-id" => options.sm_client_id = Some(parse_value(&arg, args.next())?),
+                "--debug-mines" => options.debug_mines = true,
+                "--stats" => options.stats = true,
+                "--reset-scores" => options.reset_scores = true,
+                "-h" | "--help" => options.help = true,
+                _ => return Err(format!("Unknown argument: {}", arg)),
+            }
+        }
+
+        let custom_board = options.rows.is_some() || options.columns.is_some() || options.mines.is_some() || options.density.is_some() || options.anti_mines.is_some();
+        if options.daily && (custom_board || options.seed.is_some()) {
+            return Err("--daily plays a preset board of its own; it can't be combined with --rows, --cols, --mines, --density, --anti-mines or --seed".to_string());
+        }
+        if (options.host.is_some() || options.join.is_some()) && options.daily {
+            return Err("--daily is played alone; it can't be combined with --host or --join".to_string());
+        }
+        if options.join.is_some() && (options.host.is_some() || options.import_board.is_some() || options.board_from_image.is_some() || options.resume) {
+            return Err("--join plays the host's board; it can't be combined with --host, --import-board, --board-from-image or --resume".to_string());
+        }
+        if options.chaos.is_some() && (options.host.is_some() || options.join.is_some()) {
+            return Err("--chaos moves mines at random; it can't be combined with --host or --join".to_string());
+        }
+        if options.debug_mines && (options.daily || options.endless) {
+            return Err("--debug-mines shows where the mines of a board are; it can't be combined with --daily or --endless".to_string());
+        }
+        if options.race && options.host.is_none() && options.join.is_none() {
+            return Err("--race needs another player; give --host or --join".to_string());
+        }
+        if options.race && (options.import_board.is_some() || options.board_from_image.is_some() || options.resume) {
+            return Err("--race is run on new boards; it can't be combined with --import-board, --board-from-image or --resume".to_string());
+        }
+        if options.board_from_image.is_some() && (options.import_board.is_some() || options.resume || options.mines.is_some() || options.density.is_some() || options.anti_mines.is_some()) {
+            return Err("--board-from-image places the mines where the image is dark; it can't be combined with --import-board, \
+                --resume, --mines, --density or --anti-mines".to_string());
+        }
+        let other_boards = options.daily || options.host.is_some() || options.join.is_some() || options.import_board.is_some() || options.board_from_image.is_some()
+            || options.resume || options.export_board.is_some();
+        let other_rules = options.anti_mines.is_some() || options.adjacency != Adjacency::King || options.wrap || options.win_by_flagging || options.flag_limit.is_some() || options.auto_flag || options.guess_protection || options.fog.is_some() || options.chaos.is_some();
+        if options.endless && (other_boards || other_rules) {
+            return Err("--endless plays a board of its own by the classic rules; it can't be combined with --daily, --host, --join, \
+                --import-board, --board-from-image, --export-board, --resume, --anti-mines, --adjacency, --wrap, --win-by-flagging, --flag-limit, --auto-flag, --guess-protection, --fog or --chaos".to_string());
+        }
+        let other_modes = options.endless || options.blitz.is_some() || options.lives.0 > 1 || options.seed.is_some();
+        if options.tutorial && (custom_board || other_boards || other_rules || other_modes) {
+            return Err("--tutorial plays boards of its own by the classic rules; it can't be combined with options \
+                that pick the board or change the rules".to_string());
+        }
+        if options.autoplay && (options.endless || options.tutorial || options.daily || options.host.is_some() || options.join.is_some()) {
+            return Err("--autoplay plays a board of its own; it can't be combined with --endless, --tutorial, --daily, --host or --join".to_string());
+        }
+        if options.campaign && (custom_board || other_boards || other_rules || other_modes || options.tutorial || options.autoplay) {
+            return Err("--campaign plays boards of its own by the classic rules; it can't be combined with --tutorial, --autoplay \
+                or options that pick the board or change the rules".to_string());
+        }
+        if options.level.is_some() && !options.campaign {
+            return Err("--level picks a campaign level; give --campaign".to_string());
+        }
+        if options.hot_seat && (options.endless || options.lives.0 > 1 || options.daily || options.host.is_some() || options.join.is_some()
+            || options.tutorial || options.campaign || options.autoplay) {
+            return Err("--hot-seat is played by two at one board; it can't be combined with --endless, --lives, --daily, --host, --join, \
+                --tutorial, --campaign or --autoplay".to_string());
+        }
+        if options.pattern.is_some() && (options.daily || options.endless || options.tutorial || options.campaign || options.race
+            || options.join.is_some() || options.import_board.is_some() || options.board_from_image.is_some() || options.resume) {
+            return Err("--pattern deals new boards of its own; it can't be combined with --daily, --endless, --tutorial, --campaign, \
+                --race, --join, --import-board, --board-from-image or --resume".to_string());
+        }
+        if let Some((rows, columns)) = options.pattern.as_ref().map(Pattern::size) {
+            let size = options.board_size();
+            if rows > size.rows as usize || columns > size.columns as usize {
+                return Err(format!("--pattern needs a board of at least {} rows and {} columns", rows, columns));
+            }
+        }
+        if options.export_replays.is_some() && (options.endless || options.tutorial || options.autoplay || options.host.is_some() || options.join.is_some()
+            || options.anti_mines.is_some() || options.adjacency != Adjacency::King || options.wrap || options.chaos.is_some() || options.guess_protection) {
+            return Err("--export-replays writes games played alone by the classic rules; it can't be combined with --endless, --tutorial, \
+                --autoplay, --host, --join, --anti-mines, --adjacency, --wrap, --chaos or --guess-protection".to_string());
+        }
+        if options.anti_mines.is_some() && options.adjacency.reach() > 8 {
+            return Err("--anti-mines can't be combined with --adjacency far-sight, as numbers only go down to -8".to_string());
+        }
+        if options.mines.is_some() && options.density.is_some() {
+            return Err("--mines and --density both set the mine count; give one of them".to_string());
+        }
+        let cell_count = options.board_size().cell_count();
+        if let Some(mines) = options.mines.filter(|&mines| mines == 0 || mines as usize >= cell_count) {
+            return Err(format!("Invalid value for --mines: {} (the board has {} cells)", mines, cell_count));
+        }
+        let mines = options.board_size().mines;
+        if let Some(anti_mines) = options.anti_mines.filter(|&anti_mines| anti_mines > mines) {
+            return Err(format!("Invalid value for --anti-mines: {} (the board has {} mines)", anti_mines, mines));
+        }
+
+        Ok(options)
+    }
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("Missing value for {}", flag))?;
+    value.parse().map_err(|_| format!("Invalid value for {}: {}", flag, value))
+}
@@ -0,0 +1,222 @@
+use crate::scores::format_millis;
+use crate::stats::data_dir;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Days shown by `--stats`.
+const DAYS_SHOWN: usize = 10;
+
+/// How a day's challenge went. A challenge counts as attempted from its first reveal,
+/// so quitting doesn't buy a second try.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Started,
+    Won,
+    Lost,
+}
+
+impl Outcome {
+    fn name(self) -> &'static str {
+        match self {
+            Outcome::Started => "started",
+            Outcome::Won => "won",
+            Outcome::Lost => "lost",
+        }
+    }
+}
+
+impl FromStr for Outcome {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "started" => Ok(Outcome::Started),
+            "won" => Ok(Outcome::Won),
+            "lost" => Ok(Outcome::Lost),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The attempt at one day's challenge on one difficulty.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DailyResult {
+    /// UTC date, as `YYYY-MM-DD`.
+    pub(crate) date: String,
+    pub(crate) difficulty: String,
+    pub(crate) outcome: Outcome,
+    /// Time played when the game ended.
+    pub(crate) millis: u64,
+}
+
+impl fmt::Display for DailyResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.outcome {
+            Outcome::Started => write!(f, "{} {}: not finished", self.date, self.difficulty),
+            Outcome::Won => write!(f, "{} {}: won in {}", self.date, self.difficulty, format_millis(self.millis)),
+            Outcome::Lost => write!(f, "{} {}: lost after {}", self.date, self.difficulty, format_millis(self.millis)),
+        }
+    }
+}
+
+/// Daily challenge results, kept apart from the other statistics in
+/// `$XDG_DATA_HOME/mineswept/daily`: one `<date> <difficulty> <outcome> <millis>` line
+/// per attempt.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct DailyLog {
+    pub(crate) results: Vec<DailyResult>,
+}
+
+impl DailyLog {
+    pub(crate) fn load() -> io::Result<DailyLog> {
+        let Some(path) = daily_path() else {
+            return Ok(DailyLog::default());
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(DailyLog::default()),
+            Err(e) => return Err(e),
+        };
+
+        let results = text.lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                Some(DailyResult {
+                    date: fields.next()?.to_string(),
+                    difficulty: fields.next()?.to_string(),
+                    outcome: fields.next()?.parse().ok()?,
+                    millis: fields.next()?.parse().ok()?,
+                })
+            })
+            .collect();
+        Ok(DailyLog { results })
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let path = daily_path().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No data directory (HOME unset)"))?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut contents = String::from("# mineswept daily challenges\n");
+        for result in &self.results {
+            contents += &format!("{} {} {} {}\n", result.date, result.difficulty, result.outcome.name(), result.millis);
+        }
+        let partial = path.with_extension("partial");
+        fs::write(&partial, contents)?;
+        fs::rename(&partial, path)
+    }
+
+    /// The attempt at `date`'s challenge on `difficulty`, if there was one.
+    pub(crate) fn attempt(&self, date: &str, difficulty: &str) -> Option<&DailyResult> {
+        self.results.iter().find(|result| result.date == date && result.difficulty == difficulty)
+    }
+
+    /// Records how the attempt at `date`'s challenge on `difficulty` stands, replacing
+    /// what was recorded for it before.
+    pub(crate) fn record(date: &str, difficulty: &str, outcome: Outcome, millis: u64) -> io::Result<()> {
+        let mut log = DailyLog::load()?;
+        log.results.retain(|result| result.date != date || result.difficulty != difficulty);
+        log.results.push(DailyResult { date: date.to_string(), difficulty: difficulty.to_string(), outcome, millis });
+        log.results.sort_by(|a, b| (&a.date, &a.difficulty).cmp(&(&b.date, &b.difficulty)));
+        log.save()
+    }
+
+    /// Most days in a row with a challenge won, on any difficulty.
+    pub(crate) fn longest_winning_run(&self) -> usize {
+        let mut days: Vec<i64> = self.results.iter()
+            .filter(|result| result.outcome == Outcome::Won)
+            .filter_map(|result| day_number(&result.date))
+            .collect();
+        days.sort_unstable();
+        days.dedup();
+        let mut longest = 0;
+        let mut run = 0;
+        for (i, &day) in days.iter().enumerate() {
+            run = if i > 0 && days[i - 1] == day - 1 { run + 1 } else { 1 };
+            longest = longest.max(run);
+        }
+        longest
+    }
+}
+
+impl fmt::Display for DailyLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.results.is_empty() {
+            return writeln!(f, "No daily challenges played yet");
+        }
+        writeln!(f, "Daily challenges")?;
+        for result in self.results.iter().rev().take(DAYS_SHOWN) {
+            writeln!(f, "{}", result)?;
+        }
+        Ok(())
+    }
+}
+
+/// Today's date in UTC as `YYYY-MM-DD`, which names the day's challenge.
+pub(crate) fn today() -> String {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs() / 86400);
+    let (year, month, day) = civil_from_days(days as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Seed of the board for `date`: its digits, so everyone playing that day gets the same.
+pub(crate) fn seed(date: &str) -> u64 {
+    date.chars().filter(char::is_ascii_digit).collect::<String>().parse().unwrap_or(0)
+}
+
+/// Days since 1970-01-01 of `date` (`YYYY-MM-DD`), undoing `civil_from_days`.
+fn day_number(date: &str) -> Option<i64> {
+    let mut fields = date.splitn(3, '-');
+    let year: i64 = fields.next()?.parse().ok()?;
+    let month: i64 = fields.next()?.parse().ok().filter(|month| (1..=12).contains(month))?;
+    let day: i64 = fields.next()?.parse().ok().filter(|day| (1..=31).contains(day))?;
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * shifted_month + 2) / 5 + day - 1;
+    let day_of_era = 365 * year_of_era + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Some(era * 146_097 + day_of_era - 719_468)
+}
+
+/// Converts days since 1970-01-01 to a proleptic Gregorian date (Howard Hinnant's
+/// `civil_from_days`).
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn daily_path() -> Option<PathBuf> {
+    Some(data_dir()?.join("daily"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn winning_runs_count_days_in_a_row_across_months() {
+        let won = |date: &str| DailyResult { date: date.to_string(), difficulty: "beginner".to_string(), outcome: Outcome::Won, millis: 1000 };
+        let mut log = DailyLog { results: ["2024-02-27", "2024-02-28", "2024-02-29", "2024-03-01", "2024-03-03"].map(won).to_vec() };
+        log.results.push(DailyResult { outcome: Outcome::Lost, ..won("2024-03-02") });
+        assert_eq!(log.longest_winning_run(), 4);
+        for days in [0, 59, 365, 11_016, 19_782] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(day_number(&format!("{:04}-{:02}-{:02}", year, month, day)), Some(days));
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use crate::scores::format_millis;
+use std::fmt;
+
+/// How efficiently a game was won, by the measures Minesweeper Arbiter shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Efficiency {
+    pub(crate) millis: u64,
+    pub(crate) bbbv: u32,
+    /// Estimated fewest clicks that win the board with flags and chords.
+    pub(crate) zini: u32,
+    /// Clicks on the board, and those that changed anything on it.
+    pub(crate) clicks: u32,
+    pub(crate) useful_clicks: u32,
+}
+
+impl Efficiency {
+    pub(crate) fn bbbv_per_second(&self) -> f64 {
+        self.bbbv as f64 * 1000.0 / self.millis.max(1) as f64
+    }
+
+    /// 3BV per click: above 1 only with chords doing the work of several clicks.
+    pub(crate) fn ioe(&self) -> f64 {
+        self.bbbv as f64 / self.clicks.max(1) as f64
+    }
+
+    /// Share of the clicks that changed anything.
+    pub(crate) fn correctness(&self) -> f64 {
+        self.useful_clicks as f64 / self.clicks.max(1) as f64
+    }
+
+    /// 3BV per useful click.
+    pub(crate) fn throughput(&self) -> f64 {
+        self.bbbv as f64 / self.useful_clicks.max(1) as f64
+    }
+
+    /// ZiNi per click: 1 for a game won in as few clicks as ZiNi's.
+    pub(crate) fn zini_efficiency(&self) -> f64 {
+        self.zini as f64 / self.clicks.max(1) as f64
+    }
+
+    /// Lines for the statistics screen, short enough for the smallest board.
+    pub(crate) fn summary(&self) -> Vec<String> {
+        vec![
+            format!("ZiNi {}  Clicks {}", self.zini, self.clicks),
+            format!("IOE {:.2}  Corr {:.2}", self.ioe(), self.correctness()),
+            format!("Thrp {:.2}  ZNE {:.2}", self.throughput(), self.zini_efficiency()),
+        ]
+    }
+}
+
+impl fmt::Display for Efficiency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Cleared 3BV {} in {}: {:.2} 3BV/s", self.bbbv, format_millis(self.millis), self.bbbv_per_second())?;
+        write!(
+            f,
+            "ZiNi {}, {} clicks ({} useful): IOE {:.2}, correctness {:.2}, throughput {:.2}, ZiNi efficiency {:.2}",
+            self.zini, self.clicks, self.useful_clicks, self.ioe(), self.correctness(), self.throughput(), self.zini_efficiency(),
+        )
+    }
+}
@@ -0,0 +1,73 @@
+/// Serializes one X11 request in the connection's (native) byte order.
+///
+/// The first four bytes are the request header: major opcode, a request-specific byte
+/// (minor opcode, depth, mode...) and the length, which `finish` fills in once the body
+/// is complete.
+#[derive(Debug)]
+pub(crate) struct RequestWriter {
+    buffer: Vec<u8>,
+}
+
+impl RequestWriter {
+    pub(crate) fn new(opcode: u8, data: u8) -> Self {
+        RequestWriter { buffer: vec![opcode, data, 0, 0] }
+    }
+
+    pub(crate) fn put_u8(mut self, value: u8) -> Self {
+        self.buffer.push(value);
+        self
+    }
+
+    pub(crate) fn put_u16(mut self, value: u16) -> Self {
+        self.buffer.extend_from_slice(&value.to_ne_bytes());
+        self
+    }
+
+    pub(crate) fn put_u32(mut self, value: u32) -> Self {
+        self.buffer.extend_from_slice(&value.to_ne_bytes());
+        self
+    }
+
+    pub(crate) fn put_bytes(mut self, bytes: &[u8]) -> Self {
+        self.buffer.extend_from_slice(bytes);
+        self
+    }
+
+    /// Appends `count` zero bytes of explicit padding.
+    pub(crate) fn pad(mut self, count: usize) -> Self {
+        self.buffer.resize(self.buffer.len() + count, 0);
+        self
+    }
+
+    /// Finishes the header of a request whose `payload_len` byte payload (plus padding to 4
+    /// bytes) is sent separately, so large payloads don't have to be copied in.
+    pub(crate) fn finish_with_payload(mut self, payload_len: usize) -> Vec<u8> {
+        self.buffer.resize(self.buffer.len().next_multiple_of(4), 0);
+
+        let length = (self.buffer.len() + payload_len.next_multiple_of(4)) / 4;
+        self.write_length(length);
+        self.buffer
+    }
+
+    /// Pads the request to a multiple of 4 bytes and writes its length.
+    ///
+    /// Requests longer than 65535 units use the BIG-REQUESTS encoding: a zero length
+    /// followed by a 32-bit length that counts the extra field itself.
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        self.buffer.resize(self.buffer.len().next_multiple_of(4), 0);
+
+        let length = self.buffer.len() / 4;
+        self.write_length(length);
+        self.buffer
+    }
+
+    /// Writes `length` (in 4-byte units) into the header.
+    fn write_length(&mut self, length: usize) {
+        if length <= u16::MAX as usize {
+            self.buffer[2..4].copy_from_slice(&(length as u16).to_ne_bytes());
+        } else {
+            let extended_length = (length as u32 + 1).to_ne_bytes();
+            self.buffer.splice(4..4, extended_length);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use crate::game::EntityKind;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+
+/// Side of the square chunks the board is generated in.
+const CHUNK_SIZE: i64 = 16;
+/// Farthest a single reveal opens from the clicked cell, in rows or columns. Boards
+/// sparse enough have openings that never end.
+const OPENING_REACH: i64 = 128;
+
+/// A cell of the board, as its column and row; both may be negative.
+pub(crate) type Cell = (i64, i64);
+
+/// Which cells of a chunk hold a mine, and what each shows.
+#[derive(Debug)]
+struct Chunk {
+    mines: Vec<bool>,
+    shown: Vec<EntityKind>,
+}
+
+impl Chunk {
+    /// Draws the mines of the chunk at `key` from the board's `seed`, so a chunk comes
+    /// out the same whenever it is first looked at.
+    fn generate(seed: u64, density: f64, (x, y): (i64, i64)) -> Chunk {
+        let chunk_seed = seed ^ (x as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ (y as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f);
+        let mut rng = StdRng::seed_from_u64(chunk_seed);
+        let cells = (CHUNK_SIZE * CHUNK_SIZE) as usize;
+        Chunk {
+            mines: (0..cells).map(|_| rng.random_bool(density)).collect(),
+            shown: vec![EntityKind::Covered; cells],
+        }
+    }
+}
+
+/// The board of an endless game (`--endless`), without edges. It is generated a chunk at
+/// a time as it is first looked at, so the same seed always gives the same board however
+/// it is explored, bar the mines the first reveal moves off.
+#[derive(Debug)]
+pub(crate) struct World {
+    seed: u64,
+    /// Chance of each cell holding a mine.
+    density: f64,
+    chunks: HashMap<(i64, i64), Chunk>,
+    /// Safe cells uncovered so far, which is the score.
+    cleared: usize,
+    /// Once the game is over every mine is shown, and every misplaced flag.
+    over: bool,
+}
+
+impl World {
+    pub(crate) fn new(seed: u64, density: f64) -> World {
+        World { seed, density, chunks: HashMap::new(), cleared: 0, over: false }
+    }
+
+    /// Covers the board up again, with the mines where they were first dealt.
+    pub(crate) fn restart(&mut self) {
+        *self = World::new(self.seed, self.density);
+    }
+
+    pub(crate) fn cleared(&self) -> usize {
+        self.cleared
+    }
+
+    /// Ends the game, showing the mines.
+    pub(crate) fn end(&mut self) {
+        self.over = true;
+    }
+
+    fn chunk(&mut self, (x, y): Cell) -> (&mut Chunk, usize) {
+        let key = (x.div_euclid(CHUNK_SIZE), y.div_euclid(CHUNK_SIZE));
+        let idx = (y.rem_euclid(CHUNK_SIZE) * CHUNK_SIZE + x.rem_euclid(CHUNK_SIZE)) as usize;
+        let (seed, density) = (self.seed, self.density);
+        (self.chunks.entry(key).or_insert_with(|| Chunk::generate(seed, density, key)), idx)
+    }
+
+    pub(crate) fn is_mine(&mut self, cell: Cell) -> bool {
+        let (chunk, idx) = self.chunk(cell);
+        chunk.mines[idx]
+    }
+
+    /// What `cell` shows.
+    pub(crate) fn shown(&mut self, cell: Cell) -> EntityKind {
+        let over = self.over;
+        let (chunk, idx) = self.chunk(cell);
+        match chunk.shown[idx] {
+            EntityKind::Covered if over && chunk.mines[idx] => EntityKind::MineIdle,
+            EntityKind::Flagged if over && !chunk.mines[idx] => EntityKind::FlagMisplaced,
+            shown => shown,
+        }
+    }
+
+    fn show(&mut self, cell: Cell, entity: EntityKind) {
+        let (chunk, idx) = self.chunk(cell);
+        chunk.shown[idx] = entity;
+    }
+
+    fn neighbours((x, y): Cell) -> impl Iterator<Item = Cell> {
+        [(-1, -1), (-1, 0), (-1, 1), (0, -1), (0, 1), (1, -1), (1, 0), (1, 1)].into_iter()
+            .map(move |(dx, dy)| (x + dx, y + dy))
+    }
+
+    /// Moves any mines off `cell` and its neighbours, so the first reveal opens an area.
+    pub(crate) fn protect(&mut self, cell: Cell) {
+        for cell in Self::neighbours(cell).chain([cell]) {
+            let (chunk, idx) = self.chunk(cell);
+            chunk.mines[idx] = false;
+        }
+    }
+
+    /// Uncovers `cell` and, through cells with no mines around them, the covered cells
+    /// connected to it. Returns whether `cell` held a mine, which then goes off.
+    pub(crate) fn reveal(&mut self, cell: Cell) -> bool {
+        if self.shown(cell) != EntityKind::Covered {
+            return false;
+        }
+        if self.is_mine(cell) {
+            self.show(cell, EntityKind::MineExploded);
+            return true;
+        }
+
+        let in_reach = |(x, y): Cell| x.abs_diff(cell.0).max(y.abs_diff(cell.1)) <= OPENING_REACH as u64;
+        let mut pending = vec![cell];
+        while let Some(next) = pending.pop() {
+            if self.shown(next) != EntityKind::Covered || self.is_mine(next) {
+                continue;
+            }
+            let mines_around = Self::neighbours(next).filter(|&n| self.is_mine(n)).count();
+            self.show(next, EntityKind::uncovered(mines_around as u8));
+            self.cleared += 1;
+
+            if mines_around == 0 {
+                pending.extend(Self::neighbours(next).filter(|&n| in_reach(n)));
+            }
+        }
+        false
+    }
+
+    /// The covered neighbours a chord on `cell` reveals: none unless it is a number with
+    /// as many flags (or mines gone off) around it.
+    pub(crate) fn chord_targets(&mut self, cell: Cell) -> Vec<Cell> {
+        let Some(number) = self.shown(cell).number().filter(|&number| number > 0) else {
+            return Vec::new();
+        };
+        let flags = Self::neighbours(cell)
+            .filter(|&n| matches!(self.shown(n), EntityKind::Flagged | EntityKind::MineExploded))
+            .count();
+        if flags != number as usize {
+            return Vec::new();
+        }
+        Self::neighbours(cell).filter(|&n| self.shown(n) == EntityKind::Covered).collect()
+    }
+
+    pub(crate) fn toggle_flag(&mut self, cell: Cell) {
+        match self.shown(cell) {
+            EntityKind::Covered => self.show(cell, EntityKind::Flagged),
+            EntityKind::Flagged => self.show(cell, EntityKind::Covered),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_come_out_the_same_in_any_order() {
+        let cells: Vec<Cell> = (-40..40).flat_map(|x| (-40..40).map(move |y| (x, y))).collect();
+        let mut forward = World::new(7, 0.2);
+        let mut backward = World::new(7, 0.2);
+        let mines: Vec<bool> = cells.iter().map(|&cell| forward.is_mine(cell)).collect();
+        let reversed: Vec<bool> = cells.iter().rev().map(|&cell| backward.is_mine(cell)).collect();
+        assert!(mines.iter().eq(reversed.iter().rev()));
+        assert!(mines.contains(&true) && mines.contains(&false));
+    }
+
+    #[test]
+    fn reveal_opens_across_chunks_and_counts_the_cleared_cells() {
+        let mut world = World::new(7, 0.0);
+        assert!(!world.reveal((0, 0)));
+        let side = 2 * OPENING_REACH as usize + 1;
+        assert_eq!(world.cleared(), side * side);
+        assert_eq!(world.shown((-OPENING_REACH, OPENING_REACH)), EntityKind::Uncovered0);
+        assert_eq!(world.shown((OPENING_REACH + 1, 0)), EntityKind::Covered);
+    }
+}
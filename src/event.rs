@@ -0,0 +1,215 @@
+use crate::x11comm::X11Connection;
+use std::collections::HashMap;
+use std::io;
+use std::ops::ControlFlow;
+
+const EVENT_KEY_PRESS: u8 = 2;
+const EVENT_KEY_RELEASE: u8 = 3;
+const EVENT_BUTTON_PRESS: u8 = 4;
+const EVENT_BUTTON_RELEASE: u8 = 5;
+const EVENT_MOTION_NOTIFY: u8 = 6;
+const EVENT_LEAVE_NOTIFY: u8 = 8;
+const EVENT_EXPOSE: u8 = 12;
+const EVENT_GRAPHICS_EXPOSURE: u8 = 13;
+const EVENT_NO_EXPOSURE: u8 = 14;
+const EVENT_VISIBILITY_NOTIFY: u8 = 15;
+const EVENT_UNMAP_NOTIFY: u8 = 18;
+const EVENT_MAP_NOTIFY: u8 = 19;
+const EVENT_CONFIGURE_NOTIFY: u8 = 22;
+const EVENT_PROPERTY_NOTIFY: u8 = 28;
+const EVENT_SELECTION_CLEAR: u8 = 29;
+const EVENT_SELECTION_REQUEST: u8 = 30;
+const EVENT_CLIENT_MESSAGE: u8 = 33;
+const EVENT_GENERIC: u8 = 35;
+
+/// Bits of [`InputEvent::state`] set while a modifier key or pointer button is held.
+pub(crate) const STATE_SHIFT: u16 = 0x1;
+pub(crate) const STATE_CONTROL: u16 = 0x4;
+pub(crate) const STATE_BUTTON1: u16 = 0x100;
+pub(crate) const STATE_BUTTON3: u16 = 0x400;
+
+/// VisibilityNotify state of a window nothing of which can be seen.
+pub(crate) const VISIBILITY_FULLY_OBSCURED: u8 = 2;
+
+/// PropertyNotify state for a property that was deleted rather than changed.
+#[allow(dead_code)]
+pub(crate) const PROPERTY_DELETED: u8 = 1;
+
+/// Pointer position and button/key detail shared by the input events.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct InputEvent {
+    /// Keycode or button number.
+    pub(crate) detail: u8,
+    /// Server time in milliseconds.
+    pub(crate) time: u32,
+    pub(crate) event_x: u16,
+    pub(crate) event_y: u16,
+    /// Modifier and button mask from just before the event.
+    pub(crate) state: u16,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ClientMessageEvent {
+    /// 8, 16 or 32; says how `data` is to be read.
+    pub(crate) format: u8,
+    pub(crate) message_type: u32,
+    pub(crate) data: [u8; 20],
+}
+
+impl ClientMessageEvent {
+    /// The data as five 32-bit values, for format 32 messages.
+    pub(crate) fn data32(&self) -> [u32; 5] {
+        std::array::from_fn(|i| u32::from_ne_bytes(self.data[i * 4..i * 4 + 4].try_into().unwrap()))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[allow(dead_code)]
+pub(crate) struct PropertyNotifyEvent {
+    pub(crate) window_id: u32,
+    pub(crate) atom: u32,
+    pub(crate) time: u32,
+    /// 0 when the property changed, [`PROPERTY_DELETED`] when it was removed.
+    pub(crate) state: u8,
+}
+
+/// Another client asking the owner of `selection` for its contents as `target`, to be
+/// stored in `property` on `requestor`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SelectionRequestEvent {
+    pub(crate) time: u32,
+    pub(crate) requestor: u32,
+    pub(crate) selection: u32,
+    pub(crate) target: u32,
+    /// 0 from obsolete clients, which mean the property named after `target`.
+    pub(crate) property: u32,
+}
+
+/// A decoded event off the wire.
+#[derive(Debug, Clone)]
+pub(crate) enum Event {
+    /// Part of the window needs repainting; `count` more Expose events follow in this batch.
+    Expose { count: u16 },
+    KeyPress(InputEvent),
+    KeyRelease(InputEvent),
+    ButtonPress(InputEvent),
+    ButtonRelease(InputEvent),
+    MotionNotify(InputEvent),
+    LeaveNotify,
+    GraphicsExposure,
+    NoExposure,
+    /// The window's visibility changed; `state` is unobscured, partially or fully obscured.
+    VisibilityNotify { state: u8 },
+    UnmapNotify,
+    MapNotify,
+    /// The window was moved, resized or restacked; carries its new size.
+    ConfigureNotify { width: u16, height: u16 },
+    PropertyNotify(PropertyNotifyEvent),
+    ClientMessage(ClientMessageEvent),
+    /// Another client took ownership of `selection` from this one.
+    SelectionClear { selection: u32 },
+    SelectionRequest(SelectionRequestEvent),
+    /// An extension event, still in its complete (possibly longer than 32 bytes) packet.
+    Generic { extension: u8, packet: Vec<u8> },
+    /// Anything the game has not asked to decode.
+    Other,
+}
+
+impl Event {
+    /// Decodes a packet as returned by [`X11Connection::next_event`].
+    pub(crate) fn decode(packet: Vec<u8>) -> Event {
+        let u16_at = |offset: usize| u16::from_ne_bytes([packet[offset], packet[offset + 1]]);
+        let u32_at = |offset: usize| u32::from_ne_bytes(packet[offset..offset + 4].try_into().unwrap());
+        let input_event = || InputEvent {
+            detail: packet[1],
+            time: u32_at(4),
+            event_x: u16_at(24),
+            event_y: u16_at(26),
+            state: u16_at(28),
+        };
+
+        // The high bit marks events sent by other clients through SendEvent
+        match packet[0] & 0x7f {
+            EVENT_EXPOSE => Event::Expose { count: u16_at(16) },
+            EVENT_KEY_PRESS => Event::KeyPress(input_event()),
+            EVENT_KEY_RELEASE => Event::KeyRelease(input_event()),
+            EVENT_BUTTON_PRESS => Event::ButtonPress(input_event()),
+            EVENT_BUTTON_RELEASE => Event::ButtonRelease(input_event()),
+            EVENT_MOTION_NOTIFY => Event::MotionNotify(input_event()),
+            EVENT_LEAVE_NOTIFY => Event::LeaveNotify,
+            EVENT_GRAPHICS_EXPOSURE => Event::GraphicsExposure,
+            EVENT_NO_EXPOSURE => Event::NoExposure,
+            EVENT_VISIBILITY_NOTIFY => Event::VisibilityNotify { state: packet[8] },
+            EVENT_UNMAP_NOTIFY => Event::UnmapNotify,
+            EVENT_MAP_NOTIFY => Event::MapNotify,
+            EVENT_CONFIGURE_NOTIFY => Event::ConfigureNotify { width: u16_at(20), height: u16_at(22) },
+            EVENT_PROPERTY_NOTIFY => Event::PropertyNotify(PropertyNotifyEvent {
+                window_id: u32_at(4),
+                atom: u32_at(8),
+                time: u32_at(12),
+                state: packet[16],
+            }),
+            EVENT_CLIENT_MESSAGE => Event::ClientMessage(ClientMessageEvent {
+                format: packet[1],
+                message_type: u32_at(8),
+                data: packet[12..32].try_into().unwrap(),
+            }),
+            EVENT_SELECTION_CLEAR => Event::SelectionClear { selection: u32_at(12) },
+            EVENT_SELECTION_REQUEST => Event::SelectionRequest(SelectionRequestEvent {
+                time: u32_at(4),
+                requestor: u32_at(12),
+                selection: u32_at(16),
+                target: u32_at(20),
+                property: u32_at(24),
+            }),
+            EVENT_GENERIC => Event::Generic { extension: packet[1], packet },
+            _ => Event::Other,
+        }
+    }
+}
+
+/// Handles a routed event; `Break` ends the event loop.
+pub(crate) type Handler<T> = fn(&mut T, &mut X11Connection, &Event) -> io::Result<ControlFlow<()>>;
+
+/// Routes ClientMessage events by message type, PropertyNotify events by property atom and
+/// selection events by selection to handlers registered for them.
+#[derive(Debug)]
+pub(crate) struct Dispatcher<T> {
+    client_messages: HashMap<u32, Handler<T>>,
+    properties: HashMap<u32, Handler<T>>,
+    selections: HashMap<u32, Handler<T>>,
+}
+
+impl<T> Dispatcher<T> {
+    pub(crate) fn new() -> Self {
+        Dispatcher { client_messages: HashMap::new(), properties: HashMap::new(), selections: HashMap::new() }
+    }
+
+    /// Registers `handler` for ClientMessage events whose type is `message_type`.
+    pub(crate) fn on_client_message(&mut self, message_type: u32, handler: Handler<T>) {
+        self.client_messages.insert(message_type, handler);
+    }
+
+    /// Registers `handler` for PropertyNotify events about `atom`.
+    #[allow(dead_code)]
+    pub(crate) fn on_property(&mut self, atom: u32, handler: Handler<T>) {
+        self.properties.insert(atom, handler);
+    }
+
+    /// Registers `handler` for SelectionRequest and SelectionClear events about `selection`.
+    pub(crate) fn on_selection(&mut self, selection: u32, handler: Handler<T>) {
+        self.selections.insert(selection, handler);
+    }
+
+    /// Returns the handler registered for `event`, if any. Handlers are plain function
+    /// pointers so the caller can pass its own state without borrowing the dispatcher.
+    pub(crate) fn handler(&self, event: &Event) -> Option<Handler<T>> {
+        match event {
+            Event::ClientMessage(message) => self.client_messages.get(&message.message_type).copied(),
+            Event::PropertyNotify(notify) => self.properties.get(&notify.atom).copied(),
+            Event::SelectionClear { selection } => self.selections.get(selection).copied(),
+            Event::SelectionRequest(request) => self.selections.get(&request.selection).copied(),
+            _ => None,
+        }
+    }
+}
@@ -1,13 +1,38 @@
-use crate::config::{ENTITIES_COLUMN_COUNT, ENTITIES_ROW_COUNT, ENTITIES_WIDTH, ENTITIES_HEIGHT};
-use std::collections::HashMap;
-use std::io::{ErrorKind, Read};
-use std::mem::{size_of, transmute};
-use std::os::unix::net::UnixStream;
-use std::process::exit;
-use std::thread::sleep;
-use std::time;
-use rand::Rng;
-use crate::x11comm::x11_copy_area;
+use crate::config::{self, entities_height, entities_width, status_bar_height, Adjacency, BellVolume, BoardSize, Chaos, Difficulty, FlagLimit, Options};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::ops::ControlFlow;
+use crate::utils::{rgba_to_pixel, zpixmap_to_rgba};
+use crate::xinput::{self, DeviceEvent, ScrollValuator, SmoothScroll, XI_DEVICE_CHANGED, XI_MOTION, XI_TOUCH_BEGIN, XI_TOUCH_END};
+use crate::present;
+use crate::xrender::{self, Render};
+use crate::xfixes;
+use crate::xsmp::{SessionManager, SessionRequest};
+use crate::savegame::{resume_path, session_board_path, SavedBoard};
+use crate::stats::Stats;
+use crate::achievements::{Achievements, Win};
+use crate::autoplay::{self, Autoplay};
+use crate::scores::{format_delta, format_millis, Scores};
+use crate::solver::{self, Deduction};
+use crate::daily::{self, DailyLog, Outcome};
+use crate::net::{Message, Mode, Peer, Progress};
+use crate::endless::{Cell, World};
+use crate::tutorial::{self, Goal, LESSONS};
+use crate::campaign::{CampaignProgress, LEVELS};
+use crate::hotseat::HotSeat;
+use crate::pattern::Pattern;
+use crate::replay::{Click, Replay};
+use crate::share;
+use crate::efficiency::Efficiency;
+use crate::event::{Dispatcher, Event, InputEvent, STATE_BUTTON1, STATE_BUTTON3, STATE_CONTROL, STATE_SHIFT, VISIBILITY_FULLY_OBSCURED};
+use std::fs::File;
+use std::io::BufWriter;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crate::x11comm::{x11_copy_area, x11_bell, x11_change_property, x11_create_pixmap, x11_get_selection_owner, x11_send_selection_notify, x11_set_selection_owner, x11_resize_window, x11_change_gc, x11_get_geometry, x11_get_image, x11_grab_pointer, x11_image_text8, x11_poly_fill_rectangle, x11_poly_rectangle, x11_set_window_cursor, x11_ungrab_pointer, GcValues, Keymap, PixelFormat, Resource, Wakeup, ATOM_ATOM, X11Connection, KEYSYM_A, KEYSYM_C, KEYSYM_D, KEYSYM_DOWN, KEYSYM_E, KEYSYM_ESCAPE, KEYSYM_F, KEYSYM_F2, KEYSYM_H, KEYSYM_KP_ENTER, KEYSYM_LEFT, KEYSYM_M, KEYSYM_P, KEYSYM_R, KEYSYM_RETURN, KEYSYM_RIGHT, KEYSYM_S, KEYSYM_SPACE, KEYSYM_T, KEYSYM_UP};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub(crate) enum EntityKind {
@@ -22,19 +47,342 @@ pub(crate) enum EntityKind {
     Uncovered6,
     Uncovered7,
     Uncovered8,
+    /// Numbers past 8, only seen with the far-sight rule (`--adjacency far-sight`).
+    Uncovered9,
+    Uncovered10,
+    Uncovered11,
+    Uncovered12,
+    Uncovered13,
+    Uncovered14,
+    Uncovered15,
+    Uncovered16,
+    Uncovered17,
+    Uncovered18,
+    Uncovered19,
+    Uncovered20,
+    Uncovered21,
+    Uncovered22,
+    Uncovered23,
+    Uncovered24,
     MineExploded,
     MineIdle,
+    /// A flag shown to have been on a cell without a mine, once the game is over.
+    FlagMisplaced,
+    /// Uncovered cells with more anti-mines than mines around them.
+    UncoveredMinus1,
+    UncoveredMinus2,
+    UncoveredMinus3,
+    UncoveredMinus4,
+    UncoveredMinus5,
+    UncoveredMinus6,
+    UncoveredMinus7,
+    UncoveredMinus8,
+    /// An uncovered cell whose mines and anti-mines around it cancel out. Unlike a 0 it
+    /// doesn't open its neighbours.
+    UncoveredBalanced,
+    AntiMineExploded,
+    AntiMineIdle,
+}
+
+impl EntityKind {
+    /// Mines around an uncovered cell, or `None` for any other kind.
+    pub(crate) fn number(self) -> Option<u8> {
+        match self {
+            EntityKind::Uncovered0 => Some(0),
+            EntityKind::Uncovered1 => Some(1),
+            EntityKind::Uncovered2 => Some(2),
+            EntityKind::Uncovered3 => Some(3),
+            EntityKind::Uncovered4 => Some(4),
+            EntityKind::Uncovered5 => Some(5),
+            EntityKind::Uncovered6 => Some(6),
+            EntityKind::Uncovered7 => Some(7),
+            EntityKind::Uncovered8 => Some(8),
+            EntityKind::Uncovered9 => Some(9),
+            EntityKind::Uncovered10 => Some(10),
+            EntityKind::Uncovered11 => Some(11),
+            EntityKind::Uncovered12 => Some(12),
+            EntityKind::Uncovered13 => Some(13),
+            EntityKind::Uncovered14 => Some(14),
+            EntityKind::Uncovered15 => Some(15),
+            EntityKind::Uncovered16 => Some(16),
+            EntityKind::Uncovered17 => Some(17),
+            EntityKind::Uncovered18 => Some(18),
+            EntityKind::Uncovered19 => Some(19),
+            EntityKind::Uncovered20 => Some(20),
+            EntityKind::Uncovered21 => Some(21),
+            EntityKind::Uncovered22 => Some(22),
+            EntityKind::Uncovered23 => Some(23),
+            EntityKind::Uncovered24 => Some(24),
+            _ => None,
+        }
+    }
+
+    /// Mines less anti-mines around an uncovered cell, or `None` for any other kind.
+    pub(crate) fn sum(self) -> Option<i8> {
+        match self {
+            EntityKind::UncoveredMinus1 => Some(-1),
+            EntityKind::UncoveredMinus2 => Some(-2),
+            EntityKind::UncoveredMinus3 => Some(-3),
+            EntityKind::UncoveredMinus4 => Some(-4),
+            EntityKind::UncoveredMinus5 => Some(-5),
+            EntityKind::UncoveredMinus6 => Some(-6),
+            EntityKind::UncoveredMinus7 => Some(-7),
+            EntityKind::UncoveredMinus8 => Some(-8),
+            EntityKind::UncoveredBalanced => Some(0),
+            _ => self.number().map(|number| number as i8),
+        }
+    }
+
+    /// Whether the cell is a mine or an anti-mine that went off.
+    pub(crate) fn exploded(self) -> bool {
+        matches!(self, EntityKind::MineExploded | EntityKind::AntiMineExploded)
+    }
+
+    /// The uncovered cell showing `count` mines around it.
+    pub(crate) fn uncovered(count: u8) -> EntityKind {
+        match count {
+            0 => EntityKind::Uncovered0,
+            1 => EntityKind::Uncovered1,
+            2 => EntityKind::Uncovered2,
+            3 => EntityKind::Uncovered3,
+            4 => EntityKind::Uncovered4,
+            5 => EntityKind::Uncovered5,
+            6 => EntityKind::Uncovered6,
+            7 => EntityKind::Uncovered7,
+            8 => EntityKind::Uncovered8,
+            9 => EntityKind::Uncovered9,
+            10 => EntityKind::Uncovered10,
+            11 => EntityKind::Uncovered11,
+            12 => EntityKind::Uncovered12,
+            13 => EntityKind::Uncovered13,
+            14 => EntityKind::Uncovered14,
+            15 => EntityKind::Uncovered15,
+            16 => EntityKind::Uncovered16,
+            17 => EntityKind::Uncovered17,
+            18 => EntityKind::Uncovered18,
+            19 => EntityKind::Uncovered19,
+            20 => EntityKind::Uncovered20,
+            21 => EntityKind::Uncovered21,
+            22 => EntityKind::Uncovered22,
+            23 => EntityKind::Uncovered23,
+            24 => EntityKind::Uncovered24,
+            _ => panic!("Invalid mine count"),
+        }
+    }
+
+    /// The uncovered cell with `mines` mines and `anti_mines` anti-mines around it.
+    pub(crate) fn counted(mines: u8, anti_mines: u8) -> EntityKind {
+        match mines as i8 - anti_mines as i8 {
+            0 if anti_mines > 0 => EntityKind::UncoveredBalanced,
+            -1 => EntityKind::UncoveredMinus1,
+            -2 => EntityKind::UncoveredMinus2,
+            -3 => EntityKind::UncoveredMinus3,
+            -4 => EntityKind::UncoveredMinus4,
+            -5 => EntityKind::UncoveredMinus5,
+            -6 => EntityKind::UncoveredMinus6,
+            -7 => EntityKind::UncoveredMinus7,
+            -8 => EntityKind::UncoveredMinus8,
+            sum => EntityKind::uncovered(sum as u8),
+        }
+    }
+}
+
+/// What uncovering a cell with `neighbours` shows: the mines among them less the
+/// anti-mines.
+fn counted_entity(mines: &[bool], anti_mines: &[bool], neighbours: impl Iterator<Item = usize>) -> EntityKind {
+    let (mines_around, anti_mines_around) = neighbours.fold((0, 0), |(plain, anti), n| match (mines[n], anti_mines[n]) {
+        (true, true) => (plain, anti + 1),
+        (true, false) => (plain + 1, anti),
+        _ => (plain, anti),
+    });
+    EntityKind::counted(mines_around, anti_mines_around)
+}
+
+/// Uncovers the cell `start` of a board `columns` wide and, through cells with no mines
+/// or anti-mines around them, every covered cell connected to it by `adjacency`. Mines
+/// and flagged cells are left alone.
+///
+/// Works off an explicit stack, with each cell queued at most once: recursing per cell
+/// overflows the stack on large boards with few mines.
+fn flood_fill(displayed_entities: &mut [EntityKind], mines: &[bool], anti_mines: &[bool], columns: usize, adjacency: Adjacency, start: usize) {
+    let rows = displayed_entities.len() / columns;
+    let neighbours = |idx: usize| adjacency.neighbours(rows, columns, idx);
+
+    let mut queued = vec![false; displayed_entities.len()];
+    let mut pending = vec![start];
+    queued[start] = true;
+    while let Some(idx) = pending.pop() {
+        if mines[idx] || displayed_entities[idx] != EntityKind::Covered {
+            continue;
+        }
+
+        displayed_entities[idx] = counted_entity(mines, anti_mines, neighbours(idx));
+
+        // Only continue flood fill if this cell has no adjacent mines
+        if displayed_entities[idx] == EntityKind::Uncovered0 {
+            for n in neighbours(idx) {
+                if !queued[n] && displayed_entities[n] == EntityKind::Covered {
+                    queued[n] = true;
+                    pending.push(n);
+                }
+            }
+        }
+    }
+}
+
+/// The board's 3BV: the fewest clicks that clear it without flagging, counting one per
+/// opening (connected area of cells with no mines around them, which opens at once)
+/// and one per numbered cell no opening reveals.
+fn three_bv(mines: &[bool], columns: usize, adjacency: Adjacency) -> u32 {
+    three_bv_cleared(mines, &vec![EntityKind::Covered; mines.len()], columns, adjacency).0
+}
+
+/// The board's 3BV, and how much of it the board showing `displayed` has cleared: the
+/// openings with a cell uncovered that opens them, and the numbers outside them that are.
+fn three_bv_cleared(mines: &[bool], displayed: &[EntityKind], columns: usize, adjacency: Adjacency) -> (u32, u32) {
+    let rows = mines.len() / columns;
+    let opening = |idx: usize| !mines[idx] && adjacency.neighbours(rows, columns, idx).all(|n| !mines[n]);
+    // Openings are the same whichever mines are anti-mines
+    let no_anti_mines = vec![false; mines.len()];
+    let mut opened = vec![EntityKind::Covered; mines.len()];
+    let mut cleared_openings = vec![EntityKind::Covered; mines.len()];
+    let (mut clicks, mut cleared) = (0, 0);
+    for idx in (0..mines.len()).filter(|&idx| opening(idx)) {
+        if opened[idx] == EntityKind::Covered {
+            flood_fill(&mut opened, mines, &no_anti_mines, columns, adjacency, idx);
+            clicks += 1;
+        }
+        if displayed[idx].sum().is_some() && cleared_openings[idx] == EntityKind::Covered {
+            flood_fill(&mut cleared_openings, mines, &no_anti_mines, columns, adjacency, idx);
+            cleared += 1;
+        }
+    }
+    for idx in (0..mines.len()).filter(|&idx| opened[idx] == EntityKind::Covered && !mines[idx]) {
+        clicks += 1;
+        cleared += displayed[idx].sum().is_some() as u32;
+    }
+    (clicks, cleared)
+}
+
+/// The board's ZiNi: an estimate of the fewest clicks that clear it with flags and
+/// chords. Opens every opening, then keeps playing the number whose chord uncovers the
+/// most cells for the clicks it takes to uncover the number and flag the mines around
+/// it, and clicks the cells left one by one.
+fn zini(mines: &[bool], columns: usize, adjacency: Adjacency) -> u32 {
+    let rows = mines.len() / columns;
+    let neighbours = |idx: usize| adjacency.neighbours(rows, columns, idx);
+    let no_anti_mines = vec![false; mines.len()];
+    let mut shown = vec![EntityKind::Covered; mines.len()];
+    let mut clicks = 0;
+    for idx in 0..mines.len() {
+        if !mines[idx] && shown[idx] == EntityKind::Covered && neighbours(idx).all(|n| !mines[n]) {
+            flood_fill(&mut shown, mines, &no_anti_mines, columns, adjacency, idx);
+            clicks += 1;
+        }
+    }
+
+    // Cells a chord on the number uncovers, less the clicks it takes
+    let premium = |shown: &[EntityKind], idx: usize| -> i32 {
+        if mines[idx] {
+            return 0;
+        }
+        let (opens, flags) = neighbours(idx).fold((0, 0), |(opens, flags), n| match (mines[n], shown[n]) {
+            (false, EntityKind::Covered) => (opens + 1, flags),
+            (true, EntityKind::Covered) => (opens, flags + 1),
+            _ => (opens, flags),
+        });
+        opens - flags - 1 - (shown[idx] == EntityKind::Covered) as i32
+    };
+    let mut best: BinaryHeap<(i32, Reverse<usize>)> = (0..mines.len())
+        .map(|idx| (premium(&shown, idx), Reverse(idx)))
+        .filter(|&(premium, _)| premium > 0)
+        .collect();
+    while let Some((queued, Reverse(idx))) = best.pop() {
+        // Chords played since may have changed what this one is worth
+        let current = premium(&shown, idx);
+        if current != queued {
+            if current > 0 {
+                best.push((current, Reverse(idx)));
+            }
+            continue;
+        }
+
+        // With the openings open, every cell left to uncover is a number
+        let mut changed = vec![idx];
+        for n in std::iter::once(idx).chain(neighbours(idx)) {
+            match (mines[n], shown[n]) {
+                (false, EntityKind::Covered) => shown[n] = counted_entity(mines, &no_anti_mines, neighbours(n)),
+                (true, EntityKind::Covered) => shown[n] = EntityKind::Flagged,
+                _ => continue,
+            }
+            clicks += (n == idx || mines[n]) as u32;
+            changed.push(n);
+        }
+        clicks += 1;
+        for idx in changed.into_iter().flat_map(|idx| std::iter::once(idx).chain(neighbours(idx))) {
+            let premium = premium(&shown, idx);
+            if premium > 0 {
+                best.push((premium, Reverse(idx)));
+            }
+        }
+    }
+    clicks + (0..mines.len()).filter(|&idx| !mines[idx] && shown[idx] == EntityKind::Covered).count() as u32
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub(crate) enum SceneState {
     Uninitialized,
+    #[allow(dead_code)]
     Initializing,
     Ready,
     Won,
-    Lost
+    Lost,
+    /// The game in progress is on hold: the clock is stopped and the board hidden.
+    Paused,
 }
 
+/// Color of the outline around the hovered cell, as RGBA.
+const HOVER_OUTLINE_RGBA: [u8; 4] = [0x20, 0x20, 0x20, 0xff];
+/// Outlines around the cell a hint proved safe or mined.
+const HINT_SAFE_RGBA: [u8; 4] = [0x00, 0xa0, 0x00, 0xff];
+const HINT_MINE_RGBA: [u8; 4] = [0xd0, 0x00, 0x00, 0xff];
+/// Line along the edges of a board that wraps around, as they continue on the other side.
+const WRAP_EDGE_RGBA: [u8; 4] = [0x20, 0x80, 0xd0, 0xff];
+/// Fill for the cells hidden by `--fog`.
+const FOG_RGBA: [u8; 4] = [0x50, 0x50, 0x58, 0xff];
+/// Markers on the cells hiding a mine or an anti-mine with `--debug-mines`.
+const DEBUG_MINE_RGBA: [u8; 4] = [0xff, 0x00, 0xff, 0xff];
+const DEBUG_ANTI_MINE_RGBA: [u8; 4] = [0x00, 0xc0, 0xff, 0xff];
+const DEBUG_MARKER_SIZE: u16 = 4;
+/// Fill for a covered cell while the left button is held on it.
+const PRESSED_CELL_RGBA: [u8; 4] = [0xc0, 0xc0, 0xc0, 0xff];
+/// The status bar lets the desktop show through when the window has an alpha channel.
+const STATUS_BAR_RGBA: [u8; 4] = [0xc0, 0xc0, 0xc0, 0xa0];
+const TEXT_RGBA: [u8; 4] = [0x00, 0x00, 0x00, 0xff];
+/// Bar along the top of the status bar showing how much of the board the other racer
+/// has cleared.
+const OPPONENT_PROGRESS_RGBA: [u8; 4] = [0x20, 0x40, 0xd0, 0xff];
+const OPPONENT_PROGRESS_HEIGHT: u16 = 3;
+/// Baseline of the status text, relative to the top of the status bar.
+const STATUS_TEXT_BASELINE: u16 = 14;
+const STATUS_TEXT_MARGIN: u16 = 4;
+/// Spacing of the lines on the statistics screen.
+const STATS_LINE_HEIGHT: u16 = 14;
+/// Ring drawn around the keyboard-focused cell; only its border is copied, so the cell
+/// shows through.
+const FOCUS_RING_SPRITE: Position = Position { x: 0, y: 91 };
+const FOCUS_RING_THICKNESS: u16 = 2;
+/// Distance of the flag mode button from the right and top edges of the status bar.
+const FLAG_MODE_BUTTON_INSET: u16 = 2;
+/// A second left click on the same number within this long chords it.
+const DOUBLE_CLICK_MS: u32 = 400;
+/// How long newly unlocked achievements are announced over the board.
+const TOAST_DURATION: Duration = Duration::from_secs(4);
+/// Cells an endless board scrolls by per notch of the mouse wheel.
+const SCROLL_STEP: i64 = 3;
+/// Touches held at least this long flag instead of reveal.
+const LONG_PRESS_MS: u32 = 500;
+
 #[derive(Debug, Clone, Copy)]
 pub(crate) struct Position {
     x: u16,
@@ -43,267 +391,2831 @@ pub(crate) struct Position {
 
 fn get_asset_coordinates() -> HashMap<EntityKind, Position> {
     let mut asset_coordinates = HashMap::new();
-    asset_coordinates.insert(EntityKind::Uncovered0, Position { x: 0 * 16, y: 22 });
-    asset_coordinates.insert(EntityKind::Uncovered1, Position { x: 1 * 16, y: 22 });
-    asset_coordinates.insert(EntityKind::Uncovered2, Position { x: 2 * 16, y: 22 });
-    asset_coordinates.insert(EntityKind::Uncovered3, Position { x: 3 * 16, y: 22 });
-    asset_coordinates.insert(EntityKind::Uncovered4, Position { x: 4 * 16, y: 22 });
-    asset_coordinates.insert(EntityKind::Uncovered5, Position { x: 5 * 16, y: 22 });
-    asset_coordinates.insert(EntityKind::Uncovered6, Position { x: 6 * 16, y: 22 });
-    asset_coordinates.insert(EntityKind::Uncovered7, Position { x: 7 * 16, y: 22 });
-    asset_coordinates.insert(EntityKind::Uncovered8, Position { x: 8 * 16, y: 22 });
+    asset_coordinates.insert(EntityKind::Uncovered0, Position { x: 0, y: 22 });
+    asset_coordinates.insert(EntityKind::Uncovered1, Position { x: 16, y: 22 });
+    asset_coordinates.insert(EntityKind::Uncovered2, Position { x: 32, y: 22 });
+    asset_coordinates.insert(EntityKind::Uncovered3, Position { x: 48, y: 22 });
+    asset_coordinates.insert(EntityKind::Uncovered4, Position { x: 64, y: 22 });
+    asset_coordinates.insert(EntityKind::Uncovered5, Position { x: 80, y: 22 });
+    asset_coordinates.insert(EntityKind::Uncovered6, Position { x: 96, y: 22 });
+    asset_coordinates.insert(EntityKind::Uncovered7, Position { x: 112, y: 22 });
+    asset_coordinates.insert(EntityKind::Uncovered8, Position { x: 128, y: 22 });
     asset_coordinates.insert(EntityKind::Covered, Position { x: 0, y: 38 });
     asset_coordinates.insert(EntityKind::Flagged, Position { x: 16, y: 38 });
     asset_coordinates.insert(EntityKind::MineExploded, Position { x: 32, y: 40 });
     asset_coordinates.insert(EntityKind::MineIdle, Position { x: 64, y: 40 });
+    asset_coordinates.insert(EntityKind::FlagMisplaced, Position { x: 48, y: 40 });
+    // Negative numbers have the digit's colour and the cell's swapped, and anti-mines are
+    // drawn white
+    asset_coordinates.insert(EntityKind::UncoveredBalanced, Position { x: 0, y: 107 });
+    asset_coordinates.insert(EntityKind::UncoveredMinus1, Position { x: 16, y: 107 });
+    asset_coordinates.insert(EntityKind::UncoveredMinus2, Position { x: 32, y: 107 });
+    asset_coordinates.insert(EntityKind::UncoveredMinus3, Position { x: 48, y: 107 });
+    asset_coordinates.insert(EntityKind::UncoveredMinus4, Position { x: 64, y: 107 });
+    asset_coordinates.insert(EntityKind::UncoveredMinus5, Position { x: 80, y: 107 });
+    asset_coordinates.insert(EntityKind::UncoveredMinus6, Position { x: 96, y: 107 });
+    asset_coordinates.insert(EntityKind::UncoveredMinus7, Position { x: 112, y: 107 });
+    asset_coordinates.insert(EntityKind::UncoveredMinus8, Position { x: 128, y: 107 });
+    asset_coordinates.insert(EntityKind::AntiMineExploded, Position { x: 0, y: 123 });
+    asset_coordinates.insert(EntityKind::AntiMineIdle, Position { x: 16, y: 123 });
+    // Numbers past 8 have two narrower digits, in the colours of 1 to 8 over again
+    asset_coordinates.insert(EntityKind::Uncovered9, Position { x: 0, y: 139 });
+    asset_coordinates.insert(EntityKind::Uncovered10, Position { x: 16, y: 139 });
+    asset_coordinates.insert(EntityKind::Uncovered11, Position { x: 32, y: 139 });
+    asset_coordinates.insert(EntityKind::Uncovered12, Position { x: 48, y: 139 });
+    asset_coordinates.insert(EntityKind::Uncovered13, Position { x: 64, y: 139 });
+    asset_coordinates.insert(EntityKind::Uncovered14, Position { x: 80, y: 139 });
+    asset_coordinates.insert(EntityKind::Uncovered15, Position { x: 96, y: 139 });
+    asset_coordinates.insert(EntityKind::Uncovered16, Position { x: 112, y: 139 });
+    asset_coordinates.insert(EntityKind::Uncovered17, Position { x: 128, y: 139 });
+    asset_coordinates.insert(EntityKind::Uncovered18, Position { x: 0, y: 155 });
+    asset_coordinates.insert(EntityKind::Uncovered19, Position { x: 16, y: 155 });
+    asset_coordinates.insert(EntityKind::Uncovered20, Position { x: 32, y: 155 });
+    asset_coordinates.insert(EntityKind::Uncovered21, Position { x: 48, y: 155 });
+    asset_coordinates.insert(EntityKind::Uncovered22, Position { x: 64, y: 155 });
+    asset_coordinates.insert(EntityKind::Uncovered23, Position { x: 80, y: 155 });
+    asset_coordinates.insert(EntityKind::Uncovered24, Position { x: 96, y: 155 });
+    // The sheet is scaled up for --scale, and the sprites with it
+    for position in asset_coordinates.values_mut() {
+        position.x *= config::scale();
+        position.y *= config::scale();
+    }
     asset_coordinates
 }
 
-// Function to convert an index to row and column
-fn idx_to_row_column(idx: u16) -> (u16, u16) {
-    let row = idx / ENTITIES_COLUMN_COUNT;
-    let column = idx % ENTITIES_COLUMN_COUNT;
-    (row, column)
-}
+/// Button a click acts as: Ctrl turns a left click into a flag (button 3) for one-button
+/// mice and trackpads, and Shift turns it into a chord (button 2, like the middle button).
+fn effective_button(event: &InputEvent) -> u8 {
+    match event.detail {
+        1 if event.state & STATE_CONTROL != 0 => 3,
+        1 if event.state & STATE_SHIFT != 0 => 2,
+        button => button,
+    }
+}
+
+/// Cursors shown over the board while playing and once the game is over.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Cursors {
+    pub(crate) playing: u32,
+    pub(crate) game_over: u32,
+}
+
+/// Server-side resources and per-connection details the scene draws with, replaced as a
+/// whole after a reconnect.
+#[derive(Debug, Clone)]
+pub(crate) struct SceneResources {
+    pub(crate) window_id: u32,
+    pub(crate) gc_id: u32,
+    pub(crate) sprite_pixmap_id: u32,
+    pub(crate) keymap: Keymap,
+    pub(crate) cursors: Cursors,
+    /// Layout of the window's pixels, for reading screenshots back.
+    pub(crate) pixel_format: PixelFormat,
+    /// Major opcode of XInput when touch events are selected on the window.
+    pub(crate) xinput_opcode: Option<u8>,
+    /// Scroll valuators of the master pointers, which pan endless boards smoothly.
+    pub(crate) scroll_valuators: Vec<ScrollValuator>,
+    /// Major opcode of XFixes 4.0 or later, used to hide the pointer.
+    pub(crate) xfixes_opcode: Option<u8>,
+    /// Off-screen pixmap the size of the board and status bar that every frame is drawn
+    /// into, then copied to the window whole.
+    pub(crate) back_buffer_id: u32,
+    /// Major opcode of Present, when the server has it: frames are then copied to the
+    /// window at vblank, rather than with a CopyArea straight away.
+    pub(crate) present_opcode: Option<u8>,
+    /// RENDER, when the server has it, to scale the board up to fill a bigger window.
+    pub(crate) render: Option<Render>,
+    pub(crate) wm_protocols: u32,
+    pub(crate) wm_delete_window: u32,
+    /// Atoms for offering the game on the clipboard: the CLIPBOARD selection and the
+    /// targets it can be converted to.
+    pub(crate) clipboard: u32,
+    pub(crate) utf8_string: u32,
+    pub(crate) targets: u32,
+}
+
+/// Everything a cell is drawn from, kept for each cell as it was last drawn into the back
+/// buffer so that only the cells that look different are drawn again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CellLook {
+    entity: EntityKind,
+    fogged: bool,
+    pressed: bool,
+    /// `--debug-mines` marks a mine here; `true` for an anti-mine.
+    marker: Option<bool>,
+    hovered: bool,
+    focused: bool,
+    hint: Option<Deduction>,
+}
+
+/// The back buffer scaled up to fill the window, kept until the window changes size.
+#[derive(Debug, Clone, Copy)]
+struct ScaledBuffer {
+    factor: f64,
+    /// Picture of the back buffer, read `factor` times bigger.
+    source_id: u32,
+    pixmap_id: u32,
+    picture_id: u32,
+}
+
+/// Where and when a touch started.
+#[derive(Debug, Clone, Copy)]
+struct Touch {
+    id: u32,
+    time: u32,
+    cell: Option<usize>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Scene {
+    state: SceneState,
+    /// Preset cycled through with D. Cycling leaves a custom `--rows`/`--cols` size behind.
+    difficulty: Difficulty,
+    size: BoardSize,
+    resources: SceneResources,
+    /// Cursor last set on the window, so it is only changed when the state flips.
+    applied_cursor: Option<u32>,
+    /// Whether XFixes has been asked to hide the pointer; hides nest, so they are tracked.
+    pointer_hidden: bool,
+    displayed_entities: Vec<EntityKind>,
+    mines: Vec<bool>,
+    /// Which of the mines are anti-mines.
+    anti_mines: Vec<bool>,
+    /// Index of the cell under the pointer, outlined while the game is in progress.
+    hovered: Option<usize>,
+    /// Cell picked with the arrow keys, ringed once the keyboard has been used.
+    focused: Option<usize>,
+    /// Covered cells drawn sunken while a button is held: the one under the left button,
+    /// or the cell and its neighbours while chording.
+    pressed: Vec<usize>,
+    /// The press previews a chord, so the cell's neighbours are sunk as well.
+    chord_preview: bool,
+    /// Both buttons have been held together. The pointer stays grabbed and releases
+    /// don't click until every button is up.
+    chording: bool,
+    /// Cell and time of the last left click, to spot double clicks.
+    last_left_click: Option<(usize, u32)>,
+    /// The window is mapped and not fully covered. Nothing is drawn otherwise, and the
+    /// board is repainted in full once it can be seen again.
+    visible: bool,
+    /// How each cell was last drawn into the back buffer, or `None` where it has to be
+    /// drawn again, e.g. after the statistics screen covered it.
+    drawn: Vec<Option<CellLook>>,
+    /// Height of the lesson text or toast drawn over the top of the board last frame, so
+    /// the cells under it are drawn again once it goes.
+    overlay_height: u16,
+    /// Window size as last reported by the server; the board is centered inside it.
+    window_size: (u16, u16),
+    /// Touch currently on the board, if any; further fingers are ignored until it lifts.
+    touch: Option<Touch>,
+    /// Scrolling by XInput valuators, in place of the wheel buttons when there are any.
+    smooth_scroll: SmoothScroll,
+    /// Serial of the last presented frame.
+    frame_serial: u32,
+    /// Whether the last presented frame hasn't completed yet.
+    frame_pending: bool,
+    scaled_buffer: Option<ScaledBuffer>,
+    /// State the bell last rang for, so a finished game rings once.
+    announced_state: SceneState,
+    bell_volume: BellVolume,
+    /// No cell has been revealed yet this game, so mines may still be moved out of the way.
+    first_reveal: bool,
+    /// Mines stay where a restored or imported board put them, even under the first click.
+    keep_mines: bool,
+    /// The board is being played again after a restart, so its layout is known and a win
+    /// doesn't count for best times.
+    replaying: bool,
+    /// Keep mines off the first revealed cell's neighbours as well, so it opens an area.
+    guaranteed_opening: bool,
+    /// Where E and quitting write the board (`--export-board`).
+    export_path: Option<PathBuf>,
+    /// Text offered on the CLIPBOARD selection while this window owns it, set by sharing
+    /// a finished game with C.
+    clipboard: Option<String>,
+    /// Time the game just over took, to share with it.
+    finished_in: Option<u64>,
+    /// Directory every finished game's replay is written to (`--export-replays`), and the
+    /// clicks played this game for it.
+    replay_dir: Option<PathBuf>,
+    replay_clicks: Vec<Click>,
+    /// Which cells the numbers count (`--adjacency`).
+    adjacency: Adjacency,
+    /// Mines a game can set off before it is lost (`--lives`), and how many are left.
+    max_lives: u8,
+    lives: u8,
+    /// Flag mines the numbers leave no doubt about after every reveal (`--auto-flag`).
+    auto_flag: bool,
+    /// Two players taking turns (`--hot-seat`), who have a life each.
+    hot_seat: Option<HotSeat>,
+    /// Move the mine off a cell revealed when there was nothing but guessing left
+    /// (`--guess-protection`).
+    guess_protection: bool,
+    /// Shape the mines are dealt in (`--pattern`).
+    pattern: Option<Pattern>,
+    /// Flagging every mine, and nothing else, wins too (`--win-by-flagging`).
+    win_by_flagging: bool,
+    /// Time a game may be played before it is lost (`--blitz`), and whether this one
+    /// ran out of it.
+    time_limit: Option<Duration>,
+    timed_out: bool,
+    /// Flags that may be placed at a time (`--flag-limit`).
+    flag_limit: Option<FlagLimit>,
+    /// The board of an endless game (`--endless`), of which the cells shown are the part
+    /// in view, and where the top-left one of those is on it.
+    endless: Option<World>,
+    camera: Cell,
+    /// How far around the uncovered cells the board can be seen (`--fog`), and which
+    /// cells are hidden for being farther.
+    fog: Option<u8>,
+    fogged: Vec<bool>,
+    /// How often the hidden mines move (`--chaos`), how often they have this game and
+    /// how many cells were clicked open since they last did.
+    chaos: Option<Chaos>,
+    shifts: u32,
+    reveals_since_shift: u16,
+    /// Mark the mines and print the board as they are placed or move (`--debug-mines`).
+    debug_mines: bool,
+    /// Lesson of the tutorial being played (`--tutorial`), and whether its goal has been met.
+    lesson: Option<usize>,
+    lesson_done: bool,
+    /// Level of the campaign being played (`--campaign`), and the stars its win earned.
+    level: Option<usize>,
+    stars: Option<u8>,
+    /// Whether a flag has been placed, or a chord has uncovered anything, this game. Both
+    /// are assumed of a restored game that was already under way.
+    flagged: bool,
+    chorded: bool,
+    /// The solver playing by itself (`--autoplay`), until a key or button is pressed.
+    autoplay: Option<Autoplay>,
+    /// Newly unlocked achievements announced over the board, and until when.
+    toast: Option<(String, Instant)>,
+    /// Seed for the next board, from `--seed`; later boards get a random one.
+    next_seed: Option<u64>,
+    /// Date of the daily challenge the next board is for, with `--daily`.
+    next_daily: Option<String>,
+    /// Date of the daily challenge being played. Its result is recorded in the daily
+    /// log instead of the statistics and best times.
+    daily: Option<String>,
+    /// Generator seeded per board, which also moves mines off the first click, so a seed
+    /// played with the same first click gives the same board.
+    rng: StdRng,
+    /// When the first cell of this game was revealed, for the statistics. Moved forward
+    /// by the time spent paused, so it always measures the time played.
+    started_at: Option<Instant>,
+    /// When the game was paused, while it is.
+    paused_at: Option<Instant>,
+    /// Left clicks and taps flag and right clicks and long presses reveal, for pointers
+    /// without a right button. Toggled with M or the button in the status bar.
+    flag_mode: bool,
+    /// 3BV of the board, known once the first reveal has settled where the mines are.
+    bbbv: Option<u32>,
+    /// Clicks played on the board this game, and how many of them changed anything. None
+    /// for a game resumed part played, whose earlier clicks aren't known.
+    clicks: Option<(u32, u32)>,
+    /// How efficiently the game just won was played.
+    efficiency: Option<Efficiency>,
+    /// Cell the last hint (H) pointed out, until the next click.
+    hint: Option<Deduction>,
+    /// Hints asked for this game. Games won with hints don't count for best times.
+    hints_used: u32,
+    /// Fastest win on the board's difficulty, shown in the status bar.
+    best_time: Option<u64>,
+    /// Percentages of the 3BV cleared to take split times at (`--splits`), the split
+    /// times taken this game, and those of the best time to compare them with.
+    splits: Vec<u8>,
+    split_times: Vec<(u8, u64)>,
+    best_splits: Vec<(u8, u64)>,
+    /// Wins in a row on the board's difficulty, shown in the status bar. None for games
+    /// the statistics aren't kept for.
+    streak: Option<u32>,
+    /// Time of the game just won, and whether it set a new record.
+    winning_time: Option<(u64, bool)>,
+    /// Lines of the statistics screen while it covers the board (toggled with T).
+    stats_screen: Option<Vec<String>>,
+    /// Handlers for ClientMessage and PropertyNotify events; atoms differ per server, so
+    /// this is rebuilt on attach.
+    dispatcher: Dispatcher<Scene>,
+    /// Desktop session the board is saved for at logout. Unlike the X connection it
+    /// survives reconnects.
+    session_manager: Option<SessionManager>,
+    /// The other player of a co-op game. The host plays the board and sends it over after
+    /// every change; the guest shows it and passes its clicks on.
+    peer: Option<Peer>,
+    /// What the host last sent of the board, so it is sent again only when it changed.
+    shared_board: Option<(SceneState, Vec<EntityKind>, Vec<bool>)>,
+    /// Seed the board was dealt from, which the progress reports of a race refer to.
+    seed: u64,
+    /// Progress last reported to the other racer, and the other racer's on this board.
+    shared_progress: Option<Progress>,
+    opponent: Option<Progress>,
+    /// Whether this side won the race for this board, once that is decided.
+    race_won: Option<bool>,
+}
+
+impl Scene {
+    pub(crate) fn new(resources: SceneResources, options: &Options) -> Self {
+        let size = options.board_size();
+        Scene{
+            state: SceneState::Uninitialized,
+            difficulty: options.difficulty,
+            size,
+            announced_state: SceneState::Uninitialized,
+            bell_volume: options.bell_volume,
+            first_reveal: true,
+            keep_mines: false,
+            replaying: false,
+            export_path: options.export_board.clone(),
+            clipboard: None,
+            finished_in: None,
+            replay_dir: options.export_replays.clone(),
+            replay_clicks: Vec::new(),
+            guaranteed_opening: options.guaranteed_opening,
+            auto_flag: options.auto_flag,
+            guess_protection: options.guess_protection,
+            pattern: options.pattern.clone(),
+            hot_seat: options.hot_seat.then(HotSeat::default),
+            win_by_flagging: options.win_by_flagging,
+            time_limit: options.blitz.map(|limit| Duration::from_secs(limit.0.into())),
+            timed_out: false,
+            flag_limit: options.flag_limit,
+            endless: options.endless.then(|| World::new(0, 0.0)),
+            camera: (0, 0),
+            fog: options.fog.map(|radius| radius.0),
+            fogged: Vec::new(),
+            chaos: options.chaos,
+            shifts: 0,
+            reveals_since_shift: 0,
+            debug_mines: options.debug_mines,
+            lesson: options.tutorial.then_some(0),
+            lesson_done: false,
+            level: None,
+            stars: None,
+            flagged: false,
+            chorded: false,
+            autoplay: options.autoplay.then(Autoplay::new),
+            toast: None,
+            adjacency: if options.wrap { options.adjacency.wrapped() } else { options.adjacency },
+            max_lives: if options.hot_seat { 2 } else { options.lives.0 },
+            lives: if options.hot_seat { 2 } else { options.lives.0 },
+            next_seed: options.seed,
+            next_daily: None,
+            daily: None,
+            rng: StdRng::seed_from_u64(0),
+            started_at: None,
+            paused_at: None,
+            flag_mode: false,
+            bbbv: None,
+            clicks: Some((0, 0)),
+            efficiency: None,
+            hint: None,
+            hints_used: 0,
+            best_time: None,
+            splits: options.splits.clone().map_or_else(Vec::new, |splits| splits.0),
+            split_times: Vec::new(),
+            best_splits: Vec::new(),
+            streak: None,
+            winning_time: None,
+            stats_screen: None,
+            dispatcher: Self::dispatcher(&resources),
+            smooth_scroll: SmoothScroll::new(resources.scroll_valuators.clone()),
+            resources,
+            applied_cursor: None,
+            pointer_hidden: false,
+            displayed_entities: vec![EntityKind::Covered; size.cell_count()],
+            mines: vec![false; size.cell_count()],
+            anti_mines: vec![false; size.cell_count()],
+            hovered: None,
+            focused: None,
+            pressed: Vec::new(),
+            chord_preview: false,
+            chording: false,
+            last_left_click: None,
+            visible: true,
+            drawn: Vec::new(),
+            overlay_height: 0,
+            window_size: (size.width(), size.height() + status_bar_height()),
+            touch: None,
+            frame_serial: 0,
+            frame_pending: false,
+            scaled_buffer: None,
+            session_manager: None,
+            peer: None,
+            shared_board: None,
+            seed: 0,
+            shared_progress: None,
+            opponent: None,
+            race_won: None,
+        }
+    }
+
+    /// Points the scene at a new set of server resources, e.g. after reconnecting.
+    pub(crate) fn attach(&mut self, resources: SceneResources) {
+        self.dispatcher = Self::dispatcher(&resources);
+        self.smooth_scroll = SmoothScroll::new(resources.scroll_valuators.clone());
+        self.resources = resources;
+        // Selections are lost with the connection
+        self.clipboard = None;
+        self.applied_cursor = None;
+        self.pointer_hidden = false;
+        self.frame_pending = false;
+        self.scaled_buffer = None;
+        self.drawn.clear();
+    }
+
+    /// Hands the scene the session manager connection, so it saves the board when asked.
+    pub(crate) fn join_session(&mut self, session_manager: SessionManager) {
+        self.session_manager = Some(session_manager);
+    }
+
+    /// Tells the session manager the game is quitting on its own, so it isn't restarted.
+    pub(crate) fn leave_session(&mut self) {
+        if let Some(session_manager) = self.session_manager.take() {
+            let _ = session_manager.close();
+        }
+    }
+
+    /// Plays with `peer`; the host sends it the board straight away.
+    pub(crate) fn connect_peer(&mut self, peer: Peer) {
+        self.peer = Some(peer);
+        self.share_with_peer();
+    }
+
+    fn is_guest(&self) -> bool {
+        self.peer.as_ref().is_some_and(|peer| !peer.is_host())
+    }
+
+    fn racing(&self) -> bool {
+        self.peer.as_ref().is_some_and(|peer| peer.mode() == Mode::Race)
+    }
+
+    /// Tells the other player what changed since last time: the co-op host sends the
+    /// board, and racers their progress, with the host dealing each new board first.
+    /// Losing the other player isn't fatal; the game just goes on alone.
+    fn share_with_peer(&mut self) {
+        let Some(peer) = self.peer.as_ref() else {
+            return;
+        };
+        let mut messages = Vec::new();
+        match (peer.mode(), peer.is_host()) {
+            (Mode::CoOp, true) => {
+                let shared = (self.state, self.displayed_entities.clone(), self.mines.clone());
+                if self.shared_board.as_ref() != Some(&shared) {
+                    messages.push(Message::Board(self.saved_board()));
+                    self.shared_board = Some(shared);
+                }
+            }
+            (Mode::CoOp, false) => {}
+            (Mode::Race, is_host) => {
+                self.update_race_result();
+                let progress = Progress {
+                    seed: self.seed,
+                    revealed: self.displayed_entities.iter().filter(|entity| entity.sum().is_some()).count(),
+                    state: self.state,
+                    millis: self.elapsed().as_millis() as u64,
+                };
+                // The clock alone doesn't make for news
+                let unchanged = self.shared_progress.is_some_and(|shared| (shared.seed, shared.revealed, shared.state) == (progress.seed, progress.revealed, progress.state));
+                if !unchanged {
+                    if is_host && self.shared_progress.is_none_or(|shared| shared.seed != self.seed) {
+                        messages.push(Message::Race { size: self.size, adjacency: self.adjacency, seed: self.seed });
+                    }
+                    messages.push(Message::Progress(progress));
+                    self.shared_progress = Some(progress);
+                }
+            }
+        }
+
+        let sent = messages.iter().try_for_each(|message| self.peer.as_mut().map_or(Ok(()), |peer| peer.send(message)));
+        if let Err(e) = sent {
+            eprintln!("Lost the other player: {}", e);
+            self.peer = None;
+        }
+    }
+
+    /// Decides the race once either racer has finished: the first to clear the board
+    /// wins, and whoever hits a mine loses.
+    fn update_race_result(&mut self) {
+        if self.race_won.is_some() {
+            return;
+        }
+        self.race_won = match (self.state, self.opponent.map(|opponent| opponent.state)) {
+            (SceneState::Won, _) | (_, Some(SceneState::Lost)) => Some(true),
+            (SceneState::Lost, _) | (_, Some(SceneState::Won)) => Some(false),
+            _ => None,
+        };
+    }
+
+    /// Starts the board of a race dealt by the host.
+    pub(crate) fn start_race(&mut self, size: BoardSize, adjacency: Adjacency, seed: u64) {
+        self.size = size;
+        self.adjacency = adjacency;
+        self.window_size = (size.width(), size.height() + status_bar_height());
+        self.next_seed = Some(seed);
+        self.deal();
+    }
+
+    /// Asks the host to act for the guest. Returns whether it was asked, which it isn't
+    /// on the host or once the host is gone, when the guest plays on by itself.
+    fn send_to_host(&mut self, message: Message) -> bool {
+        if !self.is_guest() {
+            return false;
+        }
+        let sent = self.peer.as_mut().map(|peer| peer.send(&message));
+        if let Some(Err(e)) = sent {
+            eprintln!("Lost the other player: {}", e);
+            self.peer = None;
+            return false;
+        }
+        true
+    }
+
+    /// Serves what the other player sent: the board on the guest, actions on the host.
+    fn on_peer_readable(&mut self, conn: &mut X11Connection) -> Result<(), std::io::Error> {
+        let received = self.peer.as_mut().map_or(Ok(Vec::new()), Peer::receive);
+        let messages = received.unwrap_or_else(|e| {
+            eprintln!("Lost the other player: {}", e);
+            self.peer = None;
+            Vec::new()
+        });
+        let is_host = self.peer.as_ref().is_some_and(Peer::is_host);
+
+        for message in messages {
+            match message {
+                Message::Race { size, adjacency, seed } if !is_host => {
+                    let resized = (size.rows, size.columns) != (self.size.rows, self.size.columns);
+                    self.start_race(size, adjacency, seed);
+                    if resized {
+                        self.fit_window_to_board(conn)?;
+                    }
+                }
+                // Reports on an earlier board may cross the next one being dealt
+                Message::Progress(progress) if progress.seed == self.seed => {
+                    self.opponent = Some(progress);
+                    self.update_race_result();
+                }
+                Message::Board(board) if !is_host => {
+                    let resized = (board.rows, board.columns) != (self.size.rows, self.size.columns);
+                    // Ring when the shared game ends, like on the host
+                    let announced_state = self.announced_state;
+                    self.restore(&board);
+                    self.announced_state = announced_state;
+                    if resized {
+                        self.fit_window_to_board(conn)?;
+                    }
+                }
+                // Actions arriving for a game that has since ended or been replaced
+                // fall on cells that no longer match, and change nothing
+                Message::Reveal(idx) | Message::Chord(idx) | Message::Flag(idx) | Message::Unflag(idx)
+                    if !is_host || idx >= self.displayed_entities.len() || self.state != SceneState::Ready => {}
+                Message::Reveal(idx) => self.click_cell(idx, 1),
+                Message::Chord(idx) => self.click_cell(idx, 2),
+                Message::Flag(idx) if self.displayed_entities[idx] == EntityKind::Covered => self.click_cell(idx, 3),
+                Message::Unflag(idx) if self.displayed_entities[idx] == EntityKind::Flagged => self.click_cell(idx, 3),
+                Message::NewGame if is_host => self.reset(),
+                Message::Restart if is_host && !self.racing() => self.restart(),
+                _ => {}
+            }
+        }
+        self.render(conn)
+    }
+
+    fn dispatcher(resources: &SceneResources) -> Dispatcher<Scene> {
+        let mut dispatcher = Dispatcher::new();
+        dispatcher.on_client_message(resources.wm_protocols, Scene::on_wm_protocols);
+        dispatcher.on_selection(resources.clipboard, Scene::on_clipboard);
+        dispatcher
+    }
+
+    /// Makes the next board today's challenge for `date`.
+    pub(crate) fn play_daily(&mut self, date: String) {
+        self.next_daily = Some(date);
+    }
+
+    /// Size of the board being played, which a reconnect has to recreate the window for.
+    pub(crate) fn board_size(&self) -> BoardSize {
+        self.size
+    }
+
+    /// Starts a new game, or with another player, has the host start it. In the tutorial
+    /// that is the lesson being played over, or once it is done the next one, and in the
+    /// campaign likewise the level.
+    pub(crate) fn reset(&mut self) {
+        if let Some(lesson) = self.lesson {
+            let next = if self.lesson_done { (lesson + 1) % LESSONS.len() } else { lesson };
+            return self.start_lesson(next);
+        }
+        if let Some(level) = self.level {
+            let next = if self.stars.is_some() { (level + 1).min(LEVELS.len() - 1) } else { level };
+            return self.start_level(next);
+        }
+        if !self.send_to_host(Message::NewGame) {
+            self.deal();
+        }
+    }
+
+    /// Starts a new game, hiding exactly as many mines as the board size calls for, and
+    /// prints the board's seed so it can be replayed with `--seed`.
+    fn deal(&mut self) {
+        // While it is still known whether the game given up was a daily challenge
+        self.abandon_game();
+        let cell_count = self.size.cell_count();
+        self.mines = vec![false; cell_count];
+
+        self.daily = self.next_daily.take();
+        if let Some(date) = &self.daily {
+            println!("Daily challenge for {}", date);
+            self.next_seed = Some(daily::seed(date));
+        }
+        let seed = self.next_seed.take().unwrap_or_else(rand::random);
+        println!("Board seed: {}", seed);
+        self.seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
+        let mine_count = (self.size.mines as usize).min(cell_count);
+        let mines = match &self.pattern {
+            Some(pattern) => pattern.place_mines(&mut self.rng, self.size.rows as usize, self.size.columns as usize, mine_count),
+            None => rand::seq::index::sample(&mut self.rng, cell_count, mine_count).into_vec(),
+        };
+        for &idx in &mines {
+            self.mines[idx] = true;
+        }
+        // Drawn after the mines so that boards without anti-mines keep their seeds
+        self.anti_mines = vec![false; cell_count];
+        if self.size.anti_mines > 0 {
+            for pick in rand::seq::index::sample(&mut self.rng, mines.len(), (self.size.anti_mines as usize).min(mines.len())) {
+                self.anti_mines[mines[pick]] = true;
+            }
+        }
+
+        if self.endless.is_some() {
+            let density = self.size.mines as f64 / cell_count as f64;
+            self.endless = Some(World::new(seed, density));
+            self.camera = (-(self.size.columns as i64 / 2), -(self.size.rows as i64 / 2));
+        }
+
+        self.restart();
+        self.keep_mines = false;
+        self.replaying = false;
+        self.print_debug_board();
+        self.opponent = None;
+        self.race_won = None;
+    }
+
+    /// Starts the board over with every cell covered again but the mines where they were,
+    /// including any the first click moved.
+    fn restart(&mut self) {
+        if self.send_to_host(Message::Restart) {
+            return;
+        }
+        if let Some(lesson) = self.lesson {
+            // Lessons start part uncovered
+            return self.start_lesson(lesson);
+        }
+        if let Some(level) = self.level {
+            return self.start_level(level);
+        }
+        // Before the first reveal nothing about the board has been seen yet
+        let seen = !self.first_reveal;
+        self.keep_mines |= seen;
+        self.replaying |= seen;
+        self.abandon_game();
+        self.displayed_entities = vec![EntityKind::Covered; self.size.cell_count()];
+        self.first_reveal = true;
+        self.started_at = None;
+        self.paused_at = None;
+        self.lives = self.max_lives;
+        if let Some(hot_seat) = self.hot_seat.as_mut() {
+            *hot_seat = HotSeat::default();
+        }
+        self.timed_out = false;
+        self.shifts = 0;
+        self.reveals_since_shift = 0;
+        self.flagged = false;
+        self.chorded = false;
+        self.bbbv = None;
+        self.clicks = Some((0, 0));
+        self.efficiency = None;
+        self.replay_clicks.clear();
+        self.finished_in = None;
+        self.winning_time = None;
+        self.split_times.clear();
+        self.hint = None;
+        self.hints_used = 0;
+        self.load_best_time();
+        self.load_streak();
+        self.state = SceneState::Ready;
+        if let Some(world) = self.endless.as_mut() {
+            world.restart();
+            self.show_world();
+        }
+        self.update_fog();
+    }
+
+    /// Hides the cells farther than the `--fog` radius from every uncovered cell. Nothing
+    /// is hidden before the first reveal, when there is nowhere to start from, nor once
+    /// the game is over.
+    fn update_fog(&mut self) {
+        let cell_count = self.displayed_entities.len();
+        let Some(radius) = self.fog.filter(|_| !self.first_reveal && matches!(self.state, SceneState::Ready | SceneState::Paused)) else {
+            self.fogged = vec![false; cell_count];
+            return;
+        };
+        let (rows, columns, radius) = (self.size.rows as i32, self.size.columns as i32, radius as i32);
+        let wraps = self.adjacency.wraps();
+        self.fogged = vec![true; cell_count];
+        for idx in 0..cell_count {
+            let entity = self.displayed_entities[idx];
+            if entity.sum().is_none() && !entity.exploded() {
+                continue;
+            }
+            let (row, column) = ((idx as i32) / columns, (idx as i32) % columns);
+            for dr in -radius..=radius {
+                for dc in -radius..=radius {
+                    // On a --wrap board the cells across an edge are as near as any
+                    let (r, c) = match wraps {
+                        true => ((row + dr).rem_euclid(rows), (column + dc).rem_euclid(columns)),
+                        false => (row + dr, column + dc),
+                    };
+                    if dr * dr + dc * dc <= radius * radius && (0..rows).contains(&r) && (0..columns).contains(&c) {
+                        self.fogged[(r * columns + c) as usize] = false;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sets up lesson `lesson` of the tutorial.
+    fn start_lesson(&mut self, lesson: usize) {
+        self.restore(&LESSONS[lesson].board());
+        self.lesson = Some(lesson);
+        self.lesson_done = false;
+        self.chorded = false;
+    }
+
+    /// Sets up level `level` of the campaign. Its opening is uncovered already, so the
+    /// clock starts with the first cell played rather than when the board is shown.
+    pub(crate) fn start_level(&mut self, level: usize) {
+        self.abandon_game();
+        self.level = Some(level);
+        self.stars = None;
+        self.restore(&LEVELS[level].board());
+        self.first_reveal = true;
+        self.started_at = None;
+        self.bbbv = None;
+        self.clicks = Some((0, 0));
+        self.replay_clicks.clear();
+        self.flagged = false;
+        self.chorded = false;
+    }
+
+    /// Marks the lesson being played done once its goal has been met.
+    fn check_lesson(&mut self) {
+        let Some(lesson) = self.lesson.map(|lesson| &LESSONS[lesson]).filter(|_| !self.lesson_done) else {
+            return;
+        };
+        self.lesson_done = match lesson.goal {
+            Goal::Reveal => {
+                let uncovered = lesson.board().cells.iter().filter(|&&(entity, _)| entity.sum().is_some()).count();
+                self.state != SceneState::Lost && self.displayed_entities.iter().filter(|entity| entity.sum().is_some()).count() > uncovered
+            }
+            Goal::Clear => self.state == SceneState::Won,
+            Goal::Flag => self.state != SceneState::Lost && self.all_mines_flagged(),
+            Goal::Chord => self.state == SceneState::Won && self.chorded,
+        };
+    }
+
+    /// Moves any mines off the first revealed cell `idx` to random free cells, and with
+    /// `--opening` off its neighbours too. Falls back to protecting `idx` alone when the
+    /// board is too crowded to clear its neighbourhood.
+    fn protect_first_reveal(&mut self, idx: usize) {
+        let mut protected: Vec<usize> = vec![idx];
+        if self.guaranteed_opening {
+            protected.extend(self.neighbours(idx));
+        }
+
+        let free = |protected: &[usize]| -> Vec<usize> {
+            (0..self.mines.len()).filter(|i| !self.mines[*i] && !protected.contains(i)).collect()
+        };
+        let mut displaced: Vec<usize> = protected.iter().copied().filter(|&i| self.mines[i]).collect();
+        let mut destinations = free(&protected);
+        if destinations.len() < displaced.len() {
+            protected.truncate(1);
+            displaced.retain(|&i| i == idx);
+            destinations = free(&protected);
+        }
+
+        let picked = rand::seq::index::sample(&mut self.rng, destinations.len(), displaced.len().min(destinations.len()));
+        for (from, to) in displaced.into_iter().zip(picked) {
+            self.mines[from] = false;
+            self.mines[destinations[to]] = true;
+            self.anti_mines[destinations[to]] = std::mem::take(&mut self.anti_mines[from]);
+        }
+    }
+
+    /// Name the board's statistics are kept under: its preset, or "custom".
+    fn difficulty_name(&self) -> &'static str {
+        if self.endless.is_some() {
+            return "endless";
+        }
+        if self.lesson.is_some() {
+            return "tutorial";
+        }
+        if self.level.is_some() {
+            return "campaign";
+        }
+        Difficulty::ALL.into_iter()
+            .find(|difficulty| difficulty.board_size() == self.size)
+            .map_or("custom", Difficulty::name)
+    }
+
+    /// Name of the board's preset. Only presets played by the classic rules have best
+    /// times, as custom boards of different sizes can't be ranked together, nor games
+    /// that forgive mistakes, count other cells or can be won otherwise with those that
+    /// don't.
+    fn preset_name(&self) -> Option<&'static str> {
+        let classic = self.max_lives == 1
+            && self.adjacency == Adjacency::King
+            && !self.win_by_flagging
+            && self.time_limit.is_none()
+            && self.peer.is_none()
+            && self.endless.is_none()
+            && self.fog.is_none()
+            && self.chaos.is_none()
+            && !self.guess_protection
+            && self.pattern.is_none()
+            && self.lesson.is_none()
+            && self.level.is_none()
+            && !self.debug_mines;
+        Some(self.difficulty_name()).filter(|&name| name != "custom" && classic)
+    }
+
+    /// Looks up the record to beat on the board's difficulty, and its split times.
+    fn load_best_time(&mut self) {
+        let scores = self.preset_name().and_then(|name| match Scores::load() {
+            Ok(scores) => Some((scores.best(name), scores.best_splits(name))),
+            Err(e) => {
+                eprintln!("Could not load best times: {}", e);
+                None
+            }
+        });
+        (self.best_time, self.best_splits) = scores.unwrap_or_default();
+    }
+
+    /// Takes the split time of each `--splits` percentage of the 3BV the board has now
+    /// been cleared past, and shows the last one taken against the best time's.
+    fn check_splits(&mut self) {
+        let Some(bbbv) = self.bbbv.filter(|_| !self.splits.is_empty() && self.preset_name().is_some()) else {
+            return;
+        };
+        let (_, cleared) = three_bv_cleared(&self.mines, &self.displayed_entities, self.size.columns as usize, self.adjacency);
+        let millis = self.elapsed().as_millis() as u64;
+        let mut taken = None;
+        for &split in &self.splits {
+            if cleared * 100 >= split as u32 * bbbv && !self.split_times.iter().any(|&(percent, _)| percent == split) {
+                self.split_times.push((split, millis));
+                taken = Some(split);
+            }
+        }
+        let Some(split) = taken else {
+            return;
+        };
+        let text = match self.best_splits.iter().find(|&&(percent, _)| percent == split) {
+            Some(&(_, best)) => format!("{}% {} {}", split, format_millis(millis), format_delta(millis, best)),
+            None => format!("{}% {}", split, format_millis(millis)),
+        };
+        self.toast = Some((text, Instant::now() + TOAST_DURATION));
+    }
+
+    /// Looks up the winning streak on the board's difficulty.
+    fn load_streak(&mut self) {
+        if self.daily.is_some() || self.lesson.is_some() || self.level.is_some() || self.autoplay.is_some() || self.hot_seat.is_some() {
+            self.streak = None;
+            return;
+        }
+        self.streak = match Stats::load() {
+            Ok(stats) => Some(stats.difficulties.get(self.difficulty_name()).map_or(0, |stats| stats.streak)),
+            Err(e) => {
+                eprintln!("Could not load statistics: {}", e);
+                None
+            }
+        };
+    }
+
+    /// Ends the streak on the board's difficulty if the game is given up half played, by
+    /// starting another, after which it no longer counts as under way.
+    fn abandon_game(&mut self) {
+        if !self.in_progress() {
+            return;
+        }
+        self.first_reveal = true;
+        if self.daily.is_some() || self.lesson.is_some() || self.autoplay.is_some() || self.hot_seat.is_some() {
+            return;
+        }
+        if let Err(e) = Stats::update(self.difficulty_name(), |stats| stats.streak = 0) {
+            eprintln!("Could not save statistics: {}", e);
+        }
+    }
+
+    /// Time played this game, not counting pauses.
+    fn elapsed(&self) -> Duration {
+        let now = self.paused_at.unwrap_or_else(Instant::now);
+        self.started_at.map_or(Duration::ZERO, |started_at| now.duration_since(started_at))
+    }
+
+    /// Stops the clock and hides the board until `resume`. Only a game in progress can be
+    /// paused, and not one shared with another player; returns whether this one was.
+    fn pause(&mut self) -> bool {
+        if self.state != SceneState::Ready || self.first_reveal || self.peer.is_some() {
+            return false;
+        }
+        self.state = SceneState::Paused;
+        self.paused_at = Some(Instant::now());
+        self.stats_screen = None;
+        true
+    }
+
+    fn resume(&mut self) {
+        if let Some(paused_at) = self.paused_at.take() {
+            self.started_at = self.started_at.map(|started_at| started_at + paused_at.elapsed());
+        }
+        self.state = SceneState::Ready;
+    }
+
+    /// Counts a game as played once its first cell is revealed. Lessons, games the solver
+    /// plays and hot-seat games aren't counted.
+    fn record_game_start(&mut self) {
+        self.started_at = Some(Instant::now());
+        if self.lesson.is_some() || self.autoplay.is_some() || self.hot_seat.is_some() {
+            return;
+        }
+        // An endless board has no end to click through
+        if self.endless.is_none() {
+            let bbbv = three_bv(&self.mines, self.size.columns as usize, self.adjacency);
+            println!("Board 3BV: {}", bbbv);
+            self.bbbv = Some(bbbv);
+        }
+        if let Some(date) = &self.daily {
+            if let Err(e) = DailyLog::record(date, self.difficulty_name(), Outcome::Started, 0) {
+                eprintln!("Could not save the daily challenge: {}", e);
+            }
+            return;
+        }
+        if let Err(e) = Stats::update(self.difficulty_name(), |stats| stats.played += 1) {
+            eprintln!("Could not save statistics: {}", e);
+        }
+    }
+
+    /// Records the win or loss the game just ended with, and the time it took.
+    fn record_game_end(&mut self) {
+        let elapsed = self.elapsed();
+        self.started_at = None;
+        self.finished_in = Some(elapsed.as_millis() as u64);
+        if self.lesson.is_some() || self.autoplay.is_some() || self.hot_seat.is_some() {
+            return;
+        }
+        let seconds = elapsed.as_secs();
+        let won = self.state == SceneState::Won;
+        let hints = self.hints_used;
+        if let (true, Some(bbbv)) = (won, self.bbbv) {
+            match self.clicks.filter(|_| self.peer.is_none()) {
+                Some((clicks, useful_clicks)) => {
+                    let zini = zini(&self.mines, self.size.columns as usize, self.adjacency);
+                    let efficiency = Efficiency { millis: elapsed.as_millis() as u64, bbbv, zini, clicks, useful_clicks };
+                    println!("{}", efficiency);
+                    self.efficiency = Some(efficiency);
+                }
+                // Clicks made before the game was resumed, or by the other player, aren't known
+                None => println!("Cleared 3BV {} in {}: {:.2} 3BV/s", bbbv, format_millis(elapsed.as_millis() as u64), bbbv as f64 / elapsed.as_secs_f64()),
+            }
+        }
+        self.export_replay(elapsed.as_millis() as u64);
+        if let Some(date) = self.daily.take() {
+            let outcome = if won { Outcome::Won } else { Outcome::Lost };
+            if let Err(e) = DailyLog::record(&date, self.difficulty_name(), outcome, elapsed.as_millis() as u64) {
+                eprintln!("Could not save the daily challenge: {}", e);
+            }
+            if won {
+                self.unlock_achievements(elapsed.as_millis() as u64, 0);
+            }
+            return;
+        }
+        if let (true, Some(level)) = (won, self.level) {
+            let stars = LEVELS[level].stars(elapsed.as_millis() as u64);
+            self.stars = Some(stars);
+            // The board was known
+            if !self.debug_mines {
+                if let Err(e) = CampaignProgress::record(level, stars) {
+                    eprintln!("Could not save campaign progress: {}", e);
+                }
+            }
+        }
+        if let (true, 0, false, Some(name)) = (won, hints, self.replaying, self.preset_name()) {
+            let millis = elapsed.as_millis() as u64;
+            match Scores::record(name, millis, &self.split_times) {
+                Ok(new_record) => {
+                    if new_record {
+                        self.best_time = Some(millis);
+                        self.best_splits = self.split_times.clone();
+                    }
+                    self.winning_time = Some((millis, new_record));
+                }
+                Err(e) => eprintln!("Could not save best time: {}", e),
+            }
+        }
+        let mut streak = 0;
+        let result = Stats::update(self.difficulty_name(), |stats| {
+            if won {
+                stats.won += 1;
+                stats.streak += 1;
+                stats.best_streak = stats.best_streak.max(stats.streak);
+            } else {
+                stats.lost += 1;
+                stats.streak = 0;
+            }
+            stats.seconds += seconds;
+            stats.hints += hints;
+            streak = stats.streak;
+        });
+        match result {
+            Ok(()) => self.streak = Some(streak),
+            Err(e) => eprintln!("Could not save statistics: {}", e),
+        }
+        if won {
+            self.unlock_achievements(elapsed.as_millis() as u64, streak);
+        }
+    }
+
+    /// Unlocks the achievements earned by the game just won in `millis`, the `streak`th
+    /// win in a row, and announces any new ones over the board.
+    fn unlock_achievements(&mut self, millis: u64, streak: u32) {
+        if self.replaying || self.debug_mines {
+            return; // The board was known
+        }
+        let win = Win {
+            preset: self.preset_name().filter(|_| self.hints_used == 0),
+            millis,
+            flagged: self.flagged,
+            chorded: self.chorded,
+            streak,
+            daily_run: DailyLog::load().map_or(0, |log| log.longest_winning_run()),
+        };
+        match Achievements::unlock(&win.earned()) {
+            Ok(new) if !new.is_empty() => {
+                let titles: Vec<&str> = new.iter().map(|achievement| achievement.title()).collect();
+                println!("Achievement unlocked: {}", titles.join(", "));
+                self.toast = Some((format!("Unlocked: {}", titles.join(", ")), Instant::now() + TOAST_DURATION));
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("Could not save achievements: {}", e),
+        }
+    }
+
+    /// Outlines a covered cell the numbers prove safe, or failing that one proven to hold
+    /// a mine that isn't flagged yet. Rings the bell when there is none.
+    fn show_hint(&mut self, conn: &mut X11Connection) -> Result<(), std::io::Error> {
+        if self.state != SceneState::Ready {
+            return Ok(());
+        }
+        if self.endless.is_some() {
+            // The numbers in view may count mines out of it, which the solver can't see
+            x11_bell(conn, self.bell_volume.0);
+            return Ok(());
+        }
+        let deductions = solver::deduce(&self.displayed_entities, self.size.columns as usize, self.adjacency, self.size.anti_mines > 0);
+        let hint = deductions.iter().copied().find(|deduction| matches!(deduction, Deduction::Safe(_)))
+            .or_else(|| deductions.iter().copied().find(|&deduction| self.displayed_entities[deduction.cell()] != EntityKind::Flagged));
+        let Some(hint) = hint else {
+            x11_bell(conn, self.bell_volume.0);
+            return Ok(());
+        };
+
+        self.hint = Some(hint);
+        self.hints_used += 1;
+        self.render(conn)
+    }
+
+    /// Shows the statistics for the board's difficulty over the board, or hides them.
+    fn toggle_stats_screen(&mut self, conn: &mut X11Connection) -> Result<(), std::io::Error> {
+        self.stats_screen = match self.stats_screen {
+            Some(_) => None,
+            None => {
+                let name = self.difficulty_name();
+                let stats = Stats::load()?.difficulties.get(name).copied().unwrap_or_default();
+                let mut lines = vec![format!("Statistics: {}", name)];
+                lines.extend(stats.summary());
+                if let Some(best) = self.best_time {
+                    lines.push(format!("Best time {}", format_millis(best)));
+                }
+                match (self.bbbv, self.winning_time) {
+                    (Some(bbbv), Some((millis, _))) => lines.push(format!("Board 3BV {} ({:.2}/s)", bbbv, bbbv as f64 * 1000.0 / millis as f64)),
+                    (Some(bbbv), None) => lines.push(format!("Board 3BV {}", bbbv)),
+                    (None, _) => {}
+                }
+                if let Some(efficiency) = &self.efficiency {
+                    lines.extend(efficiency.summary());
+                }
+                lines.push("A for achievements".to_string());
+                lines.push("T or click to close".to_string());
+                Some(lines)
+            }
+        };
+        self.render(conn)
+    }
+
+    /// Shows the achievements unlocked so far over the board, or hides them.
+    fn toggle_achievements_screen(&mut self, conn: &mut X11Connection) -> Result<(), std::io::Error> {
+        let mut lines = Achievements::load()?.summary();
+        lines.push("A or click to close".to_string());
+        // From the statistics screen, A switches over
+        self.stats_screen = match &self.stats_screen {
+            Some(shown) if *shown == lines => None,
+            _ => Some(lines),
+        };
+        self.render(conn)
+    }
+
+    /// Covers the board with lines of text, for the statistics and pause screens.
+    fn draw_text_screen(&self, conn: &mut X11Connection, lines: &[impl AsRef<str>]) {
+        let (drawable_id, (left, top)) = self.draw_target();
+        x11_change_gc(conn, self.resources.gc_id, &GcValues::new().foreground(self.pixel(STATUS_BAR_RGBA)));
+        x11_poly_fill_rectangle(conn, drawable_id, self.resources.gc_id, &[(left, top, self.size.width(), self.size.height())]);
+        for (i, line) in lines.iter().enumerate() {
+            let y = top + STATUS_TEXT_BASELINE + i as u16 * STATS_LINE_HEIGHT;
+            self.draw_text(conn, left + STATUS_TEXT_MARGIN, y, line.as_ref());
+        }
+    }
+
+    /// Switches to the next difficulty preset: starts a new game on a board of its size and
+    /// resizes the window (and back buffer) to fit.
+    fn cycle_difficulty(&mut self, conn: &mut X11Connection) -> Result<(), std::io::Error> {
+        if self.is_guest() {
+            eprintln!("Only the host can change the difficulty");
+            return Ok(());
+        }
+        self.difficulty = self.difficulty.next();
+        self.size = self.difficulty.board_size();
+        self.reset();
+        self.fit_window_to_board(conn)
+    }
+
+    /// Starts a new game as `reset` does, resizing the window if the board it deals is of
+    /// another size, as the next campaign level can be.
+    fn new_game(&mut self, conn: &mut X11Connection) -> Result<(), std::io::Error> {
+        let size = (self.size.rows, self.size.columns);
+        self.reset();
+        if (self.size.rows, self.size.columns) != size {
+            return self.fit_window_to_board(conn);
+        }
+        self.render(conn)
+    }
+
+    /// Resizes the window (and back buffer) to the board, after its size changed.
+    fn fit_window_to_board(&mut self, conn: &mut X11Connection) -> Result<(), std::io::Error> {
+        self.hovered = None;
+        self.focused = None;
+        self.pressed.clear();
+
+        let (width, height) = (self.size.width(), self.size.height() + status_bar_height());
+        // Wait for the last frame to be copied out before replacing its pixmap
+        self.begin_frame(conn)?;
+        self.release_scaled_buffer(conn)?;
+        conn.release(Resource::Pixmap(self.resources.back_buffer_id))?;
+        self.resources.back_buffer_id = conn.generate_id()?;
+        x11_create_pixmap(conn, self.resources.window_id, self.resources.back_buffer_id, width, height, self.resources.pixel_format.depth);
+        conn.track(Resource::Pixmap(self.resources.back_buffer_id));
+        self.drawn.clear();
+        x11_resize_window(conn, self.resources.window_id, width, height);
+        self.render(conn)
+    }
+
+    /// Refuses to save an endless board, of which only the part in view would be.
+    fn check_savable(&self) -> Result<(), std::io::Error> {
+        match self.endless {
+            Some(_) => Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "An endless board can't be saved")),
+            None => Ok(()),
+        }
+    }
+
+    /// The board as it would be saved to disk.
+    fn saved_board(&self) -> SavedBoard {
+        SavedBoard {
+            rows: self.size.rows,
+            columns: self.size.columns,
+            state: self.state,
+            elapsed_millis: self.elapsed().as_millis() as u64,
+            adjacency: self.adjacency,
+            cells: self.displayed_entities.iter().copied().zip(self.mines.iter().copied()).collect(),
+            anti_mines: (0..self.anti_mines.len()).filter(|&idx| self.anti_mines[idx]).collect(),
+        }
+    }
+
+    /// Continues the game in `board`, which may be of another size than the window was
+    /// created for.
+    pub(crate) fn restore(&mut self, board: &SavedBoard) {
+        self.size = board.board_size();
+        self.adjacency = board.adjacency;
+        self.window_size = (self.size.width(), self.size.height() + status_bar_height());
+        (self.displayed_entities, self.mines) = board.cells.iter().copied().unzip();
+        self.anti_mines = vec![false; self.mines.len()];
+        for &idx in &board.anti_mines {
+            self.anti_mines[idx] = true;
+        }
+        // The board keeps numbers and mines without their sign
+        for idx in 0..self.displayed_entities.len() {
+            self.displayed_entities[idx] = match self.displayed_entities[idx] {
+                entity if entity.sum().is_some() => self.counted_entity(idx),
+                EntityKind::MineIdle if self.anti_mines[idx] => EntityKind::AntiMineIdle,
+                EntityKind::MineExploded if self.anti_mines[idx] => EntityKind::AntiMineExploded,
+                entity => entity,
+            };
+        }
+        self.state = board.state;
+        self.first_reveal = board.cells.iter().all(|&(entity, _)| matches!(entity, EntityKind::Covered | EntityKind::Flagged));
+        self.keep_mines = true;
+        self.replaying = false;
+        self.flagged = !self.first_reveal;
+        self.chorded = !self.first_reveal;
+        // Carry on the clock from where the game was saved
+        self.started_at = match self.in_progress() {
+            true => Instant::now().checked_sub(Duration::from_millis(board.elapsed_millis)),
+            false => None,
+        };
+        self.paused_at = None;
+        let exploded = self.displayed_entities.iter().filter(|entity| entity.exploded()).count();
+        self.lives = self.max_lives.saturating_sub(exploded as u8).max(1);
+        self.bbbv = (!self.first_reveal).then(|| three_bv(&self.mines, self.size.columns as usize, self.adjacency));
+        self.clicks = self.first_reveal.then_some((0, 0));
+        self.efficiency = None;
+        self.replay_clicks.clear();
+        self.finished_in = matches!(board.state, SceneState::Won | SceneState::Lost).then_some(board.elapsed_millis);
+        // The splits a saved game had passed are taken at the next reveal
+        self.split_times.clear();
+        self.hint = None;
+        self.hints_used = 0;
+        self.load_best_time();
+        self.load_streak();
+        // Don't ring again for a game that was already over
+        self.announced_state = board.state;
+    }
+
+    /// A cell has been revealed and the game isn't over yet.
+    fn in_progress(&self) -> bool {
+        matches!(self.state, SceneState::Ready | SceneState::Paused) && !self.first_reveal
+    }
+
+    /// Writes the board to the file `--resume` continues from, returning its path.
+    fn save_game(&self) -> Result<PathBuf, std::io::Error> {
+        self.check_savable()?;
+        let path = resume_path().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "No state directory (HOME unset)"))?;
+        self.saved_board().save(&path)?;
+        Ok(path)
+    }
+
+    /// Writes the board to the `--export-board` path, or else to a new file in the current
+    /// directory, returning where.
+    fn export_board(&self) -> Result<PathBuf, std::io::Error> {
+        self.check_savable()?;
+        let path = self.export_path.clone().unwrap_or_else(|| {
+            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+            PathBuf::from(format!("mineswept-{}.board", timestamp))
+        });
+        self.saved_board().save(&path)?;
+        Ok(path)
+    }
+
+    /// Writes the board to the `--export-board` path, if given, when quitting.
+    pub(crate) fn export_board_on_exit(&self) {
+        if self.export_path.is_none() {
+            return;
+        }
+        match self.export_board() {
+            Ok(path) => println!("Exported the board to {}", path.display()),
+            Err(e) => eprintln!("Could not export the board: {}", e),
+        }
+    }
+
+    /// Writes the game just over to the `--export-replays` directory, if given. Only games
+    /// played from the start by the classic rules can be, with the mines where they were.
+    fn export_replay(&self, millis: u64) {
+        let Some(dir) = &self.replay_dir else {
+            return;
+        };
+        let classic = self.adjacency == Adjacency::King && !self.anti_mines.contains(&true) && self.chaos.is_none() && !self.guess_protection;
+        if !classic || self.clicks.is_none() || self.peer.is_some() {
+            return;
+        }
+        let replay = Replay {
+            level: self.difficulty_name(),
+            columns: self.size.columns as usize,
+            mines: self.mines.clone(),
+            bbbv: self.bbbv,
+            millis,
+            won: self.state == SceneState::Won,
+            hints: self.hints_used,
+            clicks: self.replay_clicks.clone(),
+        };
+        match replay.save(dir) {
+            Ok(path) => println!("Exported the replay to {}", path.display()),
+            Err(e) => eprintln!("Could not export the replay: {}", e),
+        }
+    }
+
+    /// Saves the game for `--resume` when quitting in the middle of it.
+    pub(crate) fn save_unfinished_game(&self) {
+        if !self.in_progress() || self.endless.is_some() || self.lesson.is_some() || self.level.is_some() || self.autoplay.is_some() || self.hot_seat.is_some() {
+            return;
+        }
+        match self.save_game() {
+            Ok(path) => println!("Saved the unfinished game to {}; continue it with --resume", path.display()),
+            Err(e) => eprintln!("Could not save the unfinished game: {}", e),
+        }
+    }
+
+    pub fn render(&mut self, conn: &mut X11Connection) -> Result<(), std::io::Error> {
+        if !self.visible {
+            return Ok(());
+        }
+        let asset_coordinates = get_asset_coordinates();
+        self.update_fog();
+        self.check_lesson();
+
+        self.begin_frame(conn)?;
+        match &self.stats_screen {
+            Some(lines) => self.draw_text_screen(conn, lines),
+            // Hidden so pausing can't buy time to study the board
+            None if self.state == SceneState::Paused => self.draw_text_screen(conn, &["Paused", "Press any key"]),
+            None => self.draw_changed_cells(conn, &asset_coordinates),
+        }
+        if self.stats_screen.is_some() || self.state == SceneState::Paused {
+            // The screen covers every cell; they are drawn again once it goes
+            self.drawn.clear();
+        }
+        self.draw_status_bar(conn);
+        self.update_cursor(conn);
+        self.announce_game_end(conn);
+        self.finish_frame(conn)
+    }
+
+    /// Drawable the frame is drawn into, and where the board's top-left corner is in it.
+    fn draw_target(&self) -> (u32, (u16, u16)) {
+        (self.resources.back_buffer_id, (0, 0))
+    }
+
+    /// Waits until the previously presented frame has been copied to the window, so the
+    /// back buffer can be drawn into again.
+    fn begin_frame(&mut self, conn: &mut X11Connection) -> Result<(), std::io::Error> {
+        if let Some(major_opcode) = self.resources.present_opcode.filter(|_| self.frame_pending) {
+            let serial = self.frame_serial;
+            conn.wait_for_event(|packet| present::is_complete_notify(packet, major_opcode, serial))?;
+            self.frame_pending = false;
+        }
+        Ok(())
+    }
+
+    /// Copies the frame to the window in one go, at the next vblank when Present is in
+    /// use, then flushes.
+    fn finish_frame(&mut self, conn: &mut X11Connection) -> Result<(), std::io::Error> {
+        let frame_id = self.scale_frame(conn)?;
+        let (left, top) = self.board_origin();
+        match self.resources.present_opcode {
+            Some(major_opcode) => {
+                self.frame_serial = self.frame_serial.wrapping_add(1);
+                present::present_pixmap(conn, major_opcode, frame_id, self.resources.window_id, self.frame_serial, left, top)?;
+                self.frame_pending = true;
+            }
+            None => {
+                let (width, height) = self.view_size();
+                x11_copy_area(conn, frame_id, self.resources.window_id, self.resources.gc_id, 0, 0, left, top, width, height);
+            }
+        }
+        conn.flush()
+    }
+
+    /// Scales the frame up to fill the window when it is bigger than the board, returning
+    /// the pixmap to copy to the window: the scaled buffer, or else the back buffer itself.
+    fn scale_frame(&mut self, conn: &mut X11Connection) -> Result<u32, std::io::Error> {
+        let factor = self.view_scale();
+        let Some(render) = self.resources.render.filter(|_| factor > 1.0) else {
+            self.release_scaled_buffer(conn)?;
+            return Ok(self.resources.back_buffer_id);
+        };
+        let (width, height) = self.view_size();
+        let buffer = match self.scaled_buffer {
+            Some(buffer) if buffer.factor == factor => buffer,
+            _ => {
+                self.release_scaled_buffer(conn)?;
+                let buffer = ScaledBuffer { factor, source_id: conn.generate_id()?, pixmap_id: conn.generate_id()?, picture_id: conn.generate_id()? };
+                xrender::create_picture(conn, render, buffer.source_id, self.resources.back_buffer_id)?;
+                xrender::set_scale(conn, render, buffer.source_id, factor)?;
+                x11_create_pixmap(conn, self.resources.window_id, buffer.pixmap_id, width, height, self.resources.pixel_format.depth);
+                xrender::create_picture(conn, render, buffer.picture_id, buffer.pixmap_id)?;
+                conn.track(Resource::Picture { major_opcode: render.major_opcode, id: buffer.source_id });
+                conn.track(Resource::Pixmap(buffer.pixmap_id));
+                conn.track(Resource::Picture { major_opcode: render.major_opcode, id: buffer.picture_id });
+                self.scaled_buffer = Some(buffer);
+                buffer
+            }
+        };
+        xrender::composite(conn, render, buffer.source_id, buffer.picture_id, width, height)?;
+        Ok(buffer.pixmap_id)
+    }
+
+    /// Frees the scaled buffer, e.g. once the window or the back buffer changes size.
+    fn release_scaled_buffer(&mut self, conn: &mut X11Connection) -> Result<(), std::io::Error> {
+        let (Some(buffer), Some(render)) = (self.scaled_buffer.take(), self.resources.render) else {
+            return Ok(());
+        };
+        conn.release(Resource::Picture { major_opcode: render.major_opcode, id: buffer.picture_id })?;
+        conn.release(Resource::Pixmap(buffer.pixmap_id))?;
+        conn.release(Resource::Picture { major_opcode: render.major_opcode, id: buffer.source_id })
+    }
+
+    fn pixel(&self, rgba: [u8; 4]) -> u32 {
+        rgba_to_pixel(rgba, &self.resources.pixel_format)
+    }
+
+    /// Draws `text` with its baseline at `(x, y)` of the frame in the status text colors.
+    pub(crate) fn draw_text(&self, conn: &mut X11Connection, x: u16, y: u16, text: &str) {
+        let (drawable_id, _) = self.draw_target();
+        x11_change_gc(conn, self.resources.gc_id, &GcValues::new().foreground(self.pixel(TEXT_RGBA)).background(self.pixel(STATUS_BAR_RGBA)));
+        x11_image_text8(conn, drawable_id, self.resources.gc_id, x, y, text);
+    }
+
+    /// Draws the instructions of the lesson being played over the top rows of the board.
+    fn draw_lesson_text(&self, conn: &mut X11Connection) {
+        let Some(lesson) = self.lesson else {
+            return;
+        };
+        let (drawable_id, (left, top)) = self.draw_target();
+        x11_change_gc(conn, self.resources.gc_id, &GcValues::new().foreground(self.pixel(STATUS_BAR_RGBA)));
+        x11_poly_fill_rectangle(conn, drawable_id, self.resources.gc_id, &[(left, top, self.size.width(), tutorial::TEXT_ROWS * entities_height())]);
+        for (i, line) in LESSONS[lesson].text.iter().enumerate() {
+            self.draw_text(conn, left + STATUS_TEXT_MARGIN, top + STATUS_TEXT_BASELINE + i as u16 * STATS_LINE_HEIGHT, line);
+        }
+    }
+
+    /// Draws the announcement of newly unlocked achievements across the top of the board.
+    fn draw_toast(&self, conn: &mut X11Connection) {
+        let Some((text, _)) = &self.toast else {
+            return;
+        };
+        let (drawable_id, (left, top)) = self.draw_target();
+        x11_change_gc(conn, self.resources.gc_id, &GcValues::new().foreground(self.pixel(STATUS_BAR_RGBA)));
+        x11_poly_fill_rectangle(conn, drawable_id, self.resources.gc_id, &[(left, top, self.size.width(), status_bar_height())]);
+        self.draw_text(conn, left + STATUS_TEXT_MARGIN, top + STATUS_TEXT_BASELINE, text);
+    }
+
+    fn draw_status_bar(&self, conn: &mut X11Connection) {
+        let (drawable_id, (left, board_top)) = self.draw_target();
+        let top = board_top + self.size.height();
+        x11_change_gc(conn, self.resources.gc_id, &GcValues::new().foreground(self.pixel(STATUS_BAR_RGBA)));
+        x11_poly_fill_rectangle(conn, drawable_id, self.resources.gc_id, &[(left, top, self.size.width(), status_bar_height())]);
+        if let Some(opponent) = self.opponent {
+            let safe_cells = self.size.cell_count() - self.size.mines as usize;
+            let width = (self.size.width() as usize * opponent.revealed / safe_cells.max(1)).min(self.size.width() as usize);
+            x11_change_gc(conn, self.resources.gc_id, &GcValues::new().foreground(self.pixel(OPPONENT_PROGRESS_RGBA)));
+            x11_poly_fill_rectangle(conn, drawable_id, self.resources.gc_id, &[(left, top, width as u16, OPPONENT_PROGRESS_HEIGHT)]);
+        }
+
+        let message = match self.state {
+            SceneState::Won if self.autoplay.is_some() => "Solver won".to_string(),
+            SceneState::Lost if self.autoplay.is_some() => "Solver lost".to_string(),
+            _ if self.autoplay.is_some() => "Demo: press any key".to_string(),
+            _ if self.lesson_done && self.lesson == Some(LESSONS.len() - 1) => "Tutorial complete!".to_string(),
+            _ if self.lesson_done => "Well done! Press Space".to_string(),
+            SceneState::Won if self.lesson.is_some() => "Not quite. Press R to retry".to_string(),
+            SceneState::Won if self.level == Some(LEVELS.len() - 1) => format!("Campaign done! {}", "*".repeat(self.stars.unwrap_or(1) as usize)),
+            SceneState::Won if self.level.is_some() => format!("{} Space: next level", "*".repeat(self.stars.unwrap_or(1) as usize)),
+            SceneState::Won | SceneState::Lost if self.hot_seat.is_some() => {
+                format!("{}. Press R", self.hot_seat.map(|hot_seat| hot_seat.result()).unwrap_or_default())
+            }
+            // Whoever is still playing once the race is decided may finish the board
+            SceneState::Ready | SceneState::Won if self.race_won == Some(true) => "You won the race".to_string(),
+            SceneState::Ready | SceneState::Won if self.race_won == Some(false) => match self.opponent {
+                Some(opponent) if opponent.state == SceneState::Won => format!("Opponent won {}", format_millis(opponent.millis)),
+                _ => "Opponent won".to_string(),
+            },
+            SceneState::Won => match self.winning_time {
+                Some((millis, true)) => format!("New record! {}", format_millis(millis)),
+                Some((millis, false)) => format!("You win! {}", format_millis(millis)),
+                None => "You win!".to_string(),
+            },
+            SceneState::Lost if self.timed_out => "Time's up! Press R".to_string(),
+            SceneState::Lost if self.endless.is_some() => format!("Boom! Cleared {}. Press R", self.cleared_cells()),
+            SceneState::Lost => "Boom! Press R".to_string(),
+            SceneState::Paused => format!("Paused at {}", format_millis(self.elapsed().as_millis() as u64)),
+            _ => {
+                let mut message = match (&self.endless, self.level, self.hot_seat) {
+                    (Some(_), _, _) => format!("Cleared {}", self.cleared_cells()),
+                    (None, Some(level), _) => format!("Level {}  Mines: {}", level + 1, self.count_unflagged_mines()),
+                    // Whose turn it is matters more than the mines left
+                    (None, None, Some(hot_seat)) => hot_seat.summary(),
+                    (None, None, None) => format!("Mines: {}", self.count_unflagged_mines()),
+                };
+                if self.max_lives > 1 && self.hot_seat.is_none() {
+                    message += &format!("  Lives {}", self.lives);
+                }
+                if let Some(flags) = self.flags_left() {
+                    message += &format!("  Flags {}", flags);
+                }
+                if let Some(left) = self.time_left() {
+                    // Counted up to the next second, so 0 only shows once time is up
+                    message += &format!("  Time {}", left.as_millis().div_ceil(1000));
+                }
+                match (self.hint, self.best_time) {
+                    (Some(Deduction::Safe(_)), _) => message += "  Hint: safe",
+                    (Some(Deduction::Mine(_)), _) => message += "  Hint: mine",
+                    (None, Some(best)) => message += &format!("  Best {}", format_millis(best)),
+                    (None, None) => {}
+                }
+                if let Some(streak) = self.streak.filter(|&streak| streak > 0) {
+                    message += &format!("  Streak {}", streak);
+                }
+                message
+            }
+        };
+        self.draw_text(conn, left + STATUS_TEXT_MARGIN, top + STATUS_TEXT_BASELINE, &message);
+        self.draw_flag_mode_button(conn);
+    }
+
+    /// Offset of the flag mode button from the board origin, at the right of the status bar.
+    fn flag_mode_button_offset(&self) -> (u16, u16) {
+        (self.size.width() - entities_width() - FLAG_MODE_BUTTON_INSET, self.size.height() + FLAG_MODE_BUTTON_INSET)
+    }
+
+    /// Draws the flag mode button as a flagged cell, outlined while the mode is on.
+    fn draw_flag_mode_button(&self, conn: &mut X11Connection) {
+        let (drawable_id, (left, top)) = self.draw_target();
+        let (dx, dy) = self.flag_mode_button_offset();
+        let flag = get_asset_coordinates()[&EntityKind::Flagged];
+        x11_copy_area(
+            conn,
+            self.resources.sprite_pixmap_id,
+            drawable_id,
+            self.resources.gc_id,
+            flag.x,
+            flag.y,
+            left + dx,
+            top + dy,
+            entities_width(),
+            entities_height(),
+        );
+        if self.flag_mode {
+            x11_change_gc(conn, self.resources.gc_id, &GcValues::new().foreground(self.pixel(HOVER_OUTLINE_RGBA)));
+            x11_poly_rectangle(conn, drawable_id, self.resources.gc_id, &[
+                (left + dx, top + dy, entities_width() - 1, entities_height() - 1),
+                (left + dx + 1, top + dy + 1, entities_width() - 3, entities_height() - 3),
+            ]);
+        }
+    }
+
+    /// Whether the window coordinates `(x, y)` fall on the flag mode button.
+    fn is_on_flag_mode_button(&self, x: u16, y: u16) -> bool {
+        let (dx, dy) = self.flag_mode_button_offset();
+        let Some((x, y)) = self.board_point(x, y) else {
+            return false;
+        };
+        (dx..dx + entities_width()).contains(&x) && (dy..dy + entities_height()).contains(&y)
+    }
+
+    fn toggle_flag_mode(&mut self, conn: &mut X11Connection) -> Result<(), std::io::Error> {
+        self.flag_mode = !self.flag_mode;
+        self.render(conn)
+    }
+
+    /// What a click with `button` does: flag mode swaps revealing (1) and flagging (3).
+    fn flag_mode_button(&self, button: u8) -> u8 {
+        match (self.flag_mode, button) {
+            (true, 1) => 3,
+            (true, 3) => 1,
+            _ => button,
+        }
+    }
+
+    /// How many times bigger the board and status bar are shown, to fill as much of a
+    /// window bigger than them as they can without stretching; 1 without RENDER.
+    fn view_scale(&self) -> f64 {
+        if self.resources.render.is_none() {
+            return 1.0;
+        }
+        let (width, height) = self.window_size;
+        let horizontal = width as f64 / self.size.width() as f64;
+        let vertical = height as f64 / (self.size.height() + status_bar_height()) as f64;
+        horizontal.min(vertical).max(1.0)
+    }
+
+    /// Size of the board and status bar as shown in the window.
+    fn view_size(&self) -> (u16, u16) {
+        let factor = self.view_scale();
+        let height = self.size.height() + status_bar_height();
+        ((self.size.width() as f64 * factor) as u16, (height as f64 * factor) as u16)
+    }
+
+    /// Top-left corner of the board, which is centered when the window manager gave us
+    /// more room than requested.
+    fn board_origin(&self) -> (u16, u16) {
+        let (width, height) = self.window_size;
+        let (view_width, view_height) = self.view_size();
+        (width.saturating_sub(view_width) / 2, height.saturating_sub(view_height) / 2)
+    }
+
+    /// Where the window coordinates `(x, y)` fall on the board as drawn in the back
+    /// buffer, undoing its centering and scaling.
+    fn board_point(&self, x: u16, y: u16) -> Option<(u16, u16)> {
+        let (left, top) = self.board_origin();
+        let factor = self.view_scale();
+        Some(((x.checked_sub(left)? as f64 / factor) as u16, (y.checked_sub(top)? as f64 / factor) as u16))
+    }
+
+    /// Reads the board and status bar back from the back buffer and writes them to a PNG
+    /// in the current directory, returning its path.
+    fn save_screenshot(&self, conn: &mut X11Connection) -> Result<PathBuf, std::io::Error> {
+        let (width, height) = (self.size.width(), self.size.height() + status_bar_height());
+        let data = x11_get_image(conn, self.resources.back_buffer_id, 0, 0, width, height)?;
+        let rgba = zpixmap_to_rgba(&data, width as usize, height as usize, &self.resources.pixel_format);
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+        let path = PathBuf::from(format!("mineswept-{}.png", timestamp));
+        let mut encoder = png::Encoder::new(BufWriter::new(File::create(&path)?), width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&rgba))
+            .map_err(std::io::Error::other)?;
+
+        Ok(path)
+    }
+
+    /// Safe cells uncovered on an endless board, the game's score.
+    fn cleared_cells(&self) -> usize {
+        self.endless.as_ref().map_or(0, World::cleared)
+    }
+
+    /// Flags that can still be placed with `--flag-limit`.
+    fn flags_left(&self) -> Option<usize> {
+        let placed = self.displayed_entities.iter().filter(|&&entity| entity == EntityKind::Flagged).count();
+        Some(self.flag_limit?.flags(self.size).saturating_sub(placed))
+    }
+
+    /// Mines left to find, assuming every flag is correct. Can go negative. Mines that
+    /// went off count as found.
+    fn count_unflagged_mines(&self) -> isize {
+        let mines = self.mines.iter().filter(|&&mine| mine).count() as isize;
+        let found = self.displayed_entities.iter()
+            .filter(|&&entity| entity == EntityKind::Flagged || entity.exploded())
+            .count() as isize;
+        mines - found
+    }
+
+    fn update_cursor(&mut self, conn: &mut X11Connection) {
+        let cursor = match self.state {
+            SceneState::Won | SceneState::Lost => self.resources.cursors.game_over,
+            _ => self.resources.cursors.playing,
+        };
+        if self.applied_cursor != Some(cursor) {
+            x11_set_window_cursor(conn, self.resources.window_id, cursor);
+            self.applied_cursor = Some(cursor);
+        }
+    }
+
+    /// Hides the pointer over the window, e.g. while the board plays itself, or shows it
+    /// again. Does nothing without XFixes.
+    pub(crate) fn set_pointer_hidden(&mut self, conn: &mut X11Connection, hidden: bool) -> Result<(), std::io::Error> {
+        let Some(major_opcode) = self.resources.xfixes_opcode else {
+            return Ok(());
+        };
+        if hidden == self.pointer_hidden {
+            return Ok(());
+        }
+        self.pointer_hidden = hidden;
+        if hidden {
+            xfixes::hide_cursor(conn, major_opcode, self.resources.window_id)
+        } else {
+            xfixes::show_cursor(conn, major_opcode, self.resources.window_id)
+        }
+    }
+
+    /// Rings the bell when the game has just been won or lost.
+    fn announce_game_end(&mut self, conn: &mut X11Connection) {
+        if self.state == self.announced_state {
+            return;
+        }
+        self.announced_state = self.state;
+        if matches!(self.state, SceneState::Won | SceneState::Lost) {
+            x11_bell(conn, self.bell_volume.0);
+        }
+    }
+
+    /// Redraws the cells that changed, e.g. the old and new hovered cell, and the status
+    /// bar if `status_bar` is set, without the checks `render` makes after every move.
+    fn render_changes(&mut self, conn: &mut X11Connection, status_bar: bool) -> Result<(), std::io::Error> {
+        if !self.visible {
+            return Ok(());
+        }
+        if self.stats_screen.is_some() || self.state == SceneState::Paused {
+            // Cells are hidden under the statistics or pause screen; redraw that instead
+            return self.render(conn);
+        }
+        let asset_coordinates = get_asset_coordinates();
+
+        self.begin_frame(conn)?;
+        self.draw_changed_cells(conn, &asset_coordinates);
+        if status_bar {
+            self.draw_status_bar(conn);
+        }
+        self.finish_frame(conn)
+    }
+
+    /// Redraws the whole board once a batch of Expose events is complete, recentering it
+    /// if the window manager resized the window meanwhile.
+    fn repaint_exposed(&mut self, conn: &mut X11Connection) -> Result<(), std::io::Error> {
+        let geometry = x11_get_geometry(conn, self.resources.window_id)?;
+        self.window_size = (geometry.width, geometry.height);
+        self.drawn.clear();
+        self.render(conn)
+    }
+
+    /// How cell `i` is to be drawn this frame.
+    fn cell_look(&self, i: usize) -> CellLook {
+        let entity = self.displayed_entities[i];
+        let ready = self.state == SceneState::Ready;
+        CellLook {
+            entity,
+            fogged: self.fogged.get(i) == Some(&true),
+            pressed: self.pressed.contains(&i),
+            marker: (self.debug_mines && self.mines[i] && matches!(entity, EntityKind::Covered | EntityKind::Flagged)).then_some(self.anti_mines[i]),
+            hovered: ready && self.hovered == Some(i),
+            focused: ready && self.focused == Some(i),
+            hint: self.hint.filter(|hint| ready && hint.cell() == i),
+        }
+    }
+
+    /// Draws the cells that look different from how the back buffer last had them, then
+    /// the lesson's text or a toast over them.
+    fn draw_changed_cells(&mut self, conn: &mut X11Connection, asset_coordinates: &HashMap<EntityKind, Position>) {
+        let cell_count = self.displayed_entities.len();
+        if self.drawn.len() != cell_count {
+            self.drawn = vec![None; cell_count];
+        }
+        let overlay_height = match (self.lesson, &self.toast) {
+            (Some(_), _) => tutorial::TEXT_ROWS * entities_height(),
+            (None, Some(_)) => status_bar_height(),
+            (None, None) => 0,
+        };
+        if overlay_height < self.overlay_height {
+            let covered_rows = self.overlay_height.div_ceil(entities_height()) as usize;
+            self.drawn.iter_mut().take(covered_rows * self.size.columns as usize).for_each(|look| *look = None);
+        }
+        self.overlay_height = overlay_height;
+
+        for i in 0..cell_count {
+            let look = self.cell_look(i);
+            if self.drawn[i] != Some(look) {
+                self.draw_cell(conn, asset_coordinates, i);
+                self.drawn[i] = Some(look);
+            }
+        }
+        self.draw_lesson_text(conn);
+        self.draw_toast(conn);
+    }
+
+    fn draw_cell(&self, conn: &mut X11Connection, asset_coordinates: &HashMap<EntityKind, Position>, i: usize) {
+        let (row, column) = self.idx_to_row_column(i as u16);
+        let (drawable_id, (left, top)) = self.draw_target();
+        let x = left + column * entities_width();
+        let y = top + row * entities_height();
+
+        if self.fogged.get(i) == Some(&true) {
+            x11_change_gc(conn, self.resources.gc_id, &GcValues::new().foreground(self.pixel(FOG_RGBA)));
+            x11_poly_fill_rectangle(conn, drawable_id, self.resources.gc_id, &[(x, y, entities_width(), entities_height())]);
+        } else if self.pressed.contains(&i) {
+            x11_change_gc(conn, self.resources.gc_id, &GcValues::new().foreground(self.pixel(PRESSED_CELL_RGBA)));
+            x11_poly_fill_rectangle(conn, drawable_id, self.resources.gc_id, &[(x, y, entities_width(), entities_height())]);
+        } else if let Some(&pos) = asset_coordinates.get(&self.displayed_entities[i]) {
+            x11_copy_area(
+                conn,
+                self.resources.sprite_pixmap_id,
+                drawable_id,
+                self.resources.gc_id,
+                pos.x,
+                pos.y,
+                x,
+                y,
+                entities_width(),
+                entities_height(),
+            );
+        }
+
+        let entity = self.displayed_entities[i];
+        if self.debug_mines && self.mines[i] && matches!(entity, EntityKind::Covered | EntityKind::Flagged) {
+            let rgba = if self.anti_mines[i] { DEBUG_ANTI_MINE_RGBA } else { DEBUG_MINE_RGBA };
+            let size = DEBUG_MARKER_SIZE * config::scale();
+            let marker = (x + entities_width() - size - 2, y + 2, size, size);
+            x11_change_gc(conn, self.resources.gc_id, &GcValues::new().foreground(self.pixel(rgba)));
+            x11_poly_fill_rectangle(conn, drawable_id, self.resources.gc_id, &[marker]);
+        }
+
+        if self.adjacency.wraps() {
+            let edges = [
+                (row == 0, (x, y, entities_width(), 1)),
+                (row == self.size.rows - 1, (x, y + entities_height() - 1, entities_width(), 1)),
+                (column == 0, (x, y, 1, entities_height())),
+                (column == self.size.columns - 1, (x + entities_width() - 1, y, 1, entities_height())),
+            ];
+            let lines: Vec<_> = edges.into_iter().filter(|&(on_edge, _)| on_edge).map(|(_, line)| line).collect();
+            if !lines.is_empty() {
+                x11_change_gc(conn, self.resources.gc_id, &GcValues::new().foreground(self.pixel(WRAP_EDGE_RGBA)));
+                x11_poly_fill_rectangle(conn, drawable_id, self.resources.gc_id, &lines);
+            }
+        }
+
+        if self.state == SceneState::Ready && self.hovered == Some(i) {
+            x11_change_gc(conn, self.resources.gc_id, &GcValues::new().foreground(self.pixel(HOVER_OUTLINE_RGBA)));
+            x11_poly_rectangle(conn, drawable_id, self.resources.gc_id, &[(x, y, entities_width() - 1, entities_height() - 1)]);
+        }
+
+        if self.state == SceneState::Ready && self.focused == Some(i) {
+            self.draw_focus_ring(conn, drawable_id, x, y);
+        }
+
+        match self.hint {
+            Some(hint) if self.state == SceneState::Ready && hint.cell() == i => {
+                let rgba = match hint {
+                    Deduction::Safe(_) => HINT_SAFE_RGBA,
+                    Deduction::Mine(_) => HINT_MINE_RGBA,
+                };
+                x11_change_gc(conn, self.resources.gc_id, &GcValues::new().foreground(self.pixel(rgba)));
+                x11_poly_rectangle(conn, drawable_id, self.resources.gc_id, &[
+                    (x, y, entities_width() - 1, entities_height() - 1),
+                    (x + 1, y + 1, entities_width() - 3, entities_height() - 3),
+                ]);
+            }
+            _ => {}
+        }
+    }
+
+    /// Copies the four edges of the focus ring sprite around the cell at `(x, y)`.
+    fn draw_focus_ring(&self, conn: &mut X11Connection, drawable_id: u32, x: u16, y: u16) {
+        let thickness = FOCUS_RING_THICKNESS * config::scale();
+        let edges = [
+            (0, 0, entities_width(), thickness),
+            (0, entities_height() - thickness, entities_width(), thickness),
+            (0, thickness, thickness, entities_height() - 2 * thickness),
+            (entities_width() - thickness, thickness, thickness, entities_height() - 2 * thickness),
+        ];
+        for (dx, dy, width, height) in edges {
+            x11_copy_area(
+                conn,
+                self.resources.sprite_pixmap_id,
+                drawable_id,
+                self.resources.gc_id,
+                FOCUS_RING_SPRITE.x * config::scale() + dx,
+                FOCUS_RING_SPRITE.y * config::scale() + dy,
+                x + dx,
+                y + dy,
+                width,
+                height,
+            );
+        }
+    }
+
+    /// Moves the hover highlight to the cell under `(x, y)`, or clears it when `None`.
+    fn on_pointer_moved(&mut self, conn: &mut X11Connection, position: Option<(u16, u16)>) -> Result<(), std::io::Error> {
+        let hovered = position
+            .and_then(|(x, y)| self.locate_entity_by_coordinate(x, y))
+            .map(|(idx, _, _)| idx);
+        if hovered == self.hovered {
+            return Ok(());
+        }
+
+        self.hovered = hovered;
+        if !self.pressed.is_empty() || self.chord_preview {
+            // Dragging with the button held moves the sunken cells along
+            self.pressed = self.cells_to_press(hovered);
+        }
+        self.render_changes(conn, false)
+    }
+
+    /// Cells to sink for a press on `cell`: itself if covered, plus its covered
+    /// neighbours when chording.
+    fn cells_to_press(&self, cell: Option<usize>) -> Vec<usize> {
+        let Some(cell) = cell else {
+            return Vec::new();
+        };
+        let mut cells = vec![cell];
+        if self.chord_preview {
+            cells.extend(self.neighbours(cell));
+        }
+        cells.retain(|&idx| self.displayed_entities[idx] == EntityKind::Covered);
+        cells
+    }
+
+    /// Hovers like a core MotionNotify, which XInput motion events take the place of, and
+    /// pans an endless board as far as the scroll valuators moved.
+    fn on_device_motion(&mut self, conn: &mut X11Connection, event: DeviceEvent) -> Result<(), std::io::Error> {
+        let cells = self.smooth_scroll.cells(&event, SCROLL_STEP as f64);
+        if self.endless.is_some() && cells != (0, 0) {
+            self.scroll(conn, cells)?;
+        }
+        self.on_pointer_moved(conn, Some((event.event_x.max(0) as u16, event.event_y.max(0) as u16)))
+    }
 
-#[derive(Debug)]
-pub(crate) struct Scene {
-    state: SceneState,
-    window_id: u32,
-    gc_id: u32,
-    sprite_pixmap_id: u32,
-    displayed_entities: Vec<EntityKind>,
-    mines: Vec<bool>,
-}
+    /// Turns XInput touches into clicks: a tap reveals, a long press flags. Touches that
+    /// end on a different cell than they started on are ignored.
+    fn on_touch(&mut self, conn: &mut X11Connection, event: DeviceEvent) -> Result<(), std::io::Error> {
+        let (x, y) = (event.event_x.max(0) as u16, event.event_y.max(0) as u16);
+        let cell = self.locate_entity_by_coordinate(x, y).map(|(idx, _, _)| idx);
 
-impl Scene {
-    pub(crate) fn new(window_id: u32, gc_id: u32, sprite_pixmap_id: u32) -> Self {
-        return Scene{
-            state: SceneState::Uninitialized,
-            window_id,
-            gc_id,
-            sprite_pixmap_id,
-            displayed_entities: vec![EntityKind::Covered; (ENTITIES_COLUMN_COUNT * ENTITIES_ROW_COUNT) as usize],
-            mines: vec![false; (ENTITIES_COLUMN_COUNT * ENTITIES_ROW_COUNT) as usize],
+        match event.event_type {
+            XI_TOUCH_BEGIN if self.touch.is_none() => {
+                self.touch = Some(Touch { id: event.detail, time: event.time, cell });
+            }
+            XI_TOUCH_END if self.state == SceneState::Paused => {
+                self.touch = None;
+                self.resume();
+                self.render(conn)?;
+            }
+            XI_TOUCH_END => {
+                let Some(touch) = self.touch.take_if(|touch| touch.id == event.detail) else {
+                    return Ok(());
+                };
+                if self.is_on_flag_mode_button(x, y) {
+                    return self.toggle_flag_mode(conn);
+                }
+                if cell.is_none() || cell != touch.cell {
+                    return Ok(());
+                }
+
+                let held_for = event.time.wrapping_sub(touch.time);
+                let button = if held_for >= LONG_PRESS_MS { 3 } else { 1 };
+                self.on_cell_clicked(x, y, self.flag_mode_button(button));
+                self.render(conn)?;
+            }
+            _ => {}
         }
+        Ok(())
     }
 
-    pub(crate) fn reset(&mut self)  {
-        for entity in &mut self.displayed_entities {
-            *entity = EntityKind::Covered;
+    /// Arrow keys move the focus ring, Space/Enter reveal the focused cell (or start a new
+    /// game once this one is over) and F flags it. Handled on press so keys auto-repeat.
+    fn on_key_pressed(&mut self, conn: &mut X11Connection, event: InputEvent) -> Result<(), std::io::Error> {
+        if self.state == SceneState::Paused {
+            return Ok(()); // The release resumes
+        }
+        let keysym = self.resources.keymap.keysym(event.detail);
+        let step = match keysym {
+            Some(KEYSYM_LEFT) => Some((0, -1)),
+            Some(KEYSYM_RIGHT) => Some((0, 1)),
+            Some(KEYSYM_UP) => Some((-1, 0)),
+            Some(KEYSYM_DOWN) => Some((1, 0)),
+            _ => None,
+        };
+        if let Some(step) = step {
+            return self.move_focus(conn, step);
         }
 
-        let mut rng = rand::thread_rng();
-        for mine in &mut self.mines {
-            *mine = rng.gen_bool(0.1);
+        match keysym {
+            Some(KEYSYM_SPACE | KEYSYM_RETURN | KEYSYM_KP_ENTER) if self.state != SceneState::Ready || self.lesson_done => self.new_game(conn),
+            Some(KEYSYM_SPACE | KEYSYM_RETURN | KEYSYM_KP_ENTER) => {
+                if let Some(idx) = self.focused {
+                    self.player_click(idx, 1);
+                    self.render(conn)?;
+                }
+                Ok(())
+            }
+            Some(KEYSYM_F) => {
+                // Flag the focused cell, or without one the cell under the pointer
+                match self.focused {
+                    Some(idx) => self.player_click(idx, 3),
+                    None => self.on_cell_clicked(event.event_x, event.event_y, 3),
+                }
+                self.render(conn)
+            }
+            _ => Ok(()),
         }
     }
 
-    pub fn render(&self, socket: &mut UnixStream) -> Result<(), std::io::Error> {
-        let asset_coordinates = get_asset_coordinates();
+    /// Moves the focus ring by `(rows, columns)`, stopping at the edges, past which an
+    /// endless board scrolls instead. The first key press shows the ring on the hovered
+    /// cell, or the middle of the board.
+    fn move_focus(&mut self, conn: &mut X11Connection, (rows, columns): (i32, i32)) -> Result<(), std::io::Error> {
+        let focused = match self.focused {
+            Some(idx) => {
+                let (row, column) = self.idx_to_row_column(idx as u16);
+                let (row, column) = (row as i32 + rows, column as i32 + columns);
+                let clamped_row = row.clamp(0, self.size.rows as i32 - 1);
+                let clamped_column = column.clamp(0, self.size.columns as i32 - 1);
+                if self.endless.is_some() && (row, column) != (clamped_row, clamped_column) {
+                    return self.scroll(conn, ((column - clamped_column) as i64, (row - clamped_row) as i64));
+                }
+                self.row_column_to_idx(clamped_row as u16, clamped_column as u16) as usize
+            }
+            None => self.hovered.unwrap_or_else(|| {
+                self.row_column_to_idx(self.size.rows / 2, self.size.columns / 2) as usize
+            }),
+        };
+
+        self.focused = Some(focused);
+        self.render_changes(conn, false)
+    }
 
-        for (i, &entity) in self.displayed_entities.iter().enumerate() {
-            if let Some(&pos) = asset_coordinates.get(&entity) {
-                let (row, column) = idx_to_row_column(i as u16);
-                x11_copy_area(
-                    socket,
-                    self.sprite_pixmap_id,
-                    self.window_id,
-                    self.gc_id,
-                    pos.x,
-                    pos.y,
-                    column * ENTITIES_WIDTH,
-                    row * ENTITIES_HEIGHT,
-                    ENTITIES_WIDTH,
-                    ENTITIES_HEIGHT,
-                );
+    /// Sinks the covered cell under the pointer while the left button is down, or the
+    /// cell and its neighbours when the other button joins in for a chord.
+    fn on_button_pressed(&mut self, conn: &mut X11Connection, event: InputEvent) -> Result<(), std::io::Error> {
+        if self.state != SceneState::Ready {
+            return Ok(());
+        }
+        let other_button_held = match event.detail {
+            1 => event.state & STATE_BUTTON3 != 0,
+            3 => event.state & STATE_BUTTON1 != 0,
+            _ => false,
+        };
+        if other_button_held && !self.chording {
+            self.chording = true;
+            self.chord_preview = true;
+            // The implicit grab only lasts until the first button comes up; keep the
+            // preview's events coming until both are released
+            x11_grab_pointer(conn, self.resources.window_id)?;
+        } else {
+            // Only a press that reveals sinks the cell
+            match self.flag_mode_button(effective_button(&event)) {
+                1 => {}
+                2 => self.chord_preview = true,
+                _ => return Ok(()),
             }
         }
-        Ok(())
+
+        let cell = self.locate_entity_by_coordinate(event.event_x, event.event_y).map(|(idx, _, _)| idx);
+        self.pressed = self.cells_to_press(cell);
+        self.render_changes(conn, false)
+    }
+
+    /// Clicks the cell under the pointer once its button comes up. Releasing the last of
+    /// both buttons chords, and so does a double left click on a number.
+    fn on_button_released(&mut self, conn: &mut X11Connection, event: InputEvent) -> Result<(), std::io::Error> {
+        self.pressed.clear();
+        if self.stats_screen.take().is_some() {
+            return self.render(conn);
+        }
+        if self.state == SceneState::Paused {
+            self.resume();
+            return self.render(conn);
+        }
+        if self.endless.is_some() && (4..=7).contains(&event.detail) {
+            if self.smooth_scroll.is_active() {
+                // Already panned by the scroll valuators these buttons are emulated from
+                return Ok(());
+            }
+            // The wheel scrolls, sideways with Shift or when tilted
+            let sideways = event.state & STATE_SHIFT != 0;
+            let step = match event.detail {
+                4 if sideways => (-SCROLL_STEP, 0),
+                5 if sideways => (SCROLL_STEP, 0),
+                4 => (0, -SCROLL_STEP),
+                5 => (0, SCROLL_STEP),
+                6 => (-SCROLL_STEP, 0),
+                _ => (SCROLL_STEP, 0),
+            };
+            return self.scroll(conn, step);
+        }
+        if self.chording {
+            let released = match event.detail {
+                1 => STATE_BUTTON1,
+                3 => STATE_BUTTON3,
+                _ => 0,
+            };
+            if event.state & (STATE_BUTTON1 | STATE_BUTTON3) & !released == 0 {
+                self.chording = false;
+                self.chord_preview = false;
+                x11_ungrab_pointer(conn);
+                self.on_cell_clicked(event.event_x, event.event_y, 2);
+            }
+        } else if event.detail == 1 && self.is_on_flag_mode_button(event.event_x, event.event_y) {
+            self.flag_mode = !self.flag_mode;
+        } else {
+            self.chord_preview = false;
+            let button = match effective_button(&event) {
+                1 if self.is_double_click(&event) => 2,
+                button => self.flag_mode_button(button),
+            };
+            self.on_cell_clicked(event.event_x, event.event_y, button);
+        }
+        self.render(conn)
     }
 
-    pub fn wait_for_x11_events(&mut self, mut stream: UnixStream) -> Result<(), std::io::Error> {
-        #[repr(C, packed)]
-        struct GenericEvent {
-            code: u8,
-            pad: [u8; 31],
-        }
-        assert_eq!(size_of::<GenericEvent>(), 32);
-
-        #[repr(C, packed)]
-        struct KeyReleaseEvent {
-            code: u8,
-            detail: u8,
-            sequence_number: u16,
-            time: u32,
-            root_id: u32,
-            event: u32,
-            child_id: u32,
-            root_x: u16,
-            root_y: u16,
-            event_x: u16,
-            event_y: u16,
-            state: u16,
-            same_screen: bool,
-            pad1: u8,
-        }
-        assert_eq!(size_of::<KeyReleaseEvent>(), 32);
-
-        #[repr(C, packed)]
-        struct ButtonReleaseEvent {
-            code: u8,
-            detail: u8,
-            seq_number: u16,
-            timestamp: u32,
-            root: u32,
-            event: u32,
-            child: u32,
-            root_x: u16,
-            root_y: u16,
-            event_x: u16,
-            event_y: u16,
-            state: u16,
-            same_screen: bool,
-            pad1: u8,
-        }
-        assert_eq!(size_of::<ButtonReleaseEvent>(), 32);
-
-        const EVENT_EXPOSURE: u8 = 0xc;
-        const EVENT_KEY_RELEASE: u8 = 0x3;
-        const EVENT_BUTTON_RELEASE: u8 = 0x5;
-
-        const KEYCODE_ENTER: u8 = 36;
+    /// Whether a left click is the second one in quick succession on the same cell.
+    fn is_double_click(&mut self, event: &InputEvent) -> bool {
+        let Some((idx, _, _)) = self.locate_entity_by_coordinate(event.event_x, event.event_y) else {
+            self.last_left_click = None;
+            return false;
+        };
+        let previous = self.last_left_click.replace((idx, event.time));
+        matches!(previous, Some((previous_idx, time)) if previous_idx == idx && event.time.wrapping_sub(time) < DOUBLE_CLICK_MS)
+    }
 
+    pub fn wait_for_x11_events(&mut self, conn: &mut X11Connection) -> Result<(), std::io::Error> {
         loop {
-            let mut generic_event = GenericEvent { code: 0, pad: [0; 31] };
-            match stream.read_exact(unsafe {
-                std::slice::from_raw_parts_mut(
-                    &mut generic_event as *mut _ as *mut u8,
-                    size_of::<GenericEvent>(),
-                )
-            }) {
-                Ok(_) => {},
-                Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => {
-                    println!("Connection closed");
+            self.share_with_peer();
+            let peer = self.peer.as_ref().map(AsRawFd::as_raw_fd);
+            let watched: Vec<RawFd> = self.session_manager.iter().map(AsRawFd::as_raw_fd).chain(peer).collect();
+            let packet = match conn.next_event(&watched, self.next_tick())? {
+                Wakeup::Event(packet) => packet,
+                Wakeup::Timeout => {
+                    self.on_tick(conn)?;
+                    continue;
+                }
+                Wakeup::Readable(fd) if Some(fd) == peer => {
+                    self.on_peer_readable(conn)?;
+                    continue;
+                }
+                Wakeup::Readable(_) => {
+                    if self.on_session_request().is_break() {
+                        return Ok(());
+                    }
+                    continue;
+                }
+            };
+            let event = Event::decode(packet);
+
+            if let Some(handler) = self.dispatcher.handler(&event) {
+                if handler(self, conn, &event)?.is_break() {
                     return Ok(());
-                },
-                Err(e) => return Err(e),
+                }
+                continue;
             }
 
-            match generic_event.code {
-                EVENT_EXPOSURE => {
-                    self.render(&mut stream)?;
+            match event {
+                Event::Expose { count: 0 } => self.repaint_exposed(conn)?,
+                // A key or button takes over from the solver; the release plays on the new board
+                Event::KeyPress(_) | Event::ButtonPress(_) if self.autoplay.is_some() => {
+                    self.stop_autoplay(conn)?;
+                    self.render(conn)?;
                 }
-                EVENT_KEY_RELEASE => {
-                    let event: KeyReleaseEvent = unsafe { transmute(generic_event) };
-                    if event.detail == KEYCODE_ENTER {
-                        self.reset();
-                        self.render(&mut stream)?;
+                Event::KeyPress(event) => {
+                    self.on_key_pressed(conn, event)?;
+                }
+                Event::KeyRelease(_) if self.state == SceneState::Paused => {
+                    self.resume();
+                    self.render(conn)?;
+                }
+                Event::KeyRelease(event) => {
+                    match self.resources.keymap.keysym(event.detail) {
+                        // Shift+R or Shift+F2 plays the same board again, R or F2 a new one
+                        // A race is only run on new boards
+                        Some(KEYSYM_R | KEYSYM_F2) if event.state & STATE_SHIFT != 0 && !self.racing() => {
+                            self.restart();
+                            self.render(conn)?;
+                        }
+                        Some(KEYSYM_R | KEYSYM_F2) => self.new_game(conn)?,
+                        Some(KEYSYM_D) => self.cycle_difficulty(conn)?,
+                        Some(KEYSYM_T) => self.toggle_stats_screen(conn)?,
+                        Some(KEYSYM_A) => self.toggle_achievements_screen(conn)?,
+                        Some(KEYSYM_H) => self.show_hint(conn)?,
+                        Some(KEYSYM_M) => self.toggle_flag_mode(conn)?,
+                        Some(KEYSYM_C) => self.share_game(conn, event.time)?,
+                        Some(KEYSYM_E) => match self.export_board() {
+                            Ok(path) => println!("Exported the board to {}", path.display()),
+                            Err(e) => eprintln!("Could not export the board: {}", e),
+                        },
+                        Some(KEYSYM_S) if event.state & STATE_CONTROL != 0 => match self.save_game() {
+                            Ok(path) => println!("Saved game to {}", path.display()),
+                            Err(e) => eprintln!("Could not save game: {}", e),
+                        },
+                        Some(KEYSYM_S) => match self.save_screenshot(conn) {
+                            Ok(path) => println!("Saved screenshot to {}", path.display()),
+                            Err(e) => eprintln!("Could not save screenshot: {}", e),
+                        },
+                        Some(KEYSYM_P | KEYSYM_ESCAPE) if self.pause() => self.render(conn)?,
+                        // Escape quits when there is no game in progress to pause
+                        Some(KEYSYM_ESCAPE) => return Ok(()),
+                        _ => {}
                     }
                 }
-                EVENT_BUTTON_RELEASE => {
-                    let event: ButtonReleaseEvent = unsafe { transmute(generic_event) };
-                    self.on_cell_clicked(event.event_x, event.event_y, event.detail);
-                    self.render(&mut stream)?;
+                Event::ButtonPress(event) => {
+                    self.on_button_pressed(conn, event)?;
+                }
+                Event::ButtonRelease(event) => {
+                    self.on_button_released(conn, event)?;
+                }
+                Event::MotionNotify(event) => {
+                    self.on_pointer_moved(conn, Some((event.event_x, event.event_y)))?;
+                }
+                Event::LeaveNotify => {
+                    self.smooth_scroll.reset();
+                    self.on_pointer_moved(conn, None)?;
+                }
+                Event::UnmapNotify => self.visible = false,
+                Event::VisibilityNotify { state } if state == VISIBILITY_FULLY_OBSCURED => self.visible = false,
+                Event::MapNotify | Event::VisibilityNotify { .. } if !self.visible => {
+                    self.visible = true;
+                    self.render(conn)?;
+                }
+                Event::ConfigureNotify { width, height } if (width, height) != self.window_size => {
+                    // E.g. after switching difficulty; the board is recentered in whatever
+                    // size the window manager settled on
+                    self.window_size = (width, height);
+                    self.render(conn)?;
+                }
+                Event::GraphicsExposure => {
+                    // Part of a CopyArea source was unavailable; repaint everything
+                    self.drawn.clear();
+                    self.render(conn)?;
+                }
+                Event::Generic { extension, packet } if Some(extension) == self.resources.xinput_opcode => {
+                    if xinput::event_type(&packet) == XI_DEVICE_CHANGED {
+                        // The pointer now follows another device, with its own valuators
+                        let valuators = xinput::query_scroll_valuators(conn, extension)?;
+                        self.smooth_scroll = SmoothScroll::new(valuators.clone());
+                        self.resources.scroll_valuators = valuators;
+                    } else if let Some(event) = DeviceEvent::decode(&packet) {
+                        match event.event_type {
+                            XI_MOTION => self.on_device_motion(conn, event)?,
+                            _ => self.on_touch(conn, event)?,
+                        }
+                    }
                 }
                 _ => {}
             }
         }
     }
 
+    /// Time left to clear the board with `--blitz`.
+    fn time_left(&self) -> Option<Duration> {
+        Some(self.time_limit?.saturating_sub(self.elapsed()))
+    }
+
+    /// Play time at which `--chaos` next moves the mines, when it does so by the clock.
+    fn next_shift(&self) -> Option<Duration> {
+        match self.chaos? {
+            Chaos::Seconds(seconds) => Some(Duration::from_secs(seconds as u64 * (self.shifts as u64 + 1))),
+            Chaos::Reveals(_) => None,
+        }
+    }
+
+    /// When the countdown next shows another second, or runs out, or the mines next move,
+    /// while the game is running, or else when the toast is to go or the solver to move.
+    fn next_tick(&self) -> Option<Instant> {
+        let toast = self.toast.as_ref().map(|&(_, until)| until);
+        let timers = toast.into_iter().chain(self.autoplay.as_ref().map(|autoplay| autoplay.next_at));
+        if self.state != SceneState::Ready || self.started_at.is_none() {
+            return timers.min();
+        }
+        let countdown = self.time_left().map(|left| {
+            let to_next_second = left - Duration::from_secs(left.as_secs());
+            if to_next_second.is_zero() { left.min(Duration::from_secs(1)) } else { to_next_second }
+        });
+        let shift = self.next_shift().map(|at| at.saturating_sub(self.elapsed()));
+        countdown.into_iter().chain(shift).min().map(|wait| Instant::now() + wait).into_iter().chain(timers).min()
+    }
+
+    /// Redraws the countdown, or ends the game once it has run out, and moves the mines,
+    /// takes the toast down or has the solver move when it is time to.
+    fn on_tick(&mut self, conn: &mut X11Connection) -> Result<(), std::io::Error> {
+        if self.autoplay.as_ref().is_some_and(|autoplay| Instant::now() >= autoplay.next_at) {
+            self.play_autoplay_move(conn)?;
+            self.render(conn)?;
+        }
+        if self.toast.as_ref().is_some_and(|&(_, until)| Instant::now() >= until) {
+            self.toast = None;
+            self.render(conn)?;
+        }
+        if self.state == SceneState::Ready && self.next_shift().is_some_and(|at| self.elapsed() >= at) {
+            self.shift_mines();
+            self.render(conn)?;
+        }
+        if self.state != SceneState::Ready || self.time_left() != Some(Duration::ZERO) {
+            return self.render_changes(conn, true);
+        }
+        let safe_cells = self.size.cell_count() - self.size.mines as usize;
+        match self.endless {
+            Some(_) => println!("Time's up: cleared {} cells", self.cleared_cells()),
+            None => println!("Time's up: uncovered {} of {} cells", safe_cells - self.count_remaining_goals(), safe_cells),
+        }
+        self.timed_out = true;
+        self.lives = 0;
+        self.state = SceneState::Lost;
+        self.uncover_all_cells();
+        self.record_game_end();
+        self.render(conn)
+    }
+
+    /// Takes the solver's next step: points out the cell it plays next with the focus ring,
+    /// plays the one pointed out last time, or deals a new board once a game has been over
+    /// for a while. The pointer is kept hidden meanwhile, so it doesn't cover the play.
+    fn play_autoplay_move(&mut self, conn: &mut X11Connection) -> Result<(), std::io::Error> {
+        let Some(mut autoplay) = self.autoplay.take() else {
+            return Ok(());
+        };
+        self.set_pointer_hidden(conn, true)?;
+        autoplay.next_at = Instant::now() + autoplay::MOVE_DELAY;
+        if self.state != SceneState::Ready {
+            self.reset();
+            autoplay.guesses = 0;
+        } else if let Some(pending) = autoplay.pending.take() {
+            self.click_cell(pending.cell, pending.button);
+            if self.state == SceneState::Lost && !pending.guess {
+                eprintln!("Solver error: cell {} was proven safe but held a mine", pending.cell);
+            }
+            if self.state != SceneState::Ready {
+                let outcome = if self.state == SceneState::Won { "won" } else { "lost" };
+                println!("Solver {} with {} guess(es)", outcome, autoplay.guesses);
+                autoplay.next_at = Instant::now() + autoplay::GAME_OVER_DELAY;
+            }
+        } else {
+            // The first click is always safe, so it goes in the middle
+            let next = match self.first_reveal {
+                true => Some(autoplay::Move { cell: self.row_column_to_idx(self.size.rows / 2, self.size.columns / 2) as usize, button: 1, guess: false }),
+                false => autoplay::next_move(
+                    &self.displayed_entities,
+                    self.size.columns as usize,
+                    self.adjacency,
+                    self.size.anti_mines > 0,
+                    self.flags_left() != Some(0),
+                    |cell| self.fogged.get(cell) != Some(&true),
+                    |candidates| rand::random::<u64>() as usize % candidates.len(),
+                ),
+            };
+            if let Some(next) = next {
+                autoplay.guesses += u32::from(next.guess);
+                self.focused = Some(next.cell);
+                autoplay.pending = Some(next);
+            }
+        }
+        self.autoplay = Some(autoplay);
+        Ok(())
+    }
+
+    /// Hands the board to the player, on a new game, and shows the pointer again.
+    fn stop_autoplay(&mut self, conn: &mut X11Connection) -> Result<(), std::io::Error> {
+        if self.autoplay.is_none() {
+            return Ok(());
+        }
+        self.reset();
+        self.autoplay = None;
+        self.focused = None;
+        self.load_streak();
+        self.set_pointer_hidden(conn, false)
+    }
+
+    /// Serves a message from the session manager: saves the board on SaveYourself and
+    /// ends the game on Die. Losing the session manager isn't fatal; the game just stops
+    /// taking part in the session.
+    fn on_session_request(&mut self) -> ControlFlow<()> {
+        let board = self.check_savable().map(|_| self.saved_board());
+        let Some(session_manager) = self.session_manager.as_mut() else {
+            return ControlFlow::Continue(());
+        };
+        let result = session_manager.next_request().and_then(|request| match request {
+            SessionRequest::SaveYourself { .. } => {
+                let path = session_board_path(session_manager.client_id())
+                    .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "No state directory (HOME unset)"));
+                let saved = board.and_then(|board| path.and_then(|path| board.save(&path)));
+                if let Err(e) = &saved {
+                    eprintln!("Could not save the board for the session: {}", e);
+                }
+                session_manager.save_yourself_done(saved.is_ok()).map(|_| ControlFlow::Continue(()))
+            }
+            SessionRequest::Die => Ok(ControlFlow::Break(())),
+            SessionRequest::ShutdownCancelled | SessionRequest::Other => Ok(ControlFlow::Continue(())),
+        });
+
+        result.unwrap_or_else(|e| {
+            eprintln!("Lost the session manager: {}", e);
+            self.session_manager = None;
+            ControlFlow::Continue(())
+        })
+    }
+
+    /// Ends the game when the window manager asks to close the window, rather than
+    /// having it kill the connection.
+    fn on_wm_protocols(&mut self, _conn: &mut X11Connection, event: &Event) -> Result<ControlFlow<()>, std::io::Error> {
+        match event {
+            Event::ClientMessage(message) if message.format == 32 && message.data32()[0] == self.resources.wm_delete_window => Ok(ControlFlow::Break(())),
+            _ => Ok(ControlFlow::Continue(())),
+        }
+    }
+
+    /// Offers the CLIPBOARD selection's contents to the client asking for them, as UTF-8
+    /// text or the list of targets it comes in; gives it up when another client takes over.
+    fn on_clipboard(&mut self, conn: &mut X11Connection, event: &Event) -> Result<ControlFlow<()>, std::io::Error> {
+        let request = match event {
+            Event::SelectionRequest(request) => request,
+            _ => {
+                self.clipboard = None;
+                return Ok(ControlFlow::Continue(()));
+            }
+        };
+        let property = if request.property == 0 { request.target } else { request.property };
+        let stored = match &self.clipboard {
+            Some(_) if request.target == self.resources.targets => {
+                let targets: Vec<u8> = [self.resources.targets, self.resources.utf8_string].iter().flat_map(|atom| atom.to_ne_bytes()).collect();
+                x11_change_property(conn, request.requestor, property, ATOM_ATOM, 32, &targets);
+                true
+            }
+            Some(text) if request.target == self.resources.utf8_string => {
+                x11_change_property(conn, request.requestor, property, self.resources.utf8_string, 8, text.as_bytes());
+                true
+            }
+            _ => false,
+        };
+        x11_send_selection_notify(conn, request.requestor, request.selection, request.target, if stored { property } else { 0 }, request.time);
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// Puts the game just over on the clipboard as a grid of emoji (C), for pasting into a
+    /// chat. `time` is the server time of the key press.
+    fn share_game(&mut self, conn: &mut X11Connection, time: u32) -> Result<(), std::io::Error> {
+        if !matches!(self.state, SceneState::Won | SceneState::Lost) || self.endless.is_some() {
+            x11_bell(conn, self.bell_volume.0);
+            return Ok(());
+        }
+        let won = self.state == SceneState::Won;
+        let text = share::emoji_grid(self.difficulty_name(), won, self.finished_in, &self.displayed_entities, self.size.columns as usize);
+        x11_set_selection_owner(conn, self.resources.window_id, self.resources.clipboard, time);
+        if x11_get_selection_owner(conn, self.resources.clipboard)? != self.resources.window_id {
+            eprintln!("Could not take over the clipboard");
+            return Ok(());
+        }
+        self.clipboard = Some(text);
+        println!("Copied the board to the clipboard");
+        Ok(())
+    }
+
     pub fn on_cell_clicked(&mut self, x: u16, y: u16, button: u8) {
-        let (idx, row, column) = self.locate_entity_by_coordinate(x, y);
+        if let Some((idx, _, _)) = self.locate_entity_by_coordinate(x, y) {
+            self.player_click(idx, button);
+        }
+    }
+
+    /// Plays a click made at this window, as opposed to by the solver or the other player
+    /// over the network, and counts it for the efficiency of the game. With `--hot-seat`
+    /// it is the turn of the player on, including the reveals a chord makes; one that
+    /// changes nothing doesn't count.
+    fn player_click(&mut self, idx: usize, button: u8) {
+        let before = (self.state == SceneState::Ready).then(|| self.displayed_entities.clone());
+        // Counted as useful until it turns out to have changed nothing, so the click that
+        // wins the game is counted by the time the game ends
+        if let (Some(_), Some((clicks, useful_clicks))) = (&before, self.clicks.as_mut()) {
+            *clicks += 1;
+            *useful_clicks += 1;
+            self.replay_clicks.push(Click { millis: self.elapsed().as_millis() as u64, button, cell: idx });
+        }
+        match self.hot_seat {
+            Some(hot_seat) => self.take_turn(hot_seat, idx, button),
+            None => self.click_cell(idx, button),
+        }
+        if let (Some(before), Some((_, useful_clicks))) = (before, self.clicks.as_mut()) {
+            *useful_clicks -= (before == self.displayed_entities) as u32;
+        }
+    }
+
+    /// Plays a click of the hot-seat player whose turn it is.
+    fn take_turn(&mut self, mut hot_seat: HotSeat, idx: usize, button: u8) {
+        let uncovered = |cells: &[EntityKind]| cells.iter().filter(|entity| entity.sum().is_some()).count();
+        let (before, lives) = (uncovered(&self.displayed_entities), self.lives);
+        self.click_cell(idx, button);
+        // Losing the game uncovers the rest of the board, which scores nothing
+        let hit_mine = self.lives < lives;
+        let cells = if hit_mine { 0 } else { uncovered(&self.displayed_entities) - before };
+        if cells > 0 || hit_mine {
+            hot_seat.play(cells as u32, hit_mine);
+            self.hot_seat = Some(hot_seat);
+        }
+    }
+
+    /// Reveals (button 1), chords (button 2) or toggles the flag on (button 3) cell `idx`.
+    fn click_cell(&mut self, idx: usize, button: u8) {
+        if self.state != SceneState::Ready {
+            return; // The board left over from a finished game is only to look at
+        }
+        if self.fogged.get(idx) == Some(&true) {
+            return; // Out of sight, out of reach
+        }
+        self.hint = None;
+        if self.is_guest() && !self.racing() {
+            // The host plays the click; what it did comes back with the board
+            let message = match (button, self.displayed_entities[idx]) {
+                (1, _) => Message::Reveal(idx),
+                (2, _) => Message::Chord(idx),
+                (3, EntityKind::Covered) => Message::Flag(idx),
+                (3, EntityKind::Flagged) => Message::Unflag(idx),
+                _ => return,
+            };
+            if self.send_to_host(message) {
+                return;
+            }
+        }
+        if self.endless.is_some() {
+            return self.click_endless_cell(idx, button);
+        }
+        let (row, column) = self.idx_to_row_column(idx as u16);
+        let (row, column) = (row as usize, column as usize);
 
         match button {
             1 => { // Left click
                 if self.displayed_entities[idx] == EntityKind::Flagged {
                     return; // Can't reveal flagged cells
                 }
+                if std::mem::take(&mut self.first_reveal) {
+                    if !self.keep_mines {
+                        self.protect_first_reveal(idx);
+                        self.print_debug_board();
+                    }
+                    self.record_game_start();
+                }
+                if self.guess_protection && self.mines[idx] {
+                    self.spare_forced_guess(idx);
+                }
 
                 let mined = self.mines[idx];
+                let exploded = if self.anti_mines[idx] { EntityKind::AntiMineExploded } else { EntityKind::MineExploded };
 
-                if mined {
-                    self.displayed_entities[idx] = EntityKind::MineExploded;
+                if mined && self.lives > 1 {
+                    // Casual mode: the mine goes off, but play continues
+                    self.lives -= 1;
+                    self.displayed_entities[idx] = exploded;
+                } else if mined {
+                    self.lives = 0;
                     self.state = SceneState::Lost;
-                    self.uncover_all_cells(EntityKind::MineExploded);
+                    self.uncover_all_cells();
+                    // Only the mine that went off is drawn exploded
+                    self.displayed_entities[idx] = exploded;
+                    self.record_game_end();
                 } else {
                     self.uncover_cells_flood_fill(row, column);
+                    self.check_splits();
+                    if let Some(Chaos::Reveals(reveals)) = self.chaos {
+                        self.reveals_since_shift += 1;
+                        if self.reveals_since_shift >= reveals {
+                            self.shift_mines();
+                        }
+                    }
 
-                    if self.count_remaining_goals() == 0 {
-                        self.state = SceneState::Won;
-                        self.uncover_all_cells(EntityKind::MineIdle);
+                    if self.auto_flag && self.count_remaining_goals() > 0 {
+                        self.flag_forced_mines();
+                    }
+                    if self.is_won() {
+                        self.win();
                     }
                 }
             },
+            2 => self.chord_cell(idx),
             3 => { // Right click
+                if self.displayed_entities[idx] == EntityKind::Covered && self.flags_left() == Some(0) {
+                    return; // Out of flags
+                }
                 if self.displayed_entities[idx] == EntityKind::Covered {
                     self.displayed_entities[idx] = EntityKind::Flagged;
+                    self.flagged = true;
                 } else if self.displayed_entities[idx] == EntityKind::Flagged {
                     self.displayed_entities[idx] = EntityKind::Covered;
                 }
+                if self.is_won() {
+                    self.win();
+                }
             },
             _ => {} // Ignore other buttons
         }
     }
 
-    fn uncover_cells_flood_fill(&mut self, row: usize, column: usize) {
-        let i = self.row_column_to_idx(row as u16, column as u16) as usize;
+    /// Plays a click on the cell in view at `idx` of an endless board.
+    fn click_endless_cell(&mut self, idx: usize, button: u8) {
+        let (row, column) = self.idx_to_row_column(idx as u16);
+        let cell = (self.camera.0 + column as i64, self.camera.1 + row as i64);
+        let Some(world) = self.endless.as_mut() else {
+            return;
+        };
+        match button {
+            1 if world.shown(cell) != EntityKind::Flagged => {
+                if std::mem::take(&mut self.first_reveal) {
+                    world.protect(cell);
+                    self.record_game_start();
+                }
+                self.reveal_endless_cell(cell);
+            }
+            2 => {
+                for n in world.chord_targets(cell) {
+                    if self.state != SceneState::Ready {
+                        break;
+                    }
+                    self.reveal_endless_cell(n);
+                }
+            }
+            3 => world.toggle_flag(cell),
+            _ => {}
+        }
+        self.show_world();
+    }
 
-        if self.mines[i] { return; }
+    /// Reveals `cell` of an endless board, which ends the game once a mine goes off with
+    /// no lives to spare.
+    fn reveal_endless_cell(&mut self, cell: Cell) {
+        let Some(world) = self.endless.as_mut() else {
+            return;
+        };
+        if !world.reveal(cell) {
+            return;
+        }
+        if self.lives > 1 {
+            self.lives -= 1;
+            return;
+        }
+        println!("Cleared {} cells", world.cleared());
+        self.lives = 0;
+        self.state = SceneState::Lost;
+        self.uncover_all_cells();
+        self.record_game_end();
+    }
 
-        if self.displayed_entities[i] != EntityKind::Covered { return; }
+    /// Copies the part of an endless board in view into the cells drawn.
+    fn show_world(&mut self) {
+        let Some(world) = self.endless.as_mut() else {
+            return;
+        };
+        let columns = self.size.columns as usize;
+        for idx in 0..self.displayed_entities.len() {
+            let cell = (self.camera.0 + (idx % columns) as i64, self.camera.1 + (idx / columns) as i64);
+            self.displayed_entities[idx] = world.shown(cell);
+            self.mines[idx] = world.is_mine(cell);
+        }
+    }
 
-        let mines_around_count = self.count_mines_around_cell(row, column);
-        self.displayed_entities[i] = match mines_around_count {
-            0 => EntityKind::Uncovered0,
-            1 => EntityKind::Uncovered1,
-            2 => EntityKind::Uncovered2,
-            3 => EntityKind::Uncovered3,
-            4 => EntityKind::Uncovered4,
-            5 => EntityKind::Uncovered5,
-            6 => EntityKind::Uncovered6,
-            7 => EntityKind::Uncovered7,
-            8 => EntityKind::Uncovered8,
-            _ => panic!("Invalid mine count"),
+    /// Moves the view of an endless board by `(columns, rows)`.
+    fn scroll(&mut self, conn: &mut X11Connection, (columns, rows): (i64, i64)) -> Result<(), std::io::Error> {
+        self.camera = (self.camera.0 + columns, self.camera.1 + rows);
+        self.pressed.clear();
+        self.show_world();
+        self.render(conn)
+    }
+
+    /// Moves the mines under covered, unflagged cells to random such cells (`--chaos`),
+    /// anti-mines staying anti-mines, and renumbers the uncovered cells around the cells
+    /// that changed.
+    fn shift_mines(&mut self) {
+        self.shifts += 1;
+        self.reveals_since_shift = 0;
+        self.hint = None;
+        if let Some(autoplay) = self.autoplay.as_mut() {
+            // What the solver was about to play may no longer follow
+            autoplay.pending = None;
+        }
+        let before: Vec<(bool, bool)> = self.mines.iter().copied().zip(self.anti_mines.iter().copied()).collect();
+        let covered: Vec<usize> = (0..self.displayed_entities.len()).filter(|&idx| self.displayed_entities[idx] == EntityKind::Covered).collect();
+        let moving: Vec<usize> = covered.iter().copied().filter(|&idx| self.mines[idx]).collect();
+        let anti_mines = moving.iter().filter(|&&idx| self.anti_mines[idx]).count();
+        for &idx in &moving {
+            self.mines[idx] = false;
+            self.anti_mines[idx] = false;
+        }
+        for (n, pick) in rand::seq::index::sample(&mut self.rng, covered.len(), moving.len()).into_iter().enumerate() {
+            self.mines[covered[pick]] = true;
+            self.anti_mines[covered[pick]] = n < anti_mines;
+        }
+
+        // Only a number next to a cell whose mine came or went can have changed
+        for (idx, &cell) in before.iter().enumerate() {
+            if cell == (self.mines[idx], self.anti_mines[idx]) {
+                continue;
+            }
+            let numbers: Vec<usize> = self.neighbours(idx).filter(|&n| self.displayed_entities[n].sum().is_some()).collect();
+            for n in numbers {
+                self.displayed_entities[n] = self.counted_entity(n);
+            }
+        }
+        self.print_debug_board();
+    }
+
+    /// Moves the mine off cell `idx`, being revealed, if the numbers proved nothing safe to
+    /// reveal instead. It goes to a covered cell next to the very same numbers, as the
+    /// other cell of a 50/50 is, so that none of them changes; without such a cell the
+    /// guess is lost as usual.
+    fn spare_forced_guess(&mut self, idx: usize) {
+        let anti_mines = self.size.anti_mines > 0;
+        if !solver::must_guess(&self.displayed_entities, self.size.columns as usize, self.adjacency, anti_mines) {
+            return;
+        }
+        let numbers_around = |cell: usize| -> Vec<usize> {
+            let mut numbers: Vec<usize> = self.neighbours(cell).filter(|&n| self.displayed_entities[n].sum().is_some()).collect();
+            numbers.sort_unstable();
+            numbers
+        };
+        let numbers = numbers_around(idx);
+        let swaps: Vec<usize> = (0..self.mines.len())
+            .filter(|&cell| cell != idx && !self.mines[cell] && self.displayed_entities[cell] == EntityKind::Covered)
+            .filter(|&cell| numbers_around(cell) == numbers)
+            .collect();
+        if swaps.is_empty() {
+            return;
+        }
+        let to = swaps[rand::seq::index::sample(&mut self.rng, swaps.len(), 1).index(0)];
+        self.mines[idx] = false;
+        self.mines[to] = true;
+        self.anti_mines[to] = std::mem::take(&mut self.anti_mines[idx]);
+        println!("Guess protection moved a mine");
+        self.print_debug_board();
+    }
+
+    /// Prints the board with `--debug-mines`, after its mines were placed or moved.
+    fn print_debug_board(&self) {
+        if self.debug_mines {
+            print!("{}", self.saved_board());
+        }
+    }
+
+    /// Reveals the unflagged neighbours of an uncovered number once as many flags as its
+    /// value (ignoring its sign) surround it. A misplaced flag loses the game like any
+    /// other mine click.
+    fn chord_cell(&mut self, idx: usize) {
+        let Some(sum) = self.displayed_entities[idx].sum().filter(|&sum| sum != 0) else {
+            return;
         };
+        let flags_around = self.neighbours(idx)
+            .filter(|&n| self.displayed_entities[n] == EntityKind::Flagged || self.displayed_entities[n].exploded())
+            .count();
+        if flags_around != sum.unsigned_abs() as usize {
+            return;
+        }
 
-        // Only continue flood fill if this cell has no adjacent mines
-        if mines_around_count == 0 {
-            if row > 0 { self.uncover_cells_flood_fill(row - 1, column); }
-            if column < (ENTITIES_COLUMN_COUNT - 1) as usize { self.uncover_cells_flood_fill(row, column + 1); }
-            if row < (ENTITIES_ROW_COUNT - 1) as usize { self.uncover_cells_flood_fill(row + 1, column); }
-            if column > 0 { self.uncover_cells_flood_fill(row, column - 1); }
-            // Diagonal cells
-            if row > 0 && column > 0 { self.uncover_cells_flood_fill(row - 1, column - 1); }
-            if row > 0 && column < (ENTITIES_COLUMN_COUNT - 1) as usize { self.uncover_cells_flood_fill(row - 1, column + 1); }
-            if row < (ENTITIES_ROW_COUNT - 1) as usize && column > 0 { self.uncover_cells_flood_fill(row + 1, column - 1); }
-            if row < (ENTITIES_ROW_COUNT - 1) as usize && column < (ENTITIES_COLUMN_COUNT - 1) as usize { self.uncover_cells_flood_fill(row + 1, column + 1); }
+        let covered: Vec<usize> = self.neighbours(idx).filter(|&n| self.displayed_entities[n] == EntityKind::Covered).collect();
+        self.chorded |= !covered.is_empty();
+        for n in covered {
+            if self.state != SceneState::Ready {
+                break;
+            }
+            self.click_cell(n, 1);
+        }
+    }
+
+    /// Flags the covered neighbours of every number that has exactly as many covered,
+    /// flagged or exploded neighbours as it counts mines (or anti-mines, for a negative
+    /// number), since they must all be mines.
+    fn flag_forced_mines(&mut self) {
+        for idx in 0..self.displayed_entities.len() {
+            let Some(sum) = self.displayed_entities[idx].sum() else {
+                continue;
+            };
+            let unrevealed: Vec<usize> = self.neighbours(idx)
+                .filter(|&n| matches!(self.displayed_entities[n], EntityKind::Covered | EntityKind::Flagged) || self.displayed_entities[n].exploded())
+                .collect();
+            if unrevealed.len() == sum.unsigned_abs() as usize {
+                for n in unrevealed {
+                    if self.displayed_entities[n] == EntityKind::Covered && self.flags_left() != Some(0) {
+                        self.displayed_entities[n] = EntityKind::Flagged;
+                    }
+                }
+            }
         }
     }
 
-    fn uncover_all_cells(&mut self, mine_type: EntityKind) {
+    fn uncover_cells_flood_fill(&mut self, row: usize, column: usize) {
+        let idx = self.row_column_to_idx(row as u16, column as u16) as usize;
+        flood_fill(&mut self.displayed_entities, &self.mines, &self.anti_mines, self.size.columns as usize, self.adjacency, idx);
+    }
+
+    /// Reveals the board once the game is over: mines, except flagged ones, which keep
+    /// their flag, and ones that already went off, and flags on cells without a mine as
+    /// misplaced.
+    fn uncover_all_cells(&mut self) {
+        if let Some(world) = self.endless.as_mut() {
+            world.end();
+            self.show_world();
+            return;
+        }
         for i in 0..self.displayed_entities.len() {
             if self.mines[i] {
-                self.displayed_entities[i] = mine_type;
+                if self.displayed_entities[i] != EntityKind::Flagged && !self.displayed_entities[i].exploded() {
+                    self.displayed_entities[i] = if self.anti_mines[i] { EntityKind::AntiMineIdle } else { EntityKind::MineIdle };
+                }
+            } else if self.displayed_entities[i] == EntityKind::Flagged {
+                self.displayed_entities[i] = EntityKind::FlagMisplaced;
             } else if self.displayed_entities[i] == EntityKind::Covered {
-                let (row, column) = self.idx_to_row_column(i as u16);
-                let mines_around_count = self.count_mines_around_cell(row as usize, column as usize);
-                self.displayed_entities[i] = match mines_around_count {
-                    0 => EntityKind::Uncovered0,
-                    1 => EntityKind::Uncovered1,
-                    2 => EntityKind::Uncovered2,
-                    3 => EntityKind::Uncovered3,
-                    4 => EntityKind::Uncovered4,
-                    5 => EntityKind::Uncovered5,
-                    6 => EntityKind::Uncovered6,
-                    7 => EntityKind::Uncovered7,
-                    8 => EntityKind::Uncovered8,
-                    _ => panic!("Invalid mine count"),
-                };
+                self.displayed_entities[i] = self.counted_entity(i);
             }
         }
     }
 
+    /// Whether every safe cell is uncovered, or with `--win-by-flagging` every mine flagged
+    /// (or gone off) without a wrong flag. Flags set before the first reveal don't win, as
+    /// the mines may still move.
+    fn is_won(&self) -> bool {
+        self.count_remaining_goals() == 0 || (self.win_by_flagging && !self.first_reveal && self.all_mines_flagged())
+    }
+
+    /// Whether every mine is flagged or has gone off, and no flag is wrong.
+    fn all_mines_flagged(&self) -> bool {
+        self.displayed_entities.iter().zip(&self.mines).all(|(&entity, &mine)| match entity {
+            EntityKind::Flagged => mine,
+            EntityKind::MineExploded | EntityKind::AntiMineExploded => true,
+            _ => !mine,
+        })
+    }
+
+    fn win(&mut self) {
+        self.state = SceneState::Won;
+        self.uncover_all_cells();
+        self.record_game_end();
+    }
+
     fn count_remaining_goals(&self) -> usize {
         self.displayed_entities.iter()
             .zip(self.mines.iter())
@@ -311,41 +3223,260 @@ impl Scene {
             .count()
     }
 
-    fn count_mines_around_cell(&self, row: usize, column: usize) -> u8 {
-        let mut count = 0;
-        for i in -1..=1 {
-            for j in -1..=1 {
-                if i == 0 && j == 0 { continue; }
-                let new_row = row as isize + i;
-                let new_col = column as isize + j;
-                if new_row >= 0 && new_row < ENTITIES_ROW_COUNT as isize &&
-                   new_col >= 0 && new_col < ENTITIES_COLUMN_COUNT as isize {
-                    let idx = self.row_column_to_idx(new_row as u16, new_col as u16) as usize;
-                    if self.mines[idx] {
-                        count += 1;
-                    }
-                }
-            }
-        }
-        count
+    /// Indices of the cells whose mines the number on `idx` counts.
+    fn neighbours(&self, idx: usize) -> impl Iterator<Item = usize> {
+        self.adjacency.neighbours(self.size.rows as usize, self.size.columns as usize, idx)
+    }
+
+    /// What the cell `idx` shows once uncovered, if it holds no mine.
+    fn counted_entity(&self, idx: usize) -> EntityKind {
+        counted_entity(&self.mines, &self.anti_mines, self.neighbours(idx))
     }
 
     fn idx_to_row_column(&self, idx: u16) -> (u16, u16) {
-        let row = idx / ENTITIES_COLUMN_COUNT;
-        let column = idx % ENTITIES_COLUMN_COUNT;
+        let row = idx / self.size.columns;
+        let column = idx % self.size.columns;
         (row, column)
     }
 
     fn row_column_to_idx(&self, row: u16, column: u16) -> u16 {
-        row * ENTITIES_COLUMN_COUNT + column
+        row * self.size.columns + column
     }
 
-    fn locate_entity_by_coordinate(&self, win_x: u16, win_y: u16) -> (usize, usize, usize) {
-        let column = win_x as usize / ENTITIES_WIDTH as usize;
-        let row = win_y as usize / ENTITIES_HEIGHT as usize;
+    fn locate_entity_by_coordinate(&self, win_x: u16, win_y: u16) -> Option<(usize, usize, usize)> {
+        let (x, y) = self.board_point(win_x, win_y)?;
+        let column = x as usize / entities_width() as usize;
+        let row = y as usize / entities_height() as usize;
+        if column >= self.size.columns as usize || row >= self.size.rows as usize {
+            return None;
+        }
         let idx = self.row_column_to_idx(row as u16, column as u16);
-        (idx as usize, row, column)
+        Some((idx as usize, row, column))
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::x11comm::ImageByteOrder;
+    use std::sync::Mutex;
+
+    /// The largest board `--rows` and `--cols` allow.
+    const COLUMNS: usize = 255;
+    const CELLS: usize = 255 * 255;
+
+    #[test]
+    fn flood_fill_opens_a_large_empty_board() {
+        let mut displayed = vec![EntityKind::Covered; CELLS];
+        flood_fill(&mut displayed, &vec![false; CELLS], &vec![false; CELLS], COLUMNS, Adjacency::King, 0);
+        assert!(displayed.iter().all(|&entity| entity == EntityKind::Uncovered0));
+    }
+
+    #[test]
+    fn flood_fill_numbers_the_cells_around_a_mine() {
+        let mut mines = vec![false; CELLS];
+        let mine = 128 * COLUMNS + 128;
+        mines[mine] = true;
+        let mut displayed = vec![EntityKind::Covered; CELLS];
+        flood_fill(&mut displayed, &mines, &vec![false; CELLS], COLUMNS, Adjacency::King, CELLS - 1);
+
+        assert_eq!(displayed[mine], EntityKind::Covered);
+        for (idx, &entity) in displayed.iter().enumerate().filter(|&(idx, _)| idx != mine) {
+            let (row, column) = (idx / COLUMNS, idx % COLUMNS);
+            let next_to_mine = row.abs_diff(128) <= 1 && column.abs_diff(128) <= 1;
+            assert_eq!(entity, if next_to_mine { EntityKind::Uncovered1 } else { EntityKind::Uncovered0 }, "cell {}", idx);
+        }
+    }
+
+    #[test]
+    fn flood_fill_stops_at_a_wall_of_mines_and_flags() {
+        // A column of mines splits the board, with a flag standing in for one of them
+        let wall = COLUMNS / 2;
+        let mut mines = vec![false; CELLS];
+        let mut displayed = vec![EntityKind::Covered; CELLS];
+        for row in 0..CELLS / COLUMNS {
+            mines[row * COLUMNS + wall] = row != 7;
+        }
+        displayed[7 * COLUMNS + wall] = EntityKind::Flagged;
+        flood_fill(&mut displayed, &mines, &vec![false; CELLS], COLUMNS, Adjacency::King, 0);
+
+        for (idx, &entity) in displayed.iter().enumerate() {
+            match idx % COLUMNS {
+                column if column < wall => assert_ne!(entity, EntityKind::Covered, "cell {}", idx),
+                column if column == wall && idx == 7 * COLUMNS + wall => assert_eq!(entity, EntityKind::Flagged),
+                _ => assert_eq!(entity, EntityKind::Covered, "cell {}", idx),
+            }
+        }
+    }
+
+    #[test]
+    fn flood_fill_counts_knight_moves() {
+        let mut mines = vec![false; 25];
+        mines[12] = true;
+        let mut displayed = vec![EntityKind::Covered; 25];
+        flood_fill(&mut displayed, &mines, &[false; 25], 5, Adjacency::Knight, 0);
 
+        let knight_moves_from_mine = [1, 3, 5, 9, 15, 19, 21, 23];
+        for (idx, &entity) in displayed.iter().enumerate() {
+            let expected = match idx {
+                12 => EntityKind::Covered,
+                idx if knight_moves_from_mine.contains(&idx) => EntityKind::Uncovered1,
+                _ => EntityKind::Uncovered0,
+            };
+            assert_eq!(entity, expected, "cell {}", idx);
+        }
+    }
+
+    #[test]
+    fn flood_fill_counts_two_steps_out_with_far_sight() {
+        // Mines all around the middle cell of a 5 by 5 board, and none in a row below
+        let mut mines = vec![true; 30];
+        mines[12] = false;
+        mines[25..].fill(false);
+        let mut displayed = vec![EntityKind::Covered; 30];
+        flood_fill(&mut displayed, &mines, &[false; 30], 5, Adjacency::FarSight, 12);
+        assert_eq!(displayed[12], EntityKind::Uncovered24);
+        flood_fill(&mut displayed, &mines, &[false; 30], 5, Adjacency::FarSight, 27);
+        assert_eq!(displayed[27], EntityKind::Uncovered10);
+    }
+
+    #[test]
+    fn three_bv_counts_openings_and_isolated_numbers() {
+        // A single row: the openings at either end each reveal the 1 next to them, and
+        // the 2 between the mines has to be clicked on its own
+        let mines = [false, false, true, false, true, false, false];
+        assert_eq!(three_bv(&mines, 7, Adjacency::King), 3);
+        assert_eq!(three_bv(&[false; 9], 3, Adjacency::King), 1);
+
+        // The left opening and the 2 are cleared, but not the right opening by its 1 alone
+        use EntityKind::{Covered, Uncovered0, Uncovered1, Uncovered2};
+        let displayed = [Uncovered0, Uncovered1, Covered, Uncovered2, Covered, Uncovered1, Covered];
+        assert_eq!(three_bv_cleared(&mines, &displayed, 7, Adjacency::King), (3, 2));
+    }
+
+    #[test]
+    fn zini_chords_where_that_saves_clicks() {
+        // Eight 1s around a mine: a chord on an edge and one from the side clear all but a corner
+        let mut mines = [false; 9];
+        mines[4] = true;
+        assert_eq!((three_bv(&mines, 3, Adjacency::King), zini(&mines, 3, Adjacency::King)), (8, 5));
+
+        // The opening clears the board in one click, no chord needed
+        mines = [true, false, false, false, false, false, false, false, false];
+        assert_eq!((three_bv(&mines, 3, Adjacency::King), zini(&mines, 3, Adjacency::King)), (1, 1));
+    }
+
+    #[test]
+    fn flood_fill_leaves_a_number_alone_past_the_start() {
+        let mut mines = vec![false; 9];
+        mines[8] = true;
+        let mut displayed = vec![EntityKind::Covered; 9];
+        flood_fill(&mut displayed, &mines, &[false; 9], 3, Adjacency::King, 4);
+        assert_eq!(displayed.iter().filter(|&&entity| entity != EntityKind::Covered).count(), 1);
+        assert_eq!(displayed[4], EntityKind::Uncovered1);
+    }
+
+    #[test]
+    fn flood_fill_subtracts_anti_mines() {
+        // A single row: a mine, an empty cell, an anti-mine and two empty cells
+        let mines = [true, false, true, false, false];
+        let anti_mines = [false, false, true, false, false];
+        let mut displayed = vec![EntityKind::Covered; 5];
+        flood_fill(&mut displayed, &mines, &anti_mines, 5, Adjacency::King, 4);
+        assert_eq!(displayed[3], EntityKind::UncoveredMinus1);
+        assert_eq!(displayed[1], EntityKind::Covered);
+
+        // Where they cancel out the cell shows it, and opens nothing more
+        flood_fill(&mut displayed, &mines, &anti_mines, 5, Adjacency::King, 1);
+        assert_eq!(displayed[1], EntityKind::UncoveredBalanced);
+    }
+
+    #[test]
+    fn flood_fill_wraps_around_the_edges() {
+        // A mine in the first column is next to the last one, so the opening stops short
+        let mut mines = vec![false; 25];
+        mines[10] = true;
+        let mut displayed = vec![EntityKind::Covered; 25];
+        flood_fill(&mut displayed, &mines, &[false; 25], 5, Adjacency::WrappedKing, 2);
+        for row in 1..4 {
+            assert_eq!(displayed[row * 5 + 4], EntityKind::Uncovered1, "row {}", row);
+        }
+        assert_eq!(displayed[4], EntityKind::Uncovered0);
+        assert_eq!(Adjacency::WrappedKing.neighbours(2, 2, 0).collect::<Vec<_>>(), vec![1, 2, 3]);
+    }
+
+    /// Held by tests that end games, as each one writes the stats.
+    static DATA_DIR: Mutex<()> = Mutex::new(());
+
+    /// A beginner game lost on the mine in the top left corner, with the mine next to it
+    /// flagged, keeping stats and best times out of the real data directory.
+    fn lost_game() -> Scene {
+        std::env::set_var("XDG_DATA_HOME", std::env::temp_dir().join("mineswept-tests"));
+        let resources = SceneResources {
+            window_id: 0,
+            gc_id: 0,
+            sprite_pixmap_id: 0,
+            keymap: Keymap::default(),
+            cursors: Cursors { playing: 0, game_over: 0 },
+            pixel_format: PixelFormat {
+                visual_id: 0,
+                depth: 24,
+                bits_per_pixel: 32,
+                scanline_pad: 32,
+                red_mask: 0xff0000,
+                green_mask: 0xff00,
+                blue_mask: 0xff,
+                alpha_mask: 0,
+                byte_order: ImageByteOrder::LsbFirst,
+            },
+            xinput_opcode: None,
+            scroll_valuators: Vec::new(),
+            xfixes_opcode: None,
+            back_buffer_id: 0,
+            present_opcode: None,
+            render: None,
+            wm_protocols: 0,
+            wm_delete_window: 0,
+            clipboard: 0,
+            utf8_string: 0,
+            targets: 0,
+        };
+        let mut scene = Scene::new(resources, &Options::default());
+        scene.reset();
+        scene.mines.fill(false);
+        scene.mines[..2].fill(true);
+        scene.keep_mines = true;
+
+        scene.click_cell(1, 3);
+        scene.click_cell(0, 1);
+        assert_eq!(scene.state, SceneState::Lost);
+        assert_eq!(scene.displayed_entities[1], EntityKind::Flagged);
+        scene
+    }
+
+    #[test]
+    fn the_board_of_a_lost_game_ignores_clicks() {
+        let _data_dir = DATA_DIR.lock().unwrap_or_else(|e| e.into_inner());
+        let mut scene = lost_game();
+        let board = scene.displayed_entities.clone();
+        for idx in 0..board.len() {
+            for button in 1..=3 {
+                scene.click_cell(idx, button);
+            }
+        }
+        assert_eq!(scene.displayed_entities, board);
+        assert_eq!(scene.state, SceneState::Lost);
+    }
+
+    #[test]
+    fn unflagging_after_a_loss_does_not_win() {
+        let _data_dir = DATA_DIR.lock().unwrap_or_else(|e| e.into_inner());
+        let mut scene = lost_game();
+        let stats = Stats::load().unwrap();
+
+        // Every safe cell is uncovered by now, which would make unflagging the mine a win
+        scene.player_click(1, 3);
+        assert_eq!(scene.state, SceneState::Lost);
+        assert_eq!(Stats::load().unwrap(), stats);
+    }
+}
@@ -0,0 +1,73 @@
+use std::cmp::Ordering;
+
+/// Two players taking turns at the same board (`--hot-seat`). Every reveal that uncovers
+/// anything passes the turn, each cell uncovered scores a point for whoever uncovered it,
+/// and a player who sets off a mine is out while the other plays on alone.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct HotSeat {
+    /// Player whose turn it is, 0 or 1.
+    pub(crate) turn: usize,
+    pub(crate) scores: [u32; 2],
+    pub(crate) out: [bool; 2],
+}
+
+impl HotSeat {
+    /// Records a turn that uncovered `cells` cells, or set off a mine, and passes the
+    /// turn to the other player unless they are out.
+    pub(crate) fn play(&mut self, cells: u32, hit_mine: bool) {
+        self.scores[self.turn] += cells;
+        self.out[self.turn] |= hit_mine;
+        let other = 1 - self.turn;
+        if !self.out[other] {
+            self.turn = other;
+        }
+    }
+
+    /// The player ahead, or `None` on a draw.
+    pub(crate) fn leader(&self) -> Option<usize> {
+        match self.scores[0].cmp(&self.scores[1]) {
+            Ordering::Greater => Some(0),
+            Ordering::Less => Some(1),
+            Ordering::Equal => None,
+        }
+    }
+
+    /// The scores for the status bar, with `>` before the player whose turn it is and `x`
+    /// after one who is out.
+    pub(crate) fn summary(&self) -> String {
+        let player = |p: usize| format!(
+            "{}P{} {}{}",
+            if p == self.turn && !self.out[p] { ">" } else { "" },
+            p + 1,
+            self.scores[p],
+            if self.out[p] { "x" } else { "" },
+        );
+        format!("{}  {}", player(0), player(1))
+    }
+
+    /// Who won the game just over, and by how much.
+    pub(crate) fn result(&self) -> String {
+        let [first, second] = self.scores;
+        match self.leader() {
+            Some(p) => format!("P{} wins {}-{}", p + 1, first.max(second), first.min(second)),
+            None => format!("Draw {}-{}", first, second),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turns_alternate_until_a_player_is_out() {
+        let mut hot_seat = HotSeat::default();
+        hot_seat.play(5, false);
+        hot_seat.play(1, true);
+        assert_eq!(hot_seat, HotSeat { turn: 0, scores: [5, 1], out: [false, true] });
+        hot_seat.play(2, false);
+        assert_eq!((hot_seat.turn, hot_seat.leader()), (0, Some(0)));
+        assert_eq!(hot_seat.summary(), ">P1 7  P2 1x");
+        assert_eq!(hot_seat.result(), "P1 wins 7-1");
+    }
+}
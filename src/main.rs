@@ -1,77 +1,625 @@
-use crate::{x11comm::{connect_x11_socket, x11_create_graphical_context, load_x11_auth_token, next_x11_id, x11_handshake, x11_create_window, x11_map_window, x11_create_pixmap, x11_put_image, x11_copy_area},
-            config::{ENTITIES_COLUMN_COUNT, ENTITIES_ROW_COUNT, ENTITIES_WIDTH, ENTITIES_HEIGHT},
-            game::Scene};
-use png;
+use crate::{x11comm::{connect_x11_socket, display_from_env, x11_create_graphical_context, x11_handshake, x11_create_window, x11_create_colormap, x11_map_window, x11_create_pixmap, x11_enable_big_requests, x11_set_wm_class, x11_set_wm_hints, x11_get_keyboard_mapping, x11_intern_atom, x11_query_pointer, x11_set_always_on_top, x11_set_wm_normal_hints, x11_set_wm_protocols, x11_set_backing_store, BACKING_STORE_NEVER, BACKING_STORE_WHEN_MAPPED, x11_open_font, x11_close_font, x11_create_glyph_cursor, ConnectionInformation, DisplayName, CURSOR_GLYPH_CROSSHAIR, CURSOR_GLYPH_X, RGB16_BLACK, RGB16_WHITE, SocketOptions, GcValues, Resource, X11Connection},
+            config::{BoardSize, Options, Placement, USAGE},
+            game::{Cursors, Scene, SceneResources}};
+use std::env;
 use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::process;
 use std::thread::sleep;
 use std::time;
-use crate::game::SceneState;
-use crate::utils::rgba_to_bgra;
+use crate::utils::{rgba_to_pixel, rgba_to_zpixmap};
+use crate::randr::Monitor;
+use crate::auth::load_x11_auth_token;
+use crate::savegame::{resume_path, session_board_path, SavedBoard};
+use crate::xsmp::SessionManager;
+use crate::stats::Stats;
+use crate::scores::Scores;
+use crate::daily::DailyLog;
+use crate::achievements::Achievements;
+use crate::campaign::{CampaignProgress, LEVELS};
+use crate::net::{Message, Mode, Peer};
 
 mod x11comm;
+mod auth;
 mod utils;
 mod game;
 mod config;
+mod encoder;
+mod shm;
+mod randr;
+mod xcmisc;
+mod xinput;
+mod present;
+mod xfixes;
+mod xsmp;
+mod savegame;
+mod stats;
+mod scores;
+mod solver;
+mod daily;
+mod net;
+mod event;
+mod endless;
+mod tutorial;
+mod achievements;
+mod autoplay;
+mod campaign;
+mod hotseat;
+mod efficiency;
+mod pattern;
+mod replay;
+mod share;
+mod xrender;
 
+const RECONNECT_ATTEMPTS: u32 = 10;
+const RECONNECT_DELAY: time::Duration = time::Duration::from_secs(1);
+/// Window background, as RGBA; only translucent with `--translucent`.
+const WINDOW_BACKGROUND_RGBA: [u8; 4] = [0xff, 0xff, 0x80, 0xc0];
 
-fn main() {
-    let auth_token = load_x11_auth_token().unwrap();
-    let mut socket = connect_x11_socket().unwrap();
-    let connection_information = x11_handshake(&mut socket, &auth_token).unwrap();
+/// Decoded RGBA sprite sheet, kept around so it can be re-uploaded after a reconnect.
+struct SpriteSheet {
+    rgba: Vec<u8>,
+    width: u16,
+    height: u16,
+}
+
+/// A live connection and the server-side resources the scene draws with.
+struct Session {
+    conn: X11Connection,
+    resources: SceneResources,
+}
+
+/// Decodes the PNG at `path` to 8-bit RGBA pixels, whatever its color type and depth, and
+/// its width and height.
+fn decode_png(path: &Path) -> io::Result<(Vec<u8>, usize, usize)> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut pngbuf = vec![0; reader.output_buffer_size()];
+    let pngoutputinfo = reader.next_frame(&mut pngbuf)?;
+    pngbuf.truncate(pngoutputinfo.buffer_size());
+
+    let rgba = match pngoutputinfo.color_type {
+        png::ColorType::Rgba => pngbuf,
+        png::ColorType::Rgb => pngbuf.chunks(3).flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 0xff]).collect(),
+        png::ColorType::GrayscaleAlpha => pngbuf.chunks(2).flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]]).collect(),
+        png::ColorType::Grayscale => pngbuf.iter().flat_map(|&g| [g, g, g, 0xff]).collect(),
+        // Expanded to RGB by the decoder
+        png::ColorType::Indexed => unreachable!("indexed PNG left unexpanded"),
+    };
+    Ok((rgba, pngoutputinfo.width as usize, pngoutputinfo.height as usize))
+}
+
+/// Scales RGBA pixels up `scale` times by repeating each pixel, which keeps sprites sharp.
+fn scale_nearest(rgba: &[u8], width: usize, scale: usize) -> Vec<u8> {
+    rgba.chunks(width * 4)
+        .flat_map(|row| {
+            let scaled_row: Vec<u8> = row.chunks(4).flat_map(|pixel| pixel.repeat(scale)).collect();
+            scaled_row.repeat(scale)
+        })
+        .collect()
+}
+
+/// Loads the sprite sheet, scaled up for `--scale`.
+fn load_sprite_sheet() -> SpriteSheet {
+    let (rgba, width, height) = decode_png(Path::new("resources/img.png")).unwrap();
+    let scale = config::scale() as usize;
+    let rgba = scale_nearest(&rgba, width, scale);
+    SpriteSheet { rgba, width: (width * scale) as u16, height: (height * scale) as u16 }
+}
+
+/// Connects to the display and creates the window (sized for `board`), GC and sprite pixmap.
+fn open_session(options: &Options, board: BoardSize, display: &DisplayName, sprite_sheet: &SpriteSheet) -> io::Result<Session> {
+    let defaults = SocketOptions::default();
+    let socket_options = SocketOptions {
+        path: options.socket.clone(),
+        timeout: options.connect_timeout.map_or(defaults.timeout, time::Duration::from_secs),
+        retries: options.connect_retries.unwrap_or(defaults.retries),
+    };
+    let mut socket = connect_x11_socket(display, &socket_options)?;
+    let auth_token = load_x11_auth_token(display)?.unwrap_or_default();
+    let mut connection_information = x11_handshake(&mut socket, &auth_token)?;
+    let mut conn = X11Connection::new(socket, &connection_information);
+    x11_enable_big_requests(&mut conn, &mut connection_information)?;
+    connection_information.select_screen(options.screen.unwrap_or(display.screen_number as usize))?;
     println!("{:#?}", connection_information);
 
-    let gc_id = next_x11_id(0, connection_information);
-    x11_create_graphical_context(&mut socket, gc_id, connection_information.root_screen.id);
+    let keymap = x11_get_keyboard_mapping(&mut conn, &connection_information)?;
 
-    let window_id = next_x11_id(gc_id, connection_information);
+    let argb_pixel_format = if options.translucent {
+        let format = connection_information.choose_argb_pixel_format();
+        if format.is_none() {
+            eprintln!("No 32-bit TrueColor visual, falling back to an opaque window");
+        }
+        format
+    } else {
+        None
+    };
+    let pixel_format = match argb_pixel_format {
+        Some(format) => format,
+        None => connection_information.choose_pixel_format()?,
+    };
+
+    let window_width = board.width();
+    let window_height = board.height() + config::status_bar_height();
+    let (window_x, window_y) = match options.position {
+        Some(position) => (position.x, position.y),
+        None => {
+            let monitor = placement_monitor(&mut conn, &connection_information, options.placement)?;
+            centered_position(&monitor, window_width, window_height)
+        }
+    };
+
+    let colormap_id = match argb_pixel_format {
+        Some(format) => {
+            let colormap_id = conn.generate_id()?;
+            x11_create_colormap(&mut conn, colormap_id, connection_information.screen().id, format.visual_id);
+            conn.track(Resource::Colormap(colormap_id));
+            Some(colormap_id)
+        }
+        None => None,
+    };
+
+    let window_id = conn.generate_id()?;
     x11_create_window(
-        &mut socket,
+        &mut conn,
         window_id,
-        connection_information.root_screen.id,
-        200,
-        200,
-        (ENTITIES_COLUMN_COUNT * ENTITIES_WIDTH) as u16,
-        (ENTITIES_ROW_COUNT * ENTITIES_HEIGHT) as u16,
-        connection_information.root_screen.root_visual_id,
+        connection_information.screen().id,
+        window_x,
+        window_y,
+        window_width,
+        window_height,
+        pixel_format.depth,
+        pixel_format.visual_id,
+        rgba_to_pixel(WINDOW_BACKGROUND_RGBA, &pixel_format),
+        colormap_id,
     );
+    conn.track(Resource::Window(window_id));
 
-    x11_map_window(&mut socket, window_id);
+    // Let the server repaint the board itself when other windows pass over it, where supported
+    let screen = connection_information.screen();
+    if screen.backing_store != BACKING_STORE_NEVER || screen.save_unders != 0 {
+        let backing_store = screen.backing_store.min(BACKING_STORE_WHEN_MAPPED);
+        x11_set_backing_store(&mut conn, window_id, backing_store, screen.save_unders != 0);
+    }
 
-    let decoder = png::Decoder::new(File::open("resources/img.png").unwrap());
-    let mut reader = decoder.read_info().unwrap();
-    let mut pngbuf = vec![0; reader.output_buffer_size()];
-    let pngoutputinfo = reader.next_frame(&mut pngbuf).unwrap();
-    let pngbytes = &pngbuf[..pngoutputinfo.buffer_size()];
-    let x11_sprite_bytes = rgba_to_bgra(pngbytes);
+    let font_id = conn.generate_id()?;
+    x11_open_font(&mut conn, font_id, "fixed");
 
-    let pixmap_id = next_x11_id(window_id, connection_information);
+    // Created on the window rather than the root, as a GC only works on drawables of the depth it was made for
+    let gc_id = conn.generate_id()?;
+    let gc_values = GcValues::new()
+        .background(rgba_to_pixel([0x00, 0xff, 0x00, 0xff], &pixel_format))
+        .font(font_id)
+        // Every CopyArea reads from the off-screen sprite pixmap, which can't be obscured,
+        // so the GraphicsExposure/NoExposure events would only be noise
+        .graphics_exposures(false);
+    x11_create_graphical_context(&mut conn, gc_id, window_id, &gc_values);
+    conn.track(Resource::Gc(gc_id));
+    // The GC keeps its own reference to the font
+    x11_close_font(&mut conn, font_id);
+
+    x11_set_wm_class(&mut conn, window_id, "mineswept", "Mineswept");
+    x11_set_wm_hints(&mut conn, window_id);
+    x11_set_wm_normal_hints(&mut conn, window_id, window_x, window_y, window_width, window_height, options.position.is_some());
+    let wm_protocols = x11_intern_atom(&mut conn, "WM_PROTOCOLS", false)?;
+    let wm_delete_window = x11_intern_atom(&mut conn, "WM_DELETE_WINDOW", false)?;
+    x11_set_wm_protocols(&mut conn, window_id, wm_protocols, &[wm_delete_window]);
+    let clipboard = x11_intern_atom(&mut conn, "CLIPBOARD", false)?;
+    let utf8_string = x11_intern_atom(&mut conn, "UTF8_STRING", false)?;
+    let targets = x11_intern_atom(&mut conn, "TARGETS", false)?;
+
+    let xinput_opcode = xinput::enable(&mut conn, window_id)?;
+    let scroll_valuators = match xinput_opcode {
+        Some(opcode) => xinput::query_scroll_valuators(&mut conn, opcode)?,
+        None => Vec::new(),
+    };
+    let xfixes_opcode = xfixes::enable(&mut conn)?;
+
+    // Every frame is drawn off-screen first, so the window never shows one half drawn
+    let back_buffer_id = conn.generate_id()?;
+    x11_create_pixmap(&mut conn, window_id, back_buffer_id, window_width, window_height, pixel_format.depth);
+    conn.track(Resource::Pixmap(back_buffer_id));
+    let present_opcode = present::enable(&mut conn, window_id)?;
+    let render = xrender::enable(&mut conn, pixel_format.visual_id)?;
+
+    x11_map_window(&mut conn, window_id);
+    if options.always_on_top {
+        x11_set_always_on_top(&mut conn, connection_information.screen().id, window_id)?;
+    }
+
+    let x11_sprite_bytes = rgba_to_zpixmap(&sprite_sheet.rgba, sprite_sheet.width as usize, &pixel_format);
+
+    let pixmap_id = conn.generate_id()?;
     x11_create_pixmap(
-        &mut socket,
+        &mut conn,
         window_id,
         pixmap_id,
-        pngoutputinfo.width as u16,
-        pngoutputinfo.height as u16,
-        24,
+        sprite_sheet.width,
+        sprite_sheet.height,
+        pixel_format.depth,
     );
+    conn.track(Resource::Pixmap(pixmap_id));
 
-
-    x11_put_image(
-        &mut socket,
-        window_id,
+    let shm_seg_id = conn.generate_id()?;
+    shm::upload_image(
+        &mut conn,
+        &connection_information,
+        shm_seg_id,
         pixmap_id,
         gc_id,
-        pngoutputinfo.width as u16,
-        pngoutputinfo.height as u16,
-        0,
-        0,
-        24,
+        sprite_sheet.width,
+        sprite_sheet.height,
+        pixel_format.depth,
         x11_sprite_bytes,
-    );
-    // TODO: figure out a way to get if the socket is empty or not
-    sleep(time::Duration::from_millis(75));
+    )?;
+
+    let cursor_font_id = conn.generate_id()?;
+    let crosshair_cursor_id = conn.generate_id()?;
+    let game_over_cursor_id = conn.generate_id()?;
+    x11_open_font(&mut conn, cursor_font_id, "cursor");
+    x11_create_glyph_cursor(&mut conn, crosshair_cursor_id, cursor_font_id, CURSOR_GLYPH_CROSSHAIR, RGB16_BLACK, RGB16_WHITE);
+    x11_create_glyph_cursor(&mut conn, game_over_cursor_id, cursor_font_id, CURSOR_GLYPH_X, RGB16_BLACK, RGB16_WHITE);
+    x11_close_font(&mut conn, cursor_font_id);
+    conn.track(Resource::Cursor(crosshair_cursor_id));
+    conn.track(Resource::Cursor(game_over_cursor_id));
+    let cursors = Cursors { playing: crosshair_cursor_id, game_over: game_over_cursor_id };
+
+    // Make sure the sprite upload and everything before it went through before drawing
+    conn.sync()?;
+
+    let resources = SceneResources {
+        window_id,
+        gc_id,
+        sprite_pixmap_id: pixmap_id,
+        keymap,
+        cursors,
+        pixel_format,
+        xinput_opcode,
+        scroll_valuators,
+        xfixes_opcode,
+        back_buffer_id,
+        present_opcode,
+        render,
+        wm_protocols,
+        wm_delete_window,
+        clipboard,
+        utf8_string,
+        targets,
+    };
+    Ok(Session { conn, resources })
+}
+
+/// Picks the monitor chosen by `placement`.
+///
+/// Without RandR 1.5 the whole screen is treated as a single monitor.
+fn placement_monitor(conn: &mut X11Connection, info: &ConnectionInformation, placement: Placement) -> io::Result<Monitor> {
+    let screen = info.screen();
+    let screen_monitor = Monitor { x: 0, y: 0, width: screen.width, height: screen.height, primary: true };
+    let monitors = randr::get_monitors(conn, screen.id)?
+        .filter(|monitors| !monitors.is_empty())
+        .unwrap_or_else(|| vec![screen_monitor]);
+
+    let monitor = match placement {
+        Placement::Primary => monitors.iter().find(|monitor| monitor.primary),
+        Placement::Pointer => {
+            let (pointer_x, pointer_y) = x11_query_pointer(conn, screen.id)?;
+            monitors.iter().find(|monitor| monitor.contains(pointer_x, pointer_y))
+        }
+    };
+    Ok(*monitor.unwrap_or(&monitors[0]))
+}
+
+/// Top-left corner that centers a `width` x `height` window on `monitor`, kept on screen.
+fn centered_position(monitor: &Monitor, width: u16, height: u16) -> (u16, u16) {
+    let x = monitor.x as i32 + (monitor.width as i32 - width as i32) / 2;
+    let y = monitor.y as i32 + (monitor.height as i32 - height as i32) / 2;
+    (x.clamp(0, u16::MAX as i32) as u16, y.clamp(0, u16::MAX as i32) as u16)
+}
+
+fn is_connection_lost(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+    )
+}
+
+/// Retries `open_session` until the server is back or the attempts run out.
+fn reconnect(options: &Options, board: BoardSize, display: &DisplayName, sprite_sheet: &SpriteSheet) -> io::Result<Session> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotConnected, "No reconnect attempt made");
+    for attempt in 1..=RECONNECT_ATTEMPTS {
+        sleep(RECONNECT_DELAY);
+        match open_session(options, board, display, sprite_sheet) {
+            Ok(session) => return Ok(session),
+            Err(e) => {
+                eprintln!("Reconnect attempt {}/{} failed: {}", attempt, RECONNECT_ATTEMPTS, e);
+                last_error = e;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+/// The board saved at logout for the session that restarted the game as `client_id`.
+fn load_session_board(client_id: &str) -> Option<SavedBoard> {
+    match SavedBoard::load(&session_board_path(client_id)?) {
+        Ok(board) => board,
+        Err(e) => {
+            eprintln!("Could not restore the session's board: {}", e);
+            None
+        }
+    }
+}
+
+/// Takes the game saved for `--resume`. The file is removed, so a game finished after
+/// resuming isn't offered again; quitting mid-game saves it anew.
+fn load_resumed_board() -> Option<SavedBoard> {
+    let path = resume_path()?;
+    match SavedBoard::load(&path) {
+        Ok(Some(board)) => {
+            let _ = std::fs::remove_file(&path);
+            Some(board)
+        }
+        Ok(None) => {
+            eprintln!("No saved game to resume, starting a new one");
+            None
+        }
+        Err(e) => {
+            eprintln!("Could not resume the saved game: {}", e);
+            None
+        }
+    }
+}
+
+/// Reads the board given with `--import-board`, exiting if it can't be played.
+fn import_board(path: &Path) -> SavedBoard {
+    match SavedBoard::load(path) {
+        Ok(Some(board)) => board,
+        Ok(None) => {
+            eprintln!("{}: no such file", path.display());
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Could not import the board: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Makes a board of the size the options give out of the PNG given with
+/// `--board-from-image`, exiting if it can't be read.
+fn board_from_image(path: &Path, options: &Options) -> SavedBoard {
+    let size = options.board_size();
+    let adjacency = if options.wrap { options.adjacency.wrapped() } else { options.adjacency };
+    let board = decode_png(path)
+        .and_then(|(rgba, width, height)| SavedBoard::from_image(&rgba, width, height, size.rows, size.columns, adjacency)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+    board.unwrap_or_else(|e| {
+        eprintln!("Could not make a board of {}: {}", path.display(), e);
+        process::exit(1);
+    })
+}
+
+/// Picks the campaign level to play with `--campaign`: the one given with `--level`, or
+/// else the first not cleared yet. Exits if the level is still locked.
+fn campaign_level(options: &Options) -> Option<usize> {
+    if !options.campaign {
+        return None;
+    }
+    let progress = CampaignProgress::load().unwrap_or_else(|e| {
+        eprintln!("Could not read campaign progress: {}", e);
+        process::exit(1);
+    });
+    let level = options.level.map_or_else(|| progress.next_level(), |level| level.0);
+    if !progress.is_unlocked(level) {
+        eprintln!("Level {} is locked: clear level {} first", level + 1, level);
+        process::exit(1);
+    }
+    Some(level)
+}
+
+/// Hosts or joins a game with `--host` or `--join`, exiting if that fails.
+fn connect_peer(options: &Options) -> Option<Peer> {
+    let mode = if options.race { Mode::Race } else { Mode::CoOp };
+    let result = match (options.host, &options.join) {
+        (Some(port), _) => Peer::host(port, mode),
+        (None, Some(address)) => Peer::join(address, mode),
+        (None, None) => return None,
+    };
+    Some(result.unwrap_or_else(|e| {
+        eprintln!("Could not connect to the other player: {}", e);
+        process::exit(1);
+    }))
+}
+
+/// Registers with the desktop's session manager, if any, so the board is saved at logout.
+fn join_session(scene: &mut Scene, options: &Options) {
+    let previous_id = options.sm_client_id.as_deref();
+    let mut session_manager = match SessionManager::connect(previous_id) {
+        Ok(Some(session_manager)) => session_manager,
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!("Not joining the desktop session: {}", e);
+            return;
+        }
+    };
+
+    // Restart the game the way it was started, minus the client ID from the last session
+    let mut args = env::args();
+    let program = args.next().unwrap_or_else(|| "mineswept-x11".to_string());
+    let mut args: Vec<String> = args.collect();
+    if let Some(position) = args.iter().position(|arg| arg == "--sm-client-id") {
+        args.drain(position..(position + 2).min(args.len()));
+    }
+    if let Err(e) = session_manager.set_restart_command(&program, &args) {
+        eprintln!("Not joining the desktop session: {}", e);
+        return;
+    }
+    scene.join_session(session_manager);
+}
+
+fn main() {
+    let options = Options::parse(env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, USAGE);
+        process::exit(2);
+    });
+    if options.help {
+        println!("{}", USAGE);
+        return;
+    }
+    if options.stats {
+        match Stats::load() {
+            Ok(stats) => print!("{}", stats),
+            Err(e) => {
+                eprintln!("Could not read statistics: {}", e);
+                process::exit(1);
+            }
+        }
+        match Scores::load() {
+            Ok(scores) => print!("\n{}", scores),
+            Err(e) => {
+                eprintln!("Could not read best times: {}", e);
+                process::exit(1);
+            }
+        }
+        match DailyLog::load() {
+            Ok(log) => print!("\n{}", log),
+            Err(e) => {
+                eprintln!("Could not read daily challenges: {}", e);
+                process::exit(1);
+            }
+        }
+        match Achievements::load() {
+            Ok(achievements) => print!("\n{}", achievements),
+            Err(e) => {
+                eprintln!("Could not read achievements: {}", e);
+                process::exit(1);
+            }
+        }
+        match CampaignProgress::load() {
+            Ok(progress) => print!("\n{}", progress),
+            Err(e) => {
+                eprintln!("Could not read campaign progress: {}", e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+    if options.reset_scores {
+        if let Err(e) = Scores::reset() {
+            eprintln!("Could not reset best times: {}", e);
+            process::exit(1);
+        }
+        println!("Best times reset");
+        return;
+    }
+
+    // Each day's challenge can be attempted once
+    let daily_date = options.daily.then(daily::today);
+    if let Some(date) = &daily_date {
+        match DailyLog::load() {
+            Ok(log) => if let Some(result) = log.attempt(date, options.difficulty.name()) {
+                println!("Today's challenge was already played: {}", result);
+                return;
+            },
+            Err(e) => {
+                eprintln!("Could not read daily challenges: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    let level = campaign_level(&options);
+
+    let display = display_from_env().unwrap();
+    config::set_scale(options.scale);
+    let sprite_sheet = load_sprite_sheet();
+
+    let mut peer = connect_peer(&options);
+
+    // A guest plays the host's board, or races on the one it deals, and the campaign the
+    // level's. Otherwise, when the session restarted the game, pick up the board from
+    // before the logout; or else the one given with --import-board or drawn by
+    // --board-from-image, with --resume the one saved last, or with --tutorial the first
+    // lesson's
+    let guest_start = peer.as_mut().filter(|peer| !peer.is_host()).map(|peer| peer.wait_for_board().unwrap_or_else(|e| {
+        eprintln!("Could not get the board from the host: {}", e);
+        process::exit(1);
+    }));
+    let (guest_board, race) = match guest_start {
+        Some(Message::Board(board)) => (Some(board), None),
+        Some(Message::Race { size, adjacency, seed }) => (None, Some((size, adjacency, seed))),
+        _ => (None, None),
+    };
+    let saved_board = guest_board
+        .or_else(|| level.map(|level| LEVELS[level].board()))
+        .or_else(|| options.sm_client_id.as_deref().and_then(load_session_board))
+        .or_else(|| options.import_board.as_deref().map(import_board))
+        .or_else(|| options.board_from_image.as_deref().map(|path| board_from_image(path, &options)))
+        .or_else(|| options.resume.then(load_resumed_board).flatten())
+        .or_else(|| options.tutorial.then(|| tutorial::LESSONS[0].board()));
+    let board_size = match (&race, &saved_board) {
+        (Some((size, _, _)), _) => *size,
+        (None, Some(board)) => board.board_size(),
+        (None, None) => options.board_size(),
+    };
+
+    let mut session = open_session(&options, board_size, &display, &sprite_sheet).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+
+    let mut scene = Scene::new(session.resources.clone(), &options);
+    match (race, level, &saved_board) {
+        (Some((size, adjacency, seed)), _, _) => scene.start_race(size, adjacency, seed),
+        (None, Some(level), _) => scene.start_level(level),
+        (None, None, Some(board)) => scene.restore(board),
+        (None, None, None) => {
+            if let Some(date) = daily_date {
+                scene.play_daily(date);
+            }
+            scene.reset();
+        }
+    }
+    join_session(&mut scene, &options);
+    if let Some(peer) = peer {
+        scene.connect_peer(peer);
+    }
+
+    loop {
+        let result = scene.render(&mut session.conn)
+            .and_then(|_| scene.wait_for_x11_events(&mut session.conn));
 
-    let mut scene = Scene::new(window_id, gc_id, pixmap_id);
-    scene.reset();
-    scene.render(&mut socket);
-    scene.wait_for_x11_events(socket);
+        match result {
+            Ok(()) => {
+                scene.export_board_on_exit();
+                scene.save_unfinished_game();
+                scene.leave_session();
+                return;
+            }
+            Err(e) if is_connection_lost(&e) => {
+                eprintln!("Connection to the X server lost ({}), reconnecting", e);
+                // The window is recreated for the board being played, which may have changed size
+                session = reconnect(&options, scene.board_size(), &display, &sprite_sheet).unwrap_or_else(|e| {
+                    eprintln!("Giving up: {}", e);
+                    scene.save_unfinished_game();
+                    process::exit(1);
+                });
+                // The board state lives in the scene; only the server resources are new
+                scene.attach(session.resources.clone());
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                scene.save_unfinished_game();
+                // process::exit skips destructors, so release the server resources first
+                drop(session);
+                process::exit(1);
+            }
+        }
+    }
 }
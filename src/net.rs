@@ -0,0 +1,297 @@
+use crate::config::{Adjacency, BoardDimension, BoardSize};
+use crate::game::SceneState;
+use crate::savegame::SavedBoard;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::str::FromStr;
+
+/// Version of the protocol, sent in the greeting. Version 2 added anti-mines.
+const PROTOCOL_VERSION: u32 = 2;
+
+/// How two players play together.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Mode {
+    /// Both play one board (`--host`/`--join`).
+    #[default]
+    CoOp,
+    /// Each plays a copy of the same board, and the first to clear it wins (`--race`).
+    Race,
+}
+
+impl Mode {
+    fn name(self) -> &'static str {
+        match self {
+            Mode::CoOp => "co-op",
+            Mode::Race => "race",
+        }
+    }
+}
+
+/// How far a player of a race has got on the board dealt with `seed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Progress {
+    pub(crate) seed: u64,
+    /// Cells uncovered so far.
+    pub(crate) revealed: usize,
+    pub(crate) state: SceneState,
+    /// Time played.
+    pub(crate) millis: u64,
+}
+
+/// What the players tell each other.
+///
+/// In a co-op game the host owns the board and sends all of it after every change; the
+/// guest sends what it wants done to it. Each action says what the player meant, so one
+/// that crosses a change by the other player, like flagging a cell that was just
+/// revealed, does nothing rather than undoing it.
+///
+/// In a race the host deals every board, by sending its size and seed, and both send how
+/// they are doing on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Message {
+    Board(SavedBoard),
+    Race { size: BoardSize, adjacency: Adjacency, seed: u64 },
+    Progress(Progress),
+    Reveal(usize),
+    Chord(usize),
+    Flag(usize),
+    Unflag(usize),
+    NewGame,
+    Restart,
+}
+
+impl Message {
+    /// Encodes the message as a line, followed by the board for `Board`.
+    fn encode(&self) -> String {
+        match self {
+            Message::Board(board) => {
+                let text = board.to_string();
+                format!("board {}\n{}", text.len(), text)
+            }
+            Message::Race { size, adjacency, seed } => {
+                format!("race {} {} {} {} {} {}\n", size.rows, size.columns, size.mines, size.anti_mines, adjacency.name(), seed)
+            }
+            Message::Progress(progress) => {
+                let state = match progress.state {
+                    SceneState::Won => "won",
+                    SceneState::Lost => "lost",
+                    _ => "playing",
+                };
+                format!("progress {} {} {} {}\n", progress.seed, progress.revealed, state, progress.millis)
+            }
+            Message::Reveal(idx) => format!("reveal {}\n", idx),
+            Message::Chord(idx) => format!("chord {}\n", idx),
+            Message::Flag(idx) => format!("flag {}\n", idx),
+            Message::Unflag(idx) => format!("unflag {}\n", idx),
+            Message::NewGame => "new\n".to_string(),
+            Message::Restart => "restart\n".to_string(),
+        }
+    }
+
+    /// Decodes the first message in `buffer`, returning it and the bytes it took, or
+    /// `None` if it hasn't all arrived yet.
+    fn decode(buffer: &[u8]) -> io::Result<Option<(Message, usize)>> {
+        let Some(end) = buffer.iter().position(|&b| b == b'\n') else {
+            return Ok(None);
+        };
+        let line = std::str::from_utf8(&buffer[..end]).map_err(|_| invalid())?;
+        let mut fields = line.split(' ');
+        let name = fields.next().unwrap_or_default();
+
+        let message = match name {
+            "board" => {
+                let length: usize = parse_field(fields.next())?;
+                let Some(text) = buffer.get(end + 1..end + 1 + length) else {
+                    return Ok(None);
+                };
+                let text = std::str::from_utf8(text).map_err(|_| invalid())?;
+                let board = text.parse().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                return Ok(Some((Message::Board(board), end + 1 + length)));
+            }
+            "race" => {
+                let rows: BoardDimension = parse_field(fields.next())?;
+                let columns: BoardDimension = parse_field(fields.next())?;
+                let size = BoardSize {
+                    rows: rows.0,
+                    columns: columns.0,
+                    mines: parse_field(fields.next())?,
+                    anti_mines: parse_field(fields.next())?,
+                };
+                if size.mines as usize >= size.cell_count() || size.anti_mines > size.mines {
+                    return Err(invalid());
+                }
+                Message::Race { size, adjacency: parse_field(fields.next())?, seed: parse_field(fields.next())? }
+            }
+            "progress" => Message::Progress(Progress {
+                seed: parse_field(fields.next())?,
+                revealed: parse_field(fields.next())?,
+                state: match fields.next().unwrap_or_default() {
+                    "won" => SceneState::Won,
+                    "lost" => SceneState::Lost,
+                    _ => SceneState::Ready,
+                },
+                millis: parse_field(fields.next())?,
+            }),
+            "reveal" => Message::Reveal(parse_field(fields.next())?),
+            "chord" => Message::Chord(parse_field(fields.next())?),
+            "flag" => Message::Flag(parse_field(fields.next())?),
+            "unflag" => Message::Unflag(parse_field(fields.next())?),
+            "new" => Message::NewGame,
+            "restart" => Message::Restart,
+            _ => return Err(invalid()),
+        };
+        Ok(Some((message, end + 1)))
+    }
+}
+
+fn invalid() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Invalid message from the other player")
+}
+
+/// Parses one space-separated field of a message.
+fn parse_field<T: FromStr>(field: Option<&str>) -> io::Result<T> {
+    field.and_then(|field| field.parse().ok()).ok_or_else(invalid)
+}
+
+/// The connection to the other player (`--host` or `--join`).
+#[derive(Debug)]
+pub(crate) struct Peer {
+    stream: TcpStream,
+    is_host: bool,
+    mode: Mode,
+    /// Bytes received but not yet decoded into a message.
+    received: Vec<u8>,
+}
+
+impl Peer {
+    /// Waits on `port` for another player to join.
+    pub(crate) fn host(port: u16, mode: Mode) -> io::Result<Peer> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        println!("Waiting for another player to join on port {}", port);
+        let (stream, address) = listener.accept()?;
+        println!("{} joined", address);
+        Peer::greet(stream, true, mode)
+    }
+
+    /// Joins the game hosted at `address` (`HOST:PORT`).
+    pub(crate) fn join(address: &str, mode: Mode) -> io::Result<Peer> {
+        let stream = TcpStream::connect(address)?;
+        println!("Joined the game at {}", address);
+        Peer::greet(stream, false, mode)
+    }
+
+    /// Checks both sides speak the same protocol and play the same mode.
+    fn greet(stream: TcpStream, is_host: bool, mode: Mode) -> io::Result<Peer> {
+        // Actions are single small writes that shouldn't wait for more to fill a packet
+        stream.set_nodelay(true)?;
+        let mut peer = Peer { stream, is_host, mode, received: Vec::new() };
+        let greeting = format!("mineswept {} {}", mode.name(), PROTOCOL_VERSION);
+        peer.stream.write_all(format!("{}\n", greeting).as_bytes())?;
+
+        while !peer.received.contains(&b'\n') {
+            peer.read()?;
+        }
+        let end = peer.received.iter().position(|&b| b == b'\n').unwrap();
+        if peer.received[..end] != *greeting.as_bytes() {
+            let message = format!("The other side isn't a mineswept {} game of this version", mode.name());
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+        peer.received.drain(..=end);
+        Ok(peer)
+    }
+
+    /// Whether this side owns the board.
+    pub(crate) fn is_host(&self) -> bool {
+        self.is_host
+    }
+
+    pub(crate) fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub(crate) fn send(&mut self, message: &Message) -> io::Result<()> {
+        self.stream.write_all(message.encode().as_bytes())
+    }
+
+    /// Reads what has arrived, returning the messages completed by it. Only call this
+    /// when the connection is readable, or it blocks until something comes.
+    pub(crate) fn receive(&mut self) -> io::Result<Vec<Message>> {
+        self.read()?;
+        self.decode_received()
+    }
+
+    /// Waits for the host to send the board, or deal it in a race, which it does as soon
+    /// as the guest joins.
+    pub(crate) fn wait_for_board(&mut self) -> io::Result<Message> {
+        loop {
+            // The board may have come along with the greeting
+            for message in self.decode_received()? {
+                if matches!(message, Message::Board(_) | Message::Race { .. }) {
+                    return Ok(message);
+                }
+            }
+            self.read()?;
+        }
+    }
+
+    fn decode_received(&mut self) -> io::Result<Vec<Message>> {
+        let mut messages = Vec::new();
+        while let Some((message, length)) = Message::decode(&self.received)? {
+            self.received.drain(..length);
+            messages.push(message);
+        }
+        Ok(messages)
+    }
+
+    fn read(&mut self) -> io::Result<()> {
+        let mut buffer = [0; 4096];
+        let length = loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "The other player left")),
+                Ok(length) => break length,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        };
+        self.received.extend_from_slice(&buffer[..length]);
+        Ok(())
+    }
+}
+
+impl AsRawFd for Peer {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::EntityKind;
+
+    #[test]
+    fn decodes_messages_once_they_have_fully_arrived() {
+        let board = SavedBoard {
+            rows: 2,
+            columns: 2,
+            state: SceneState::Ready,
+            elapsed_millis: 500,
+            adjacency: Adjacency::King,
+            cells: vec![(EntityKind::Uncovered1, false), (EntityKind::Flagged, true), (EntityKind::Covered, false), (EntityKind::Uncovered1, false)],
+            anti_mines: Vec::new(),
+        };
+        let encoded = Message::Board(board.clone()).encode() + &Message::Unflag(1).encode();
+
+        let board_length = encoded.len() - "unflag 1\n".len();
+        assert!(Message::decode(&encoded.as_bytes()[..board_length - 1]).unwrap().is_none());
+        let (message, length) = Message::decode(encoded.as_bytes()).unwrap().unwrap();
+        assert_eq!((message, length), (Message::Board(board), board_length));
+        assert_eq!(Message::decode(&encoded.as_bytes()[length..]).unwrap(), Some((Message::Unflag(1), 9)));
+        assert!(Message::decode(b"explode 3\n").is_err());
+        assert!(Message::decode(b"reveal\n").is_err());
+
+        let race = Message::Race { size: BoardSize { rows: 9, columns: 9, mines: 10, anti_mines: 3 }, adjacency: Adjacency::Knight, seed: 42 };
+        assert_eq!(Message::decode(race.encode().as_bytes()).unwrap().map(|(message, _)| message), Some(race));
+    }
+}
@@ -0,0 +1,117 @@
+use rand::Rng;
+use std::str::FromStr;
+
+/// Longest word `--pattern` spells out.
+const MAX_LETTERS: usize = 10;
+
+/// A shape the mines are dealt in (`--pattern`), from the masks bundled in
+/// `resources/patterns`: rows of `#` (part of the shape) and `.`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Pattern {
+    Heart,
+    Creeper,
+    /// A word in capital letters, one 5 by 7 glyph per letter.
+    Text(String),
+}
+
+impl FromStr for Pattern {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "heart" => Ok(Pattern::Heart),
+            "creeper" => Ok(Pattern::Creeper),
+            word if (1..=MAX_LETTERS).contains(&word.len()) && word.chars().all(|c| c.is_ascii_alphabetic()) => Ok(Pattern::Text(word.to_ascii_uppercase())),
+            _ => Err(()),
+        }
+    }
+}
+
+fn parse_mask(text: &str) -> Vec<Vec<bool>> {
+    text.lines().map(|line| line.chars().map(|c| c == '#').collect()).collect()
+}
+
+impl Pattern {
+    fn mask(&self) -> Vec<Vec<bool>> {
+        match self {
+            Pattern::Heart => parse_mask(include_str!("../resources/patterns/heart.mask")),
+            Pattern::Creeper => parse_mask(include_str!("../resources/patterns/creeper.mask")),
+            Pattern::Text(word) => {
+                // Each glyph follows a line with its letter, and the glyphs are set a column apart
+                let letters: Vec<&str> = include_str!("../resources/patterns/letters.mask").lines().collect();
+                let mut mask = vec![Vec::new(); 7];
+                for (n, letter) in word.bytes().enumerate() {
+                    let start = (letter - b'A') as usize * 8 + 1;
+                    for (row, line) in mask.iter_mut().zip(&letters[start..start + 7]) {
+                        if n > 0 {
+                            row.push(false);
+                        }
+                        row.extend(line.chars().map(|c| c == '#'));
+                    }
+                }
+                mask
+            }
+        }
+    }
+
+    /// Rows and columns the shape takes up at its smallest.
+    pub(crate) fn size(&self) -> (usize, usize) {
+        let mask = self.mask();
+        (mask.len(), mask[0].len())
+    }
+
+    /// Picks where `mines` mines go on a board `rows` by `columns`: the shape, centered and
+    /// scaled up as far as the board and the mines allow, gets as many of them as it has
+    /// cells, and the cells around it the rest.
+    pub(crate) fn place_mines(&self, rng: &mut impl Rng, rows: usize, columns: usize, mines: usize) -> Vec<usize> {
+        let mask = self.mask();
+        let (mask_rows, mask_columns) = self.size();
+        let mask_cells = mask.iter().flatten().filter(|&&cell| cell).count();
+        let scale = (2..)
+            .take_while(|&scale| mask_rows * scale <= rows && mask_columns * scale <= columns && mask_cells * scale * scale <= mines)
+            .last()
+            .unwrap_or(1);
+
+        let (top, left) = (rows.saturating_sub(mask_rows * scale) / 2, columns.saturating_sub(mask_columns * scale) / 2);
+        let in_shape = |idx: usize| {
+            let (row, column) = (idx / columns, idx % columns);
+            let (mask_row, mask_column) = (row.wrapping_sub(top) / scale, column.wrapping_sub(left) / scale);
+            row >= top && column >= left && mask.get(mask_row).and_then(|cells| cells.get(mask_column)) == Some(&true)
+        };
+        let (shape, around): (Vec<usize>, Vec<usize>) = (0..rows * columns).partition(|&idx| in_shape(idx));
+
+        let inside = mines.min(shape.len());
+        let outside = (mines - inside).min(around.len());
+        let mut placed: Vec<usize> = rand::seq::index::sample(rng, shape.len(), inside).into_iter().map(|pick| shape[pick]).collect();
+        placed.extend(rand::seq::index::sample(rng, around.len(), outside).into_iter().map(|pick| around[pick]));
+        placed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn fills_the_shape_before_the_cells_around_it() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let heart: Pattern = "heart".parse().unwrap();
+        assert_eq!(heart.size(), (8, 9));
+
+        // The heart has 46 cells; at twice the size on a 16 by 30 board it would need 184 mines
+        let mut mines = heart.place_mines(&mut rng, 16, 30, 99);
+        mines.sort_unstable();
+        mines.dedup();
+        assert_eq!(mines.len(), 99);
+        for (row, cells) in heart.mask().iter().enumerate() {
+            for column in (0..9).filter(|&column| cells[column]) {
+                assert!(mines.contains(&((4 + row) * 30 + 10 + column)));
+            }
+        }
+
+        assert_eq!("Hi".parse::<Pattern>().unwrap().size(), (7, 11));
+        assert!("hi there".parse::<Pattern>().is_err());
+    }
+}
@@ -0,0 +1,88 @@
+use crate::encoder::RequestWriter;
+use crate::x11comm::{x11_query_extension, X11Connection};
+use std::io;
+
+const PRESENT_QUERY_VERSION: u8 = 0;
+const PRESENT_PIXMAP: u8 = 1;
+const PRESENT_SELECT_INPUT: u8 = 3;
+
+const PRESENT_MAJOR_VERSION: u32 = 1;
+const PRESENT_MINOR_VERSION: u32 = 0;
+
+const PRESENT_EVENT_MASK_COMPLETE_NOTIFY: u32 = 2;
+const PRESENT_EVENT_COMPLETE_NOTIFY: u16 = 1;
+
+/// Always copy rather than flip, so the back buffer doesn't have to match the window.
+const PRESENT_OPTION_COPY: u32 = 2;
+
+fn present_query_version(conn: &mut X11Connection, major_opcode: u8) -> io::Result<(u32, u32)> {
+    let request = RequestWriter::new(major_opcode, PRESENT_QUERY_VERSION)
+        .put_u32(PRESENT_MAJOR_VERSION)
+        .put_u32(PRESENT_MINOR_VERSION)
+        .finish();
+    let reply = conn.send_with_reply(&request)?;
+
+    Ok((
+        u32::from_ne_bytes([reply[8], reply[9], reply[10], reply[11]]),
+        u32::from_ne_bytes([reply[12], reply[13], reply[14], reply[15]]),
+    ))
+}
+
+/// Negotiates Present and asks for CompleteNotify events on `window_id`.
+///
+/// Returns the extension's major opcode, or `None` when the server lacks Present.
+pub(crate) fn enable(conn: &mut X11Connection, window_id: u32) -> io::Result<Option<u8>> {
+    let Some(extension) = x11_query_extension(conn, "Present")? else {
+        return Ok(None);
+    };
+    if present_query_version(conn, extension.major_opcode)? < (PRESENT_MAJOR_VERSION, PRESENT_MINOR_VERSION) {
+        return Ok(None);
+    }
+
+    // The event selection is itself a resource, freed along with the window
+    let event_id = conn.generate_id()?;
+    let request = RequestWriter::new(extension.major_opcode, PRESENT_SELECT_INPUT)
+        .put_u32(event_id)
+        .put_u32(window_id)
+        .put_u32(PRESENT_EVENT_MASK_COMPLETE_NOTIFY)
+        .finish();
+    conn.send(&request)?;
+
+    Ok(Some(extension.major_opcode))
+}
+
+/// Queues `pixmap_id` to be copied to `window_id` at `(x, y)` on the next vblank.
+///
+/// A CompleteNotify carrying `serial` is sent once the copy happened; until then the
+/// pixmap must not be drawn into.
+pub(crate) fn present_pixmap(conn: &mut X11Connection, major_opcode: u8, pixmap_id: u32, window_id: u32, serial: u32, x: u16, y: u16) -> io::Result<()> {
+    let request = RequestWriter::new(major_opcode, PRESENT_PIXMAP)
+        .put_u32(window_id)
+        .put_u32(pixmap_id)
+        .put_u32(serial)
+        .put_u32(0) // valid region: the whole pixmap
+        .put_u32(0) // update region: the whole pixmap
+        .put_u16(x)
+        .put_u16(y)
+        .put_u32(0) // target CRTC: let the server pick
+        .put_u32(0) // wait fence
+        .put_u32(0) // idle fence
+        .put_u32(PRESENT_OPTION_COPY)
+        .pad(4)
+        .put_bytes(&0u64.to_ne_bytes()) // target MSC: the next one
+        .put_bytes(&0u64.to_ne_bytes()) // divisor
+        .put_bytes(&0u64.to_ne_bytes()) // remainder
+        .finish();
+    conn.send(&request).map(|_| ())
+}
+
+/// Whether `packet` is the CompleteNotify for the frame presented with `serial`.
+pub(crate) fn is_complete_notify(packet: &[u8], major_opcode: u8, serial: u32) -> bool {
+    const EVENT_GENERIC: u8 = 35;
+
+    packet.len() >= 32
+        && packet[0] & 0x7f == EVENT_GENERIC
+        && packet[1] == major_opcode
+        && u16::from_ne_bytes([packet[8], packet[9]]) == PRESENT_EVENT_COMPLETE_NOTIFY
+        && u32::from_ne_bytes([packet[20], packet[21], packet[22], packet[23]]) == serial
+}
@@ -0,0 +1,87 @@
+use crate::encoder::RequestWriter;
+use crate::x11comm::{x11_query_extension, X11Connection};
+use byteorder::{NativeEndian, ReadBytesExt};
+use std::io::{self, Cursor};
+
+const RANDR_QUERY_VERSION: u8 = 0;
+const RANDR_GET_MONITORS: u8 = 42;
+
+/// GetMonitors was added in RandR 1.5.
+const RANDR_MAJOR_VERSION: u32 = 1;
+const RANDR_MINOR_VERSION: u32 = 5;
+
+/// A logical monitor: the area of the root window covered by one (or several tiled)
+/// outputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Monitor {
+    pub(crate) x: i16,
+    pub(crate) y: i16,
+    pub(crate) width: u16,
+    pub(crate) height: u16,
+    pub(crate) primary: bool,
+}
+
+impl Monitor {
+    pub(crate) fn contains(&self, x: i16, y: i16) -> bool {
+        let (x, y) = (x as i32, y as i32);
+        x >= self.x as i32 && x < self.x as i32 + self.width as i32
+            && y >= self.y as i32 && y < self.y as i32 + self.height as i32
+    }
+}
+
+fn randr_query_version(conn: &mut X11Connection, major_opcode: u8) -> io::Result<(u32, u32)> {
+    let request = RequestWriter::new(major_opcode, RANDR_QUERY_VERSION)
+        .put_u32(RANDR_MAJOR_VERSION)
+        .put_u32(RANDR_MINOR_VERSION)
+        .finish();
+    let reply = conn.send_with_reply(&request)?;
+
+    let mut buffer = Cursor::new(&reply[8..]);
+    Ok((buffer.read_u32::<NativeEndian>()?, buffer.read_u32::<NativeEndian>()?))
+}
+
+/// Lists the active monitors on the screen of `root_id`.
+///
+/// Returns `None` when the server lacks RandR 1.5, so the caller can fall back to the
+/// core screen dimensions.
+pub(crate) fn get_monitors(conn: &mut X11Connection, root_id: u32) -> io::Result<Option<Vec<Monitor>>> {
+    let Some(extension) = x11_query_extension(conn, "RANDR")? else {
+        return Ok(None);
+    };
+    let version = randr_query_version(conn, extension.major_opcode)?;
+    if version < (RANDR_MAJOR_VERSION, RANDR_MINOR_VERSION) {
+        return Ok(None);
+    }
+
+    let request = RequestWriter::new(extension.major_opcode, RANDR_GET_MONITORS)
+        .put_u32(root_id)
+        .put_u8(1) // active monitors only
+        .pad(3)
+        .finish();
+    let reply = conn.send_with_reply(&request)?;
+
+    let mut buffer = Cursor::new(&reply[8..]);
+    let _timestamp = buffer.read_u32::<NativeEndian>()?;
+    let monitor_count = buffer.read_u32::<NativeEndian>()?;
+    let _output_count = buffer.read_u32::<NativeEndian>()?;
+    buffer.set_position(24);
+
+    let mut monitors = Vec::with_capacity(monitor_count as usize);
+    for _ in 0..monitor_count {
+        let _name = buffer.read_u32::<NativeEndian>()?;
+        let primary = buffer.read_u8()? != 0;
+        let _automatic = buffer.read_u8()?;
+        let output_count = buffer.read_u16::<NativeEndian>()?;
+        let x = buffer.read_i16::<NativeEndian>()?;
+        let y = buffer.read_i16::<NativeEndian>()?;
+        let width = buffer.read_u16::<NativeEndian>()?;
+        let height = buffer.read_u16::<NativeEndian>()?;
+        let _width_mm = buffer.read_u32::<NativeEndian>()?;
+        let _height_mm = buffer.read_u32::<NativeEndian>()?;
+        buffer.set_position(buffer.position() + output_count as u64 * 4);
+
+        monitors.push(Monitor { x, y, width, height, primary });
+    }
+
+    Ok(Some(monitors))
+}
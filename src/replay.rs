@@ -0,0 +1,110 @@
+use crate::config::{SPRITE_HEIGHT, SPRITE_WIDTH};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A click played on the board: `button` 1 reveals, 2 chords and 3 flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Click {
+    /// Time into the game, 0 until the first reveal starts the clock.
+    pub(crate) millis: u64,
+    pub(crate) button: u8,
+    pub(crate) cell: usize,
+}
+
+/// A finished game written out for `--export-replays`, in the RawVF text format the
+/// community's video tools convert to and from: a header, the mines, then every press and
+/// release with its time, at the middle of the cell clicked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Replay {
+    /// Difficulty name, e.g. `expert` or `custom`.
+    pub(crate) level: &'static str,
+    pub(crate) columns: usize,
+    pub(crate) mines: Vec<bool>,
+    pub(crate) bbbv: Option<u32>,
+    pub(crate) millis: u64,
+    pub(crate) won: bool,
+    /// Hints asked for during the game, so assisted runs can be told apart.
+    pub(crate) hints: u32,
+    pub(crate) clicks: Vec<Click>,
+}
+
+impl fmt::Display for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = |millis: u64| millis as f64 / 1000.0;
+        let mut level = self.level.to_string();
+        level[..1].make_ascii_uppercase();
+        writeln!(f, "RawVF_Version: Rev1")?;
+        writeln!(f, "Program: mineswept-x11")?;
+        writeln!(f, "Version: {}", env!("CARGO_PKG_VERSION"))?;
+        writeln!(f, "Level: {}", level)?;
+        writeln!(f, "Width: {}", self.columns)?;
+        writeln!(f, "Height: {}", self.mines.len() / self.columns)?;
+        writeln!(f, "Mines: {}", self.mines.iter().filter(|&&mine| mine).count())?;
+        writeln!(f, "Marks: Off")?;
+        writeln!(f, "Mode: Classic")?;
+        writeln!(f, "Time: {:.3}", seconds(self.millis))?;
+        if let Some(bbbv) = self.bbbv {
+            writeln!(f, "BBBV: {}", bbbv)?;
+        }
+        writeln!(f, "Hints: {}", self.hints)?;
+        writeln!(f, "Board:")?;
+        for row in self.mines.chunks(self.columns) {
+            let line: String = row.iter().map(|&mine| if mine { '*' } else { '0' }).collect();
+            writeln!(f, "{}", line)?;
+        }
+
+        writeln!(f, "Events:")?;
+        writeln!(f, "0.000 start")?;
+        for click in &self.clicks {
+            let (row, column) = (click.cell / self.columns, click.cell % self.columns);
+            let (x, y) = (column * SPRITE_WIDTH as usize + SPRITE_WIDTH as usize / 2, row * SPRITE_HEIGHT as usize + SPRITE_HEIGHT as usize / 2);
+            let button = match click.button {
+                1 => 'l',
+                2 => 'm',
+                _ => 'r',
+            };
+            for action in ['c', 'r'] {
+                writeln!(f, "{:.3} {}{} {} {} ({} {})", seconds(click.millis), button, action, x, y, column + 1, row + 1)?;
+            }
+        }
+        writeln!(f, "{:.3} {}", seconds(self.millis), if self.won { "won" } else { "blast" })
+    }
+}
+
+impl Replay {
+    /// Writes the replay to a new file in `dir`, returning its path.
+    pub(crate) fn save(&self, dir: &Path) -> io::Result<PathBuf> {
+        fs::create_dir_all(dir)?;
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_millis());
+        let path = dir.join(format!("mineswept-{}.rawvf", timestamp));
+        fs::write(&path, self.to_string())?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_presses_and_releases_at_the_middle_of_cells() {
+        let replay = Replay {
+            level: "custom",
+            columns: 3,
+            mines: vec![true, false, false, false, false, false],
+            bbbv: Some(1),
+            millis: 1250,
+            won: true,
+            hints: 2,
+            clicks: vec![Click { millis: 0, button: 3, cell: 0 }, Click { millis: 1250, button: 1, cell: 5 }],
+        };
+        let text = replay.to_string();
+        assert!(text.starts_with("RawVF_Version: Rev1\n"));
+        assert!(text.contains("\nLevel: Custom\nWidth: 3\nHeight: 2\nMines: 1\n"));
+        assert!(text.contains("\nBBBV: 1\nHints: 2\nBoard:\n*00\n000\nEvents:\n0.000 start\n0.000 rc 8 8 (1 1)\n0.000 rr 8 8 (1 1)\n"));
+        assert!(text.ends_with("1.250 lc 40 24 (3 2)\n1.250 lr 40 24 (3 2)\n1.250 won\n"));
+    }
+}
@@ -0,0 +1,424 @@
+use crate::config::{Adjacency, BoardSize};
+use crate::game::{EntityKind, SceneState};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const HEADER: &str = "mineswept board ";
+/// Version 2 added the `elapsed` line, version 3 the `adjacency` one and version 4 the
+/// `anti-mines` one; boards from before load with no time played, the classic adjacency
+/// and no anti-mines.
+const FORMAT_VERSION: u32 = 4;
+
+/// A board as written to disk: its size, the game state, how long it has been played and
+/// every cell.
+///
+/// The format is plain text, one character per cell, so saved games can be inspected:
+/// `.`/`*` covered without/with a mine, `f`/`F` flagged without/with a mine, `0`-`9`
+/// uncovered, `X` an exploded mine, `M` a revealed one and `W` a flag that turned out to
+/// be wrong. Which mines are anti-mines is listed apart, by cell index; the numbers around
+/// them are written without their sign, and those past 8 as `9`, and all are worked out
+/// again when the board is played.
+///
+/// Boards written by hand, e.g. to share a layout or reproduce a bug, can leave out the
+/// header lines and give just the rows of cells, most simply `.` and `*`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SavedBoard {
+    pub(crate) rows: u16,
+    pub(crate) columns: u16,
+    pub(crate) state: SceneState,
+    /// Time played since the first cell was revealed.
+    pub(crate) elapsed_millis: u64,
+    /// Which cells the numbers count.
+    pub(crate) adjacency: Adjacency,
+    /// What each cell shows and whether it holds a mine, row by row.
+    pub(crate) cells: Vec<(EntityKind, bool)>,
+    /// Indices of the mines that are anti-mines, in order.
+    pub(crate) anti_mines: Vec<usize>,
+}
+
+fn cell_char(entity: EntityKind, mine: bool) -> char {
+    match (entity, mine) {
+        (EntityKind::Covered, false) => '.',
+        (EntityKind::Covered, true) => '*',
+        (EntityKind::Flagged, false) => 'f',
+        (EntityKind::Flagged, true) => 'F',
+        (EntityKind::MineExploded, _) => 'X',
+        (EntityKind::MineIdle, _) => 'M',
+        (EntityKind::FlagMisplaced, _) => 'W',
+        (EntityKind::Uncovered0, _) => '0',
+        (EntityKind::Uncovered1, _) => '1',
+        (EntityKind::Uncovered2, _) => '2',
+        (EntityKind::Uncovered3, _) => '3',
+        (EntityKind::Uncovered4, _) => '4',
+        (EntityKind::Uncovered5, _) => '5',
+        (EntityKind::Uncovered6, _) => '6',
+        (EntityKind::Uncovered7, _) => '7',
+        (EntityKind::Uncovered8, _) => '8',
+        (EntityKind::Uncovered9 | EntityKind::Uncovered10 | EntityKind::Uncovered11 | EntityKind::Uncovered12
+            | EntityKind::Uncovered13 | EntityKind::Uncovered14 | EntityKind::Uncovered15 | EntityKind::Uncovered16
+            | EntityKind::Uncovered17 | EntityKind::Uncovered18 | EntityKind::Uncovered19 | EntityKind::Uncovered20
+            | EntityKind::Uncovered21 | EntityKind::Uncovered22 | EntityKind::Uncovered23 | EntityKind::Uncovered24, _) => '9',
+        (EntityKind::AntiMineExploded, _) => 'X',
+        (EntityKind::AntiMineIdle, _) => 'M',
+        (EntityKind::UncoveredBalanced, _) => '0',
+        (EntityKind::UncoveredMinus1, _) => '1',
+        (EntityKind::UncoveredMinus2, _) => '2',
+        (EntityKind::UncoveredMinus3, _) => '3',
+        (EntityKind::UncoveredMinus4, _) => '4',
+        (EntityKind::UncoveredMinus5, _) => '5',
+        (EntityKind::UncoveredMinus6, _) => '6',
+        (EntityKind::UncoveredMinus7, _) => '7',
+        (EntityKind::UncoveredMinus8, _) => '8',
+    }
+}
+
+fn parse_cell(c: char) -> Option<(EntityKind, bool)> {
+    Some(match c {
+        '.' => (EntityKind::Covered, false),
+        '*' => (EntityKind::Covered, true),
+        'f' => (EntityKind::Flagged, false),
+        'F' => (EntityKind::Flagged, true),
+        'X' => (EntityKind::MineExploded, true),
+        'M' => (EntityKind::MineIdle, true),
+        'W' => (EntityKind::FlagMisplaced, false),
+        '0' => (EntityKind::Uncovered0, false),
+        '1' => (EntityKind::Uncovered1, false),
+        '2' => (EntityKind::Uncovered2, false),
+        '3' => (EntityKind::Uncovered3, false),
+        '4' => (EntityKind::Uncovered4, false),
+        '5' => (EntityKind::Uncovered5, false),
+        '6' => (EntityKind::Uncovered6, false),
+        '7' => (EntityKind::Uncovered7, false),
+        '8' => (EntityKind::Uncovered8, false),
+        '9' => (EntityKind::Uncovered9, false),
+        _ => return None,
+    })
+}
+
+fn state_name(state: SceneState) -> &'static str {
+    match state {
+        SceneState::Won => "won",
+        SceneState::Lost => "lost",
+        _ => "ready",
+    }
+}
+
+impl fmt::Display for SavedBoard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}{}", HEADER, FORMAT_VERSION)?;
+        writeln!(f, "size {} {}", self.rows, self.columns)?;
+        writeln!(f, "state {}", state_name(self.state))?;
+        writeln!(f, "elapsed {}", self.elapsed_millis)?;
+        writeln!(f, "adjacency {}", self.adjacency.name())?;
+        write!(f, "anti-mines")?;
+        for idx in &self.anti_mines {
+            write!(f, " {}", idx)?;
+        }
+        writeln!(f)?;
+        for row in self.cells.chunks(self.columns as usize) {
+            let line: String = row.iter().map(|&(entity, mine)| cell_char(entity, mine)).collect();
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SavedBoard {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        if !text.starts_with(HEADER) {
+            return SavedBoard::parse_grid(text);
+        }
+
+        let mut lines = text.lines();
+        let version = lines.next()
+            .and_then(|line| line.strip_prefix(HEADER))
+            .and_then(|version| version.parse::<u32>().ok())
+            .filter(|version| (1..=FORMAT_VERSION).contains(version))
+            .ok_or("Not a saved board, or saved by a newer version")?;
+
+        let size = lines.next().and_then(|line| line.strip_prefix("size ")).ok_or("Missing board size")?;
+        let (rows, columns) = size.split_once(' ').ok_or("Invalid board size")?;
+        let rows: u16 = rows.parse().map_err(|_| "Invalid row count")?;
+        let columns: u16 = columns.parse().map_err(|_| "Invalid column count")?;
+
+        let state = match lines.next().and_then(|line| line.strip_prefix("state ")) {
+            Some("ready") => SceneState::Ready,
+            Some("won") => SceneState::Won,
+            Some("lost") => SceneState::Lost,
+            _ => return Err("Invalid game state".to_string()),
+        };
+
+        let elapsed_millis = match version {
+            1 => 0,
+            _ => lines.next()
+                .and_then(|line| line.strip_prefix("elapsed "))
+                .and_then(|millis| millis.parse().ok())
+                .ok_or("Missing or invalid elapsed time")?,
+        };
+        let adjacency = match version {
+            1 | 2 => Adjacency::King,
+            _ => lines.next()
+                .and_then(|line| line.strip_prefix("adjacency "))
+                .and_then(|adjacency| adjacency.parse().ok())
+                .ok_or("Missing or invalid adjacency")?,
+        };
+
+        let anti_mines: Vec<usize> = match version {
+            1..=3 => Vec::new(),
+            _ => lines.next()
+                .and_then(|line| line.strip_prefix("anti-mines"))
+                .and_then(|indices| indices.split_whitespace().map(|idx| idx.parse().ok()).collect())
+                .ok_or("Missing or invalid anti-mines")?,
+        };
+
+        let mut cells = Vec::with_capacity(rows as usize * columns as usize);
+        for _ in 0..rows {
+            let line = lines.next().ok_or("Missing board rows")?;
+            if line.chars().count() != columns as usize {
+                return Err("Board row of the wrong length".to_string());
+            }
+            for c in line.chars() {
+                cells.push(parse_cell(c).ok_or_else(|| format!("Invalid cell {:?}", c))?);
+            }
+        }
+
+        if anti_mines.iter().any(|&idx| !cells.get(idx).is_some_and(|&(_, mine)| mine)) {
+            return Err("Anti-mine listed on a cell without a mine".to_string());
+        }
+        if !anti_mines.is_empty() && adjacency.reach() > 8 {
+            return Err(format!("Anti-mines can't be played with {} adjacency", adjacency.name()));
+        }
+
+        Ok(SavedBoard { rows, columns, state, elapsed_millis, adjacency, cells, anti_mines })
+    }
+}
+
+impl SavedBoard {
+    /// Parses a bare grid of cells, as a board that hasn't been played yet.
+    fn parse_grid(text: &str) -> Result<SavedBoard, String> {
+        let lines: Vec<&str> = text.lines().map(str::trim_end).filter(|line| !line.is_empty()).collect();
+        let columns = lines.first().map_or(0, |line| line.chars().count());
+        if !(2..=255).contains(&lines.len()) || !(2..=255).contains(&columns) {
+            return Err("A board needs 2 to 255 rows and columns".to_string());
+        }
+
+        let mut cells = Vec::with_capacity(lines.len() * columns);
+        for line in &lines {
+            if line.chars().count() != columns {
+                return Err("Board row of the wrong length".to_string());
+            }
+            for c in line.chars() {
+                cells.push(parse_cell(c).ok_or_else(|| format!("Invalid cell {:?}", c))?);
+            }
+        }
+        if cells.iter().all(|&(_, mine)| mine) {
+            return Err("A board needs at least one cell without a mine".to_string());
+        }
+
+        Ok(SavedBoard {
+            rows: lines.len() as u16,
+            columns: columns as u16,
+            state: SceneState::Ready,
+            elapsed_millis: 0,
+            adjacency: Adjacency::King,
+            cells,
+            anti_mines: Vec::new(),
+        })
+    }
+
+    /// Makes a board `rows` by `columns` out of an image `width` by `height` pixels of
+    /// RGBA, stretched over it: each cell whose share of the image is darker on average
+    /// than mid-grey holds a mine. Transparent pixels count as white.
+    pub(crate) fn from_image(rgba: &[u8], width: usize, height: usize, rows: u16, columns: u16, adjacency: Adjacency) -> Result<SavedBoard, String> {
+        let luminance = |x: usize, y: usize| {
+            let pixel = &rgba[(y * width + x) * 4..][..4];
+            let luminance = 0.299 * pixel[0] as f64 + 0.587 * pixel[1] as f64 + 0.114 * pixel[2] as f64;
+            let alpha = pixel[3] as f64 / 255.0;
+            luminance * alpha + 255.0 * (1.0 - alpha)
+        };
+        // The pixels a cell covers, at least one even where the image is smaller than the board
+        let span = |cell: usize, cells: usize, pixels: usize| {
+            let start = (cell * pixels / cells).min(pixels - 1);
+            start..(((cell + 1) * pixels / cells).max(start + 1))
+        };
+
+        let mut cells = Vec::with_capacity(rows as usize * columns as usize);
+        for row in 0..rows as usize {
+            let ys = span(row, rows as usize, height);
+            for column in 0..columns as usize {
+                let xs = span(column, columns as usize, width);
+                let pixels = ys.len() * xs.len();
+                let total: f64 = ys.clone().flat_map(|y| xs.clone().map(move |x| (x, y))).map(|(x, y)| luminance(x, y)).sum();
+                cells.push((EntityKind::Covered, total / (pixels as f64) < 128.0));
+            }
+        }
+        if cells.iter().all(|&(_, mine)| mine) {
+            return Err("the image is too dark to leave any cell without a mine".to_string());
+        }
+
+        Ok(SavedBoard { rows, columns, state: SceneState::Ready, elapsed_millis: 0, adjacency, cells, anti_mines: Vec::new() })
+    }
+
+    pub(crate) fn board_size(&self) -> BoardSize {
+        let mines = self.cells.iter().filter(|&&(_, mine)| mine).count();
+        BoardSize { rows: self.rows, columns: self.columns, mines: mines as u16, anti_mines: self.anti_mines.len() as u16 }
+    }
+
+    pub(crate) fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Written aside and renamed, so a save cut short by logout can't clobber the last one
+        let partial = path.with_extension("partial");
+        fs::write(&partial, self.to_string())?;
+        fs::rename(&partial, path)
+    }
+
+    /// Parses a board in the binary MBF format board packs are shared in: the width and
+    /// height in a byte each, the mine count in two (big-endian), then the column and row
+    /// of each mine in a byte each.
+    fn parse_mbf(bytes: &[u8]) -> Result<SavedBoard, String> {
+        let [columns, rows, count_high, count_low, positions @ ..] = bytes else {
+            return Err("MBF file too short".to_string());
+        };
+        let (rows, columns) = (*rows as usize, *columns as usize);
+        if rows < 2 || columns < 2 {
+            return Err("A board needs 2 to 255 rows and columns".to_string());
+        }
+        let count = u16::from_be_bytes([*count_high, *count_low]) as usize;
+        if positions.len() != count * 2 {
+            return Err(format!("MBF file lists {} mine positions for {} mines", positions.len() / 2, count));
+        }
+
+        let mut cells = vec![(EntityKind::Covered, false); rows * columns];
+        for position in positions.chunks(2) {
+            let (column, row) = (position[0] as usize, position[1] as usize);
+            if column >= columns || row >= rows {
+                return Err(format!("Mine at column {}, row {} off the board", column, row));
+            }
+            if std::mem::replace(&mut cells[row * columns + column].1, true) {
+                return Err(format!("Two mines at column {}, row {}", column, row));
+            }
+        }
+        if cells.iter().all(|&(_, mine)| mine) {
+            return Err("A board needs at least one cell without a mine".to_string());
+        }
+
+        Ok(SavedBoard {
+            rows: rows as u16,
+            columns: columns as u16,
+            state: SceneState::Ready,
+            elapsed_millis: 0,
+            adjacency: Adjacency::King,
+            cells,
+            anti_mines: Vec::new(),
+        })
+    }
+
+    /// Reads the board at `path`, or `None` if nothing was saved there. Files ending in
+    /// `.mbf` are read as MBF, any other as text.
+    pub(crate) fn load(path: &Path) -> io::Result<Option<SavedBoard>> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let board = match path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("mbf")) {
+            true => SavedBoard::parse_mbf(&bytes),
+            false => String::from_utf8(bytes).map_err(|_| "Not a text board".to_string()).and_then(|text| text.parse()),
+        };
+        board
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+    }
+}
+
+/// Directory for state kept between runs: `$XDG_STATE_HOME/mineswept`, by default
+/// `~/.local/state/mineswept`.
+pub(crate) fn state_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_STATE_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(base.join("mineswept"))
+}
+
+/// Where Ctrl+S and quitting mid-game save the board for `--resume`.
+pub(crate) fn resume_path() -> Option<PathBuf> {
+    Some(state_dir()?.join("saved.board"))
+}
+
+/// Where the board is saved for the session manager under `client_id`.
+pub(crate) fn session_board_path(client_id: &str) -> Option<PathBuf> {
+    // Client IDs are printable ASCII, but keep path separators out regardless
+    let name: String = client_id.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' }).collect();
+    Some(state_dir()?.join(format!("session-{}.board", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_bare_grid() {
+        let board: SavedBoard = "..*\n*.f\n".parse().unwrap();
+        assert_eq!((board.rows, board.columns, board.state), (2, 3, SceneState::Ready));
+        assert_eq!(board.board_size().mines, 2);
+        assert_eq!(board.cells[5], (EntityKind::Flagged, false));
+    }
+
+    #[test]
+    fn draws_mines_where_an_image_is_dark() {
+        // A 4 by 2 image, its left half black and its right half white but for a
+        // transparent black pixel, on a board of 2 by 4 cells
+        let (black, white, clear) = ([0, 0, 0, 255], [255, 255, 255, 255], [0, 0, 0, 0]);
+        let rgba: Vec<u8> = [black, black, white, clear, black, black, white, white].concat();
+        let board = SavedBoard::from_image(&rgba, 4, 2, 2, 4, Adjacency::King).unwrap();
+        let mines: Vec<bool> = board.cells.iter().map(|&(_, mine)| mine).collect();
+        assert_eq!(mines, [true, true, false, false, true, true, false, false]);
+
+        assert!(SavedBoard::from_image(&black, 1, 1, 2, 2, Adjacency::King).is_err());
+    }
+
+    #[test]
+    fn parses_mbf_boards() {
+        // 3 wide and 2 high, with mines in the top left and bottom right corners
+        let board = SavedBoard::parse_mbf(&[3, 2, 0, 2, 0, 0, 2, 1]).unwrap();
+        assert_eq!(board, "*..\n..*\n".parse().unwrap());
+
+        assert!(SavedBoard::parse_mbf(&[3, 2, 0, 2, 0, 0]).is_err());
+        assert!(SavedBoard::parse_mbf(&[3, 2, 0, 1, 3, 0]).is_err());
+        assert!(SavedBoard::parse_mbf(&[3, 2, 0, 2, 1, 1, 1, 1]).is_err());
+    }
+
+    #[test]
+    fn rejects_ragged_grids() {
+        assert!("...\n..\n".parse::<SavedBoard>().is_err());
+        assert!("..x\n...\n".parse::<SavedBoard>().is_err());
+    }
+
+    #[test]
+    fn round_trips_through_text() {
+        let board = SavedBoard {
+            rows: 2,
+            columns: 2,
+            state: SceneState::Lost,
+            elapsed_millis: 1234,
+            adjacency: Adjacency::Knight,
+            cells: vec![
+                (EntityKind::MineExploded, true),
+                (EntityKind::Uncovered1, false),
+                (EntityKind::FlagMisplaced, false),
+                (EntityKind::Flagged, true),
+            ],
+            anti_mines: vec![3],
+        };
+        assert_eq!(board.to_string().parse::<SavedBoard>(), Ok(board));
+    }
+}
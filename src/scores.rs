@@ -0,0 +1,168 @@
+use crate::stats::data_dir;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Version written in the scores file header. Files with a newer version are left
+/// untouched rather than rewritten in a format their writer wouldn't understand.
+const FORMAT_VERSION: u32 = 1;
+/// Winning times kept per difficulty.
+const TIMES_KEPT: usize = 10;
+
+/// A winning time and when it was set.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Score {
+    pub(crate) millis: u64,
+    /// Seconds since the Unix epoch.
+    pub(crate) achieved_at: u64,
+    /// Time at which each split of the game (`--splits`) was reached, as the percentage
+    /// of the board's 3BV cleared and the milliseconds played.
+    pub(crate) splits: Vec<(u8, u64)>,
+}
+
+/// The fastest wins per difficulty name, stored in `$XDG_DATA_HOME/mineswept/scores`: a
+/// `version N` line, then `<difficulty> <millis> <unix time>` lines, followed by a
+/// `<percent>%:<millis>` field per split. Fields after those are ignored, so later
+/// versions can append to a line without breaking older readers.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Scores {
+    pub(crate) difficulties: BTreeMap<String, Vec<Score>>,
+}
+
+impl Scores {
+    pub(crate) fn load() -> io::Result<Scores> {
+        let Some(path) = scores_path() else {
+            return Ok(Scores::default());
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Scores::default()),
+            Err(e) => return Err(e),
+        };
+
+        let mut lines = text.lines().filter(|line| !line.starts_with('#'));
+        let version: Option<u32> = lines.next()
+            .and_then(|line| line.strip_prefix("version "))
+            .and_then(|version| version.trim().parse().ok());
+        match version {
+            Some(version) if version <= FORMAT_VERSION => {}
+            _ => return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: unknown format, written by a newer version?", path.display()),
+            )),
+        }
+
+        let mut scores = Scores::default();
+        for line in lines {
+            let mut fields = line.split_whitespace();
+            let (Some(name), Some(millis), Some(achieved_at)) = (fields.next(), fields.next(), fields.next()) else {
+                continue;
+            };
+            let (Ok(millis), Ok(achieved_at)) = (millis.parse(), achieved_at.parse()) else {
+                continue;
+            };
+            let splits = fields
+                .map_while(|field| {
+                    let (percent, millis) = field.split_once("%:")?;
+                    Some((percent.parse().ok()?, millis.parse().ok()?))
+                })
+                .collect();
+            scores.difficulties.entry(name.to_string()).or_default().push(Score { millis, achieved_at, splits });
+        }
+        for times in scores.difficulties.values_mut() {
+            times.sort();
+        }
+        Ok(scores)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let path = scores_path().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No data directory (HOME unset)"))?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut contents = format!("# mineswept best times\nversion {}\n", FORMAT_VERSION);
+        for (name, times) in &self.difficulties {
+            for score in times {
+                contents += &format!("{} {} {}", name, score.millis, score.achieved_at);
+                for (percent, millis) in &score.splits {
+                    contents += &format!(" {}%:{}", percent, millis);
+                }
+                contents += "\n";
+            }
+        }
+        let partial = path.with_extension("partial");
+        fs::write(&partial, contents)?;
+        fs::rename(&partial, path)
+    }
+
+    /// Fastest time for `difficulty`, if it was ever won.
+    pub(crate) fn best(&self, difficulty: &str) -> Option<u64> {
+        Some(self.difficulties.get(difficulty)?.first()?.millis)
+    }
+
+    /// Split times of the fastest win for `difficulty`; empty if it had none.
+    pub(crate) fn best_splits(&self, difficulty: &str) -> Vec<(u8, u64)> {
+        self.difficulties.get(difficulty).and_then(|times| times.first()).map_or_else(Vec::new, |score| score.splits.clone())
+    }
+
+    /// Adds a win in `millis`, with the `splits` reached on the way, to the leaderboard
+    /// of `difficulty` and saves it. Returns whether it beat the previous best.
+    pub(crate) fn record(difficulty: &str, millis: u64, splits: &[(u8, u64)]) -> io::Result<bool> {
+        let mut scores = Scores::load()?;
+        let new_record = scores.best(difficulty).is_none_or(|best| millis < best);
+
+        let achieved_at = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+        let times = scores.difficulties.entry(difficulty.to_string()).or_default();
+        times.push(Score { millis, achieved_at, splits: splits.to_vec() });
+        times.sort();
+        times.truncate(TIMES_KEPT);
+
+        scores.save()?;
+        Ok(new_record)
+    }
+
+    /// Forgets every recorded time.
+    pub(crate) fn reset() -> io::Result<()> {
+        match scores_path().map(fs::remove_file) {
+            Some(Err(e)) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Scores {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.difficulties.is_empty() {
+            return writeln!(f, "No best times yet");
+        }
+        writeln!(f, "Best times")?;
+        for (name, times) in &self.difficulties {
+            let times: Vec<String> = times.iter().map(|score| format_millis(score.millis)).collect();
+            writeln!(f, "{:<14} {}", name, times.join("  "))?;
+        }
+        Ok(())
+    }
+}
+
+/// `12.3s`, or `2m05.0s` past a minute.
+pub(crate) fn format_millis(millis: u64) -> String {
+    let (minutes, seconds, tenths) = (millis / 60_000, millis / 1000 % 60, millis / 100 % 10);
+    match minutes {
+        0 => format!("{}.{}s", seconds, tenths),
+        _ => format!("{}m{:02}.{}s", minutes, seconds, tenths),
+    }
+}
+
+/// `+1.2s` or `-0.8s`: how far `millis` is behind or ahead of `best`.
+pub(crate) fn format_delta(millis: u64, best: u64) -> String {
+    let sign = if millis > best { '+' } else { '-' };
+    format!("{}{}", sign, format_millis(millis.abs_diff(best)))
+}
+
+fn scores_path() -> Option<PathBuf> {
+    Some(data_dir()?.join("scores"))
+}
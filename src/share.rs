@@ -0,0 +1,36 @@
+use crate::game::EntityKind;
+use crate::scores::format_millis;
+
+/// The game just over as text to paste into a chat: a line with the difficulty, how the
+/// game ended and its time, then a row of emoji for each row of the board as it was left.
+pub(crate) fn emoji_grid(difficulty: &str, won: bool, millis: Option<u64>, displayed: &[EntityKind], columns: usize) -> String {
+    let outcome = if won { "won" } else { "lost" };
+    let mut text = match millis {
+        Some(millis) => format!("Mineswept {}: {} in {}\n", difficulty, outcome, format_millis(millis)),
+        None => format!("Mineswept {}: {}\n", difficulty, outcome),
+    };
+    for row in displayed.chunks(columns) {
+        text.extend(row.iter().map(|&entity| match entity {
+            entity if entity.sum().is_some() => '\u{1f7e9}', // green square
+            entity if entity.exploded() => '\u{1f4a5}', // collision
+            EntityKind::Flagged => '\u{1f6a9}', // triangular flag
+            EntityKind::MineIdle | EntityKind::AntiMineIdle => '\u{1f4a3}', // bomb
+            EntityKind::FlagMisplaced => '\u{274c}', // cross mark
+            _ => '\u{2b1c}', // white square
+        }));
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_a_square_per_cell() {
+        use EntityKind::{Covered, Flagged, MineExploded, MineIdle, Uncovered0, Uncovered1};
+        let displayed = [Uncovered0, Uncovered1, Flagged, MineExploded, MineIdle, Covered];
+        assert_eq!(emoji_grid("custom", false, None, &displayed, 3), "Mineswept custom: lost\n🟩🟩🚩\n💥💣⬜\n");
+    }
+}
@@ -0,0 +1,151 @@
+use crate::encoder::RequestWriter;
+use crate::x11comm::{x11_put_image, x11_query_extension, ConnectionInformation, X11Connection};
+use std::ffi::c_void;
+use std::io;
+use std::ptr;
+
+const IPC_PRIVATE: i32 = 0;
+const IPC_CREAT: i32 = 0o1000;
+const IPC_RMID: i32 = 0;
+
+extern "C" {
+    fn shmget(key: i32, size: usize, shmflg: i32) -> i32;
+    fn shmat(shmid: i32, shmaddr: *const c_void, shmflg: i32) -> *mut c_void;
+    fn shmdt(shmaddr: *const c_void) -> i32;
+    fn shmctl(shmid: i32, cmd: i32, buf: *mut c_void) -> i32;
+}
+
+const SHM_QUERY_VERSION: u8 = 0;
+const SHM_ATTACH: u8 = 1;
+const SHM_DETACH: u8 = 2;
+const SHM_PUT_IMAGE: u8 = 3;
+
+/// A System V shared memory segment attached both locally and by the X server.
+#[derive(Debug)]
+pub(crate) struct ShmSegment {
+    major_opcode: u8,
+    seg_id: u32,
+    addr: *mut u8,
+    size: usize,
+}
+
+fn shm_query_version(conn: &mut X11Connection, major_opcode: u8) -> io::Result<()> {
+    let request = RequestWriter::new(major_opcode, SHM_QUERY_VERSION).finish();
+    conn.send_with_reply(&request).map(|_| ())
+}
+
+impl ShmSegment {
+    /// Allocates a segment of `size` bytes and attaches it to the server as `seg_id`.
+    pub(crate) fn create(conn: &mut X11Connection, major_opcode: u8, seg_id: u32, size: usize) -> io::Result<Self> {
+        let shm_id = unsafe { shmget(IPC_PRIVATE, size, IPC_CREAT | 0o600) };
+        if shm_id < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let addr = unsafe { shmat(shm_id, ptr::null(), 0) };
+        if addr as isize == -1 {
+            let error = io::Error::last_os_error();
+            unsafe { shmctl(shm_id, IPC_RMID, ptr::null_mut()) };
+            return Err(error);
+        }
+
+        let request = RequestWriter::new(major_opcode, SHM_ATTACH)
+            .put_u32(seg_id)
+            .put_u32(shm_id as u32)
+            .put_u8(1) // read-only
+            .pad(3)
+            .finish();
+        let attached = conn
+            .send(&request)
+            // Round trip so a failed attach (e.g. BadAccess across namespaces) is reported here
+            .and_then(|_| shm_query_version(conn, major_opcode));
+
+        // The segment lives on until both sides detach
+        unsafe { shmctl(shm_id, IPC_RMID, ptr::null_mut()) };
+
+        if let Err(e) = attached {
+            unsafe { shmdt(addr) };
+            return Err(e);
+        }
+
+        Ok(ShmSegment { major_opcode, seg_id, addr: addr as *mut u8, size })
+    }
+
+    /// Copies `data` into the segment and asks the server to draw it with ShmPutImage.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn put_image(&mut self,
+                            conn: &mut X11Connection,
+                            drawable_id: u32,
+                            gc_id: u32,
+                            width: u16,
+                            height: u16,
+                            dst_x: u16,
+                            dst_y: u16,
+                            depth: u8,
+                            data: &[u8]) -> io::Result<()> {
+        if data.len() > self.size {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Image does not fit in the shared memory segment"));
+        }
+        unsafe { ptr::copy_nonoverlapping(data.as_ptr(), self.addr, data.len()) };
+
+        let request = RequestWriter::new(self.major_opcode, SHM_PUT_IMAGE)
+            .put_u32(drawable_id)
+            .put_u32(gc_id)
+            .put_u16(width) // total width
+            .put_u16(height) // total height
+            .put_u16(0) // src x
+            .put_u16(0) // src y
+            .put_u16(width)
+            .put_u16(height)
+            .put_u16(dst_x)
+            .put_u16(dst_y)
+            .put_u8(depth)
+            .put_u8(2) // ZPixmap
+            .put_u8(0) // send_event
+            .pad(1)
+            .put_u32(self.seg_id)
+            .put_u32(0) // offset
+            .finish();
+        conn.send(&request)?;
+
+        // The server reads the segment asynchronously; wait until it is done before reuse
+        shm_query_version(conn, self.major_opcode)
+    }
+
+    /// Detaches the segment on the server and locally.
+    pub(crate) fn destroy(self, conn: &mut X11Connection) -> io::Result<()> {
+        let request = RequestWriter::new(self.major_opcode, SHM_DETACH)
+            .put_u32(self.seg_id)
+            .finish();
+        let result = conn.send(&request);
+        unsafe { shmdt(self.addr as *const c_void) };
+        result.map(|_| ())
+    }
+}
+
+/// Uploads a ZPixmap image through MIT-SHM when available, falling back to PutImage.
+///
+/// `seg_id` is a free resource id used for the temporary segment.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn upload_image(conn: &mut X11Connection,
+                           info: &ConnectionInformation,
+                           seg_id: u32,
+                           drawable_id: u32,
+                           gc_id: u32,
+                           width: u16,
+                           height: u16,
+                           depth: u8,
+                           data: Vec<u8>) -> io::Result<()> {
+    if let Some(extension) = x11_query_extension(conn, "MIT-SHM")? {
+        match ShmSegment::create(conn, extension.major_opcode, seg_id, data.len()) {
+            Ok(mut segment) => {
+                let result = segment.put_image(conn, drawable_id, gc_id, width, height, 0, 0, depth, &data);
+                segment.destroy(conn)?;
+                return result;
+            }
+            Err(e) => eprintln!("MIT-SHM unavailable, falling back to PutImage: {}", e),
+        }
+    }
+
+    x11_put_image(conn, info, drawable_id, gc_id, width, height, 0, 0, depth, &data)
+}
@@ -0,0 +1,157 @@
+use crate::config::Adjacency;
+use crate::game::EntityKind;
+use std::collections::BTreeMap;
+#[cfg(test)]
+use crate::savegame::SavedBoard;
+
+/// What the visible numbers prove about a covered cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Deduction {
+    Safe(usize),
+    Mine(usize),
+}
+
+impl Deduction {
+    pub(crate) fn cell(self) -> usize {
+        match self {
+            Deduction::Safe(idx) | Deduction::Mine(idx) => idx,
+        }
+    }
+}
+
+/// The mines less anti-mines among `cells` (sorted) add up to `sum`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Constraint {
+    cells: Vec<usize>,
+    sum: i8,
+}
+
+/// Finds every covered cell the uncovered numbers prove safe or mined, on a board of
+/// `columns` columns showing `cells` whose numbers count the mines of `adjacency`'s
+/// neighbours. With `anti_mines` the numbers are the mines less the anti-mines around
+/// them, and an anti-mine counts as a mine to avoid.
+///
+/// Only numbers count as evidence: flags may be wrong, so flagged cells are treated as
+/// covered. Each number bounds its unknown neighbours, and a number whose unknown
+/// neighbours contain another's settles the cells only it sees. Both are applied until
+/// nothing new follows, which covers the patterns a player reads off the board without
+/// guessing, though not every deduction that is logically possible.
+pub(crate) fn deduce(cells: &[EntityKind], columns: usize, adjacency: Adjacency, anti_mines: bool) -> Vec<Deduction> {
+    let rows = cells.len() / columns;
+    let neighbours = |idx: usize| adjacency.neighbours(rows, columns, idx);
+    let unknown = |idx: usize| matches!(cells[idx], EntityKind::Covered | EntityKind::Flagged);
+
+    // What each cell adds to the numbers around it: 1 for a mine, -1 for an anti-mine
+    let mut known: BTreeMap<usize, i8> = BTreeMap::new();
+    loop {
+        let mut constraints: Vec<Constraint> = Vec::new();
+        for (idx, entity) in cells.iter().enumerate() {
+            let Some(sum) = entity.sum() else {
+                continue;
+            };
+            let mut unknown_cells = Vec::new();
+            // Mines that went off in a game with lives to spare are known too
+            let mut known_sum: i8 = neighbours(idx)
+                .map(|n| match cells[n] {
+                    EntityKind::MineExploded => 1,
+                    EntityKind::AntiMineExploded => -1,
+                    _ => 0,
+                })
+                .sum();
+            for n in neighbours(idx).filter(|&n| unknown(n)) {
+                match known.get(&n) {
+                    Some(&value) => known_sum += value,
+                    None => unknown_cells.push(n),
+                }
+            }
+            if !unknown_cells.is_empty() {
+                constraints.push(Constraint { cells: unknown_cells, sum: sum - known_sum });
+            }
+        }
+        constraints.sort_by(|a, b| a.cells.cmp(&b.cells));
+        constraints.dedup();
+
+        let mut found = false;
+        let mut settle = |cells: &[usize], sum: i8, known: &mut BTreeMap<usize, i8>| {
+            // Without anti-mines nothing can make up for a mine, so a sum of 0 clears
+            // every cell; with them only a single cell is settled by its value
+            let value = match sum {
+                sum if sum.unsigned_abs() as usize == cells.len() => sum.signum(),
+                0 if !anti_mines => 0,
+                sum if cells.len() == 1 && sum.abs() <= 1 => sum,
+                _ => return,
+            };
+            for &cell in cells {
+                found |= known.insert(cell, value).is_none();
+            }
+        };
+
+        for constraint in &constraints {
+            settle(&constraint.cells, constraint.sum, &mut known);
+        }
+        for a in &constraints {
+            for b in &constraints {
+                if a.cells.len() >= b.cells.len() || (!anti_mines && b.sum < a.sum) || !a.cells.iter().all(|cell| b.cells.binary_search(cell).is_ok()) {
+                    continue;
+                }
+                let rest: Vec<usize> = b.cells.iter().copied().filter(|cell| a.cells.binary_search(cell).is_err()).collect();
+                settle(&rest, b.sum - a.sum, &mut known);
+            }
+        }
+
+        if !found {
+            break;
+        }
+    }
+
+    known.into_iter()
+        .map(|(idx, value)| if value == 0 { Deduction::Safe(idx) } else { Deduction::Mine(idx) })
+        .collect()
+}
+
+/// Whether any move left on the board showing `cells` is a guess, as `deduce` proves no
+/// covered or flagged cell safe. It misses some deductions, so a board it finds nothing
+/// on may still have a safe cell that a deeper search would find.
+pub(crate) fn must_guess(cells: &[EntityKind], columns: usize, adjacency: Adjacency, anti_mines: bool) -> bool {
+    !deduce(cells, columns, adjacency, anti_mines).iter().any(|deduction| matches!(deduction, Deduction::Safe(_)))
+}
+
+/// Plays `board` by the solver's deductions alone, returning whether that clears it.
+#[cfg(test)]
+pub(crate) fn clears_without_guessing(board: &SavedBoard) -> bool {
+    let (rows, columns) = (board.rows as usize, board.columns as usize);
+    let mines: Vec<bool> = board.cells.iter().map(|&(_, mine)| mine).collect();
+    let count = |idx: usize| Adjacency::King.neighbours(rows, columns, idx).filter(|&n| mines[n]).count() as u8;
+    let mut cells: Vec<EntityKind> = board.cells.iter().enumerate()
+        .map(|(idx, &(entity, _))| if entity.number().is_some() { EntityKind::uncovered(count(idx)) } else { entity })
+        .collect();
+    loop {
+        let safe: Vec<usize> = deduce(&cells, columns, Adjacency::King, false).into_iter()
+            .filter_map(|deduction| match deduction {
+                Deduction::Safe(idx) if cells[idx] == EntityKind::Covered => Some(idx),
+                _ => None,
+            })
+            .collect();
+        if safe.is_empty() {
+            return (0..cells.len()).all(|idx| mines[idx] || cells[idx] != EntityKind::Covered);
+        }
+        for idx in safe {
+            cells[idx] = EntityKind::uncovered(count(idx));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn must_guess_between_cells_the_numbers_cant_tell_apart() {
+        use EntityKind::{Covered, Flagged, Uncovered0, Uncovered1};
+        // Two ones over two covered cells: either could be the mine
+        assert!(must_guess(&[Uncovered1, Uncovered1, Covered, Covered], 2, Adjacency::King, false));
+        // A flag settles nothing, but a zero does
+        assert!(must_guess(&[Uncovered1, Uncovered1, Flagged, Covered], 2, Adjacency::King, false));
+        assert!(!must_guess(&[Uncovered0, Covered, Covered], 3, Adjacency::King, false));
+    }
+}
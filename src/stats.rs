@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Totals kept for one difficulty.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct DifficultyStats {
+    /// Games in which at least one cell was revealed, finished or not.
+    pub(crate) played: u32,
+    pub(crate) won: u32,
+    pub(crate) lost: u32,
+    /// Wins in a row up to the last finished game.
+    pub(crate) streak: u32,
+    pub(crate) best_streak: u32,
+    /// Time spent in finished games.
+    pub(crate) seconds: u64,
+    /// Hints asked for in finished games.
+    pub(crate) hints: u32,
+}
+
+impl DifficultyStats {
+    /// Parses the `key=value` fields of a stats line. Unknown keys are skipped, so files
+    /// written by newer versions still load.
+    fn parse(fields: &str) -> DifficultyStats {
+        let mut stats = DifficultyStats::default();
+        for (key, value) in fields.split_whitespace().filter_map(|field| field.split_once('=')) {
+            match key {
+                "played" => stats.played = value.parse().unwrap_or(0),
+                "won" => stats.won = value.parse().unwrap_or(0),
+                "lost" => stats.lost = value.parse().unwrap_or(0),
+                "streak" => stats.streak = value.parse().unwrap_or(0),
+                "best_streak" => stats.best_streak = value.parse().unwrap_or(0),
+                "seconds" => stats.seconds = value.parse().unwrap_or(0),
+                "hints" => stats.hints = value.parse().unwrap_or(0),
+                _ => {}
+            }
+        }
+        stats
+    }
+
+    /// Lines for the in-game stats screen.
+    pub(crate) fn summary(&self) -> [String; 3] {
+        [
+            format!("Played {}  Won {}", self.played, self.won),
+            format!("Streak {} (best {})", self.streak, self.best_streak),
+            format!("Time {}  Hints {}", format_duration(self.seconds), self.hints),
+        ]
+    }
+}
+
+/// `1h02m03s`, leaving out leading zero units.
+pub(crate) fn format_duration(seconds: u64) -> String {
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m{:02}s", m, s),
+        (h, m, s) => format!("{}h{:02}m{:02}s", h, m, s),
+    }
+}
+
+/// Statistics per difficulty name, as stored in `$XDG_DATA_HOME/mineswept/stats`: one
+/// line per difficulty, its name followed by `key=value` fields.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Stats {
+    pub(crate) difficulties: BTreeMap<String, DifficultyStats>,
+}
+
+impl Stats {
+    /// Loads the stats file; a missing file means no games recorded yet.
+    pub(crate) fn load() -> io::Result<Stats> {
+        let Some(path) = stats_path() else {
+            return Ok(Stats::default());
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Stats::default()),
+            Err(e) => return Err(e),
+        };
+
+        let difficulties = text.lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| {
+                let (name, fields) = line.split_once(' ').unwrap_or((line, ""));
+                (!name.is_empty()).then(|| (name.to_string(), DifficultyStats::parse(fields)))
+            })
+            .collect();
+        Ok(Stats { difficulties })
+    }
+
+    pub(crate) fn save(&self) -> io::Result<()> {
+        let path = stats_path().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No data directory (HOME unset)"))?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let partial = path.with_extension("partial");
+        fs::write(&partial, self.to_file_contents())?;
+        fs::rename(&partial, path)
+    }
+
+    /// Loads the stats, applies `change` to the entry for `difficulty` and writes them back
+    /// straight away, so several running games don't overwrite each other's results.
+    pub(crate) fn update(difficulty: &str, change: impl FnOnce(&mut DifficultyStats)) -> io::Result<()> {
+        let mut stats = Stats::load()?;
+        change(stats.difficulties.entry(difficulty.to_string()).or_default());
+        stats.save()
+    }
+
+    fn to_file_contents(&self) -> String {
+        let mut contents = String::from("# mineswept statistics\n");
+        for (name, stats) in &self.difficulties {
+            contents += &format!(
+                "{} played={} won={} lost={} streak={} best_streak={} seconds={} hints={}\n",
+                name, stats.played, stats.won, stats.lost, stats.streak, stats.best_streak, stats.seconds, stats.hints,
+            );
+        }
+        contents
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.difficulties.is_empty() {
+            return writeln!(f, "No games played yet");
+        }
+        writeln!(f, "{:<14} {:>6} {:>6} {:>6} {:>6} {:>6} {:>12} {:>6}", "", "Played", "Won", "Lost", "Streak", "Best", "Time", "Hints")?;
+        for (name, stats) in &self.difficulties {
+            writeln!(
+                f,
+                "{:<14} {:>6} {:>6} {:>6} {:>6} {:>6} {:>12} {:>6}",
+                name, stats.played, stats.won, stats.lost, stats.streak, stats.best_streak, format_duration(stats.seconds), stats.hints,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Directory for data kept between runs: `$XDG_DATA_HOME/mineswept`, by default
+/// `~/.local/share/mineswept`.
+pub(crate) fn data_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".local/share"),
+    };
+    Some(base.join("mineswept"))
+}
+
+fn stats_path() -> Option<PathBuf> {
+    Some(data_dir()?.join("stats"))
+}
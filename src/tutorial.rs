@@ -0,0 +1,125 @@
+use crate::savegame::SavedBoard;
+
+/// Rows at the top of every lesson board that its instructions are drawn over. They and
+/// the row below are uncovered, so the numbers the player can see say all there is.
+pub(crate) const TEXT_ROWS: u16 = 2;
+
+/// What the player has to do to finish a lesson.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Goal {
+    /// Uncover a cell without setting off a mine.
+    Reveal,
+    /// Uncover every safe cell.
+    Clear,
+    /// Flag every mine, and nothing else.
+    Flag,
+    /// Uncover every safe cell, chording at least once.
+    Chord,
+}
+
+/// A step of the tutorial (`--tutorial`): a small board, with what to do on it.
+#[derive(Debug)]
+pub(crate) struct Lesson {
+    pub(crate) text: [&'static str; 2],
+    /// The board as an indented bare grid (see `SavedBoard`); the numbers are counted
+    /// when it is loaded.
+    board: &'static str,
+    pub(crate) goal: Goal,
+}
+
+impl Lesson {
+    pub(crate) fn board(&self) -> SavedBoard {
+        let grid: Vec<&str> = self.board.lines().map(str::trim).collect();
+        grid.join("\n").parse().expect("lesson boards are valid")
+    }
+}
+
+pub(crate) const LESSONS: [Lesson; 5] = [
+    Lesson {
+        text: ["Lesson 1: click a covered cell to uncover it.", "Some hide mines; uncover one and you lose."],
+        board: "
+            00000000000000000000
+            00000000000000000000
+            00000000000000000000
+            ....................
+            ......*.............
+            ....................
+            ..............*.....
+            ....................
+            ...*................",
+        goal: Goal::Reveal,
+    },
+    Lesson {
+        text: ["Lesson 2: a number counts the mines around it.", "A blank counts none. Uncover every safe cell."],
+        board: "
+            00000000000000000000
+            00000000000000000000
+            00000000000000000000
+            00000000000000000000
+            0000000...0000000000
+            0000000.*.0000000000
+            0000000...0000000000
+            00000000000000000000
+            00000000000000000000",
+        goal: Goal::Clear,
+    },
+    Lesson {
+        text: ["Lesson 3: a 1 by a single covered cell says it's", "a mine. Right click (or press F on) it to flag it."],
+        board: "
+            00000000000000000000
+            00000000000000000000
+            00000000000000000000
+            00000000000000000000
+            00000000000000000000
+            00000000*00000000000
+            00000000000000000000
+            00000000000000000000
+            00000000000000000000",
+        goal: Goal::Flag,
+    },
+    Lesson {
+        text: ["Lesson 4: middle or double click a number with all", "its mines flagged to uncover the rest around it."],
+        board: "
+            00000000000000000000
+            00000000000000000000
+            00000000000000000000
+            00000000000000000000
+            0000000..00000000000
+            0000000F000000000000
+            00000000000000000000
+            00000000000000000000
+            00000000000000000000",
+        goal: Goal::Chord,
+    },
+    Lesson {
+        text: ["Lesson 5: clear a whole board. R starts a lesson", "over, and H shows a safe cell if you're stuck."],
+        board: "
+            00000000000000000000
+            00000000000000000000
+            00000000000000000000
+            ..*.....*.......*...
+            ....................
+            .*....*....*.....*..
+            ....................
+            ...*.....*......*...
+            .......*.........*..",
+        goal: Goal::Clear,
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::clears_without_guessing;
+
+    #[test]
+    fn lessons_fit_under_their_text_and_can_be_cleared_by_reasoning() {
+        for lesson in &LESSONS {
+            let board = lesson.board();
+            assert_eq!((board.rows, board.columns), (LESSONS[0].board().rows, LESSONS[0].board().columns));
+            let top = (TEXT_ROWS + 1) as usize * board.columns as usize;
+            assert!(board.cells[..top].iter().all(|&(entity, mine)| entity.number().is_some() && !mine));
+            assert!(clears_without_guessing(&board) || lesson.goal == Goal::Reveal);
+        }
+    }
+}
@@ -1,17 +1,18 @@
+use crate::x11comm::{ImageByteOrder, PixelFormat};
+
 pub(crate) fn rgba_to_bgra(rgba: &[u8]) -> Vec<u8> {
     // Ensure the input length is a multiple of 4
-    assert!(rgba.len() % 4 == 0, "Input length must be a multiple of 4");
+    assert!(rgba.len().is_multiple_of(4), "Input length must be a multiple of 4");
 
     // Create a vector to hold the converted BGRA data
     let mut bgra = Vec::with_capacity(rgba.len());
 
     // Iterate over the input data in chunks of 4 (representing one pixel)
     for pixel in rgba.chunks(4) {
-        // Extract RGBA components
+        // Extract RGB components
         let r = pixel[0];
         let g = pixel[1];
         let b = pixel[2];
-        let a = pixel[3];
 
         // Push BGRA components to the output vector
         bgra.push(b); // Blue
@@ -21,4 +22,106 @@ pub(crate) fn rgba_to_bgra(rgba: &[u8]) -> Vec<u8> {
     }
 
     bgra
-}
\ No newline at end of file
+}
+
+/// Converts RGBA pixels to ZPixmap data for the given pixel format.
+///
+/// Handles any TrueColor layout described by channel masks (e.g. 24-bit 888, 16-bit 565,
+/// 30-bit 10-10-10), padding each scanline to the format's scanline_pad.
+pub(crate) fn rgba_to_zpixmap(rgba: &[u8], width: usize, format: &PixelFormat) -> Vec<u8> {
+    let is_bgrx = format.bits_per_pixel == 32
+        && format.red_mask == 0x00_ff_00_00
+        && format.green_mask == 0x00_00_ff_00
+        && format.blue_mask == 0x00_00_00_ff
+        && format.alpha_mask == 0
+        && format.byte_order == ImageByteOrder::LsbFirst;
+    if is_bgrx {
+        return rgba_to_bgra(rgba);
+    }
+
+    let bytes_per_pixel = format.bits_per_pixel as usize / 8;
+    let scanline_pad_bytes = (format.scanline_pad as usize / 8).max(1);
+    let row_length = (width * bytes_per_pixel).div_ceil(scanline_pad_bytes) * scanline_pad_bytes;
+
+    let mut data = Vec::with_capacity(row_length * (rgba.len() / 4 / width.max(1)));
+    for row in rgba.chunks(width * 4) {
+        let row_start = data.len();
+        for pixel in row.chunks(4) {
+            let value = rgba_to_pixel([pixel[0], pixel[1], pixel[2], pixel[3]], format);
+            match format.byte_order {
+                ImageByteOrder::LsbFirst => data.extend_from_slice(&value.to_le_bytes()[..bytes_per_pixel]),
+                ImageByteOrder::MsbFirst => data.extend_from_slice(&value.to_be_bytes()[4 - bytes_per_pixel..]),
+            }
+        }
+        data.resize(row_start + row_length, 0);
+    }
+
+    data
+}
+
+/// Packs one RGBA color into a pixel value. Visuals with an alpha channel get
+/// premultiplied color, as compositors expect; others ignore alpha.
+pub(crate) fn rgba_to_pixel(rgba: [u8; 4], format: &PixelFormat) -> u32 {
+    let [mut red, mut green, mut blue, alpha] = rgba;
+    if format.alpha_mask != 0 {
+        let premultiply = |channel: u8| ((channel as u32 * alpha as u32 + 127) / 255) as u8;
+        (red, green, blue) = (premultiply(red), premultiply(green), premultiply(blue));
+    }
+
+    scale_to_mask(red, format.red_mask)
+        | scale_to_mask(green, format.green_mask)
+        | scale_to_mask(blue, format.blue_mask)
+        | scale_to_mask(alpha, format.alpha_mask)
+}
+
+/// Converts ZPixmap data in the given pixel format back to opaque RGBA pixels.
+pub(crate) fn zpixmap_to_rgba(data: &[u8], width: usize, height: usize, format: &PixelFormat) -> Vec<u8> {
+    let bytes_per_pixel = format.bits_per_pixel as usize / 8;
+    let scanline_pad_bytes = (format.scanline_pad as usize / 8).max(1);
+    let row_length = (width * bytes_per_pixel).div_ceil(scanline_pad_bytes) * scanline_pad_bytes;
+
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for row in data.chunks(row_length).take(height) {
+        for pixel in row[..width * bytes_per_pixel].chunks(bytes_per_pixel) {
+            let mut bytes = [0u8; 4];
+            let value = match format.byte_order {
+                ImageByteOrder::LsbFirst => {
+                    bytes[..bytes_per_pixel].copy_from_slice(pixel);
+                    u32::from_le_bytes(bytes)
+                }
+                ImageByteOrder::MsbFirst => {
+                    bytes[4 - bytes_per_pixel..].copy_from_slice(pixel);
+                    u32::from_be_bytes(bytes)
+                }
+            };
+            rgba.extend_from_slice(&[
+                scale_from_mask(value, format.red_mask),
+                scale_from_mask(value, format.green_mask),
+                scale_from_mask(value, format.blue_mask),
+                0xff,
+            ]);
+        }
+    }
+
+    rgba
+}
+
+/// Extracts the channel selected by `mask` and scales it to 8 bits.
+fn scale_from_mask(value: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+    let max = (1u64 << mask.count_ones()) - 1;
+    let channel = ((value & mask) >> mask.trailing_zeros()) as u64;
+    ((channel * 255 + max / 2) / max) as u8
+}
+
+/// Scales an 8-bit channel to the width of `mask` and shifts it into place.
+fn scale_to_mask(channel: u8, mask: u32) -> u32 {
+    if mask == 0 {
+        return 0;
+    }
+    let max = (1u64 << mask.count_ones()) - 1;
+    let scaled = (channel as u64 * max + 127) / 255;
+    (scaled as u32) << mask.trailing_zeros()
+}
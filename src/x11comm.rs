@@ -1,382 +1,1439 @@
-use std::io::{self, Read, Cursor, Write};
-use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
-use std::{env, process};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{self, IoSlice, Read, Cursor, Write};
+use byteorder::{NativeEndian, ReadBytesExt};
+use crate::encoder::RequestWriter;
+use crate::xcmisc;
+use crate::xrender;
+use crate::auth::AuthToken;
+use std::{env, fmt};
 use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
 use std::fs;
-use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::os::unix::net::UnixStream;
+use std::ffi::{c_int, c_short, c_ulong};
 
-const AUTH_ENTRY_FAMILY_LOCAL: u16 = 1;
-const AUTH_ENTRY_MAGIC_COOKIE: &str = "MIT-MAGIC-COOKIE-1";
 
-type AuthToken = [u8; 16];
-
-#[derive(Debug)]
-struct AuthEntry {
-    family: u16,
-    address: Vec<u8>,
-    display_number: String,
-    auth_name: String,
-    auth_data: Vec<u8>,
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DisplayName {
+    pub(crate) host: Option<String>,
+    pub(crate) display_number: u16,
+    pub(crate) screen_number: u16,
 }
 
-#[repr(C, packed)]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
+#[allow(dead_code)] // Parsed in full from the handshake, not every field is used yet
 pub struct Screen {
     pub id: u32,
     colormap: u32,
     white: u32,
     black: u32,
     input_mask: u32,
-    width: u16,
-    height: u16,
+    pub(crate) width: u16,
+    pub(crate) height: u16,
     width_mm: u16,
     height_mm: u16,
     maps_min: u16,
     maps_max: u16,
     pub(crate) root_visual_id: u32,
-    backing_store: u8,
-    save_unders: u8,
-    root_depth: u8,
-    depths_count: u8,
+    /// 0 (Never), 1 (WhenMapped) or 2 (Always): the most backing store the server offers.
+    pub(crate) backing_store: u8,
+    pub(crate) save_unders: u8,
+    pub(crate) root_depth: u8,
+    pub(crate) depths: Vec<DepthInfo>,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub struct ConnectionInformation {
-    pub root_screen: Screen,
+    pub(crate) screens: Vec<Screen>,
+    /// Index into `screens` of the screen the game runs on.
+    pub(crate) screen_number: usize,
     pub resource_id_base: u32,
     pub resource_id_mask: u32,
+    pub(crate) image_byte_order: ImageByteOrder,
+    /// Maximum request length in 4-byte units.
+    pub(crate) maximum_request_length: u32,
+    pub(crate) min_keycode: u8,
+    pub(crate) max_keycode: u8,
+    pub(crate) pixmap_formats: Vec<PixmapFormat>,
 }
 
-#[repr(C, packed)]
-struct HandshakeRequest {
-    endianness: u8,
-    pad1: u8,
-    major_version: u16,
-    minor_version: u16,
-    authorization_len: u16,
-    authorization_data_len: u16,
-    pad2: u16,
-}
-
-#[repr(C, packed)]
-struct GraphicalContextRequest {
-    opcode:   u8,
-    pad1:     u8,
-    length:   u16,
-    id:       u32,
-    drawable: u32,
-    bitmask:  u32,
-    value1:   u32,
-}
-
-#[repr(C, packed)]
-struct CreateWindowRequest {
-    opcode:         u8,
-    depth:          u8,
-    request_length: u16,
-    window_id:      u32,
-    parent_id:      u32,
-    x:              u16,
-    y:              u16,
-    width:          u16,
-    height:         u16,
-    border_width:   u16,
-    class:          u16,
-    root_visual_id: u32,
-    bitmask:        u32,
-    value1:         u32,
-    value2:         u32,
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PixmapFormat {
+    pub(crate) depth: u8,
+    pub(crate) bits_per_pixel: u8,
+    pub(crate) scanline_pad: u8,
 }
 
-#[repr(C, packed)]
-struct MapWindowRequest {
-    opcode: u8,
-    pad1: u8,
-    request_length: u16,
-    window_id: u32,
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct VisualType {
+    pub(crate) id: u32,
+    pub(crate) class: u8,
+    pub(crate) red_mask: u32,
+    pub(crate) green_mask: u32,
+    pub(crate) blue_mask: u32,
 }
 
-#[repr(C, packed)]
-struct CreatePixmapRequest {
-    opcode:         u8,
-    depth:          u8,
-    request_length: u16,
-    pixmap_id:      u32,
-    drawable_id:    u32,
-    width:          u16,
-    height:         u16,
-}
-
-#[repr(C, packed)]
-struct PutImageRequest {
-    opcode:         u8,
-    format:         u8,
-    request_length: u16,
-    drawable_id:    u32,
-    gc_id:          u32,
-    width:          u16,
-    height:         u16,
-    dst_x:          u16,
-    dst_y:          u16,
-    left_pad:       u8,
-    depth:          u8,
-    pad1:           u16,
-}
-
-#[repr(C, packed)]
-struct CopyAreaRequest {
-    opcode:         u8,
-    pad1:           u8,
-    request_length: u16,
-    src_id:         u32,
-    dst_id:         u32,
-    gc_id:          u32,
-    src_x:          u16,
-    src_y:          u16,
-    dst_x:          u16,
-    dst_y:          u16,
-    width:          u16,
-    height:         u16,
-}
-
-#[repr(C, packed)]
-struct StaticResponse {
-    success: u8,
-    pad1: u8,
-    major_version: u16,
-    minor_version: u16,
-    length: u16,
-}
-
-#[repr(C, packed)]
-struct DynamicResponse {
-    release_number: u32,
-    resource_id_base: u32,
-    resource_id_mask: u32,
-    motion_buffer_size: u32,
-    vendor_length: u16,
-    maximum_request_length: u16,
-    screens_in_root_count: u8,
-    formats_count: u8,
-    image_byte_order: u8,
-    bitmap_format_bit_order: u8,
-    bitmap_format_scanline_unit: u8,
-    bitmap_format_scanline_pad: u8,
-    min_keycode: u8,
-    max_keycode: u8,
-    pad2: u32,
+#[derive(Debug, Clone)]
+pub(crate) struct DepthInfo {
+    pub(crate) depth: u8,
+    pub(crate) visuals: Vec<VisualType>,
 }
 
+/// Everything needed to pack client-side pixels into ZPixmap data for a visual.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PixelFormat {
+    pub(crate) visual_id: u32,
+    pub(crate) depth: u8,
+    pub(crate) bits_per_pixel: u8,
+    pub(crate) scanline_pad: u8,
+    pub(crate) red_mask: u32,
+    pub(crate) green_mask: u32,
+    pub(crate) blue_mask: u32,
+    /// Bits holding (premultiplied) alpha; 0 for opaque visuals.
+    pub(crate) alpha_mask: u32,
+    pub(crate) byte_order: ImageByteOrder,
+}
+
+pub(crate) const ATOM_ATOM: u32 = 4;
+pub(crate) const ATOM_STRING: u32 = 31;
+pub(crate) const ATOM_WM_HINTS: u32 = 35;
+pub(crate) const ATOM_WM_NORMAL_HINTS: u32 = 40;
+pub(crate) const ATOM_WM_SIZE_HINTS: u32 = 41;
+pub(crate) const ATOM_WM_CLASS: u32 = 67;
+
+const VISUAL_CLASS_TRUE_COLOR: u8 = 4;
+const VISUAL_CLASS_DIRECT_COLOR: u8 = 5;
+
+impl ConnectionInformation {
+    pub(crate) fn screen(&self) -> &Screen {
+        &self.screens[self.screen_number]
+    }
+
+    pub(crate) fn select_screen(&mut self, screen_number: usize) -> io::Result<()> {
+        if screen_number >= self.screens.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Screen {} does not exist (the server has {})", screen_number, self.screens.len()),
+            ));
+        }
+        self.screen_number = screen_number;
+        Ok(())
+    }
 
-fn read_x11_auth_entry(buffer: &mut Cursor<Vec<u8>>) -> io::Result<Option<AuthEntry>> {
-    let family = buffer.read_u16::<LittleEndian>()?;
+    /// Picks the root visual of the selected screen and the pixmap format matching its depth.
+    ///
+    /// The window inherits the root window's depth, so anything else would BadMatch
+    /// without a dedicated colormap.
+    pub(crate) fn choose_pixel_format(&self) -> io::Result<PixelFormat> {
+        let root_visual_id = self.screen().root_visual_id;
+        let root_depth = self.screen().root_depth;
 
-    let address_len = buffer.read_u16::<BigEndian>()?;
-    let mut address = vec![0u8; address_len as usize];
-    buffer.read_exact(&mut address)?;
+        let visual = self.screen().depths.iter()
+            .filter(|depth| depth.depth == root_depth)
+            .flat_map(|depth| depth.visuals.iter())
+            .find(|visual| visual.id == root_visual_id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Root visual not found in the depths list"))?;
 
-    let display_number_len = buffer.read_u16::<BigEndian>()?;
-    let mut display_number = vec![0u8; display_number_len as usize];
-    buffer.read_exact(&mut display_number)?;
-    let display_number = String::from_utf8_lossy(&display_number).to_string();
+        if visual.class != VISUAL_CLASS_TRUE_COLOR && visual.class != VISUAL_CLASS_DIRECT_COLOR {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Unsupported root visual class {} (need TrueColor or DirectColor)", visual.class),
+            ));
+        }
 
-    let auth_name_len = buffer.read_u16::<BigEndian>()?;
-    let mut auth_name = vec![0u8; auth_name_len as usize];
-    buffer.read_exact(&mut auth_name)?;
-    let auth_name = String::from_utf8_lossy(&auth_name).to_string();
+        let format = self.pixmap_formats.iter()
+            .find(|format| format.depth == root_depth)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("No pixmap format for depth {}", root_depth)))?;
 
-    let auth_data_len = buffer.read_u16::<BigEndian>()?;
-    let mut auth_data = vec![0u8; auth_data_len as usize];
-    buffer.read_exact(&mut auth_data)?;
+        Ok(PixelFormat {
+            visual_id: visual.id,
+            depth: root_depth,
+            bits_per_pixel: format.bits_per_pixel,
+            scanline_pad: format.scanline_pad,
+            red_mask: visual.red_mask,
+            green_mask: visual.green_mask,
+            blue_mask: visual.blue_mask,
+            alpha_mask: 0,
+            byte_order: self.image_byte_order,
+        })
+    }
 
-    Ok(Some(AuthEntry {
-        family,
-        address,
-        display_number,
-        auth_name,
-        auth_data,
-    }))
+    /// Finds a 32-bit TrueColor visual whose spare 8 bits carry alpha, for windows that a
+    /// compositor blends with what is behind them. Returns `None` when there is none.
+    pub(crate) fn choose_argb_pixel_format(&self) -> Option<PixelFormat> {
+        const ARGB_DEPTH: u8 = 32;
+
+        let visual = self.screen().depths.iter()
+            .filter(|depth| depth.depth == ARGB_DEPTH)
+            .flat_map(|depth| depth.visuals.iter())
+            .find(|visual| visual.class == VISUAL_CLASS_TRUE_COLOR)?;
+        let format = self.pixmap_formats.iter().find(|format| format.depth == ARGB_DEPTH)?;
+
+        Some(PixelFormat {
+            visual_id: visual.id,
+            depth: ARGB_DEPTH,
+            bits_per_pixel: format.bits_per_pixel,
+            scanline_pad: format.scanline_pad,
+            red_mask: visual.red_mask,
+            green_mask: visual.green_mask,
+            blue_mask: visual.blue_mask,
+            alpha_mask: !(visual.red_mask | visual.green_mask | visual.blue_mask),
+            byte_order: self.image_byte_order,
+        })
+    }
 }
 
-pub(crate) fn load_x11_auth_token() -> io::Result<AuthToken> {
-    let filename = env::var("XAUTHORITY").unwrap_or_else(|_| {
-        let home = env::var("HOME").expect("HOME environment variable not set");
-        PathBuf::from(home).join(".Xauthority").to_str().unwrap().to_string()
-    });
+/// Byte order the server expects for pixel data in ZPixmap images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ImageByteOrder {
+    LsbFirst,
+    MsbFirst,
+}
+
+/// Byte order announced in the handshake. Requests are serialized from native-endian
+/// structs, so the connection always speaks the host's byte order.
+const NATIVE_BYTE_ORDER: u8 = if cfg!(target_endian = "little") { b'l' } else { b'B' };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum X11ErrorCode {
+    BadRequest,
+    BadValue,
+    BadWindow,
+    BadPixmap,
+    BadAtom,
+    BadCursor,
+    BadFont,
+    BadMatch,
+    BadDrawable,
+    BadAccess,
+    BadAlloc,
+    BadColormap,
+    BadGContext,
+    BadIDChoice,
+    BadName,
+    BadLength,
+    BadImplementation,
+    Unknown(u8),
+}
+
+impl From<u8> for X11ErrorCode {
+    fn from(code: u8) -> Self {
+        match code {
+            1 => X11ErrorCode::BadRequest,
+            2 => X11ErrorCode::BadValue,
+            3 => X11ErrorCode::BadWindow,
+            4 => X11ErrorCode::BadPixmap,
+            5 => X11ErrorCode::BadAtom,
+            6 => X11ErrorCode::BadCursor,
+            7 => X11ErrorCode::BadFont,
+            8 => X11ErrorCode::BadMatch,
+            9 => X11ErrorCode::BadDrawable,
+            10 => X11ErrorCode::BadAccess,
+            11 => X11ErrorCode::BadAlloc,
+            12 => X11ErrorCode::BadColormap,
+            13 => X11ErrorCode::BadGContext,
+            14 => X11ErrorCode::BadIDChoice,
+            15 => X11ErrorCode::BadName,
+            16 => X11ErrorCode::BadLength,
+            17 => X11ErrorCode::BadImplementation,
+            other => X11ErrorCode::Unknown(other),
+        }
+    }
+}
 
-    let data = fs::read(&filename)?;
-    let mut buffer = Cursor::new(data);
+/// An error packet sent by the server in response to a failed request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct X11Error {
+    pub(crate) code: X11ErrorCode,
+    pub(crate) sequence_number: u16,
+    pub(crate) bad_value: u32,
+    pub(crate) minor_opcode: u16,
+    pub(crate) major_opcode: u8,
+}
 
-    while let Ok(Some(auth_entry)) = read_x11_auth_entry(&mut buffer) {
-        if auth_entry.family == AUTH_ENTRY_FAMILY_LOCAL
-            && auth_entry.auth_name == AUTH_ENTRY_MAGIC_COOKIE
-            && auth_entry.auth_data.len() == std::mem::size_of::<AuthToken>()
-        {
-            let mut token = [0u8; 16];
-            token.copy_from_slice(&auth_entry.auth_data);
-            return Ok(token);
+impl X11Error {
+    /// Decodes a 32-byte error packet (the first byte is always 0).
+    pub(crate) fn decode(packet: &[u8; 32]) -> Self {
+        X11Error {
+            code: X11ErrorCode::from(packet[1]),
+            sequence_number: u16::from_ne_bytes([packet[2], packet[3]]),
+            bad_value: u32::from_ne_bytes([packet[4], packet[5], packet[6], packet[7]]),
+            minor_opcode: u16::from_ne_bytes([packet[8], packet[9]]),
+            major_opcode: packet[10],
         }
     }
+}
 
-    Err(io::Error::new(io::ErrorKind::NotFound, "No suitable X11 auth token found"))
+fn request_name(major_opcode: u8) -> &'static str {
+    match major_opcode {
+        1 => "CreateWindow",
+        2 => "ChangeWindowAttributes",
+        4 => "DestroyWindow",
+        8 => "MapWindow",
+        12 => "ConfigureWindow",
+        14 => "GetGeometry",
+        16 => "InternAtom",
+        18 => "ChangeProperty",
+        25 => "SendEvent",
+        26 => "GrabPointer",
+        27 => "UngrabPointer",
+        38 => "QueryPointer",
+        43 => "GetInputFocus",
+        53 => "CreatePixmap",
+        54 => "FreePixmap",
+        55 => "CreateGC",
+        56 => "ChangeGC",
+        60 => "FreeGC",
+        45 => "OpenFont",
+        46 => "CloseFont",
+        62 => "CopyArea",
+        67 => "PolyRectangle",
+        70 => "PolyFillRectangle",
+        72 => "PutImage",
+        73 => "GetImage",
+        76 => "ImageText8",
+        78 => "CreateColormap",
+        79 => "FreeColormap",
+        93 => "CreateCursor",
+        94 => "CreateGlyphCursor",
+        95 => "FreeCursor",
+        98 => "QueryExtension",
+        101 => "GetKeyboardMapping",
+        104 => "Bell",
+        _ => "unknown request",
+    }
 }
 
-pub(crate) fn connect_x11_socket() -> io::Result<UnixStream> {
-    let possible_socket_paths = ["/tmp/.X11-unix/X0", "/tmp/.X11-unix/X1"];
+impl fmt::Display for X11Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "X11 error {:?} in {} (opcode {}.{}), sequence {}, bad value {:#x}",
+            self.code,
+            request_name(self.major_opcode),
+            self.major_opcode,
+            self.minor_opcode,
+            self.sequence_number,
+            self.bad_value,
+        )
+    }
+}
+
+impl std::error::Error for X11Error {}
+
+impl From<X11Error> for io::Error {
+    fn from(error: X11Error) -> Self {
+        io::Error::other(error)
+    }
+}
+
+/// Parses a DISPLAY string of the form `[host]:display[.screen]`.
+///
+/// `:0`, `unix:0` and `<local hostname>:0` all refer to the local unix socket. A host
+/// starting with `/` is taken as a literal socket path (as set by XQuartz/launchd).
+pub(crate) fn parse_display(display: &str) -> Option<DisplayName> {
+    let (host, rest) = display.rsplit_once(':')?;
+    let (display_number, screen_number) = match rest.split_once('.') {
+        Some((display_number, screen_number)) => (display_number, screen_number.parse().ok()?),
+        None => (rest, 0),
+    };
+
+    Some(DisplayName {
+        host: if host.is_empty() { None } else { Some(host.to_string()) },
+        display_number: display_number.parse().ok()?,
+        screen_number,
+    })
+}
 
-    for &socket_path in &possible_socket_paths {
-        match UnixStream::connect(socket_path) {
-            Ok(stream) => return Ok(stream),
-            Err(_) => continue,
+/// Reads DISPLAY from the environment. When it is unset, as can happen when launching
+/// from a Wayland session, the lowest-numbered local display found is used, and `:0`
+/// if there is none.
+pub(crate) fn display_from_env() -> io::Result<DisplayName> {
+    let display = match env::var("DISPLAY") {
+        Ok(display) if !display.is_empty() => display,
+        _ => {
+            let display_number = discover_display_numbers().into_iter().next().unwrap_or(0);
+            return Ok(DisplayName { host: None, display_number, screen_number: 0 });
         }
+    };
+    parse_display(&display).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid DISPLAY value: {:?}", display))
+    })
+}
+
+/// Numbers of the local displays with a listening socket, in ascending order. Looks for
+/// `X<n>` sockets in `/tmp/.X11-unix` and `$XDG_RUNTIME_DIR/.X11-unix`, and on Linux for
+/// abstract ones in `/proc/net/unix`, which is the only trace some Xwayland setups leave.
+fn discover_display_numbers() -> Vec<u16> {
+    fn display_number(socket_name: &str) -> Option<u16> {
+        socket_name.strip_prefix('X')?.parse().ok()
     }
 
-    eprintln!("Failed to connect to X11 socket");
-    process::exit(1);
+    let mut socket_dirs = vec![PathBuf::from("/tmp/.X11-unix")];
+    if let Some(runtime_dir) = env::var_os("XDG_RUNTIME_DIR") {
+        socket_dirs.push(PathBuf::from(runtime_dir).join(".X11-unix"));
+    }
+
+    let mut numbers: Vec<u16> = socket_dirs.iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| display_number(entry.ok()?.file_name().to_str()?))
+        .collect();
+
+    // The last column of each line is the socket's path, @-prefixed when abstract
+    if let Ok(sockets) = fs::read_to_string("/proc/net/unix") {
+        numbers.extend(sockets.lines()
+            .filter_map(|line| line.split_whitespace().last()?.strip_prefix("@/tmp/.X11-unix/"))
+            .filter_map(display_number));
+    }
+
+    numbers.sort_unstable();
+    numbers.dedup();
+    numbers
 }
 
-pub(crate) fn x11_handshake(socket: &mut UnixStream, auth_token: &AuthToken) -> Result<ConnectionInformation, std::io::Error> {
-    let request = HandshakeRequest {
-        endianness: b'l',
-        pad1: 0,
-        major_version: 11,
-        minor_version: 0,
-        authorization_len: AUTH_ENTRY_MAGIC_COOKIE.len() as u16,
-        authorization_data_len: size_of::<AuthToken>() as u16,
-        pad2: 0,
+pub(crate) fn local_hostname() -> Option<String> {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| fs::read_to_string("/etc/hostname"))
+        .ok()
+        .map(|hostname| hostname.trim().to_string())
+}
+
+impl fmt::Display for DisplayName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}.{}", self.host.as_deref().unwrap_or(""), self.display_number, self.screen_number)
+    }
+}
+
+/// A place an X server may be listening.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum SocketAddress {
+    /// A name in Linux's abstract socket namespace, which has no file on disk.
+    Abstract(String),
+    Path(PathBuf),
+}
+
+impl fmt::Display for SocketAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // The leading @ is how ss and netstat show abstract names
+            SocketAddress::Abstract(name) => write!(f, "@{}", name),
+            SocketAddress::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl SocketAddress {
+    pub(crate) fn connect(&self) -> io::Result<UnixStream> {
+        match self {
+            #[cfg(target_os = "linux")]
+            SocketAddress::Abstract(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                let address = std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes())?;
+                UnixStream::connect_addr(&address)
+            }
+            #[cfg(not(target_os = "linux"))]
+            SocketAddress::Abstract(_) => Err(io::Error::new(io::ErrorKind::Unsupported, "Abstract sockets are Linux-only")),
+            SocketAddress::Path(path) => UnixStream::connect(path),
+        }
+    }
+}
+
+impl DisplayName {
+    /// Candidate socket addresses for this display, in the order they should be tried.
+    ///
+    /// Local displays are tried in the abstract namespace first, as Xorg and Xwayland
+    /// listen there too on Linux, then in `/tmp/.X11-unix` and under `$XDG_RUNTIME_DIR`,
+    /// where some sandboxed and nested servers put their sockets.
+    pub(crate) fn socket_addresses(&self) -> io::Result<Vec<SocketAddress>> {
+        let is_local = match self.host.as_deref() {
+            None | Some("unix") => true,
+            Some(path) if path.starts_with('/') => {
+                return Ok(vec![SocketAddress::Path(PathBuf::from(format!("{}:{}", path, self.display_number)))]);
+            }
+            Some(host) => local_hostname().as_deref() == Some(host),
+        };
+        if !is_local {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Remote X11 displays are not supported (host {:?})", self.host.as_deref().unwrap_or("")),
+            ));
+        }
+
+        let socket_name = format!(".X11-unix/X{}", self.display_number);
+        let mut addresses = Vec::new();
+        if cfg!(target_os = "linux") {
+            addresses.push(SocketAddress::Abstract(format!("/tmp/{}", socket_name)));
+        }
+        addresses.push(SocketAddress::Path(PathBuf::from("/tmp").join(&socket_name)));
+        if let Some(runtime_dir) = env::var_os("XDG_RUNTIME_DIR") {
+            addresses.push(SocketAddress::Path(PathBuf::from(runtime_dir).join(&socket_name)));
+        }
+        Ok(addresses)
+    }
+}
+
+/// Where to look for the server socket and how long to keep trying.
+#[derive(Debug, Clone)]
+pub(crate) struct SocketOptions {
+    /// Explicit socket path; skips the paths derived from DISPLAY.
+    pub(crate) path: Option<PathBuf>,
+    /// Total time to spend retrying before giving up.
+    pub(crate) timeout: Duration,
+    /// Number of extra passes over the candidate addresses after the first one fails.
+    pub(crate) retries: u32,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        SocketOptions { path: None, timeout: Duration::from_secs(5), retries: 3 }
+    }
+}
+
+const CONNECT_RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// Every socket path that was tried and why connecting to it failed.
+#[derive(Debug)]
+pub(crate) struct ConnectError {
+    display: DisplayName,
+    attempts: u32,
+    failures: Vec<(SocketAddress, io::Error)>,
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Could not connect to X server {} after {} attempt(s)", self.display, self.attempts)?;
+        for (address, error) in &self.failures {
+            write!(f, "\n  {}: {}", address, error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConnectError {}
+
+impl From<ConnectError> for io::Error {
+    fn from(error: ConnectError) -> Self {
+        let kind = error.failures.last().map_or(io::ErrorKind::NotFound, |(_, e)| e.kind());
+        io::Error::new(kind, error)
+    }
+}
+
+/// Connects to the first reachable candidate socket, retrying while the server may still
+/// be starting up.
+pub(crate) fn connect_x11_socket(display: &DisplayName, options: &SocketOptions) -> io::Result<UnixStream> {
+    let addresses = match &options.path {
+        Some(path) => vec![SocketAddress::Path(path.clone())],
+        None => display.socket_addresses()?,
     };
 
-    let padding = [0u8; 2];
+    let started = Instant::now();
+    let mut error = ConnectError { display: display.clone(), attempts: 0, failures: Vec::new() };
+    loop {
+        error.attempts += 1;
+        error.failures.clear();
+        for address in &addresses {
+            match address.connect() {
+                Ok(stream) => return Ok(stream),
+                Err(e) => error.failures.push((address.clone(), e)),
+            }
+        }
+
+        if error.attempts > options.retries || started.elapsed() + CONNECT_RETRY_DELAY > options.timeout {
+            return Err(error.into());
+        }
+        thread::sleep(CONNECT_RETRY_DELAY);
+    }
+}
+
+const PACKET_ERROR: u8 = 0;
+const PACKET_REPLY: u8 = 1;
+const PACKET_GENERIC_EVENT: u8 = 35;
 
-    socket.write_all(unsafe { std::slice::from_raw_parts(&request as *const _ as *const u8, size_of::<HandshakeRequest>()) })?;
-    socket.write_all(AUTH_ENTRY_MAGIC_COOKIE.as_bytes())?;
-    socket.write_all(&padding)?;
-    socket.write_all(auth_token)?;
+/// Outgoing requests are flushed automatically once this many bytes are buffered.
+const OUTPUT_BUFFER_CAPACITY: usize = 64 * 1024;
 
-    let mut static_response = StaticResponse { success: 0, pad1: 0, major_version: 0, minor_version: 0, length: 0 };
-    socket.read_exact(unsafe { std::slice::from_raw_parts_mut(&mut static_response as *mut _ as *mut u8, size_of::<StaticResponse>()) })?;
+/// A connection to the X server that numbers outgoing requests and routes incoming
+/// replies, errors and events by sequence number.
+///
+/// Requests are buffered and only written on `flush`, when the buffer fills up, or
+/// before blocking on a read.
+#[derive(Debug)]
+pub(crate) struct X11Connection {
+    stream: UnixStream,
+    output: Vec<u8>,
+    /// Sequence number of the last request sent; the handshake counts as 0.
+    sequence_number: u16,
+    events: VecDeque<Vec<u8>>,
+    errors: VecDeque<X11Error>,
+    /// Replies read while waiting for something else, until `wait_for_reply` claims them.
+    /// Every request that has a reply must be waited for, or its reply stays here.
+    replies: HashMap<u16, Vec<u8>>,
+    /// Server-side resources released (in reverse order) when the connection is dropped.
+    owned: Vec<Resource>,
+    ids: IdAllocator,
+    /// Major opcode of XC-MISC, looked up the first time the ID range runs out.
+    xc_misc_opcode: Option<Option<u8>>,
+}
+
+impl X11Connection {
+    /// Wraps a stream on which the handshake described by `info` has already completed.
+    pub(crate) fn new(stream: UnixStream, info: &ConnectionInformation) -> Self {
+        X11Connection {
+            stream,
+            output: Vec::with_capacity(OUTPUT_BUFFER_CAPACITY),
+            sequence_number: 0,
+            events: VecDeque::new(),
+            errors: VecDeque::new(),
+            replies: HashMap::new(),
+            owned: Vec::new(),
+            ids: IdAllocator::new(info),
+            xc_misc_opcode: None,
+        }
+    }
 
-    assert_eq!(static_response.success, 1);
+    /// Returns a fresh resource ID for a window, pixmap, GC, font, cursor...
+    pub(crate) fn generate_id(&mut self) -> io::Result<u32> {
+        if self.ids.next > self.ids.last || self.ids.next == 0 {
+            self.refill_ids()?;
+        }
 
-    let mut recv_buf = vec![0u8; static_response.length as usize * 4];
+        let id = self.ids.next;
+        // Wrapping past u32::MAX marks the run as used up
+        self.ids.next = self.ids.next.checked_add(self.ids.increment).unwrap_or(0);
+        Ok(id)
+    }
+
+    fn refill_ids(&mut self) -> io::Result<()> {
+        let exhausted = || io::Error::other("Ran out of X11 resource IDs");
+
+        let major_opcode = match self.xc_misc_opcode {
+            Some(major_opcode) => major_opcode,
+            None => {
+                let major_opcode = x11_query_extension(self, "XC-MISC")?.map(|extension| extension.major_opcode);
+                if let Some(major_opcode) = major_opcode {
+                    xcmisc::xc_misc_get_version(self, major_opcode)?;
+                }
+                self.xc_misc_opcode = Some(major_opcode);
+                major_opcode
+            }
+        };
+        let major_opcode = major_opcode.ok_or_else(exhausted)?;
+
+        let (start_id, count) = xcmisc::xc_misc_get_xid_range(self, major_opcode)?;
+        if count == 0 {
+            return Err(exhausted());
+        }
+        self.ids.next = start_id;
+        self.ids.last = start_id + (count - 1) * self.ids.increment;
+        Ok(())
+    }
+
+    /// Registers a resource to be freed when the connection is dropped.
+    pub(crate) fn track(&mut self, resource: Resource) {
+        self.owned.push(resource);
+    }
+
+    /// Frees a tracked resource now, e.g. a pixmap being replaced by a larger one.
+    pub(crate) fn release(&mut self, resource: Resource) -> io::Result<()> {
+        self.owned.retain(|&owned| owned != resource);
+        resource.free(self)
+    }
+
+    /// Queues one complete request and returns the sequence number the server assigns to it.
+    pub(crate) fn send(&mut self, request: &[u8]) -> io::Result<u16> {
+        self.send_vectored(&[request])
+    }
+
+    /// Queues one request made of several parts, e.g. a header and a large payload
+    /// borrowed from the caller.
+    ///
+    /// Parts that don't fit in the output buffer are written, together with whatever was
+    /// already buffered, in a single vectored write instead of being copied.
+    pub(crate) fn send_vectored(&mut self, parts: &[&[u8]]) -> io::Result<u16> {
+        let length: usize = parts.iter().map(|part| part.len()).sum();
+        if self.output.len() + length <= OUTPUT_BUFFER_CAPACITY {
+            for part in parts {
+                self.output.extend_from_slice(part);
+            }
+        } else {
+            let mut slices: Vec<IoSlice> = std::iter::once(&self.output[..])
+                .chain(parts.iter().copied())
+                .filter(|part| !part.is_empty())
+                .map(IoSlice::new)
+                .collect();
+            write_all_vectored(&mut self.stream, &mut slices)?;
+            self.output.clear();
+        }
+
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+        Ok(self.sequence_number)
+    }
+
+    /// Writes all buffered requests to the server.
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        if !self.output.is_empty() {
+            self.stream.write_all(&self.output)?;
+            self.output.clear();
+        }
+        Ok(())
+    }
+
+    /// Sends a request that generates a reply and waits for it.
+    pub(crate) fn send_with_reply(&mut self, request: &[u8]) -> io::Result<Vec<u8>> {
+        let sequence_number = self.send(request)?;
+        self.wait_for_reply(sequence_number)
+    }
+
+    /// Reads until the reply (or error) for `sequence_number` arrives. Whatever else is
+    /// read on the way is queued by `demultiplex`, so several requests can be sent before
+    /// their replies are collected, in any order.
+    pub(crate) fn wait_for_reply(&mut self, sequence_number: u16) -> io::Result<Vec<u8>> {
+        self.flush()?;
+
+        loop {
+            if let Some(reply) = self.replies.remove(&sequence_number) {
+                return Ok(reply);
+            }
+            if let Some(position) = self.errors.iter().position(|error| error.sequence_number == sequence_number) {
+                return Err(self.errors.remove(position).unwrap().into());
+            }
+            self.demultiplex()?;
+        }
+    }
+
+    /// Waits until the server has processed every request sent so far, by making a
+    /// GetInputFocus round trip. An error caused by one of those requests is returned as `Err`.
+    pub(crate) fn sync(&mut self) -> io::Result<()> {
+        const OPCODE_GET_INPUT_FOCUS: u8 = 43;
+
+        let request = RequestWriter::new(OPCODE_GET_INPUT_FOCUS, 0).finish();
+        self.send_with_reply(&request)?;
+        match self.errors.pop_front() {
+            Some(error) => Err(error.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the next event: 32 bytes, or more for GenericEvents. Errors caused by
+    /// requests sent without waiting for a reply are returned as `Err`, in the order they
+    /// arrived.
+    ///
+    /// While waiting, the descriptors in `watched` are polled too, and the first of them
+    /// that is readable is returned as soon as there is one, so the caller can serve it
+    /// without blocking. Waiting ends at `deadline` too, if given.
+    pub(crate) fn next_event(&mut self, watched: &[RawFd], deadline: Option<Instant>) -> io::Result<Wakeup> {
+        self.flush()?;
+
+        loop {
+            if let Some(error) = self.errors.pop_front() {
+                return Err(error.into());
+            }
+            if let Some(event) = self.events.pop_front() {
+                return Ok(Wakeup::Event(event));
+            }
+            if !watched.is_empty() || deadline.is_some() {
+                if let Some(wakeup) = poll_readable(self.stream.as_raw_fd(), watched, deadline)? {
+                    return Ok(wakeup);
+                }
+            }
+            self.demultiplex()?;
+        }
+    }
+
+    /// Reads until an event matching `is_wanted` arrives, leaving other events queued for
+    /// `next_event`. Errors are returned as `Err`, since the awaited event may never come.
+    pub(crate) fn wait_for_event(&mut self, is_wanted: impl Fn(&[u8]) -> bool) -> io::Result<Vec<u8>> {
+        self.flush()?;
+
+        loop {
+            if let Some(error) = self.errors.pop_front() {
+                return Err(error.into());
+            }
+            if let Some(position) = self.events.iter().position(|event| is_wanted(event)) {
+                return Ok(self.events.remove(position).unwrap());
+            }
+            self.demultiplex()?;
+        }
+    }
+
+    /// Reads one packet and files it under errors, replies (by sequence number) or events.
+    /// This is the only place the connection reads from the server.
+    fn demultiplex(&mut self) -> io::Result<()> {
+        let packet = self.read_packet()?;
+        match packet[0] {
+            PACKET_ERROR => self.errors.push_back(X11Error::decode(packet[..32].try_into().unwrap())),
+            PACKET_REPLY => {
+                let sequence_number = u16::from_ne_bytes([packet[2], packet[3]]);
+                self.replies.insert(sequence_number, packet);
+            }
+            _ => self.events.push_back(packet),
+        }
+        Ok(())
+    }
+
+    /// Reads one packet from the server, including the variable-length tail of replies
+    /// and generic events.
+    fn read_packet(&mut self) -> io::Result<Vec<u8>> {
+        let mut packet = vec![0u8; 32];
+        self.stream.read_exact(&mut packet)?;
+
+        if packet[0] == PACKET_REPLY || packet[0] & 0x7f == PACKET_GENERIC_EVENT {
+            let extra_length = u32::from_ne_bytes([packet[4], packet[5], packet[6], packet[7]]) as usize * 4;
+            packet.resize(32 + extra_length, 0);
+            self.stream.read_exact(&mut packet[32..])?;
+        }
+
+        Ok(packet)
+    }
+}
+
+#[repr(C)]
+struct PollFd {
+    fd: c_int,
+    events: c_short,
+    revents: c_short,
+}
+
+const POLLIN: c_short = 0x1;
+
+/// Why [`X11Connection::next_event`] stopped waiting.
+#[derive(Debug)]
+pub(crate) enum Wakeup {
+    /// An event arrived: 32 bytes, or more for GenericEvents.
+    Event(Vec<u8>),
+    /// One of the watched descriptors can be read from.
+    Readable(RawFd),
+    /// The deadline passed.
+    Timeout,
+}
+
+extern "C" {
+    fn poll(fds: *mut PollFd, nfds: c_ulong, timeout: c_int) -> c_int;
+}
+
+/// Blocks until `fd` or one of `watched` can be read from, or `deadline` passes. Returns
+/// `None` if it was `fd`.
+fn poll_readable(fd: RawFd, watched: &[RawFd], deadline: Option<Instant>) -> io::Result<Option<Wakeup>> {
+    let mut fds: Vec<PollFd> = std::iter::once(fd)
+        .chain(watched.iter().copied())
+        .map(|fd| PollFd { fd, events: POLLIN, revents: 0 })
+        .collect();
+    loop {
+        // Rounded up, so a wakeup never comes before the deadline
+        let timeout = deadline.map_or(-1, |deadline| {
+            let nanos = deadline.saturating_duration_since(Instant::now()).as_nanos();
+            nanos.div_ceil(1_000_000).min(c_int::MAX as u128) as c_int
+        });
+        let ready = unsafe { poll(fds.as_mut_ptr(), fds.len() as c_ulong, timeout) };
+        if ready == 0 {
+            return Ok(Some(Wakeup::Timeout));
+        }
+        if ready > 0 {
+            return Ok(fds[1..].iter().find(|fd| fd.revents != 0).map(|fd| Wakeup::Readable(fd.fd)));
+        }
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+}
+
+fn write_all_vectored(stream: &mut UnixStream, mut slices: &mut [IoSlice]) -> io::Result<()> {
+    while !slices.is_empty() {
+        match stream.write_vectored(slices) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::WriteZero)),
+            Ok(written) => IoSlice::advance_slices(&mut slices, written),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+impl Drop for X11Connection {
+    fn drop(&mut self) {
+        // Errors are ignored: the server may already be gone, in which case it has freed
+        // everything itself
+        for resource in std::mem::take(&mut self.owned).into_iter().rev() {
+            let _ = resource.free(self);
+        }
+        let _ = self.flush();
+    }
+}
+
+/// A server-side resource owned by this client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Resource {
+    Window(u32),
+    Pixmap(u32),
+    Gc(u32),
+    Cursor(u32),
+    Colormap(u32),
+    /// A RENDER picture, freed through the extension's major opcode.
+    Picture { major_opcode: u8, id: u32 },
+}
+
+impl Resource {
+    fn free(self, conn: &mut X11Connection) -> io::Result<()> {
+        match self {
+            Resource::Window(id) => x11_destroy_window(conn, id),
+            Resource::Pixmap(id) => x11_free_pixmap(conn, id),
+            Resource::Gc(id) => x11_free_gc(conn, id),
+            Resource::Cursor(id) => x11_free_cursor(conn, id),
+            Resource::Colormap(id) => x11_free_colormap(conn, id),
+            Resource::Picture { major_opcode, id } => xrender::free_picture(conn, major_opcode, id),
+        }
+    }
+}
+
+pub(crate) fn x11_destroy_window(conn: &mut X11Connection, window_id: u32) -> io::Result<()> {
+    const OPCODE: u8 = 4;
+
+    let request = RequestWriter::new(OPCODE, 0)
+        .put_u32(window_id)
+        .finish();
+
+    conn.send(&request).map(|_| ())
+}
+
+pub(crate) fn x11_free_pixmap(conn: &mut X11Connection, pixmap_id: u32) -> io::Result<()> {
+    const OPCODE: u8 = 54;
+
+    let request = RequestWriter::new(OPCODE, 0)
+        .put_u32(pixmap_id)
+        .finish();
+
+    conn.send(&request).map(|_| ())
+}
+
+pub(crate) fn x11_free_gc(conn: &mut X11Connection, gc_id: u32) -> io::Result<()> {
+    const OPCODE: u8 = 60;
+
+    let request = RequestWriter::new(OPCODE, 0)
+        .put_u32(gc_id)
+        .finish();
+
+    conn.send(&request).map(|_| ())
+}
+
+pub(crate) fn x11_free_cursor(conn: &mut X11Connection, cursor_id: u32) -> io::Result<()> {
+    const OPCODE: u8 = 95;
+
+    let request = RequestWriter::new(OPCODE, 0)
+        .put_u32(cursor_id)
+        .finish();
+
+    conn.send(&request).map(|_| ())
+}
+
+const SETUP_FAILED: u8 = 0;
+const SETUP_SUCCESS: u8 = 1;
+
+pub(crate) fn x11_handshake(socket: &mut UnixStream, auth_token: &AuthToken) -> Result<ConnectionInformation, std::io::Error> {
+    let auth_name = auth_token.name.as_bytes();
+    let auth_data = &auth_token.data[..];
+
+    let mut request = vec![NATIVE_BYTE_ORDER, 0];
+    request.extend_from_slice(&11u16.to_ne_bytes()); // major version
+    request.extend_from_slice(&0u16.to_ne_bytes()); // minor version
+    request.extend_from_slice(&(auth_name.len() as u16).to_ne_bytes());
+    request.extend_from_slice(&(auth_data.len() as u16).to_ne_bytes());
+    request.extend_from_slice(&[0, 0]);
+    request.extend_from_slice(auth_name);
+    request.resize(request.len().next_multiple_of(4), 0);
+    request.extend_from_slice(auth_data);
+    request.resize(request.len().next_multiple_of(4), 0);
+    socket.write_all(&request)?;
+
+    let mut static_response = [0u8; 8];
+    socket.read_exact(&mut static_response)?;
+    let status = static_response[0];
+    let length = u16::from_ne_bytes([static_response[6], static_response[7]]);
+
+    let mut recv_buf = vec![0u8; length as usize * 4];
     socket.read_exact(&mut recv_buf)?;
 
-    let mut dynamic_response = DynamicResponse {
-        release_number: 0, resource_id_base: 0, resource_id_mask: 0, motion_buffer_size: 0,
-        vendor_length: 0, maximum_request_length: 0, screens_in_root_count: 0, formats_count: 0,
-        image_byte_order: 0, bitmap_format_bit_order: 0, bitmap_format_scanline_unit: 0,
-        bitmap_format_scanline_pad: 0, min_keycode: 0, max_keycode: 0, pad2: 0,
-    };
-    let dynamic_response_slice = unsafe { std::slice::from_raw_parts_mut(&mut dynamic_response as *mut _ as *mut u8, size_of::<DynamicResponse>()) };
-    dynamic_response_slice.copy_from_slice(&recv_buf[..size_of::<DynamicResponse>()]);
-
-    let vendor_length_padded = round_up_4(dynamic_response.vendor_length as u32) as usize;
-    let formats_length = 8 * dynamic_response.formats_count as usize;
-    let screen_offset = size_of::<DynamicResponse>() + vendor_length_padded + formats_length;
-
-    let mut screen = Screen {
-        id: 0, colormap: 0, white: 0, black: 0, input_mask: 0,
-        width: 0, height: 0, width_mm: 0, height_mm: 0,
-        maps_min: 0, maps_max: 0, root_visual_id: 0,
-        backing_store: 0, save_unders: 0, root_depth: 0, depths_count: 0,
-    };
-    let screen_slice = unsafe { std::slice::from_raw_parts_mut(&mut screen as *mut _ as *mut u8, size_of::<Screen>()) };
-    screen_slice.copy_from_slice(&recv_buf[screen_offset..screen_offset + size_of::<Screen>()]);
+    if status != SETUP_SUCCESS {
+        // Failed carries the reason's length in the second byte; Authenticate pads it with NULs
+        let reason_length = match status {
+            SETUP_FAILED => (static_response[1] as usize).min(recv_buf.len()),
+            _ => recv_buf.iter().position(|&byte| byte == 0).unwrap_or(recv_buf.len()),
+        };
+        let reason = String::from_utf8_lossy(&recv_buf[..reason_length]);
+        let auth = if auth_token.name.is_empty() { "no authorization".to_string() } else { auth_token.name.clone() };
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("X server refused the connection ({}): {}", auth, reason.trim_end()),
+        ));
+    }
+
+    let mut buffer = Cursor::new(&recv_buf[..]);
+    let _release_number = buffer.read_u32::<NativeEndian>()?;
+    let resource_id_base = buffer.read_u32::<NativeEndian>()?;
+    let resource_id_mask = buffer.read_u32::<NativeEndian>()?;
+    let _motion_buffer_size = buffer.read_u32::<NativeEndian>()?;
+    let vendor_length = buffer.read_u16::<NativeEndian>()?;
+    let maximum_request_length = buffer.read_u16::<NativeEndian>()?;
+    let screens_in_root_count = buffer.read_u8()?;
+    let formats_count = buffer.read_u8()?;
+    let image_byte_order = buffer.read_u8()?;
+    let _bitmap_format_bit_order = buffer.read_u8()?;
+    let _bitmap_format_scanline_unit = buffer.read_u8()?;
+    let _bitmap_format_scanline_pad = buffer.read_u8()?;
+    let min_keycode = buffer.read_u8()?;
+    let max_keycode = buffer.read_u8()?;
+    let _pad = buffer.read_u32::<NativeEndian>()?;
+
+    let formats_offset = buffer.position() as usize + round_up_4(vendor_length as u32) as usize;
+    let formats_length = 8 * formats_count as usize;
+    let screen_offset = formats_offset + formats_length;
+
+    let pixmap_formats = recv_buf[formats_offset..screen_offset]
+        .chunks_exact(8)
+        .map(|format| PixmapFormat { depth: format[0], bits_per_pixel: format[1], scanline_pad: format[2] })
+        .collect();
+
+    let mut screens_buffer = Cursor::new(&recv_buf[screen_offset..]);
+    let screens = (0..screens_in_root_count)
+        .map(|_| read_screen(&mut screens_buffer))
+        .collect::<io::Result<Vec<_>>>()?;
 
     Ok(ConnectionInformation {
-        resource_id_base: dynamic_response.resource_id_base,
-        resource_id_mask: dynamic_response.resource_id_mask,
-        root_screen: screen,
+        resource_id_base,
+        resource_id_mask,
+        screens,
+        screen_number: 0,
+        image_byte_order: if image_byte_order == 0 { ImageByteOrder::LsbFirst } else { ImageByteOrder::MsbFirst },
+        maximum_request_length: maximum_request_length as u32,
+        min_keycode,
+        max_keycode,
+        pixmap_formats,
     })
 }
 
+fn read_screen(buffer: &mut Cursor<&[u8]>) -> io::Result<Screen> {
+    let id = buffer.read_u32::<NativeEndian>()?;
+    let colormap = buffer.read_u32::<NativeEndian>()?;
+    let white = buffer.read_u32::<NativeEndian>()?;
+    let black = buffer.read_u32::<NativeEndian>()?;
+    let input_mask = buffer.read_u32::<NativeEndian>()?;
+    let width = buffer.read_u16::<NativeEndian>()?;
+    let height = buffer.read_u16::<NativeEndian>()?;
+    let width_mm = buffer.read_u16::<NativeEndian>()?;
+    let height_mm = buffer.read_u16::<NativeEndian>()?;
+    let maps_min = buffer.read_u16::<NativeEndian>()?;
+    let maps_max = buffer.read_u16::<NativeEndian>()?;
+    let root_visual_id = buffer.read_u32::<NativeEndian>()?;
+    let backing_store = buffer.read_u8()?;
+    let save_unders = buffer.read_u8()?;
+    let root_depth = buffer.read_u8()?;
+    let depths_count = buffer.read_u8()?;
+
+    let depths = (0..depths_count)
+        .map(|_| read_depth_info(buffer))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    Ok(Screen {
+        id, colormap, white, black, input_mask,
+        width, height, width_mm, height_mm,
+        maps_min, maps_max, root_visual_id,
+        backing_store, save_unders, root_depth, depths,
+    })
+}
+
+fn read_depth_info(buffer: &mut Cursor<&[u8]>) -> io::Result<DepthInfo> {
+    let depth = buffer.read_u8()?;
+    let _pad = buffer.read_u8()?;
+    let visuals_count = buffer.read_u16::<NativeEndian>()?;
+    let _pad = buffer.read_u32::<NativeEndian>()?;
+
+    let mut visuals = Vec::with_capacity(visuals_count as usize);
+    for _ in 0..visuals_count {
+        let id = buffer.read_u32::<NativeEndian>()?;
+        let class = buffer.read_u8()?;
+        let _bits_per_rgb = buffer.read_u8()?;
+        let _colormap_entries = buffer.read_u16::<NativeEndian>()?;
+        let red_mask = buffer.read_u32::<NativeEndian>()?;
+        let green_mask = buffer.read_u32::<NativeEndian>()?;
+        let blue_mask = buffer.read_u32::<NativeEndian>()?;
+        let _pad = buffer.read_u32::<NativeEndian>()?;
+        visuals.push(VisualType { id, class, red_mask, green_mask, blue_mask });
+    }
+
+    Ok(DepthInfo { depth, visuals })
+}
+
 fn round_up_4(n: u32) -> u32 {
     (n + 3) & !3
 }
 
-pub(crate) fn next_x11_id(current_id: u32, info: ConnectionInformation) -> u32 {
-    return 1 + ((info.resource_id_mask & (current_id)) | info.resource_id_base)
+/// Hands out resource IDs from the range assigned at connection setup, then from runs of
+/// unused IDs reported by XC-MISC once that range is exhausted.
+#[derive(Debug)]
+struct IdAllocator {
+    /// Step between consecutive IDs: the lowest bit of the resource ID mask.
+    increment: u32,
+    next: u32,
+    /// Last ID of the current run, inclusive.
+    last: u32,
+}
+
+impl IdAllocator {
+    fn new(info: &ConnectionInformation) -> Self {
+        let mask = info.resource_id_mask;
+        let increment = mask & mask.wrapping_neg();
+        IdAllocator {
+            increment,
+            // ID 0 means "None" in many requests, so skip the first one of the range
+            next: info.resource_id_base | increment,
+            last: info.resource_id_base | mask,
+        }
+    }
+}
+
+/// Raster operation applied when drawing with a GC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub(crate) enum GcFunction {
+    Clear = 0,
+    And = 1,
+    Copy = 3,
+    Xor = 6,
+    Or = 7,
+    Invert = 10,
+    Set = 15,
+}
+
+/// Value list for CreateGC/ChangeGC; only the values that were set are sent.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct GcValues {
+    /// Keyed by value-mask bit, so iterating yields the values in wire order.
+    values: BTreeMap<u32, u32>,
 }
 
-pub(crate) fn x11_create_graphical_context(socket: &mut UnixStream, gc_id: u32, root_id: u32) {
+impl GcValues {
+    const FLAG_FUNCTION: u32 = 1 << 0;
+    const FLAG_FOREGROUND: u32 = 1 << 2;
+    const FLAG_BACKGROUND: u32 = 1 << 3;
+    const FLAG_LINE_WIDTH: u32 = 1 << 4;
+    const FLAG_FONT: u32 = 1 << 14;
+    const FLAG_GRAPHICS_EXPOSURES: u32 = 1 << 16;
+
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    fn set(mut self, flag: u32, value: u32) -> Self {
+        self.values.insert(flag, value);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn function(self, function: GcFunction) -> Self {
+        self.set(Self::FLAG_FUNCTION, function as u32)
+    }
+
+    pub(crate) fn foreground(self, pixel: u32) -> Self {
+        self.set(Self::FLAG_FOREGROUND, pixel)
+    }
+
+    pub(crate) fn background(self, pixel: u32) -> Self {
+        self.set(Self::FLAG_BACKGROUND, pixel)
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn line_width(self, width: u16) -> Self {
+        self.set(Self::FLAG_LINE_WIDTH, width as u32)
+    }
+
+    pub(crate) fn font(self, font_id: u32) -> Self {
+        self.set(Self::FLAG_FONT, font_id)
+    }
+
+    pub(crate) fn graphics_exposures(self, enabled: bool) -> Self {
+        self.set(Self::FLAG_GRAPHICS_EXPOSURES, enabled as u32)
+    }
+
+    /// Appends the value mask followed by the values.
+    fn write(&self, request: RequestWriter) -> RequestWriter {
+        let mask = self.values.keys().fold(0, |mask, flag| mask | flag);
+        self.values.values().fold(request.put_u32(mask), |request, &value| request.put_u32(value))
+    }
+}
+
+pub(crate) fn x11_create_graphical_context(conn: &mut X11Connection, gc_id: u32, root_id: u32, values: &GcValues) {
     const OPCODE: u8 = 55;
-    const FLAG_GC_BG: u32 = 8;
-    const BITMASK: u32 = FLAG_GC_BG;
-    const VALUE1: u32 = 0x00_00_ff_00;
-    
-    let request = GraphicalContextRequest {
-        opcode:   OPCODE,
-        pad1:     0,
-        length:   5,
-        id:       gc_id,
-        drawable: root_id,
-        bitmask:  BITMASK,
-        value1:   VALUE1,
-    };
 
-    return socket.write_all(unsafe { std::slice::from_raw_parts(&request as *const _ as *const u8, size_of::<GraphicalContextRequest>()) }).unwrap()
+    let request = RequestWriter::new(OPCODE, 0)
+        .put_u32(gc_id)
+        .put_u32(root_id);
+    let request = values.write(request).finish();
+
+    conn.send(&request).unwrap();
+}
+
+pub(crate) fn x11_change_gc(conn: &mut X11Connection, gc_id: u32, values: &GcValues) {
+    const OPCODE: u8 = 56;
+
+    let request = RequestWriter::new(OPCODE, 0)
+        .put_u32(gc_id);
+    let request = values.write(request).finish();
+
+    conn.send(&request).unwrap();
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn x11_create_window(
-    socket: &mut UnixStream,
+    conn: &mut X11Connection,
     window_id: u32,
     parent_id: u32,
     x: u16,
     y: u16,
     width: u16,
     height: u16,
+    depth: u8,
     root_visual_id: u32,
+    background_pixel: u32,
+    colormap_id: Option<u32>,
 ){
     const FLAG_WIN_BG_PIXEL: u32 = 2;
+    const FLAG_WIN_BORDER_PIXEL: u32 = 8;
     const FLAG_WIN_EVENT: u32 = 0x800;
-    const FLAG_COUNT: u16 = 2;
+    const FLAG_WIN_COLORMAP: u32 = 0x2000;
     const EVENT_FLAG_EXPOSURE: u32 = 0x80_00;
     const EVENT_FLAG_KEY_PRESS: u32 = 0x1;
     const EVENT_FLAG_KEY_RELEASE: u32 = 0x2;
     const EVENT_FLAG_BUTTON_PRESS: u32 = 0x4;
     const EVENT_FLAG_BUTTON_RELEASE: u32 = 0x8;
-    const FLAGS: u32 = FLAG_WIN_BG_PIXEL | FLAG_WIN_EVENT;
-    const DEPTH: u8 = 24;
+    const EVENT_FLAG_LEAVE_WINDOW: u32 = 0x20;
+    const EVENT_FLAG_POINTER_MOTION: u32 = 0x40;
+    const EVENT_FLAG_VISIBILITY_CHANGE: u32 = 0x1_00_00;
+    const EVENT_FLAG_STRUCTURE_NOTIFY: u32 = 0x2_00_00;
+    const EVENT_FLAG_PROPERTY_CHANGE: u32 = 0x40_00_00;
     const BORDER_WIDTH: u16 = 0;
     const CLASS_INPUT_OUTPUT: u16 = 1;
     const OPCODE: u8 = 1;
-    const BACKGROUND_PIXEL_COLOR: u32 = 0x00_ff_ff_80;
-
-    let request = CreateWindowRequest {
-        opcode:          OPCODE,
-        depth:           DEPTH,
-        request_length:  8 + FLAG_COUNT,
-        window_id:       window_id,
-        parent_id:       parent_id,
-        x:               x,
-        y:               y,
-        width:           width,
-        height:          height,
-        border_width:    BORDER_WIDTH,
-        class:           CLASS_INPUT_OUTPUT,
-        root_visual_id:  root_visual_id,
-        bitmask:         FLAGS,
-        value1:          BACKGROUND_PIXEL_COLOR,
-        value2:          EVENT_FLAG_EXPOSURE | EVENT_FLAG_BUTTON_RELEASE | EVENT_FLAG_BUTTON_PRESS | EVENT_FLAG_KEY_PRESS | EVENT_FLAG_KEY_RELEASE,
-    };
-    return socket.write_all(unsafe { std::slice::from_raw_parts(&request as *const _ as *const u8, size_of::<CreateWindowRequest>()) }).unwrap()
+    const BORDER_PIXEL: u32 = 0;
+    const EVENT_MASK: u32 = EVENT_FLAG_EXPOSURE | EVENT_FLAG_BUTTON_RELEASE | EVENT_FLAG_BUTTON_PRESS | EVENT_FLAG_KEY_PRESS | EVENT_FLAG_KEY_RELEASE
+        | EVENT_FLAG_POINTER_MOTION | EVENT_FLAG_LEAVE_WINDOW | EVENT_FLAG_VISIBILITY_CHANGE | EVENT_FLAG_STRUCTURE_NOTIFY
+        | EVENT_FLAG_PROPERTY_CHANGE;
+
+    // A window with a visual other than its parent's needs its own colormap and border
+    // pixel, or CreateWindow fails with BadMatch
+    let request = RequestWriter::new(OPCODE, depth)
+        .put_u32(window_id)
+        .put_u32(parent_id)
+        .put_u16(x)
+        .put_u16(y)
+        .put_u16(width)
+        .put_u16(height)
+        .put_u16(BORDER_WIDTH)
+        .put_u16(CLASS_INPUT_OUTPUT)
+        .put_u32(root_visual_id);
+    let request = match colormap_id {
+        Some(colormap_id) => request
+            .put_u32(FLAG_WIN_BG_PIXEL | FLAG_WIN_BORDER_PIXEL | FLAG_WIN_EVENT | FLAG_WIN_COLORMAP)
+            .put_u32(background_pixel)
+            .put_u32(BORDER_PIXEL)
+            .put_u32(EVENT_MASK)
+            .put_u32(colormap_id),
+        None => request
+            .put_u32(FLAG_WIN_BG_PIXEL | FLAG_WIN_EVENT)
+            .put_u32(background_pixel)
+            .put_u32(EVENT_MASK),
+    }
+    .finish();
+
+    conn.send(&request).unwrap();
 }
 
-pub(crate) fn x11_map_window(socket: &mut UnixStream, window_id: u32) {
+pub(crate) fn x11_map_window(conn: &mut X11Connection, window_id: u32) {
     const OPCODE: u8 = 8;
 
-    let request = MapWindowRequest {
-        opcode: OPCODE,
-        pad1: 0,
-        request_length: 2,
-        window_id: window_id,
-    };
+    let request = RequestWriter::new(OPCODE, 0)
+        .put_u32(window_id)
+        .finish();
 
-    return socket.write_all(unsafe { std::slice::from_raw_parts(&request as *const _ as *const u8, size_of::<MapWindowRequest>()) }).unwrap()
+    conn.send(&request).unwrap();
 }
 
-pub(crate) fn x11_create_pixmap(socket: &mut UnixStream,
+/// Replaces a window property. `format` is 8, 16 or 32 and `data` holds whole items of that size.
+pub(crate) fn x11_change_property(conn: &mut X11Connection, window_id: u32, property: u32, type_: u32, format: u8, data: &[u8]) {
+    const OPCODE: u8 = 18;
+    const MODE_REPLACE: u8 = 0;
+
+    let request = RequestWriter::new(OPCODE, MODE_REPLACE)
+        .put_u32(window_id)
+        .put_u32(property)
+        .put_u32(type_)
+        .put_u8(format)
+        .pad(3)
+        .put_u32(data.len() as u32 / (format as u32 / 8))
+        .put_bytes(data)
+        .finish();
+
+    conn.send(&request).unwrap();
+}
+
+/// Sets WM_CLASS to the NUL-separated `instance` and `class` names.
+pub(crate) fn x11_set_wm_class(conn: &mut X11Connection, window_id: u32, instance: &str, class: &str) {
+    let value = format!("{}\0{}\0", instance, class);
+    x11_change_property(conn, window_id, ATOM_WM_CLASS, ATOM_STRING, 8, value.as_bytes());
+}
+
+/// Sets WM_HINTS so the window manager gives the window keyboard input and maps it normally.
+pub(crate) fn x11_set_wm_hints(conn: &mut X11Connection, window_id: u32) {
+    const FLAG_INPUT_HINT: u32 = 1;
+    const FLAG_STATE_HINT: u32 = 2;
+    const STATE_NORMAL: u32 = 1;
+
+    // flags, input, initial_state, icon_pixmap, icon_window, icon_x, icon_y, icon_mask, window_group
+    let hints: [u32; 9] = [FLAG_INPUT_HINT | FLAG_STATE_HINT, 1, STATE_NORMAL, 0, 0, 0, 0, 0, 0];
+    let data: Vec<u8> = hints.iter().flat_map(|value| value.to_ne_bytes()).collect();
+    x11_change_property(conn, window_id, ATOM_WM_HINTS, ATOM_WM_HINTS, 32, &data);
+}
+
+/// Sets WM_NORMAL_HINTS with the window's position and size, so window managers honor
+/// the position passed to CreateWindow instead of picking their own. `user_specified`
+/// marks a position the user asked for explicitly.
+pub(crate) fn x11_set_wm_normal_hints(conn: &mut X11Connection, window_id: u32, x: u16, y: u16, width: u16, height: u16, user_specified: bool) {
+    const FLAG_US_POSITION: u32 = 1;
+    const FLAG_P_POSITION: u32 = 4;
+    const FLAG_P_SIZE: u32 = 8;
+
+    let position_flag = if user_specified { FLAG_US_POSITION } else { FLAG_P_POSITION };
+    // flags, x, y, width, height (obsolete but still read by some window managers), then
+    // min/max size, increments, aspect ratios, base size and gravity, all unset
+    let mut hints = [0u32; 18];
+    hints[..5].copy_from_slice(&[position_flag | FLAG_P_SIZE, x as u32, y as u32, width as u32, height as u32]);
+    let data: Vec<u8> = hints.iter().flat_map(|value| value.to_ne_bytes()).collect();
+    x11_change_property(conn, window_id, ATOM_WM_NORMAL_HINTS, ATOM_WM_SIZE_HINTS, 32, &data);
+}
+
+/// Sets WM_PROTOCOLS to the protocols (e.g. WM_DELETE_WINDOW) the window takes part in.
+/// The window manager then sends them as ClientMessages instead of acting on its own.
+pub(crate) fn x11_set_wm_protocols(conn: &mut X11Connection, window_id: u32, wm_protocols: u32, protocols: &[u32]) {
+    let data: Vec<u8> = protocols.iter().flat_map(|atom| atom.to_ne_bytes()).collect();
+    x11_change_property(conn, window_id, wm_protocols, ATOM_ATOM, 32, &data);
+}
+
+/// Returns the atom for `name`, creating it unless `only_if_exists` is set (in which case
+/// a missing atom comes back as 0).
+pub(crate) fn x11_intern_atom(conn: &mut X11Connection, name: &str, only_if_exists: bool) -> io::Result<u32> {
+    const OPCODE: u8 = 16;
+
+    let request = RequestWriter::new(OPCODE, only_if_exists as u8)
+        .put_u16(name.len() as u16)
+        .pad(2)
+        .put_bytes(name.as_bytes())
+        .finish();
+
+    let reply = conn.send_with_reply(&request)?;
+    Ok(u32::from_ne_bytes([reply[8], reply[9], reply[10], reply[11]]))
+}
+
+/// Sends a 32-bit format ClientMessage about `window_id` to the root window, the way
+/// EWMH expects clients to ask the window manager for state changes.
+pub(crate) fn x11_send_root_client_message(conn: &mut X11Connection, root_id: u32, window_id: u32, message_type: u32, data: [u32; 5]) {
+    const OPCODE: u8 = 25;
+    const EVENT_CLIENT_MESSAGE: u8 = 33;
+    const EVENT_FLAG_SUBSTRUCTURE_NOTIFY: u32 = 0x8_00_00;
+    const EVENT_FLAG_SUBSTRUCTURE_REDIRECT: u32 = 0x10_00_00;
+    const PROPAGATE: u8 = 0;
+    const FORMAT: u8 = 32;
+
+    let mut request = RequestWriter::new(OPCODE, PROPAGATE)
+        .put_u32(root_id)
+        .put_u32(EVENT_FLAG_SUBSTRUCTURE_NOTIFY | EVENT_FLAG_SUBSTRUCTURE_REDIRECT)
+        .put_u8(EVENT_CLIENT_MESSAGE)
+        .put_u8(FORMAT)
+        .put_u16(0) // sequence number, filled in by the server
+        .put_u32(window_id)
+        .put_u32(message_type);
+    for value in data {
+        request = request.put_u32(value);
+    }
+
+    conn.send(&request.finish()).unwrap();
+}
+
+/// Makes `window_id` the owner of `selection` (e.g. CLIPBOARD) as of `time`, the server
+/// time of the event that asked for it. Other clients then ask the owner for its contents
+/// with SelectionRequest events.
+pub(crate) fn x11_set_selection_owner(conn: &mut X11Connection, window_id: u32, selection: u32, time: u32) {
+    const OPCODE: u8 = 22;
+
+    let request = RequestWriter::new(OPCODE, 0)
+        .put_u32(window_id)
+        .put_u32(selection)
+        .put_u32(time)
+        .finish();
+
+    conn.send(&request).unwrap();
+}
+
+/// Returns the window owning `selection`, or 0 if none does.
+pub(crate) fn x11_get_selection_owner(conn: &mut X11Connection, selection: u32) -> io::Result<u32> {
+    const OPCODE: u8 = 23;
+
+    let request = RequestWriter::new(OPCODE, 0)
+        .put_u32(selection)
+        .finish();
+
+    let reply = conn.send_with_reply(&request)?;
+    Ok(u32::from_ne_bytes([reply[8], reply[9], reply[10], reply[11]]))
+}
+
+/// Answers a SelectionRequest by sending `requestor` a SelectionNotify: `property` is
+/// where the selection was stored on the requestor's window, or 0 if it couldn't be
+/// converted to `target`.
+pub(crate) fn x11_send_selection_notify(conn: &mut X11Connection, requestor: u32, selection: u32, target: u32, property: u32, time: u32) {
+    const OPCODE: u8 = 25;
+    const EVENT_SELECTION_NOTIFY: u8 = 31;
+    const PROPAGATE: u8 = 0;
+
+    let request = RequestWriter::new(OPCODE, PROPAGATE)
+        .put_u32(requestor)
+        .put_u32(0) // event mask: sent to the client that created the requestor window
+        .put_u8(EVENT_SELECTION_NOTIFY)
+        .pad(1)
+        .put_u16(0) // sequence number, filled in by the server
+        .put_u32(time)
+        .put_u32(requestor)
+        .put_u32(selection)
+        .put_u32(target)
+        .put_u32(property)
+        .pad(8)
+        .finish();
+
+    conn.send(&request).unwrap();
+}
+
+/// Asks an EWMH window manager to keep `window_id` above other windows.
+///
+/// Call right after mapping: the ClientMessage covers window managers that already manage
+/// the window, and the property covers those that read it when they get to the MapRequest.
+pub(crate) fn x11_set_always_on_top(conn: &mut X11Connection, root_id: u32, window_id: u32) -> io::Result<()> {
+    const NET_WM_STATE_ADD: u32 = 1;
+    const SOURCE_APPLICATION: u32 = 1;
+
+    let net_wm_state = x11_intern_atom(conn, "_NET_WM_STATE", false)?;
+    let net_wm_state_above = x11_intern_atom(conn, "_NET_WM_STATE_ABOVE", false)?;
+    x11_change_property(conn, window_id, net_wm_state, ATOM_ATOM, 32, &net_wm_state_above.to_ne_bytes());
+    x11_send_root_client_message(
+        conn,
+        root_id,
+        window_id,
+        net_wm_state,
+        [NET_WM_STATE_ADD, net_wm_state_above, 0, SOURCE_APPLICATION, 0],
+    );
+    Ok(())
+}
+
+pub(crate) fn x11_create_pixmap(conn: &mut X11Connection,
                                 window_id: u32,
                                 pixmap_id: u32,
                                 width: u16,
@@ -384,21 +1441,21 @@ pub(crate) fn x11_create_pixmap(socket: &mut UnixStream,
                                 depth: u8) {
     const OPCODE: u8 = 53;
 
-    let request = CreatePixmapRequest {
-        opcode         : OPCODE,
-        depth          : depth,
-        request_length : 4,
-        pixmap_id      : pixmap_id,
-        drawable_id    : window_id,
-        width          : width,
-        height         : height,
-    };
+    let request = RequestWriter::new(OPCODE, depth)
+        .put_u32(pixmap_id)
+        .put_u32(window_id)
+        .put_u16(width)
+        .put_u16(height)
+        .finish();
 
-    return socket.write_all(unsafe { std::slice::from_raw_parts(&request as *const _ as *const u8, size_of::<CreatePixmapRequest>()) }).unwrap()
+    conn.send(&request).unwrap();
 }
 
-pub(crate) fn x11_put_image(socket: &mut UnixStream,
-                                window_id: u32,
+/// Sends a ZPixmap image with PutImage, split into horizontal bands so that no request
+/// exceeds the server's maximum request length.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn x11_put_image(conn: &mut X11Connection,
+                                info: &ConnectionInformation,
                                 drawable_id: u32,
                                 gc_id: u32,
                                 width: u16,
@@ -406,33 +1463,75 @@ pub(crate) fn x11_put_image(socket: &mut UnixStream,
                                 dst_x: u16,
                                 dst_y: u16,
                                 depth: u8,
-                                data: Vec<u8>,) {
-    let data_length_padded = round_up_4(data.len() as u32);
+                                data: &[u8]) -> io::Result<()> {
     const OPCODE: u8 = 72;
+    const FORMAT_ZPIXMAP: u8 = 2;
+    const HEADER_UNITS: u32 = 6;
+
+    if height == 0 || data.is_empty() {
+        return Ok(());
+    }
+
+    let stride = data.len() / height as usize;
+    // Reserve one unit for the extended length field of a BIG-REQUESTS request
+    let max_payload = (info.maximum_request_length - HEADER_UNITS - 1) as usize * 4;
+    let rows_per_chunk = (max_payload / stride).clamp(1, height as usize);
+
+    for (chunk_index, chunk) in data.chunks(rows_per_chunk * stride).enumerate() {
+        let request = RequestWriter::new(OPCODE, FORMAT_ZPIXMAP)
+            .put_u32(drawable_id)
+            .put_u32(gc_id)
+            .put_u16(width)
+            .put_u16((chunk.len() / stride) as u16)
+            .put_u16(dst_x)
+            .put_u16(dst_y + (chunk_index * rows_per_chunk) as u16)
+            .put_u8(0) // left pad
+            .put_u8(depth)
+            .pad(2)
+            .finish_with_payload(chunk.len());
+        let padding = [0u8; 3];
+
+        conn.send_vectored(&[&request, chunk, &padding[..chunk.len().next_multiple_of(4) - chunk.len()]])?;
+    }
+    Ok(())
+}
+
+/// Reads back a rectangle of `drawable_id` as ZPixmap data in the drawable's pixel format.
+pub(crate) fn x11_get_image(conn: &mut X11Connection, drawable_id: u32, x: u16, y: u16, width: u16, height: u16) -> io::Result<Vec<u8>> {
+    const OPCODE: u8 = 73;
+    const FORMAT_ZPIXMAP: u8 = 2;
+    const ALL_PLANES: u32 = u32::MAX;
+
+    let request = RequestWriter::new(OPCODE, FORMAT_ZPIXMAP)
+        .put_u32(drawable_id)
+        .put_u16(x)
+        .put_u16(y)
+        .put_u16(width)
+        .put_u16(height)
+        .put_u32(ALL_PLANES)
+        .finish();
+
+    let mut reply = conn.send_with_reply(&request)?;
+    Ok(reply.split_off(32))
+}
 
-    let request = PutImageRequest {
-        opcode         : OPCODE,
-        format         : 2, // ZPixmap
-        request_length : (6 + data_length_padded / 4) as u16,
-        drawable_id    : drawable_id,
-        gc_id          : gc_id,
-        width          : width,
-        height         : height,
-        dst_x          : dst_x,
-        dst_y          : dst_y,
-        left_pad       : 0,
-        depth          : depth,
-        pad1           : 0,
+/// Enables the BIG-REQUESTS extension if the server supports it, raising
+/// `info.maximum_request_length` to the extended limit.
+pub(crate) fn x11_enable_big_requests(conn: &mut X11Connection, info: &mut ConnectionInformation) -> io::Result<bool> {
+    const BIG_REQ_ENABLE: u8 = 0;
+
+    let Some(extension) = x11_query_extension(conn, "BIG-REQUESTS")? else {
+        return Ok(false);
     };
 
-    let padding_len = data_length_padded - data.len() as u32;
-    println!("req length {:} = calculated {:}", ((6 + data_length_padded / 4) as u16), ((size_of::<PutImageRequest>()) + data.len() + padding_len as usize) / 4);
-    socket.write_all(unsafe { std::slice::from_raw_parts(&request as *const _ as *const u8, size_of::<PutImageRequest>()) });
-    socket.write_all(&*data);
-    socket.write_all(&*vec![0u8; padding_len as usize]);
+    let request = RequestWriter::new(extension.major_opcode, BIG_REQ_ENABLE).finish();
+    let reply = conn.send_with_reply(&request)?;
+    info.maximum_request_length = u32::from_ne_bytes([reply[8], reply[9], reply[10], reply[11]]);
+    Ok(true)
 }
 
-pub(crate) fn x11_copy_area(socket: &mut UnixStream,
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn x11_copy_area(conn: &mut X11Connection,
                             src_id: u32,
                             dst_id: u32,
                             gc_id: u32,
@@ -444,20 +1543,405 @@ pub(crate) fn x11_copy_area(socket: &mut UnixStream,
                             height: u16) {
     const OPCODE: u8 = 62;
 
-    let request = CopyAreaRequest {
-        opcode         : OPCODE,
-        pad1           : 0,
-        request_length : 7,
-        src_id         : src_id,
-        dst_id         : dst_id,
-        gc_id          : gc_id,
-        src_x          : src_x,
-        src_y          : src_y,
-        dst_x          : dst_x,
-        dst_y          : dst_y,
-        width          : width,
-        height         : height,
-    };
+    let request = RequestWriter::new(OPCODE, 0)
+        .put_u32(src_id)
+        .put_u32(dst_id)
+        .put_u32(gc_id)
+        .put_u16(src_x)
+        .put_u16(src_y)
+        .put_u16(dst_x)
+        .put_u16(dst_y)
+        .put_u16(width)
+        .put_u16(height)
+        .finish();
+
+    conn.send(&request).unwrap();
+}
+
+/// Outlines each `(x, y, width, height)` rectangle with the GC's foreground color.
+pub(crate) fn x11_poly_rectangle(conn: &mut X11Connection, drawable_id: u32, gc_id: u32, rectangles: &[(u16, u16, u16, u16)]) {
+    const OPCODE: u8 = 67;
+
+    let mut request = RequestWriter::new(OPCODE, 0)
+        .put_u32(drawable_id)
+        .put_u32(gc_id);
+    for &(x, y, width, height) in rectangles {
+        request = request.put_u16(x).put_u16(y).put_u16(width).put_u16(height);
+    }
+
+    conn.send(&request.finish()).unwrap();
+}
+
+/// Fills each `(x, y, width, height)` rectangle with the GC's foreground color.
+pub(crate) fn x11_poly_fill_rectangle(conn: &mut X11Connection, drawable_id: u32, gc_id: u32, rectangles: &[(u16, u16, u16, u16)]) {
+    const OPCODE: u8 = 70;
+
+    let mut request = RequestWriter::new(OPCODE, 0)
+        .put_u32(drawable_id)
+        .put_u32(gc_id);
+    for &(x, y, width, height) in rectangles {
+        request = request.put_u16(x).put_u16(y).put_u16(width).put_u16(height);
+    }
+
+    conn.send(&request.finish()).unwrap();
+}
+
+/// Draws `text` with its baseline starting at `(x, y)`, filling the text's bounding box
+/// with the GC's background and the glyphs with its foreground. Longer strings than 255
+/// bytes are truncated.
+pub(crate) fn x11_image_text8(conn: &mut X11Connection, drawable_id: u32, gc_id: u32, x: u16, y: u16, text: &str) {
+    const OPCODE: u8 = 76;
+
+    let text = &text.as_bytes()[..text.len().min(u8::MAX as usize)];
+    let request = RequestWriter::new(OPCODE, text.len() as u8)
+        .put_u32(drawable_id)
+        .put_u32(gc_id)
+        .put_u16(x)
+        .put_u16(y)
+        .put_bytes(text)
+        .finish();
+
+    conn.send(&request).unwrap();
+}
+
+/// Creates a colormap for `visual_id` on the screen of `window_id`, as needed for windows
+/// whose visual differs from the root's.
+pub(crate) fn x11_create_colormap(conn: &mut X11Connection, colormap_id: u32, window_id: u32, visual_id: u32) {
+    const OPCODE: u8 = 78;
+    const ALLOC_NONE: u8 = 0;
+
+    let request = RequestWriter::new(OPCODE, ALLOC_NONE)
+        .put_u32(colormap_id)
+        .put_u32(window_id)
+        .put_u32(visual_id)
+        .finish();
+
+    conn.send(&request).unwrap();
+}
+
+pub(crate) fn x11_free_colormap(conn: &mut X11Connection, colormap_id: u32) -> io::Result<()> {
+    const OPCODE: u8 = 79;
+
+    let request = RequestWriter::new(OPCODE, 0)
+        .put_u32(colormap_id)
+        .finish();
+
+    conn.send(&request).map(|_| ())
+}
+
+/// Glyph indices in the standard "cursor" font (see X11/cursorfont.h).
+pub(crate) const CURSOR_GLYPH_X: u16 = 0;
+pub(crate) const CURSOR_GLYPH_CROSSHAIR: u16 = 34;
+
+/// 16-bit per channel color, as used by the cursor requests.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Rgb16 {
+    pub(crate) red: u16,
+    pub(crate) green: u16,
+    pub(crate) blue: u16,
+}
+
+pub(crate) const RGB16_BLACK: Rgb16 = Rgb16 { red: 0, green: 0, blue: 0 };
+pub(crate) const RGB16_WHITE: Rgb16 = Rgb16 { red: 0xffff, green: 0xffff, blue: 0xffff };
+
+pub(crate) fn x11_open_font(conn: &mut X11Connection, font_id: u32, name: &str) {
+    const OPCODE: u8 = 45;
+
+    let request = RequestWriter::new(OPCODE, 0)
+        .put_u32(font_id)
+        .put_u16(name.len() as u16)
+        .pad(2)
+        .put_bytes(name.as_bytes())
+        .finish();
+
+    conn.send(&request).unwrap();
+}
+
+pub(crate) fn x11_close_font(conn: &mut X11Connection, font_id: u32) {
+    const OPCODE: u8 = 46;
+
+    let request = RequestWriter::new(OPCODE, 0)
+        .put_u32(font_id)
+        .finish();
+
+    conn.send(&request).unwrap();
+}
+
+/// Creates a cursor from a glyph of a cursor font, masked by the glyph after it (as the
+/// standard cursor font lays them out).
+pub(crate) fn x11_create_glyph_cursor(conn: &mut X11Connection, cursor_id: u32, font_id: u32, glyph: u16, foreground: Rgb16, background: Rgb16) {
+    const OPCODE: u8 = 94;
+
+    let request = RequestWriter::new(OPCODE, 0)
+        .put_u32(cursor_id)
+        .put_u32(font_id)
+        .put_u32(font_id)
+        .put_u16(glyph)
+        .put_u16(glyph + 1)
+        .put_u16(foreground.red)
+        .put_u16(foreground.green)
+        .put_u16(foreground.blue)
+        .put_u16(background.red)
+        .put_u16(background.green)
+        .put_u16(background.blue)
+        .finish();
+
+    conn.send(&request).unwrap();
+}
+
+/// Creates a cursor from 1-bit deep source and mask pixmaps (mask may be 0 for none).
+#[allow(dead_code)]
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn x11_create_pixmap_cursor(conn: &mut X11Connection,
+                                       cursor_id: u32,
+                                       source_pixmap_id: u32,
+                                       mask_pixmap_id: u32,
+                                       foreground: Rgb16,
+                                       background: Rgb16,
+                                       hotspot_x: u16,
+                                       hotspot_y: u16) {
+    const OPCODE: u8 = 93;
+
+    let request = RequestWriter::new(OPCODE, 0)
+        .put_u32(cursor_id)
+        .put_u32(source_pixmap_id)
+        .put_u32(mask_pixmap_id)
+        .put_u16(foreground.red)
+        .put_u16(foreground.green)
+        .put_u16(foreground.blue)
+        .put_u16(background.red)
+        .put_u16(background.green)
+        .put_u16(background.blue)
+        .put_u16(hotspot_x)
+        .put_u16(hotspot_y)
+        .finish();
+
+    conn.send(&request).unwrap();
+}
+
+/// Sets the cursor shown while the pointer is inside `window_id`.
+pub(crate) fn x11_set_window_cursor(conn: &mut X11Connection, window_id: u32, cursor_id: u32) {
+    const OPCODE: u8 = 2;
+    const FLAG_WIN_CURSOR: u32 = 0x4000;
+
+    let request = RequestWriter::new(OPCODE, 0)
+        .put_u32(window_id)
+        .put_u32(FLAG_WIN_CURSOR)
+        .put_u32(cursor_id)
+        .finish();
+
+    conn.send(&request).unwrap();
+}
+
+pub(crate) const BACKING_STORE_NEVER: u8 = 0;
+pub(crate) const BACKING_STORE_WHEN_MAPPED: u8 = 1;
 
-    return socket.write_all(unsafe { std::slice::from_raw_parts(&request as *const _ as *const u8, size_of::<CopyAreaRequest>()) }).unwrap()
+/// Asks the server to keep the window's contents while it is covered (`backing_store`,
+/// 0 to 2 as in the Screen info) and to save what is under it while it is mapped.
+pub(crate) fn x11_set_backing_store(conn: &mut X11Connection, window_id: u32, backing_store: u8, save_under: bool) {
+    const OPCODE: u8 = 2;
+    const FLAG_WIN_BACKING_STORE: u32 = 0x40;
+    const FLAG_WIN_SAVE_UNDER: u32 = 0x400;
+
+    let request = RequestWriter::new(OPCODE, 0)
+        .put_u32(window_id)
+        .put_u32(FLAG_WIN_BACKING_STORE | FLAG_WIN_SAVE_UNDER)
+        .put_u32(backing_store as u32)
+        .put_u32(save_under as u32)
+        .finish();
+
+    conn.send(&request).unwrap();
+}
+
+/// Asks for `window_id` to be resized. The window manager may intercept the request and
+/// pick another size; the outcome arrives as Expose events.
+pub(crate) fn x11_resize_window(conn: &mut X11Connection, window_id: u32, width: u16, height: u16) {
+    const OPCODE: u8 = 12;
+    const FLAG_CONFIG_WIDTH: u16 = 0x4;
+    const FLAG_CONFIG_HEIGHT: u16 = 0x8;
+
+    let request = RequestWriter::new(OPCODE, 0)
+        .put_u32(window_id)
+        .put_u16(FLAG_CONFIG_WIDTH | FLAG_CONFIG_HEIGHT)
+        .pad(2)
+        .put_u32(width as u32)
+        .put_u32(height as u32)
+        .finish();
+
+    conn.send(&request).unwrap();
+}
+
+/// Actively grabs the pointer for `window_id`, so button and motion events keep coming
+/// to it (and only it) even outside the window. Returns whether the grab succeeded.
+pub(crate) fn x11_grab_pointer(conn: &mut X11Connection, window_id: u32) -> io::Result<bool> {
+    const OPCODE: u8 = 26;
+    const OWNER_EVENTS: u8 = 0;
+    const EVENT_FLAG_BUTTON_PRESS: u16 = 0x4;
+    const EVENT_FLAG_BUTTON_RELEASE: u16 = 0x8;
+    const EVENT_FLAG_POINTER_MOTION: u16 = 0x40;
+    const GRAB_MODE_ASYNC: u8 = 1;
+    const GRAB_SUCCESS: u8 = 0;
+
+    let request = RequestWriter::new(OPCODE, OWNER_EVENTS)
+        .put_u32(window_id)
+        .put_u16(EVENT_FLAG_BUTTON_PRESS | EVENT_FLAG_BUTTON_RELEASE | EVENT_FLAG_POINTER_MOTION)
+        .put_u8(GRAB_MODE_ASYNC) // pointer
+        .put_u8(GRAB_MODE_ASYNC) // keyboard
+        .put_u32(0) // confine_to: None
+        .put_u32(0) // cursor: keep the window's
+        .put_u32(0) // CurrentTime
+        .finish();
+
+    let reply = conn.send_with_reply(&request)?;
+    Ok(reply[1] == GRAB_SUCCESS)
+}
+
+pub(crate) fn x11_ungrab_pointer(conn: &mut X11Connection) {
+    const OPCODE: u8 = 27;
+
+    let request = RequestWriter::new(OPCODE, 0)
+        .put_u32(0) // CurrentTime
+        .finish();
+
+    conn.send(&request).unwrap();
+}
+
+/// Rings the bell at `percent` (-100 to 100) relative to the keyboard's base volume.
+pub(crate) fn x11_bell(conn: &mut X11Connection, percent: i8) {
+    const OPCODE: u8 = 104;
+
+    let request = RequestWriter::new(OPCODE, percent as u8).finish();
+
+    conn.send(&request).unwrap();
+}
+
+/// Returns the pointer position relative to the root of `window_id`'s screen.
+pub(crate) fn x11_query_pointer(conn: &mut X11Connection, window_id: u32) -> io::Result<(i16, i16)> {
+    const OPCODE: u8 = 38;
+
+    let request = RequestWriter::new(OPCODE, 0)
+        .put_u32(window_id)
+        .finish();
+
+    let reply = conn.send_with_reply(&request)?;
+    let mut buffer = Cursor::new(&reply[16..]);
+    Ok((buffer.read_i16::<NativeEndian>()?, buffer.read_i16::<NativeEndian>()?))
+}
+
+/// Position and size of a drawable as reported by GetGeometry.
+#[derive(Debug, Clone, Copy)]
+#[allow(dead_code)]
+pub(crate) struct Geometry {
+    pub(crate) root_id: u32,
+    pub(crate) depth: u8,
+    pub(crate) x: i16,
+    pub(crate) y: i16,
+    pub(crate) width: u16,
+    pub(crate) height: u16,
+    pub(crate) border_width: u16,
+}
+
+pub(crate) fn x11_get_geometry(conn: &mut X11Connection, drawable_id: u32) -> io::Result<Geometry> {
+    const OPCODE: u8 = 14;
+
+    let request = RequestWriter::new(OPCODE, 0)
+        .put_u32(drawable_id)
+        .finish();
+
+    let reply = conn.send_with_reply(&request)?;
+    let mut buffer = Cursor::new(&reply[8..]);
+
+    Ok(Geometry {
+        depth: reply[1],
+        root_id: buffer.read_u32::<NativeEndian>()?,
+        x: buffer.read_i16::<NativeEndian>()?,
+        y: buffer.read_i16::<NativeEndian>()?,
+        width: buffer.read_u16::<NativeEndian>()?,
+        height: buffer.read_u16::<NativeEndian>()?,
+        border_width: buffer.read_u16::<NativeEndian>()?,
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ExtensionInfo {
+    pub(crate) major_opcode: u8,
+}
+
+pub(crate) fn x11_query_extension(conn: &mut X11Connection, name: &str) -> io::Result<Option<ExtensionInfo>> {
+    const OPCODE: u8 = 98;
+
+    let request = RequestWriter::new(OPCODE, 0)
+        .put_u16(name.len() as u16)
+        .pad(2)
+        .put_bytes(name.as_bytes())
+        .finish();
+
+    let reply = conn.send_with_reply(&request)?;
+    let present = reply[8] != 0;
+
+    Ok(present.then_some(ExtensionInfo { major_opcode: reply[9] }))
+}
+
+pub(crate) const KEYSYM_RETURN: u32 = 0xff0d;
+pub(crate) const KEYSYM_ESCAPE: u32 = 0xff1b;
+pub(crate) const KEYSYM_LEFT: u32 = 0xff51;
+pub(crate) const KEYSYM_UP: u32 = 0xff52;
+pub(crate) const KEYSYM_RIGHT: u32 = 0xff53;
+pub(crate) const KEYSYM_DOWN: u32 = 0xff54;
+pub(crate) const KEYSYM_KP_ENTER: u32 = 0xff8d;
+pub(crate) const KEYSYM_F2: u32 = 0xffbf;
+pub(crate) const KEYSYM_SPACE: u32 = 0x20;
+pub(crate) const KEYSYM_A: u32 = 0x61;
+pub(crate) const KEYSYM_C: u32 = 0x63;
+pub(crate) const KEYSYM_D: u32 = 0x64;
+pub(crate) const KEYSYM_E: u32 = 0x65;
+pub(crate) const KEYSYM_F: u32 = 0x66;
+pub(crate) const KEYSYM_H: u32 = 0x68;
+pub(crate) const KEYSYM_M: u32 = 0x6d;
+pub(crate) const KEYSYM_P: u32 = 0x70;
+pub(crate) const KEYSYM_R: u32 = 0x72;
+pub(crate) const KEYSYM_S: u32 = 0x73;
+pub(crate) const KEYSYM_T: u32 = 0x74;
+
+/// Keycode to keysym table as returned by GetKeyboardMapping.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Keymap {
+    min_keycode: u8,
+    keysyms_per_keycode: u8,
+    keysyms: Vec<u32>,
+}
+
+impl Keymap {
+    /// Returns the unshifted keysym for `keycode`, with Latin letters folded to lowercase.
+    pub(crate) fn keysym(&self, keycode: u8) -> Option<u32> {
+        let index = (keycode.checked_sub(self.min_keycode)? as usize) * self.keysyms_per_keycode as usize;
+        let keysym = *self.keysyms.get(index)?;
+        match keysym {
+            0 => None,
+            0x41..=0x5a => Some(keysym + 0x20),
+            _ => Some(keysym),
+        }
+    }
+}
+
+pub(crate) fn x11_get_keyboard_mapping(conn: &mut X11Connection, info: &ConnectionInformation) -> io::Result<Keymap> {
+    const OPCODE: u8 = 101;
+
+    let request = RequestWriter::new(OPCODE, 0)
+        .put_u8(info.min_keycode)
+        .put_u8(info.max_keycode - info.min_keycode + 1)
+        .pad(2)
+        .finish();
+
+    let reply = conn.send_with_reply(&request)?;
+    let keysyms = reply[32..]
+        .chunks_exact(4)
+        .map(|keysym| u32::from_ne_bytes([keysym[0], keysym[1], keysym[2], keysym[3]]))
+        .collect();
+
+    Ok(Keymap {
+        min_keycode: info.min_keycode,
+        keysyms_per_keycode: reply[1],
+        keysyms,
+    })
 }
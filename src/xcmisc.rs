@@ -0,0 +1,29 @@
+use crate::encoder::RequestWriter;
+use crate::x11comm::X11Connection;
+use std::io;
+
+const XC_MISC_GET_VERSION: u8 = 0;
+const XC_MISC_GET_XID_RANGE: u8 = 1;
+
+const XC_MISC_MAJOR_VERSION: u16 = 1;
+const XC_MISC_MINOR_VERSION: u16 = 1;
+
+pub(crate) fn xc_misc_get_version(conn: &mut X11Connection, major_opcode: u8) -> io::Result<()> {
+    let request = RequestWriter::new(major_opcode, XC_MISC_GET_VERSION)
+        .put_u16(XC_MISC_MAJOR_VERSION)
+        .put_u16(XC_MISC_MINOR_VERSION)
+        .finish();
+    conn.send_with_reply(&request).map(|_| ())
+}
+
+/// Asks the server for a contiguous run of resource IDs that are no longer in use.
+///
+/// Returns the first ID and how many follow it (0 when none are left).
+pub(crate) fn xc_misc_get_xid_range(conn: &mut X11Connection, major_opcode: u8) -> io::Result<(u32, u32)> {
+    let request = RequestWriter::new(major_opcode, XC_MISC_GET_XID_RANGE).finish();
+    let reply = conn.send_with_reply(&request)?;
+
+    let start_id = u32::from_ne_bytes([reply[8], reply[9], reply[10], reply[11]]);
+    let count = u32::from_ne_bytes([reply[12], reply[13], reply[14], reply[15]]);
+    Ok((start_id, count))
+}
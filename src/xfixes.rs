@@ -0,0 +1,52 @@
+use crate::encoder::RequestWriter;
+use crate::x11comm::{x11_query_extension, X11Connection};
+use std::io;
+
+const XFIXES_QUERY_VERSION: u8 = 0;
+const XFIXES_HIDE_CURSOR: u8 = 29;
+const XFIXES_SHOW_CURSOR: u8 = 30;
+
+/// HideCursor and ShowCursor appeared in XFixes 4.0.
+const XFIXES_MAJOR_VERSION: u32 = 4;
+const XFIXES_MINOR_VERSION: u32 = 0;
+
+fn xfixes_query_version(conn: &mut X11Connection, major_opcode: u8) -> io::Result<(u32, u32)> {
+    let request = RequestWriter::new(major_opcode, XFIXES_QUERY_VERSION)
+        .put_u32(XFIXES_MAJOR_VERSION)
+        .put_u32(XFIXES_MINOR_VERSION)
+        .finish();
+    let reply = conn.send_with_reply(&request)?;
+
+    Ok((
+        u32::from_ne_bytes([reply[8], reply[9], reply[10], reply[11]]),
+        u32::from_ne_bytes([reply[12], reply[13], reply[14], reply[15]]),
+    ))
+}
+
+/// Negotiates XFixes 4.0. Returns the extension's major opcode, or `None` when the server
+/// lacks it or only has an older version.
+pub(crate) fn enable(conn: &mut X11Connection) -> io::Result<Option<u8>> {
+    let Some(extension) = x11_query_extension(conn, "XFIXES")? else {
+        return Ok(None);
+    };
+    if xfixes_query_version(conn, extension.major_opcode)? < (XFIXES_MAJOR_VERSION, XFIXES_MINOR_VERSION) {
+        return Ok(None);
+    }
+    Ok(Some(extension.major_opcode))
+}
+
+/// Hides the pointer while it is over `window_id`. Calls nest: the pointer only comes back
+/// after as many `show_cursor` calls, or when the client disconnects.
+pub(crate) fn hide_cursor(conn: &mut X11Connection, major_opcode: u8, window_id: u32) -> io::Result<()> {
+    let request = RequestWriter::new(major_opcode, XFIXES_HIDE_CURSOR)
+        .put_u32(window_id)
+        .finish();
+    conn.send(&request).map(|_| ())
+}
+
+pub(crate) fn show_cursor(conn: &mut X11Connection, major_opcode: u8, window_id: u32) -> io::Result<()> {
+    let request = RequestWriter::new(major_opcode, XFIXES_SHOW_CURSOR)
+        .put_u32(window_id)
+        .finish();
+    conn.send(&request).map(|_| ())
+}
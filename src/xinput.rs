@@ -0,0 +1,247 @@
+use crate::encoder::RequestWriter;
+use crate::x11comm::{x11_query_extension, X11Connection};
+use std::collections::HashMap;
+use std::io;
+
+const XI_SELECT_EVENTS: u8 = 46;
+const XI_QUERY_VERSION: u8 = 47;
+const XI_QUERY_DEVICE: u8 = 48;
+
+/// Touch events need XInput 2.2.
+const XI_MAJOR_VERSION: u16 = 2;
+const XI_MINOR_VERSION: u16 = 2;
+
+const XI_ALL_MASTER_DEVICES: u16 = 1;
+const XI_MASTER_POINTER: u16 = 1;
+const XI_SCROLL_CLASS: u16 = 3;
+const XI_SCROLL_TYPE_HORIZONTAL: u16 = 2;
+
+/// A master pointer switched to a slave device with other classes, e.g. other scroll
+/// valuators.
+pub(crate) const XI_DEVICE_CHANGED: u16 = 1;
+pub(crate) const XI_MOTION: u16 = 6;
+pub(crate) const XI_TOUCH_BEGIN: u16 = 18;
+pub(crate) const XI_TOUCH_UPDATE: u16 = 19;
+pub(crate) const XI_TOUCH_END: u16 = 20;
+
+/// Type of the XI2 event in a GenericEvent packet from XInput.
+pub(crate) fn event_type(packet: &[u8]) -> u16 {
+    u16::from_ne_bytes([packet[8], packet[9]])
+}
+
+/// Converts a 32.32 fixed point number, as valuators and scroll increments are sent.
+fn fp3232(integral: u32, fraction: u32) -> f64 {
+    integral as i32 as f64 + fraction as f64 / 4294967296.0
+}
+
+/// The fields of an XIDeviceEvent (touch, button, motion...) the game cares about.
+#[derive(Debug, Clone)]
+pub(crate) struct DeviceEvent {
+    pub(crate) event_type: u16,
+    /// Master device the event came through.
+    pub(crate) device_id: u16,
+    /// Touch ID for touch events, button or keycode otherwise.
+    pub(crate) detail: u32,
+    pub(crate) time: u32,
+    pub(crate) event_x: i16,
+    pub(crate) event_y: i16,
+    /// Numbers and positions of the valuators the event carries.
+    pub(crate) valuators: Vec<(u16, f64)>,
+}
+
+impl DeviceEvent {
+    /// Decodes an XI2 device event from a complete GenericEvent packet.
+    pub(crate) fn decode(packet: &[u8]) -> Option<Self> {
+        const HEADER_SIZE: usize = 80;
+
+        if packet.len() < HEADER_SIZE {
+            return None;
+        }
+        let u16_at = |offset: usize| u16::from_ne_bytes([packet[offset], packet[offset + 1]]);
+        let u32_at = |offset: usize| packet.get(offset..offset + 4).map(|bytes| u32::from_ne_bytes(bytes.try_into().unwrap()));
+
+        // After the header come the button mask, the valuator mask, then the value of each
+        // valuator set in the mask
+        let (buttons_len, valuators_len) = (u16_at(48) as usize, u16_at(50) as usize);
+        let mask_offset = HEADER_SIZE + buttons_len * 4;
+        let mut value_offset = mask_offset + valuators_len * 4;
+        let mut valuators = Vec::new();
+        for number in 0..valuators_len * 32 {
+            if u32_at(mask_offset + number / 32 * 4)? & 1 << (number % 32) == 0 {
+                continue;
+            }
+            valuators.push((number as u16, fp3232(u32_at(value_offset)?, u32_at(value_offset + 4)?)));
+            value_offset += 8;
+        }
+
+        // Positions are 16.16 fixed point
+        Some(DeviceEvent {
+            event_type: u16_at(8),
+            device_id: u16_at(10),
+            time: u32_at(12)?,
+            detail: u32_at(16)?,
+            event_x: (u32_at(40)? as i32 >> 16) as i16,
+            event_y: (u32_at(44)? as i32 >> 16) as i16,
+            valuators,
+        })
+    }
+}
+
+/// A valuator of a master pointer that scrolls, from its scroll class.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ScrollValuator {
+    pub(crate) device_id: u16,
+    pub(crate) number: u16,
+    pub(crate) horizontal: bool,
+    /// How far the valuator moves for one step of a wheel.
+    pub(crate) increment: f64,
+}
+
+/// Pans by the scroll valuators of motion events, which move smoothly on touchpads and
+/// high-resolution wheels, rather than by wheel buttons.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SmoothScroll {
+    valuators: Vec<ScrollValuator>,
+    /// Where each valuator was at the last event, by device and number.
+    positions: HashMap<(u16, u16), f64>,
+    /// Cells scrolled but not panned yet, sideways and down.
+    remainder: (f64, f64),
+}
+
+impl SmoothScroll {
+    pub(crate) fn new(valuators: Vec<ScrollValuator>) -> Self {
+        SmoothScroll { valuators, ..SmoothScroll::default() }
+    }
+
+    /// Whether any pointer scrolls by valuators; the wheel buttons it also sends are then
+    /// left alone.
+    pub(crate) fn is_active(&self) -> bool {
+        !self.valuators.is_empty()
+    }
+
+    /// Forgets where the valuators were, e.g. once the pointer left the window, as they
+    /// may have moved elsewhere meanwhile.
+    pub(crate) fn reset(&mut self) {
+        self.positions.clear();
+        self.remainder = (0.0, 0.0);
+    }
+
+    /// Whole cells to pan by for `event`, sideways and down, at `cells_per_step` for each
+    /// step of a wheel. What's left of a cell is kept for the next event.
+    pub(crate) fn cells(&mut self, event: &DeviceEvent, cells_per_step: f64) -> (i64, i64) {
+        for &(number, position) in &event.valuators {
+            let Some(valuator) = self.valuators.iter().find(|valuator| valuator.device_id == event.device_id && valuator.number == number) else {
+                continue;
+            };
+            // The first event only tells where the valuator starts from
+            let Some(previous) = self.positions.insert((event.device_id, number), position) else {
+                continue;
+            };
+            let cells = (position - previous) / valuator.increment * cells_per_step;
+            match valuator.horizontal {
+                true => self.remainder.0 += cells,
+                false => self.remainder.1 += cells,
+            }
+        }
+        let whole = (self.remainder.0.trunc(), self.remainder.1.trunc());
+        self.remainder = (self.remainder.0 - whole.0, self.remainder.1 - whole.1);
+        (whole.0 as i64, whole.1 as i64)
+    }
+}
+
+fn xi_query_version(conn: &mut X11Connection, major_opcode: u8) -> io::Result<(u16, u16)> {
+    let request = RequestWriter::new(major_opcode, XI_QUERY_VERSION)
+        .put_u16(XI_MAJOR_VERSION)
+        .put_u16(XI_MINOR_VERSION)
+        .finish();
+    let reply = conn.send_with_reply(&request)?;
+
+    Ok((u16::from_ne_bytes([reply[8], reply[9]]), u16::from_ne_bytes([reply[10], reply[11]])))
+}
+
+fn xi_select_events(conn: &mut X11Connection, major_opcode: u8, window_id: u32, mask: u32) -> io::Result<()> {
+    let request = RequestWriter::new(major_opcode, XI_SELECT_EVENTS)
+        .put_u32(window_id)
+        .put_u16(1) // number of masks
+        .pad(2)
+        .put_u16(XI_ALL_MASTER_DEVICES)
+        .put_u16(1) // mask length in 4-byte units
+        .put_u32(mask)
+        .finish();
+    conn.send(&request).map(|_| ())
+}
+
+/// Lists the scroll valuators of the master pointers, from the scroll classes of the
+/// slave devices they currently follow.
+pub(crate) fn query_scroll_valuators(conn: &mut X11Connection, major_opcode: u8) -> io::Result<Vec<ScrollValuator>> {
+    const DEVICE_INFO_SIZE: usize = 12;
+
+    let request = RequestWriter::new(major_opcode, XI_QUERY_DEVICE)
+        .put_u16(XI_ALL_MASTER_DEVICES)
+        .pad(2)
+        .finish();
+    let reply = conn.send_with_reply(&request)?;
+    let u16_at = |offset: usize| reply.get(offset..offset + 2).map(|bytes| u16::from_ne_bytes([bytes[0], bytes[1]]));
+    let u32_at = |offset: usize| reply.get(offset..offset + 4).map(|bytes| u32::from_ne_bytes(bytes.try_into().unwrap()));
+
+    let mut valuators = Vec::new();
+    let mut offset = 32;
+    for _ in 0..u16_at(8).unwrap_or(0) {
+        let (Some(device_id), Some(device_use), Some(classes), Some(name_len)) = (u16_at(offset), u16_at(offset + 2), u16_at(offset + 6), u16_at(offset + 8)) else {
+            break;
+        };
+        offset += DEVICE_INFO_SIZE + (name_len as usize).next_multiple_of(4);
+        for _ in 0..classes {
+            let (Some(class_type), Some(length)) = (u16_at(offset), u16_at(offset + 2)) else {
+                break;
+            };
+            if class_type == XI_SCROLL_CLASS && device_use == XI_MASTER_POINTER {
+                if let (Some(number), Some(scroll_type), Some(integral), Some(fraction)) = (u16_at(offset + 6), u16_at(offset + 8), u32_at(offset + 16), u32_at(offset + 20)) {
+                    let increment = fp3232(integral, fraction);
+                    // A zero increment would scroll infinitely far
+                    if increment != 0.0 {
+                        valuators.push(ScrollValuator { device_id, number, horizontal: scroll_type == XI_SCROLL_TYPE_HORIZONTAL, increment });
+                    }
+                }
+            }
+            offset += length as usize * 4;
+        }
+    }
+    Ok(valuators)
+}
+
+/// Selects touch events on `window_id`, which also stops the server from emulating
+/// pointer events for touches on it, and motion events for their scroll valuators, which
+/// take the place of core MotionNotify events there.
+///
+/// Returns the extension's major opcode, which identifies its GenericEvents, or `None`
+/// when the server lacks XInput 2.2.
+pub(crate) fn enable(conn: &mut X11Connection, window_id: u32) -> io::Result<Option<u8>> {
+    let Some(extension) = x11_query_extension(conn, "XInputExtension")? else {
+        return Ok(None);
+    };
+    if xi_query_version(conn, extension.major_opcode)? < (XI_MAJOR_VERSION, XI_MINOR_VERSION) {
+        return Ok(None);
+    }
+
+    let mask = 1 << XI_DEVICE_CHANGED | 1 << XI_MOTION | 1 << XI_TOUCH_BEGIN | 1 << XI_TOUCH_UPDATE | 1 << XI_TOUCH_END;
+    xi_select_events(conn, extension.major_opcode, window_id, mask)?;
+    Ok(Some(extension.major_opcode))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pans_whole_cells_and_keeps_the_rest() {
+        let mut scroll = SmoothScroll::new(vec![ScrollValuator { device_id: 2, number: 3, horizontal: false, increment: 120.0 }]);
+        let event = |position: f64| DeviceEvent { event_type: XI_MOTION, device_id: 2, detail: 0, time: 0, event_x: 0, event_y: 0, valuators: vec![(3, position)] };
+
+        assert_eq!(scroll.cells(&event(1000.0), 3.0), (0, 0));
+        // Half a wheel step is a cell and a half
+        assert_eq!(scroll.cells(&event(1060.0), 3.0), (0, 1));
+        assert_eq!(scroll.cells(&event(1080.0), 3.0), (0, 1));
+        assert_eq!(scroll.cells(&event(960.0), 3.0), (0, -3));
+    }
+}
@@ -0,0 +1,144 @@
+use crate::encoder::RequestWriter;
+use crate::x11comm::{x11_query_extension, X11Connection};
+use std::io;
+
+const RENDER_QUERY_VERSION: u8 = 0;
+const RENDER_QUERY_PICT_FORMATS: u8 = 1;
+const RENDER_CREATE_PICTURE: u8 = 4;
+const RENDER_FREE_PICTURE: u8 = 7;
+const RENDER_COMPOSITE: u8 = 8;
+const RENDER_SET_PICTURE_TRANSFORM: u8 = 28;
+const RENDER_SET_PICTURE_FILTER: u8 = 30;
+
+/// Picture transforms and filters appeared in RENDER 0.6.
+const RENDER_MAJOR_VERSION: u32 = 0;
+const RENDER_MINOR_VERSION: u32 = 6;
+
+/// PictOpSrc: the destination takes the source's pixels as they are.
+const PICT_OP_SRC: u8 = 1;
+/// 1.0 as a RENDER fixed-point number, with 16 fractional bits.
+const FIXED_ONE: f64 = 65536.0;
+
+/// Sizes of the entries of a QueryPictFormats reply.
+const PICT_FORMAT_INFO_SIZE: usize = 28;
+const PICT_SCREEN_SIZE: usize = 8;
+const PICT_DEPTH_SIZE: usize = 8;
+const PICT_VISUAL_SIZE: usize = 8;
+
+/// RENDER, negotiated along with the picture format of the window's visual, which its
+/// back buffer shares.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Render {
+    pub(crate) major_opcode: u8,
+    format: u32,
+}
+
+fn render_query_version(conn: &mut X11Connection, major_opcode: u8) -> io::Result<(u32, u32)> {
+    let request = RequestWriter::new(major_opcode, RENDER_QUERY_VERSION)
+        .put_u32(RENDER_MAJOR_VERSION)
+        .put_u32(RENDER_MINOR_VERSION)
+        .finish();
+    let reply = conn.send_with_reply(&request)?;
+
+    Ok((
+        u32::from_ne_bytes([reply[8], reply[9], reply[10], reply[11]]),
+        u32::from_ne_bytes([reply[12], reply[13], reply[14], reply[15]]),
+    ))
+}
+
+/// Finds the picture format of `visual_id` in a QueryPictFormats reply: after the formats
+/// come the screens, each listing its depths, each listing its visuals and their formats.
+fn visual_format(reply: &[u8], visual_id: u32) -> Option<u32> {
+    let u32_at = |offset: usize| reply.get(offset..offset + 4).map(|bytes| u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+    let u16_at = |offset: usize| reply.get(offset..offset + 2).map(|bytes| u16::from_ne_bytes([bytes[0], bytes[1]]));
+
+    let (formats, screens) = (u32_at(8)? as usize, u32_at(12)?);
+    let mut offset = 32 + formats * PICT_FORMAT_INFO_SIZE;
+    for _ in 0..screens {
+        let depths = u32_at(offset)?;
+        offset += PICT_SCREEN_SIZE;
+        for _ in 0..depths {
+            let visuals = u16_at(offset + 2)? as usize;
+            offset += PICT_DEPTH_SIZE;
+            for visual in 0..visuals {
+                let entry = offset + visual * PICT_VISUAL_SIZE;
+                if u32_at(entry)? == visual_id {
+                    return u32_at(entry + 4);
+                }
+            }
+            offset += visuals * PICT_VISUAL_SIZE;
+        }
+    }
+    None
+}
+
+/// Negotiates RENDER 0.6 and looks up the picture format of `visual_id`. Returns `None`
+/// when the server lacks it, only has an older version or no format for the visual.
+pub(crate) fn enable(conn: &mut X11Connection, visual_id: u32) -> io::Result<Option<Render>> {
+    let Some(extension) = x11_query_extension(conn, "RENDER")? else {
+        return Ok(None);
+    };
+    if render_query_version(conn, extension.major_opcode)? < (RENDER_MAJOR_VERSION, RENDER_MINOR_VERSION) {
+        return Ok(None);
+    }
+
+    let request = RequestWriter::new(extension.major_opcode, RENDER_QUERY_PICT_FORMATS).finish();
+    let reply = conn.send_with_reply(&request)?;
+    Ok(visual_format(&reply, visual_id).map(|format| Render { major_opcode: extension.major_opcode, format }))
+}
+
+/// Creates picture `picture_id` to draw to or from `drawable_id`.
+pub(crate) fn create_picture(conn: &mut X11Connection, render: Render, picture_id: u32, drawable_id: u32) -> io::Result<()> {
+    let request = RequestWriter::new(render.major_opcode, RENDER_CREATE_PICTURE)
+        .put_u32(picture_id)
+        .put_u32(drawable_id)
+        .put_u32(render.format)
+        .put_u32(0) // no attributes
+        .finish();
+    conn.send(&request).map(|_| ())
+}
+
+pub(crate) fn free_picture(conn: &mut X11Connection, major_opcode: u8, picture_id: u32) -> io::Result<()> {
+    let request = RequestWriter::new(major_opcode, RENDER_FREE_PICTURE)
+        .put_u32(picture_id)
+        .finish();
+    conn.send(&request).map(|_| ())
+}
+
+/// Has `picture_id` read `factor` times bigger as a source, without smoothing, so the
+/// sprites' pixels stay sharp.
+pub(crate) fn set_scale(conn: &mut X11Connection, render: Render, picture_id: u32, factor: f64) -> io::Result<()> {
+    // The transform takes destination coordinates back to the source's
+    let inverse = (FIXED_ONE / factor).round() as i32 as u32;
+    let one = FIXED_ONE as u32;
+    let mut request = RequestWriter::new(render.major_opcode, RENDER_SET_PICTURE_TRANSFORM).put_u32(picture_id);
+    for entry in [inverse, 0, 0, 0, inverse, 0, 0, 0, one] {
+        request = request.put_u32(entry);
+    }
+    conn.send(&request.finish())?;
+
+    let filter = b"nearest";
+    let request = RequestWriter::new(render.major_opcode, RENDER_SET_PICTURE_FILTER)
+        .put_u32(picture_id)
+        .put_u16(filter.len() as u16)
+        .pad(2)
+        .put_bytes(filter)
+        .finish();
+    conn.send(&request).map(|_| ())
+}
+
+/// Copies a `width` by `height` area from the top-left of `source_id`, through its
+/// transform, to the top-left of `destination_id`.
+pub(crate) fn composite(conn: &mut X11Connection, render: Render, source_id: u32, destination_id: u32, width: u16, height: u16) -> io::Result<()> {
+    let request = RequestWriter::new(render.major_opcode, RENDER_COMPOSITE)
+        .put_u8(PICT_OP_SRC)
+        .pad(3)
+        .put_u32(source_id)
+        .put_u32(0) // no mask
+        .put_u32(destination_id)
+        .pad(12) // source, mask and destination origins all at (0, 0)
+        .put_u16(width)
+        .put_u16(height)
+        .finish();
+    conn.send(&request).map(|_| ())
+}
@@ -0,0 +1,374 @@
+use crate::auth::{load_ice_auth_cookie, AUTH_NAME_MAGIC_COOKIE};
+use crate::x11comm::SocketAddress;
+use std::env;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+// ICE (Inter-Client Exchange) messages, major opcode 0
+const ICE_ERROR: u8 = 0;
+const ICE_BYTE_ORDER: u8 = 1;
+const ICE_CONNECTION_SETUP: u8 = 2;
+const ICE_AUTH_REQUIRED: u8 = 3;
+const ICE_AUTH_REPLY: u8 = 4;
+const ICE_CONNECTION_REPLY: u8 = 6;
+const ICE_PROTOCOL_SETUP: u8 = 7;
+const ICE_PROTOCOL_REPLY: u8 = 8;
+const ICE_PING: u8 = 9;
+const ICE_PING_REPLY: u8 = 10;
+
+const ICE_LSB_FIRST: u8 = 0;
+const ICE_MSB_FIRST: u8 = 1;
+
+// XSMP messages, sent under the major opcode chosen in ProtocolSetup
+const SM_ERROR: u8 = 0;
+const SM_REGISTER_CLIENT: u8 = 1;
+const SM_REGISTER_CLIENT_REPLY: u8 = 2;
+const SM_SAVE_YOURSELF: u8 = 3;
+const SM_SAVE_YOURSELF_DONE: u8 = 8;
+const SM_DIE: u8 = 9;
+const SM_SHUTDOWN_CANCELLED: u8 = 10;
+const SM_CONNECTION_CLOSED: u8 = 11;
+const SM_SET_PROPERTIES: u8 = 12;
+
+/// Major opcode XSMP messages are sent with; ICE leaves the choice to each side.
+const XSMP_OPCODE: u8 = 1;
+const VENDOR: &str = "mineswept";
+const RELEASE: &str = env!("CARGO_PKG_VERSION");
+
+/// Serializes one ICE message in native byte order. Like [`RequestWriter`](crate::encoder::RequestWriter),
+/// but ICE headers are 8 bytes with a 32-bit length counted in 8-byte units.
+#[derive(Debug)]
+struct MessageWriter {
+    buffer: Vec<u8>,
+}
+
+impl MessageWriter {
+    fn new(major_opcode: u8, minor_opcode: u8, data: [u8; 2]) -> Self {
+        MessageWriter { buffer: vec![major_opcode, minor_opcode, data[0], data[1], 0, 0, 0, 0] }
+    }
+
+    fn put_u8(mut self, value: u8) -> Self {
+        self.buffer.push(value);
+        self
+    }
+
+    fn put_u16(mut self, value: u16) -> Self {
+        self.buffer.extend_from_slice(&value.to_ne_bytes());
+        self
+    }
+
+    fn put_u32(mut self, value: u32) -> Self {
+        self.buffer.extend_from_slice(&value.to_ne_bytes());
+        self
+    }
+
+    fn put_bytes(mut self, bytes: &[u8]) -> Self {
+        self.buffer.extend_from_slice(bytes);
+        self
+    }
+
+    fn pad(mut self, count: usize) -> Self {
+        self.buffer.resize(self.buffer.len() + count, 0);
+        self
+    }
+
+    /// ICE STRING: 16-bit length and bytes, padded to 4.
+    fn put_string(self, value: &str) -> Self {
+        let padding = (2 + value.len()).next_multiple_of(4) - (2 + value.len());
+        self.put_u16(value.len() as u16).put_bytes(value.as_bytes()).pad(padding)
+    }
+
+    /// XSMP ARRAY8: 32-bit length and bytes, padded to 8.
+    fn put_array8(self, value: &[u8]) -> Self {
+        let padding = (4 + value.len()).next_multiple_of(8) - (4 + value.len());
+        self.put_u32(value.len() as u32).put_bytes(value).pad(padding)
+    }
+
+    /// XSMP LISTofARRAY8: 32-bit count, padding to 8, then the arrays.
+    fn put_array8_list(self, values: &[&[u8]]) -> Self {
+        values.iter().fold(self.put_u32(values.len() as u32).pad(4), |message, value| message.put_array8(value))
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.buffer.resize(self.buffer.len().next_multiple_of(8), 0);
+        let length = (self.buffer.len() as u32 - 8) / 8;
+        self.buffer[4..8].copy_from_slice(&length.to_ne_bytes());
+        self.buffer
+    }
+}
+
+/// One message off the ICE connection, with its header.
+struct Message {
+    major_opcode: u8,
+    minor_opcode: u8,
+    bytes: Vec<u8>,
+}
+
+impl Message {
+    /// Whether this is an XSMP message rather than an ICE one. The session manager picks
+    /// its own opcode for the messages it sends, and XSMP is the only protocol set up.
+    fn is_xsmp(&self) -> bool {
+        self.major_opcode != 0
+    }
+
+    /// The ARRAY8 starting at `offset`, if the message is long enough to hold it.
+    fn array8_at(&self, offset: usize) -> Option<&[u8]> {
+        let len = u32::from_ne_bytes(self.bytes.get(offset..offset + 4)?.try_into().unwrap()) as usize;
+        self.bytes.get(offset + 4..offset + 4 + len)
+    }
+}
+
+/// What the session manager asked of the game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SessionRequest {
+    /// Save state so the game can be restarted where it left off, then call
+    /// [`SessionManager::save_yourself_done`].
+    SaveYourself { shutdown: bool },
+    /// The session is ending; exit.
+    Die,
+    /// A logout that asked us to save was called off.
+    ShutdownCancelled,
+    /// Something that needs no action, e.g. an ICE ping that was already answered.
+    Other,
+}
+
+/// Connection to the X session manager named by `SESSION_MANAGER`, over ICE on a local
+/// socket. Only what's needed to be saved and restarted with the session is implemented:
+/// no interaction, no phase 2, no TCP.
+#[derive(Debug)]
+pub(crate) struct SessionManager {
+    stream: UnixStream,
+    client_id: String,
+}
+
+impl SessionManager {
+    /// Connects and registers with the session manager, resuming `previous_id` when the
+    /// session restarted us. Returns `None` outside a session.
+    pub(crate) fn connect(previous_id: Option<&str>) -> io::Result<Option<SessionManager>> {
+        let Some(addresses) = env::var("SESSION_MANAGER").ok().filter(|value| !value.is_empty()) else {
+            return Ok(None);
+        };
+
+        let mut last_error = io::Error::new(io::ErrorKind::Unsupported, "No local transport in SESSION_MANAGER");
+        for network_id in addresses.split(',') {
+            let Some(address) = parse_network_id(network_id) else {
+                continue;
+            };
+            match address.connect() {
+                Ok(stream) => return SessionManager::open(stream, network_id, previous_id).map(Some),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    fn open(stream: UnixStream, network_id: &str, previous_id: Option<&str>) -> io::Result<SessionManager> {
+        let mut session_manager = SessionManager { stream, client_id: String::new() };
+        session_manager.setup_connection(network_id)?;
+        session_manager.setup_protocol(network_id)?;
+
+        session_manager.client_id = match session_manager.register(previous_id.unwrap_or(""))? {
+            Some(client_id) => client_id,
+            // The session manager forgot about the old ID; start over as a new client
+            None if previous_id.is_some() => session_manager.register("")?
+                .ok_or_else(|| io::Error::other("Session manager rejected the registration"))?,
+            None => return Err(io::Error::other("Session manager rejected the registration")),
+        };
+        Ok(session_manager)
+    }
+
+    /// Client ID to pass back through `--sm-client-id` when the session restarts the game.
+    pub(crate) fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    /// Exchanges byte orders and opens the ICE connection.
+    fn setup_connection(&mut self, network_id: &str) -> io::Result<()> {
+        let byte_order = if cfg!(target_endian = "little") { ICE_LSB_FIRST } else { ICE_MSB_FIRST };
+        let cookie = load_ice_auth_cookie("ICE", network_id)?;
+
+        self.send(&MessageWriter::new(0, ICE_BYTE_ORDER, [byte_order, 0]).finish())?;
+        let auth_names: &[&str] = if cookie.is_some() { &[AUTH_NAME_MAGIC_COOKIE] } else { &[] };
+        let setup = auth_names.iter().fold(
+            MessageWriter::new(0, ICE_CONNECTION_SETUP, [1, auth_names.len() as u8])
+                .put_u8(0) // must authenticate
+                .pad(7)
+                .put_string(VENDOR)
+                .put_string(RELEASE),
+            |message, name| message.put_string(name),
+        );
+        self.send(&setup.put_u16(1).put_u16(0).finish())?;
+
+        let reply = self.read_message()?;
+        if reply.is_xsmp() || reply.minor_opcode != ICE_BYTE_ORDER {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Session manager did not send its byte order"));
+        }
+        if reply.bytes[2] != byte_order {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "Session manager uses a different byte order"));
+        }
+
+        self.authenticate(cookie.as_deref(), ICE_CONNECTION_REPLY)
+    }
+
+    /// Asks for XSMP 1.0 on the connection.
+    fn setup_protocol(&mut self, network_id: &str) -> io::Result<()> {
+        let cookie = load_ice_auth_cookie("XSMP", network_id)?;
+
+        let auth_names: &[&str] = if cookie.is_some() { &[AUTH_NAME_MAGIC_COOKIE] } else { &[] };
+        let setup = auth_names.iter().fold(
+            MessageWriter::new(0, ICE_PROTOCOL_SETUP, [XSMP_OPCODE, 0])
+                .put_u8(1) // version count
+                .put_u8(auth_names.len() as u8)
+                .pad(6)
+                .put_string("XSMP")
+                .put_string(VENDOR)
+                .put_string(RELEASE),
+            |message, name| message.put_string(name),
+        );
+        self.send(&setup.put_u16(1).put_u16(0).finish())?;
+
+        self.authenticate(cookie.as_deref(), ICE_PROTOCOL_REPLY)
+    }
+
+    /// Answers authentication challenges with `cookie` until the `accepted` reply arrives.
+    fn authenticate(&mut self, cookie: Option<&[u8]>, accepted: u8) -> io::Result<()> {
+        loop {
+            let message = self.read_message()?;
+            match (message.is_xsmp(), message.minor_opcode) {
+                (false, minor_opcode) if minor_opcode == accepted => return Ok(()),
+                (false, ICE_AUTH_REQUIRED) => {
+                    let cookie = cookie.ok_or_else(|| io::Error::new(io::ErrorKind::PermissionDenied, "Session manager requires authentication"))?;
+                    let reply = MessageWriter::new(0, ICE_AUTH_REPLY, [0, 0])
+                        .put_u16(cookie.len() as u16)
+                        .pad(6)
+                        .put_bytes(cookie)
+                        .finish();
+                    self.send(&reply)?;
+                }
+                (false, ICE_PING) => self.send(&MessageWriter::new(0, ICE_PING_REPLY, [0, 0]).finish())?,
+                (false, ICE_ERROR) => {
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Session manager refused the connection"));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Sends RegisterClient and returns the ID the session manager assigned, or `None` if
+    /// it rejected `previous_id`.
+    fn register(&mut self, previous_id: &str) -> io::Result<Option<String>> {
+        let request = MessageWriter::new(XSMP_OPCODE, SM_REGISTER_CLIENT, [0, 0])
+            .put_array8(previous_id.as_bytes())
+            .finish();
+        self.send(&request)?;
+
+        loop {
+            let message = self.read_message()?;
+            match (message.is_xsmp(), message.minor_opcode) {
+                (false, ICE_PING) => self.send(&MessageWriter::new(0, ICE_PING_REPLY, [0, 0]).finish())?,
+                (false, ICE_ERROR) | (true, SM_ERROR) => return Ok(None),
+                (true, SM_REGISTER_CLIENT_REPLY) => {
+                    let client_id = message.array8_at(8)
+                        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Truncated RegisterClientReply"))?;
+                    return Ok(Some(String::from_utf8_lossy(client_id).to_string()));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Tells the session manager how to restart the game: `program` with `args`, plus the
+    /// client ID so the restarted game can pick up the saved board.
+    pub(crate) fn set_restart_command(&mut self, program: &str, args: &[String]) -> io::Result<()> {
+        let clone_command: Vec<&[u8]> = std::iter::once(program)
+            .chain(args.iter().map(String::as_str))
+            .map(str::as_bytes)
+            .collect();
+        let mut restart_command = clone_command.clone();
+        restart_command.extend([b"--sm-client-id".as_slice(), self.client_id.as_bytes()]);
+        let user_id = env::var("USER").or_else(|_| env::var("LOGNAME")).unwrap_or_default();
+        let current_directory = env::current_dir().unwrap_or_else(|_| PathBuf::from("/"));
+
+        let properties: [(&str, &str, Vec<&[u8]>); 5] = [
+            ("Program", "ARRAY8", vec![program.as_bytes()]),
+            ("UserID", "ARRAY8", vec![user_id.as_bytes()]),
+            ("CurrentDirectory", "ARRAY8", vec![current_directory.as_os_str().as_encoded_bytes()]),
+            ("CloneCommand", "LISTofARRAY8", clone_command),
+            ("RestartCommand", "LISTofARRAY8", restart_command),
+        ];
+        let request = properties.iter().fold(
+            MessageWriter::new(XSMP_OPCODE, SM_SET_PROPERTIES, [0, 0])
+                .put_u32(properties.len() as u32)
+                .pad(4),
+            |message, (name, type_, values)| {
+                message.put_array8(name.as_bytes()).put_array8(type_.as_bytes()).put_array8_list(values)
+            },
+        );
+        self.send(&request.finish())
+    }
+
+    /// Reads and decodes the next message; call when the descriptor is readable.
+    pub(crate) fn next_request(&mut self) -> io::Result<SessionRequest> {
+        let message = self.read_message()?;
+        let request = match (message.is_xsmp(), message.minor_opcode) {
+            (false, ICE_PING) => {
+                self.send(&MessageWriter::new(0, ICE_PING_REPLY, [0, 0]).finish())?;
+                SessionRequest::Other
+            }
+            (true, SM_SAVE_YOURSELF) => SessionRequest::SaveYourself { shutdown: message.bytes[9] != 0 },
+            (true, SM_DIE) => SessionRequest::Die,
+            (true, SM_SHUTDOWN_CANCELLED) => SessionRequest::ShutdownCancelled,
+            _ => SessionRequest::Other,
+        };
+        Ok(request)
+    }
+
+    /// Answers a SaveYourself once the board has been saved, or couldn't be.
+    pub(crate) fn save_yourself_done(&mut self, success: bool) -> io::Result<()> {
+        self.send(&MessageWriter::new(XSMP_OPCODE, SM_SAVE_YOURSELF_DONE, [success as u8, 0]).finish())
+    }
+
+    /// Says goodbye before exiting, so the session manager doesn't treat it as a crash.
+    pub(crate) fn close(mut self) -> io::Result<()> {
+        let request = MessageWriter::new(XSMP_OPCODE, SM_CONNECTION_CLOSED, [0, 0])
+            .put_array8_list(&[])
+            .finish();
+        self.send(&request)
+    }
+
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        self.stream.write_all(message)
+    }
+
+    fn read_message(&mut self) -> io::Result<Message> {
+        let mut bytes = vec![0u8; 8];
+        self.stream.read_exact(&mut bytes)?;
+        let length = u32::from_ne_bytes(bytes[4..8].try_into().unwrap()) as usize * 8;
+        bytes.resize(8 + length, 0);
+        self.stream.read_exact(&mut bytes[8..])?;
+
+        Ok(Message { major_opcode: bytes[0], minor_opcode: bytes[1], bytes })
+    }
+}
+
+impl AsRawFd for SessionManager {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
+
+/// Socket for a `SESSION_MANAGER` entry such as `local/host:@/tmp/.ICE-unix/1234` or
+/// `unix/host:/tmp/.ICE-unix/1234`; `None` for TCP entries.
+fn parse_network_id(network_id: &str) -> Option<SocketAddress> {
+    let (transport, rest) = network_id.split_once('/')?;
+    if transport != "local" && transport != "unix" {
+        return None;
+    }
+    let (_host, path) = rest.split_once(':')?;
+    Some(match path.strip_prefix('@') {
+        Some(name) => SocketAddress::Abstract(name.to_string()),
+        None => SocketAddress::Path(PathBuf::from(path)),
+    })
+}